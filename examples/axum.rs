@@ -0,0 +1,37 @@
+use std::net::SocketAddr;
+
+use axum::{routing::any, Extension, Router};
+use webdav_handler::axum::{DavRequest, DavResponse};
+use webdav_handler::{fakels::FakeLs, localfs::LocalFs, DavConfig, DavHandler};
+
+async fn dav_handler(Extension(davhandler): Extension<DavHandler>, req: DavRequest) -> DavResponse {
+    if let Some(prefix) = req.prefix() {
+        let config = DavConfig::new().strip_prefix(prefix);
+        davhandler.handle_with(config, req.request).await.into()
+    } else {
+        davhandler.handle(req.request).await.into()
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let dir = "/tmp";
+    let addr: SocketAddr = ([127, 0, 0, 1], 4918).into();
+
+    let dav_server = DavHandler::builder()
+        .filesystem(LocalFs::new(dir, false, false, false))
+        .locksystem(FakeLs::new())
+        .build_handler();
+
+    let app = Router::new()
+        .route("/", any(dav_handler))
+        .route("/*path", any(dav_handler))
+        .layer(Extension(dav_server));
+
+    println!("axum example: listening on {:?} serving {}", addr, dir);
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    // with the connect info, the handler knows the address of the client.
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, app).await.unwrap();
+}
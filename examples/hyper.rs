@@ -1,5 +1,5 @@
 use std::convert::Infallible;
-use webdav_handler::{fakels::FakeLs, localfs::LocalFs, DavHandler};
+use webdav_handler::{fakels::FakeLs, localfs::LocalFs, DavConfig, DavHandler};
 
 #[tokio::main]
 async fn main() {
@@ -12,12 +12,14 @@ async fn main() {
         .locksystem(FakeLs::new())
         .build_handler();
 
-    let make_service = hyper::service::make_service_fn(move |_| {
+    let make_service = hyper::service::make_service_fn(move |conn: &hyper::server::conn::AddrStream| {
         let dav_server = dav_server.clone();
+        let remote_addr = conn.remote_addr();
         async move {
             let func = move |req| {
                 let dav_server = dav_server.clone();
-                async move { Ok::<_, Infallible>(dav_server.handle(req).await) }
+                let config = DavConfig::new().remote_addr(remote_addr);
+                async move { Ok::<_, Infallible>(dav_server.handle_with(config, req).await) }
             };
             Ok::<_, Infallible>(hyper::service::service_fn(func))
         }
@@ -0,0 +1,21 @@
+use poem::{listener::TcpListener, Route, Server};
+use webdav_handler::poem::DavEndpoint;
+use webdav_handler::{fakels::FakeLs, localfs::LocalFs, DavHandler};
+
+#[tokio::main]
+async fn main() -> Result<(), std::io::Error> {
+    env_logger::init();
+    let dir = "/tmp";
+    let addr = "127.0.0.1:4918";
+
+    let dav_server = DavHandler::builder()
+        .filesystem(LocalFs::new(dir, false, false, false))
+        .locksystem(FakeLs::new())
+        .build_handler();
+
+    // everything under /dav is served by the handler, "/dav" is the prefix.
+    let app = Route::new().nest("/dav", DavEndpoint::new(dav_server));
+
+    println!("poem example: listening on {} serving {}", addr, dir);
+    Server::new(TcpListener::bind(addr)).run(app).await
+}
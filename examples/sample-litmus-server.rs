@@ -17,18 +17,18 @@ use hyper;
 
 use headers::{authorization::Basic, Authorization, HeaderMapExt};
 
-use webdav_handler::{fakels, localfs, memfs, memls, DavConfig, DavHandler};
+use webdav_handler::{fakels, localfs, memfs, memls, AuthResult, DavHandler};
 use webdav_handler::{body::Body, time::UtcOffset};
 
 #[derive(Clone)]
 struct Server {
-    dh:   DavHandler,
-    auth: bool,
+    dh: DavHandler,
 }
 
 impl Server {
     pub fn new(directory: String, memls: bool, fakels: bool, auth: bool) -> Self {
-        let mut config = DavHandler::builder();
+        // litmus does Depth: infinity PROPFINDs.
+        let mut config = DavHandler::builder().propfind_max_depth(None);
         if directory != "" {
             let utctime = time::UtcOffset::current_local_offset().map(UtcOffset::from).ok();
             config = config
@@ -43,38 +43,23 @@ impl Server {
         if memls {
             config = config.locksystem(memls::MemLs::new());
         }
+        if auth {
+            // we want the client to authenticate.
+            config = config.authenticator(|req| {
+                match req.headers().typed_get::<Authorization<Basic>>() {
+                    Some(Authorization(basic)) => AuthResult::Ok(basic.username().to_string()),
+                    None => AuthResult::Challenge("Basic realm=\"foo\"".to_string()),
+                }
+            });
+        }
 
         Server {
             dh: config.build_handler(),
-            auth,
         }
     }
 
     async fn handle(&self, req: hyper::Request<hyper::Body>) -> Result<hyper::Response<Body>, Infallible> {
-        let user = if self.auth {
-            // we want the client to authenticate.
-            match req.headers().typed_get::<Authorization<Basic>>() {
-                Some(Authorization(basic)) => Some(basic.username().to_string()),
-                None => {
-                    // return a 401 reply.
-                    let response = hyper::Response::builder()
-                        .status(401)
-                        .header("WWW-Authenticate", "Basic realm=\"foo\"")
-                        .body(Body::from("please auth".to_string()))
-                        .unwrap();
-                    return Ok(response);
-                },
-            }
-        } else {
-            None
-        };
-
-        if let Some(user) = user {
-            let config = DavConfig::new().principal(user);
-            Ok(self.dh.handle_with(config, req).await)
-        } else {
-            Ok(self.dh.handle(req).await)
-        }
+        Ok(self.dh.handle(req).await)
     }
 }
 
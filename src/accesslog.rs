@@ -0,0 +1,164 @@
+//
+// Access log in the Combined Log Format, enabled with `DavConfig::access_log`.
+//
+// The line for a request is written when its response body has been sent,
+// or when it is dropped (because the client went away, for example), so
+// that the number of bytes is the number that was actually sent.
+//
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use futures::StreamExt;
+use http::{Request, Response};
+
+use crate::async_stream::AsyncStream;
+use crate::body::Body;
+use crate::time::systemtime_to_clf;
+
+pub(crate) type AccessLog = Arc<Mutex<Box<dyn io::Write + Send>>>;
+
+pub(crate) struct AccessLogEntry {
+    log:        AccessLog,
+    remote:     Option<SocketAddr>,
+    principal:  Option<String>,
+    time:       SystemTime,
+    request:    String,
+    referer:    Option<String>,
+    user_agent: Option<String>,
+    status:     u16,
+    bytes:      u64,
+}
+
+impl AccessLogEntry {
+    pub(crate) fn new(log: AccessLog, req: &Request<()>, remote: Option<SocketAddr>) -> AccessLogEntry {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+        };
+        let uri = req.uri().path_and_query().map_or("/", |p| p.as_str());
+        AccessLogEntry {
+            log,
+            remote,
+            principal: None,
+            time: SystemTime::now(),
+            request: format!("{} {} {:?}", req.method(), uri, req.version()),
+            referer: header(http::header::REFERER),
+            user_agent: header(http::header::USER_AGENT),
+            status: 0,
+            bytes: 0,
+        }
+    }
+
+    // Count the bytes of the response body, and log when it is done.
+    pub(crate) fn log_response(self, principal: Option<String>, resp: Response<Body>) -> Response<Body> {
+        let mut entry = self;
+        entry.principal = principal;
+        entry.status = resp.status().as_u16();
+        let (parts, mut body) = resp.into_parts();
+        let body = Body::from(AsyncStream::new(move |mut tx| {
+            async move {
+                while let Some(chunk) = body.next().await {
+                    let chunk = chunk?;
+                    entry.sent(chunk.len());
+                    tx.send(chunk).await;
+                }
+                Ok(())
+            }
+        }));
+        Response::from_parts(parts, body)
+    }
+
+    fn sent(&mut self, len: usize) {
+        self.bytes += len as u64;
+    }
+}
+
+impl Drop for AccessLogEntry {
+    fn drop(&mut self) {
+        let quoted = |s: &Option<String>| s.as_deref().map_or("-".to_string(), escape);
+        let line = format!(
+            "{} - {} [{}] \"{}\" {} {} \"{}\" \"{}\"\n",
+            self.remote.map_or("-".to_string(), |a| a.ip().to_string()),
+            self.principal.as_deref().map_or("-".to_string(), |p| escape(p).replace(' ', "%20")),
+            systemtime_to_clf(self.time),
+            escape(&self.request),
+            self.status,
+            if self.bytes == 0 { "-".to_string() } else { self.bytes.to_string() },
+            quoted(&self.referer),
+            quoted(&self.user_agent),
+        );
+        if let Ok(mut log) = self.log.lock() {
+            if let Err(e) = log.write_all(line.as_bytes()) {
+                error!("access log: {}", e);
+            }
+        }
+    }
+}
+
+// Escape quotes, backslashes and control characters, like Apache does.
+fn escape(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                r.push('\\');
+                r.push(c);
+            },
+            c if c.is_control() => r.push_str(&format!("\\x{:02x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use crate::memfs::MemFs;
+    use crate::{DavConfig, DavHandler};
+
+    #[derive(Clone, Default)]
+    struct Buf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buf {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn combined() {
+        let buf = Buf::default();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .access_log(buf.clone())
+            .build_handler();
+        let put = http::Request::put("/file?x=1").body(hyper::Body::from("hello")).unwrap();
+        dav.handle(put).await;
+        let get = http::Request::get("/file")
+            .header("User-Agent", "test \"agent\"")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let config = DavConfig::new()
+            .principal("joe")
+            .remote_addr("192.0.2.1:4918".parse().unwrap());
+        let resp = dav.handle_with(config, get).await;
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "hello");
+
+        let log = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("- - - ["));
+        assert!(lines[0].ends_with("] \"PUT /file?x=1 HTTP/1.1\" 201 - \"-\" \"-\""));
+        assert!(lines[1].starts_with("192.0.2.1 - joe ["));
+        assert!(lines[1].ends_with(" +0000] \"GET /file HTTP/1.1\" 200 5 \"-\" \"test \\\"agent\\\"\""));
+    }
+}
@@ -13,6 +13,7 @@
 //! ```
 //!
 use std::io;
+use std::path::Path;
 
 use std::pin::Pin;
 use std::task::{Context, Poll};
@@ -23,6 +24,8 @@ use bytes::Bytes;
 use futures::{future, Stream};
 use pin_project::pin_project;
 
+use crate::{localfs::LocalFs, DavHandler, DavMethodSet};
+
 /// http::Request compatibility.
 ///
 /// Wraps `http::Request<DavBody>` and implements `actix_web::FromRequest`.
@@ -51,6 +54,9 @@ impl FromRequest for DavRequest {
         for (name, value) in req.headers().iter() {
             builder = builder.header(name, value);
         }
+        if let Some(addr) = req.peer_addr() {
+            builder = builder.extension(addr);
+        }
         let path = req.match_info().path();
         let tail = req.match_info().unprocessed();
         let prefix = match &path[..path.len() - tail.len()] {
@@ -145,3 +151,24 @@ impl actix_web::Responder for DavResponse {
         resp
     }
 }
+
+/// Creates a route that serves a single file, ignoring the request path,
+/// like `warp::dav_file`.
+///
+/// Only `GET`, `HEAD` and `OPTIONS` are allowed.
+///
+/// ```no_run
+/// use actix_web::App;
+///
+/// let app = App::new().route("/hosts", webdav_handler::actix::dav_file("/etc/hosts"));
+/// ```
+pub fn dav_file(file: impl AsRef<Path>) -> actix_web::Route {
+    let handler = DavHandler::builder()
+        .filesystem(LocalFs::new_file(file, false))
+        .methods(DavMethodSet::HTTP_RO)
+        .build_handler();
+    actix_web::web::route().to(move |req: DavRequest| {
+        let handler = handler.clone();
+        async move { DavResponse::from(handler.handle(req.request).await) }
+    })
+}
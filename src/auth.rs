@@ -0,0 +1,340 @@
+//! Digest authentication (RFC7616, RFC2617).
+//!
+//! Some clients (older Windows and macOS WebDAV clients, for one) will
+//! not send a password with Basic authentication over plain HTTP, but
+//! they will do Digest authentication. [`DigestAuth`] does the server
+//! side of that: it hands out nonces, checks the `Authorization: Digest`
+//! header, and generates the `WWW-Authenticate: Digest` challenge. Only
+//! the `MD5` algorithm with `qop=auth` is supported, since that is what
+//! clients actually implement.
+//!
+//! A nonce is valid for a short while (5 minutes by default). After that
+//! the client gets a new challenge with `stale=true`, and it retries with
+//! a new nonce without asking the user again. Every request must use a
+//! higher nonce count (`nc`) than the previous one with the same nonce,
+//! so a captured request can not be replayed.
+//!
+//! Nonces are kept in memory, so with several instances of a server
+//! behind a load balancer, clients must stick to one instance.
+//!
+//! Example:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use webdav_handler::{auth::DigestAuth, memfs::MemFs, DavHandler};
+//!
+//! let digest = Arc::new(DigestAuth::with_password("webdav", |user| {
+//!     match user {
+//!         "alice" => Some("secret".to_string()),
+//!         _ => None,
+//!     }
+//! }));
+//! let dav_server = DavHandler::builder()
+//!     .filesystem(MemFs::new())
+//!     .authenticator(move |req| digest.authenticate(req))
+//!     .build_handler();
+//! ```
+//!
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use http::Request;
+use lru::LruCache;
+use parking_lot::Mutex;
+use uuid::Uuid;
+
+use crate::davhandler::AuthResult;
+
+// Maximum number of outstanding nonces.
+const MAX_NONCES: usize = 10000;
+
+const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+type Lookup = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+struct Nonce {
+    created: Instant,
+    nc:      u32,
+}
+
+/// Server side of HTTP Digest authentication.
+///
+/// Use [`authenticate`](DigestAuth::authenticate) as the
+/// [`authenticator`](crate::DavConfig::authenticator) of the handler.
+pub struct DigestAuth {
+    realm:  String,
+    // username -> HA1, that is md5(username:realm:password).
+    ha1:    Lookup,
+    ttl:    Duration,
+    nonces: Mutex<LruCache<String, Nonce>>,
+}
+
+impl fmt::Debug for DigestAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DigestAuth")
+            .field("realm", &self.realm)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+/// `md5(username:realm:password)` as a hex string.
+///
+/// This is what a [`DigestAuth::with_ha1`] lookup function returns. It
+/// can be stored instead of the password, for example in a htdigest file.
+pub fn ha1(username: &str, realm: &str, password: &str) -> String {
+    md5_hex(&format!("{}:{}:{}", username, realm, password))
+}
+
+fn md5_hex(s: &str) -> String {
+    format!("{:x}", md5::compute(s.as_bytes()))
+}
+
+impl DigestAuth {
+    /// Create a new `DigestAuth` for `realm`.
+    ///
+    /// The function returns the password of a user, or `None`
+    /// if the user does not exist.
+    pub fn with_password<F>(realm: impl Into<String>, password: F) -> DigestAuth
+    where F: Fn(&str) -> Option<String> + Send + Sync + 'static {
+        let realm = realm.into();
+        let r = realm.clone();
+        let lookup = move |user: &str| password(user).map(|pw| ha1(user, &r, &pw));
+        DigestAuth::new(realm, Arc::new(lookup))
+    }
+
+    /// Create a new `DigestAuth` for `realm`.
+    ///
+    /// The function returns the HA1 of a user (see [`ha1`]), or `None`
+    /// if the user does not exist. That way the server does not need
+    /// to know the passwords.
+    pub fn with_ha1<F>(realm: impl Into<String>, ha1: F) -> DigestAuth
+    where F: Fn(&str) -> Option<String> + Send + Sync + 'static {
+        DigestAuth::new(realm.into(), Arc::new(ha1))
+    }
+
+    fn new(realm: String, ha1: Lookup) -> DigestAuth {
+        DigestAuth {
+            realm,
+            ha1,
+            ttl:    DEFAULT_TTL,
+            nonces: Mutex::new(LruCache::new(std::num::NonZeroUsize::new(MAX_NONCES).unwrap())),
+        }
+    }
+
+    /// How long a nonce can be used. The default is 5 minutes.
+    pub fn nonce_ttl(self, ttl: Duration) -> DigestAuth {
+        let mut this = self;
+        this.ttl = ttl;
+        this
+    }
+
+    /// Generate a `WWW-Authenticate` challenge with a new nonce.
+    ///
+    /// Set `stale` if the client sent the right credentials, but
+    /// with an expired nonce.
+    pub fn challenge(&self, stale: bool) -> String {
+        let nonce = Uuid::new_v4().simple().to_string();
+        let entry = Nonce {
+            created: Instant::now(),
+            nc:      0,
+        };
+        self.nonces.lock().put(nonce.clone(), entry);
+        format!(
+            "Digest realm=\"{}\", qop=\"auth\", algorithm=MD5, nonce=\"{}\"{}",
+            quote(&self.realm),
+            nonce,
+            if stale { ", stale=true" } else { "" }
+        )
+    }
+
+    /// Check the `Authorization: Digest` header of a request.
+    ///
+    /// Returns `AuthResult::Ok(username)` if it is valid, and
+    /// otherwise `AuthResult::Challenge` with a new challenge.
+    pub fn authenticate(&self, req: &Request<()>) -> AuthResult {
+        match self.check(req) {
+            Ok(user) => AuthResult::Ok(user),
+            Err(stale) => AuthResult::Challenge(self.challenge(stale)),
+        }
+    }
+
+    // Ok(username), or Err(stale).
+    fn check(&self, req: &Request<()>) -> Result<String, bool> {
+        let hdr = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or(false)?;
+        let params = match hdr.split_once(' ') {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("digest") => {
+                parse_params(rest).ok_or(false)?
+            },
+            _ => return Err(false),
+        };
+        let param = |name: &str| params.get(name).map(|s| s.as_str()).ok_or(false);
+
+        let username = param("username")?;
+        let nonce = param("nonce")?;
+        let uri = param("uri")?;
+        let cnonce = param("cnonce")?;
+        let nc_str = param("nc")?;
+        let response = param("response")?;
+        if param("realm")? != self.realm || param("qop")? != "auth" {
+            return Err(false);
+        }
+        if params.get("algorithm").is_some_and(|a| !a.eq_ignore_ascii_case("md5")) {
+            return Err(false);
+        }
+        let nc = u32::from_str_radix(nc_str, 16).map_err(|_| false)?;
+
+        // the uri must be the one of this request.
+        let req_uri = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        if uri != req_uri && uri != *req.uri() {
+            return Err(false);
+        }
+
+        let ha1 = (self.ha1)(username).ok_or(false)?;
+        let ha2 = md5_hex(&format!("{}:{}", req.method(), uri));
+        let expected = md5_hex(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc_str, cnonce, ha2));
+        if !response.eq_ignore_ascii_case(&expected) {
+            debug!("DigestAuth: wrong response for user {}", username);
+            return Err(false);
+        }
+
+        // The credentials are right. Now see if the nonce is still good.
+        let mut nonces = self.nonces.lock();
+        let entry = match nonces.get_mut(nonce) {
+            Some(entry) => entry,
+            None => return Err(true),
+        };
+        if entry.created.elapsed() > self.ttl {
+            nonces.pop(nonce);
+            return Err(true);
+        }
+        if nc <= entry.nc {
+            debug!("DigestAuth: nonce count {} reused by user {}", nc, username);
+            return Err(false);
+        }
+        entry.nc = nc;
+        Ok(username.to_string())
+    }
+}
+
+fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Parse `name=value, name="quoted value", ...`.
+fn parse_params(s: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|&c| c == ',' || c.is_ascii_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c.is_ascii_whitespace() {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        while chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            chars.next();
+        }
+        if chars.next() != Some('=') {
+            return None;
+        }
+        while chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            chars.next();
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => value.push(chars.next()?),
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c.is_ascii_whitespace() {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+        }
+        params.insert(name.to_ascii_lowercase(), value);
+    }
+    Some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example from RFC2617 section 3.5.
+    const AUTH: &str = r#"Digest username="Mufasa", realm="testrealm@host.com",
+        nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", uri="/dir/index.html",
+        qop=auth, nc=00000001, cnonce="0a4f113b",
+        response="6629fae49393a05397450978507c4ef1",
+        opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+
+    fn request(auth: &str) -> Request<()> {
+        Request::builder()
+            .uri("/dir/index.html")
+            .header("Authorization", auth.replace('\n', ""))
+            .body(())
+            .unwrap()
+    }
+
+    fn digest() -> DigestAuth {
+        let digest = DigestAuth::with_password("testrealm@host.com", |user| {
+            (user == "Mufasa").then(|| "Circle Of Life".to_string())
+        });
+        let nonce = Nonce {
+            created: Instant::now(),
+            nc:      0,
+        };
+        digest.nonces.lock().put("dcd98b7102dd2f0e8b11d0f600bfb0c093".to_string(), nonce);
+        digest
+    }
+
+    #[test]
+    fn test_digest_ok_and_replay() {
+        let digest = digest();
+        assert_eq!(digest.check(&request(AUTH)), Ok("Mufasa".to_string()));
+        // same nonce count again.
+        assert_eq!(digest.check(&request(AUTH)), Err(false));
+    }
+
+    #[test]
+    fn test_digest_wrong() {
+        let digest = digest();
+        let auth = AUTH.replace("6629fae4", "6629fae5");
+        assert_eq!(digest.check(&request(&auth)), Err(false));
+        let auth = AUTH.replace("/dir/index.html", "/dir/other.html");
+        assert_eq!(digest.check(&request(&auth)), Err(false));
+    }
+
+    #[test]
+    fn test_digest_stale() {
+        let digest = digest().nonce_ttl(Duration::from_secs(0));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(digest.check(&request(AUTH)), Err(true));
+        match digest.authenticate(&request(AUTH)) {
+            AuthResult::Challenge(c) => assert!(c.ends_with("stale=true")),
+            _ => panic!("expected a challenge"),
+        }
+    }
+}
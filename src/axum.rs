@@ -0,0 +1,255 @@
+//! Adapters to use the standard `http` types with Axum.
+//!
+//! Axum 0.7 is built on `http` 1.x, while this crate uses `http` 0.2.
+//! The adapters in this module take care of converting between the two.
+//! They are enabled with the `axum` feature (or its older name, `axum-compat`).
+//!
+//! Using the adapters in this module, it's easy to build a webdav
+//! handler for axum:
+//!
+//! ```no_run
+//! use axum::{routing::any, Extension, Router};
+//! use webdav_handler::{DavConfig, DavHandler, axum::DavRequest, axum::DavResponse};
+//!
+//! async fn dav_handler(Extension(davhandler): Extension<DavHandler>, req: DavRequest) -> DavResponse {
+//!     if let Some(prefix) = req.prefix() {
+//!         let config = DavConfig::new().strip_prefix(prefix);
+//!         davhandler.handle_with(config, req.request).await.into()
+//!     } else {
+//!         davhandler.handle(req.request).await.into()
+//!     }
+//! }
+//!
+//! # fn router(davhandler: DavHandler) -> Router {
+//! Router::new()
+//!     .route("/dav/*path", any(dav_handler))
+//!     .layer(Extension(davhandler))
+//! # }
+//! ```
+//!
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::BodyDataStream;
+use axum::extract::{ConnectInfo, FromRequest, MatchedPath, OriginalUri, Request};
+use axum::response::{IntoResponse, Response};
+use axum::routing::MethodRouter;
+use bytes::Bytes;
+use futures::Stream;
+use pin_project::pin_project;
+
+use crate::util::route_prefix;
+use crate::{localfs::LocalFs, DavHandler, DavMethodSet};
+
+/// http::Request compatibility.
+///
+/// Wraps `http::Request<DavBody>` and implements `axum::extract::FromRequest`.
+pub struct DavRequest {
+    pub request: http::Request<DavBody>,
+    prefix:      Option<String>,
+}
+
+impl DavRequest {
+    /// Returns the request path minus the tail.
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+}
+
+#[axum::async_trait]
+impl<S> FromRequest<S> for DavRequest
+where S: Send + Sync
+{
+    type Rejection = Infallible;
+
+    async fn from_request(req: Request, _state: &S) -> Result<DavRequest, Infallible> {
+        let (parts, body) = req.into_parts();
+
+        // If we are nested, the router has stripped the uri. We want the original.
+        let uri = match parts.extensions.get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri.to_string(),
+            None => parts.uri.to_string(),
+        };
+        let full_path = uri.split('?').next().unwrap_or("/").to_string();
+        let matched = parts.extensions.get::<MatchedPath>().map(|m| m.as_str());
+        let prefix = route_prefix(&full_path, parts.uri.path(), matched);
+
+        let mut builder = http::Request::builder()
+            .method(http::Method::from_bytes(parts.method.as_str().as_bytes()).unwrap())
+            .uri(uri)
+            .version(convert_version(parts.version));
+        for (name, value) in parts.headers.iter() {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+        if let Some(ConnectInfo(addr)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+            builder = builder.extension(*addr);
+        }
+
+        let body = DavBody {
+            body: body.into_data_stream(),
+        };
+        let stdreq = DavRequest {
+            request: builder.body(body).unwrap(),
+            prefix,
+        };
+        Ok(stdreq)
+    }
+}
+
+fn convert_version(version: axum::http::Version) -> http::Version {
+    match version {
+        axum::http::Version::HTTP_09 => http::Version::HTTP_09,
+        axum::http::Version::HTTP_10 => http::Version::HTTP_10,
+        axum::http::Version::HTTP_2 => http::Version::HTTP_2,
+        axum::http::Version::HTTP_3 => http::Version::HTTP_3,
+        _ => http::Version::HTTP_11,
+    }
+}
+
+/// Body type for `DavRequest`.
+///
+/// It wraps axum's `Body` and implements `http_body::Body`.
+#[pin_project]
+pub struct DavBody {
+    #[pin]
+    body: BodyDataStream,
+}
+
+impl http_body::Body for DavBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>>
+    {
+        let this = self.project();
+        match this.body.poll_next(cx) {
+            Poll::Ready(Some(Ok(data))) => Poll::Ready(Some(Ok(data))),
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(io::Error::other(err)))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>>
+    {
+        Poll::Ready(Ok(None))
+    }
+}
+
+/// `http::Response` compatibility.
+///
+/// Wraps `http::Response<dav_handler::body::Body>` and implements axum::response::IntoResponse.
+pub struct DavResponse(pub http::Response<crate::body::Body>);
+
+impl From<http::Response<crate::body::Body>> for DavResponse {
+    fn from(resp: http::Response<crate::body::Body>) -> DavResponse {
+        DavResponse(resp)
+    }
+}
+
+impl IntoResponse for DavResponse {
+    fn into_response(self) -> Response {
+        use crate::body::BodyType;
+
+        let (parts, body) = self.0.into_parts();
+        let body = match body.inner {
+            BodyType::Bytes(None) | BodyType::Empty => axum::body::Body::empty(),
+            BodyType::Bytes(Some(b)) => axum::body::Body::from(b),
+            b @ BodyType::AsyncStream(..) => axum::body::Body::from_stream(crate::body::Body { inner: b }),
+        };
+        let mut resp = Response::new(body);
+        *resp.status_mut() = axum::http::StatusCode::from_u16(parts.status.as_u16()).unwrap();
+        let headers = resp.headers_mut();
+        for (name, value) in parts.headers.iter() {
+            let name = axum::http::HeaderName::from_bytes(name.as_str().as_bytes()).unwrap();
+            let value = axum::http::HeaderValue::from_bytes(value.as_bytes()).unwrap();
+            headers.append(name, value);
+        }
+        resp
+    }
+}
+
+/// Creates a route that serves a single file, ignoring the request path,
+/// like `warp::dav_file`.
+///
+/// Only `GET`, `HEAD` and `OPTIONS` are allowed.
+///
+/// ```no_run
+/// use axum::Router;
+///
+/// # fn router() -> Router {
+/// Router::new().route("/hosts", webdav_handler::axum::dav_file("/etc/hosts"))
+/// # }
+/// ```
+pub fn dav_file<S>(file: impl AsRef<Path>) -> MethodRouter<S>
+where S: Clone + Send + Sync + 'static {
+    let handler = DavHandler::builder()
+        .filesystem(LocalFs::new_file(file, false))
+        .methods(DavMethodSet::HTTP_RO)
+        .build_handler();
+    axum::routing::any(move |req: DavRequest| {
+        async move { DavResponse::from(handler.handle(req.request).await) }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::dav_file;
+    use crate::testing::tempdir;
+
+    async fn request(addr: std::net::SocketAddr, req: &str) -> String {
+        let mut conn = tokio::net::TcpStream::connect(addr).await.unwrap();
+        conn.write_all(req.as_bytes()).await.unwrap();
+        let mut resp = String::new();
+        conn.read_to_string(&mut resp).await.unwrap();
+        resp
+    }
+
+    #[tokio::test]
+    async fn single_file() {
+        let dir = tempdir();
+        std::fs::write(dir.join("hosts"), "127.0.0.1 localhost\n").unwrap();
+        let app = Router::new()
+            .route("/hosts", dav_file(dir.join("hosts")))
+            .route("/any/*path", dav_file(dir.join("hosts")));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let req = |method: &str, path: &str| {
+            format!("{} {} HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n", method, path)
+        };
+        // whatever the path, it is the file.
+        for path in ["/hosts", "/any/thing", "/any/other/"] {
+            let resp = request(addr, &req("GET", path)).await;
+            assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+            assert!(resp.ends_with("\r\n\r\n127.0.0.1 localhost\n"), "{}", resp);
+        }
+        let resp = request(addr, &req("HEAD", "/hosts")).await;
+        assert!(resp.starts_with("HTTP/1.1 200") && resp.contains("content-length: 20\r\n"), "{}", resp);
+        assert!(resp.ends_with("\r\n\r\n"), "{}", resp);
+
+        // and it is read-only.
+        let resp = request(addr, &req("OPTIONS", "/hosts")).await;
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+        assert!(!resp.contains("PUT"), "{}", resp);
+        for method in ["PUT", "DELETE", "PROPFIND"] {
+            let resp = request(addr, &req(method, "/hosts")).await;
+            assert!(resp.starts_with("HTTP/1.1 405"), "{} {}", method, resp);
+        }
+        assert_eq!(std::fs::read(dir.join("hosts")).unwrap(), b"127.0.0.1 localhost\n");
+    }
+}
@@ -31,6 +31,52 @@ impl Body {
             inner: BodyType::Empty,
         }
     }
+
+    /// Read the whole body into one buffer.
+    ///
+    /// Handy in tests, and when the response is small and is
+    /// needed in one piece anyway.
+    pub async fn into_bytes(self) -> io::Result<Bytes> {
+        use futures::StreamExt;
+
+        let mut body = self;
+        let mut data = Vec::new();
+        while let Some(chunk) = body.next().await {
+            data.extend_from_slice(&chunk?);
+        }
+        Ok(Bytes::from(data))
+    }
+}
+
+impl Body {
+    // Pass the body through unchanged, and log the first `max` bytes
+    // of it at debug level when it is done.
+    pub(crate) fn logged(self, what: String, max: usize) -> Body {
+        use futures::StreamExt;
+
+        let mut body = self;
+        Body::from(AsyncStream::new(move |mut tx| {
+            async move {
+                let mut data = Vec::new();
+                let mut len = 0;
+                while let Some(chunk) = body.next().await {
+                    let chunk = chunk?;
+                    len += chunk.len();
+                    let n = std::cmp::min(chunk.len(), max - data.len());
+                    data.extend_from_slice(&chunk[..n]);
+                    tx.send(chunk).await;
+                }
+                log_body(&what, &data, len);
+                Ok(())
+            }
+        }))
+    }
+}
+
+// `data` is the start of a body of `len` bytes.
+pub(crate) fn log_body(what: &str, data: &[u8], len: usize) {
+    let more = if len > data.len() { " ..." } else { "" };
+    debug!("{} ({} bytes): {}{}", what, len, String::from_utf8_lossy(data), more);
 }
 
 impl Stream for Body {
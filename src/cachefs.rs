@@ -0,0 +1,787 @@
+//! Filesystem that caches another filesystem on local disk.
+//!
+//! [`CacheFs`] sits in front of a slow backend, like `S3Fs` or `SftpFs`.
+//! When a file is read from start to end, its contents are copied into a
+//! local directory while they are streamed to the client, and the next
+//! read is served from there, for as long as the metadata (ETag, or size
+//! and modification time) of the file on the backend stays the same.
+//!
+//! Metadata is kept in memory for a short while, see
+//! [`CacheFs::with_metadata_ttl`]. The cached files are evicted, least
+//! recently used first, when their total size gets over the maximum.
+//! Everything that changes a file goes straight to the backend, and
+//! removes it from the cache.
+//!
+//! Example:
+//!
+//! ```no_run
+//! use webdav_handler::{cachefs::CacheFs, localfs::LocalFs, DavHandler};
+//!
+//! let remote = LocalFs::new("/mnt/nfs/share", false, false, false);
+//! let dav_server = DavHandler::builder()
+//!     .filesystem(CacheFs::new(remote, "/var/cache/webdav", 1 << 30).unwrap())
+//!     .build_handler();
+//! ```
+//!
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::io::SeekFrom;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use futures::future::FutureExt;
+use lru::LruCache;
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::localfs::LocalFs;
+
+// Cache the metadata of at most this many paths, the least recently
+// used ones are forgotten first.
+const MAX_META_ENTRIES: usize = 10000;
+
+/// Filesystem that caches the files of another filesystem on local disk.
+#[derive(Clone)]
+pub struct CacheFs {
+    inner:    Box<dyn DavFileSystem>,
+    cache:    Arc<Cache>,
+    meta_ttl: Duration,
+}
+
+struct Cache {
+    store:    Box<LocalFs>,
+    dir:      PathBuf,
+    max_size: u64,
+    state:    Mutex<State>,
+}
+
+struct State {
+    meta:   LruCache<Vec<u8>, (Box<dyn DavMetaData>, Instant)>,
+    bodies: HashMap<Vec<u8>, CachedBody>,
+    size:   u64,
+    tick:   u64,
+}
+
+// A cached file in the store.
+struct CachedBody {
+    name:      DavPath,
+    validator: String,
+    size:      u64,
+    used:      u64,
+}
+
+// Identifies the version of a file on the backend.
+fn validator(meta: &dyn DavMetaData) -> String {
+    match meta.etag() {
+        Some(etag) => etag,
+        None => format!("{}-{:?}", meta.len(), meta.modified().ok()),
+    }
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            meta:   LruCache::new(NonZeroUsize::new(MAX_META_ENTRIES).unwrap()),
+            bodies: HashMap::new(),
+            size:   0,
+            tick:   0,
+        }
+    }
+
+    // Forget `path`, and everything below it if `tree` is set.
+    // Returns the files in the store that can be removed.
+    fn invalidate(&mut self, path: &DavPath, tree: bool) -> Vec<DavPath> {
+        let key = path.as_bytes();
+        let key = key.strip_suffix(b"/").unwrap_or(key);
+        let matches = |k: &[u8]| {
+            let k = k.strip_suffix(b"/").unwrap_or(k);
+            k == key || (tree && k.starts_with(key) && k.get(key.len()) == Some(&b'/'))
+        };
+        let stale: Vec<_> = self.meta.iter().map(|(k, _)| k).filter(|k| matches(k)).cloned().collect();
+        for k in stale {
+            self.meta.pop(&k);
+        }
+        let gone: Vec<_> = self.bodies.keys().filter(|k| matches(k)).cloned().collect();
+        let mut names = Vec::new();
+        for k in gone {
+            if let Some(body) = self.bodies.remove(&k) {
+                self.size -= body.size;
+                names.push(body.name);
+            }
+        }
+        names
+    }
+
+    // Make room for `size` bytes. Returns the files in the store that can be removed.
+    fn evict(&mut self, size: u64, max_size: u64) -> Vec<DavPath> {
+        let mut names = Vec::new();
+        while self.size + size > max_size {
+            let lru = self.bodies.iter().min_by_key(|(_, b)| b.used).map(|(k, _)| k.clone());
+            match lru.and_then(|k| self.bodies.remove(&k)) {
+                Some(body) => {
+                    self.size -= body.size;
+                    names.push(body.name);
+                },
+                None => break,
+            }
+        }
+        names
+    }
+}
+
+impl CacheFs {
+    /// Create a new `CacheFs` in front of `inner`.
+    ///
+    /// The cached files are stored in the directory `dir`, which is
+    /// created if it does not exist, and take up at most `max_size` bytes.
+    /// Files that are larger than that are never cached.
+    pub fn new(
+        inner: Box<dyn DavFileSystem>,
+        dir: impl AsRef<Path>,
+        max_size: u64,
+    ) -> io::Result<Box<CacheFs>>
+    {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        // files left behind by an earlier run.
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "cache") {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+        let cache = Cache {
+            store: LocalFs::new(dir, false, false, false),
+            dir: dir.to_path_buf(),
+            max_size,
+            state: Mutex::new(State::new()),
+        };
+        Ok(Box::new(CacheFs {
+            inner,
+            cache: Arc::new(cache),
+            meta_ttl: Duration::from_secs(5),
+        }))
+    }
+
+    /// How long metadata of the backend is cached. The default is 5 seconds.
+    ///
+    /// This is also how long it can take before a file that was changed on
+    /// the backend, but not through this `CacheFs`, is read from the backend
+    /// again. A TTL of zero disables caching of metadata.
+    pub fn with_metadata_ttl(self: Box<Self>, ttl: Duration) -> Box<Self> {
+        let mut this = self;
+        this.meta_ttl = ttl;
+        this
+    }
+
+    // Forget `path`, and remove its cached contents.
+    async fn invalidate(&self, path: &DavPath, tree: bool) {
+        let names = self.cache.state.lock().unwrap().invalidate(path, tree);
+        self.remove(names).await;
+    }
+
+    async fn remove(&self, names: Vec<DavPath>) {
+        for name in names {
+            if let Err(e) = self.cache.store.remove_file(&name).await {
+                debug!("cachefs: remove {}: {:?}", name, e);
+            }
+        }
+    }
+
+    // The cached contents of `path`, if they are still current.
+    fn lookup(&self, path: &DavPath, validator: &str) -> Option<DavPath> {
+        let mut state = self.cache.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let body = state.bodies.get_mut(path.as_bytes())?;
+        if body.validator != validator {
+            return None;
+        }
+        body.used = tick;
+        Some(body.name.clone())
+    }
+
+    // Metadata of `path`, if we have it and it is fresh.
+    fn cached_meta(&self, path: &DavPath) -> Option<Box<dyn DavMetaData>> {
+        let mut state = self.cache.state.lock().unwrap();
+        match state.meta.get(path.as_bytes()) {
+            Some((meta, when)) if when.elapsed() < self.meta_ttl => Some(meta.clone()),
+            _ => None,
+        }
+    }
+
+    // `name` in the store now has the complete contents of `path`.
+    // Returns false if there was no room for it.
+    async fn store(&self, path: &DavPath, name: &DavPath, validator: String, size: u64) -> bool {
+        let (names, stored) = {
+            let mut state = self.cache.state.lock().unwrap();
+            let mut names = state.invalidate(path, false);
+            names.extend(state.evict(size, self.cache.max_size));
+            let stored = state.size + size <= self.cache.max_size;
+            if stored {
+                state.tick += 1;
+                let body = CachedBody {
+                    name: name.clone(),
+                    validator,
+                    size,
+                    used: state.tick,
+                };
+                state.size += size;
+                state.bodies.insert(path.as_bytes().to_vec(), body);
+            } else {
+                names.push(name.clone());
+            }
+            (names, stored)
+        };
+        self.remove(names).await;
+        stored
+    }
+
+    // Open a file for reading, from the cache if possible.
+    async fn open_read(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        let meta = self.metadata(path).await?;
+        if meta.is_dir() || meta.len() > self.cache.max_size {
+            return self.inner.open(path, options).await;
+        }
+        let validator = validator(&*meta);
+
+        if let Some(name) = self.lookup(path, &validator) {
+            if let Ok(file) = self.cache.store.open(&name, OpenOptions::read()).await {
+                trace!("cachefs: {}: hit", path);
+                return Ok(Box::new(CachedFile { file, meta }));
+            }
+        }
+
+        trace!("cachefs: {}: miss", path);
+        let src = self.inner.open(path, options).await?;
+        let name = DavPath::new(&format!("/{}.cache", uuid::Uuid::new_v4().simple())).unwrap();
+        let mut oo = OpenOptions::write();
+        oo.create_new = true;
+        let dst = match self.cache.store.open(&name, oo).await {
+            Ok(dst) => Some(dst),
+            Err(e) => {
+                debug!("cachefs: {}: not cached: {:?}", path, e);
+                None
+            },
+        };
+        Ok(Box::new(FillFile {
+            src,
+            dst,
+            fs: self.clone(),
+            path: path.clone(),
+            name,
+            validator,
+            meta,
+            pos: 0,
+        }))
+    }
+}
+
+impl DavFileSystem for CacheFs {
+    fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move {
+            let writes = options.write || options.append || options.truncate || options.create;
+            if !writes && !options.create_new {
+                return self.open_read(path, options).await;
+            }
+            self.invalidate(path, false).await;
+            let file = self.inner.open(path, options).await?;
+            Ok(Box::new(WriteFile {
+                file,
+                fs: self.clone(),
+                path: path.clone(),
+            }) as Box<dyn DavFile>)
+        }
+        .boxed()
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            if let Some(meta) = self.cached_meta(path) {
+                return Ok(meta);
+            }
+            let meta = self.inner.metadata(path).await?;
+            if self.meta_ttl > Duration::ZERO {
+                let mut state = self.cache.state.lock().unwrap();
+                state.meta.put(path.as_bytes().to_vec(), (meta.clone(), Instant::now()));
+            }
+            Ok(meta)
+        }
+        .boxed()
+    }
+
+    fn exists<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        async move {
+            match self.cached_meta(path) {
+                Some(meta) => Ok(!path.is_collection() || meta.is_dir()),
+                None => self.inner.exists(path).await,
+            }
+        }
+        .boxed()
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+    {
+        self.inner.read_dir(path, meta)
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            self.invalidate(path, false).await;
+            self.inner.create_dir(path).await
+        }
+        .boxed()
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let res = self.inner.remove_dir(path).await;
+            self.invalidate(path, true).await;
+            res
+        }
+        .boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let res = self.inner.remove_file(path).await;
+            self.invalidate(path, false).await;
+            res
+        }
+        .boxed()
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let res = self.inner.remove_dir_all(path).await;
+            self.invalidate(path, true).await;
+            res
+        }
+        .boxed()
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let res = self.inner.rename(from, to).await;
+            self.invalidate(from, true).await;
+            self.invalidate(to, true).await;
+            res
+        }
+        .boxed()
+    }
+
+    fn temp_path(&self, path: &DavPath) -> Option<DavPath> {
+        self.inner.temp_path(path)
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let res = self.inner.copy(from, to).await;
+            self.invalidate(to, false).await;
+            res
+        }
+        .boxed()
+    }
+
+    fn bind<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let res = self.inner.bind(from, to).await;
+            self.invalidate(to, false).await;
+            res
+        }
+        .boxed()
+    }
+
+    fn supports_bind(&self) -> bool {
+        self.inner.supports_bind()
+    }
+
+    fn capabilities(&self) -> FsCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.inner.set_accessed(path, tm)
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        async move {
+            let res = self.inner.set_modified(path, tm).await;
+            self.invalidate(path, false).await;
+            res
+        }
+        .boxed()
+    }
+
+    fn have_props<'a>(&'a self, path: &'a DavPath) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        self.inner.have_props(path)
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(http::StatusCode, DavProp)>>
+    {
+        self.inner.patch_props(path, patch)
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        self.inner.get_props(path, do_content)
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        self.inner.get_prop(path, prop)
+    }
+
+    fn get_named_props<'a>(&'a self, path: &'a DavPath, props: Vec<DavProp>) -> FsFuture<'a, Vec<DavProp>> {
+        self.inner.get_named_props(path, props)
+    }
+
+    fn quota<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        self.inner.quota(path)
+    }
+
+    fn privileges<'a>(&'a self, path: &'a DavPath, principal: Option<&'a str>) -> FsFuture<'a, PrivilegeSet> {
+        self.inner.privileges(path, principal)
+    }
+
+    fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        self.inner.content_type(path)
+    }
+
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.inner.collection_etag(path)
+    }
+
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.inner.sync_token(path)
+    }
+
+    fn changes_since<'a>(
+        &'a self,
+        path: &'a DavPath,
+        token: &'a str,
+        deep: bool,
+    ) -> FsFuture<'a, Vec<Change>> {
+        self.inner.changes_since(path, token, deep)
+    }
+}
+
+impl std::fmt::Debug for CacheFs {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let state = self.cache.state.lock().unwrap();
+        f.debug_struct("CacheFs")
+            .field("files", &state.bodies.len())
+            .field("size", &state.size)
+            .field("max_size", &self.cache.max_size)
+            .finish()
+    }
+}
+
+// A file that is read from the cache, with the metadata from the backend.
+#[derive(Debug)]
+struct CachedFile {
+    file: Box<dyn DavFile>,
+    meta: Box<dyn DavMetaData>,
+}
+
+impl DavFile for CachedFile {
+    fn metadata<'a>(&'a mut self) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        let meta = self.meta.clone();
+        Box::pin(futures::future::ok(meta))
+    }
+
+    fn write_buf<'a>(&'a mut self, _buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'a, ()> {
+        Box::pin(futures::future::err(FsError::Forbidden))
+    }
+
+    fn write_bytes<'a>(&'a mut self, _buf: bytes::Bytes) -> FsFuture<'a, ()> {
+        Box::pin(futures::future::err(FsError::Forbidden))
+    }
+
+    fn read_bytes<'a>(&'a mut self, count: usize) -> FsFuture<'a, bytes::Bytes> {
+        self.file.read_bytes(count)
+    }
+
+    fn seek<'a>(&'a mut self, pos: SeekFrom) -> FsFuture<'a, u64> {
+        self.file.seek(pos)
+    }
+
+    fn flush<'a>(&'a mut self) -> FsFuture<'a, ()> {
+        Box::pin(futures::future::ok(()))
+    }
+}
+
+// A file that is read from the backend for the first time. What is read
+// is also written to the store, and once the whole file has been read, in
+// order, it is added to the cache. If the reader seeks elsewhere, or stops
+// early (like a HEAD request), the partial copy is thrown away.
+struct FillFile {
+    src:       Box<dyn DavFile>,
+    dst:       Option<Box<dyn DavFile>>,
+    fs:        CacheFs,
+    path:      DavPath,
+    name:      DavPath,
+    validator: String,
+    meta:      Box<dyn DavMetaData>,
+    pos:       u64,
+}
+
+impl FillFile {
+    // Stop writing to the store, and remove what is there.
+    fn abandon(&mut self) {
+        if self.dst.take().is_some() {
+            trace!("cachefs: {}: not cached", self.path);
+            let _ = std::fs::remove_file(self.fs.cache.dir.join(self.name.as_rel_ospath()));
+        }
+    }
+
+    async fn fill(&mut self, buf: &bytes::Bytes) {
+        let dst = match self.dst.as_mut() {
+            Some(dst) => dst,
+            None => return,
+        };
+        let len = self.meta.len();
+        let pos = self.pos + buf.len() as u64;
+        // a file that is longer or shorter than its metadata says has
+        // changed while we were reading it.
+        if pos > len || (buf.is_empty() && pos < len) || dst.write_bytes(buf.clone()).await.is_err() {
+            self.abandon();
+            return;
+        }
+        self.pos = pos;
+        if pos == len {
+            let mut dst = self.dst.take().unwrap();
+            if dst.flush().await.is_err() {
+                self.dst = Some(dst);
+                self.abandon();
+                return;
+            }
+            drop(dst);
+            let validator = std::mem::take(&mut self.validator);
+            self.fs.store(&self.path, &self.name, validator, pos).await;
+        }
+    }
+}
+
+impl Drop for FillFile {
+    fn drop(&mut self) {
+        self.abandon();
+    }
+}
+
+impl std::fmt::Debug for FillFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("FillFile").field("path", &self.path).field("pos", &self.pos).finish()
+    }
+}
+
+impl DavFile for FillFile {
+    fn metadata<'a>(&'a mut self) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        let meta = self.meta.clone();
+        Box::pin(futures::future::ok(meta))
+    }
+
+    fn write_buf<'a>(&'a mut self, _buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'a, ()> {
+        Box::pin(futures::future::err(FsError::Forbidden))
+    }
+
+    fn write_bytes<'a>(&'a mut self, _buf: bytes::Bytes) -> FsFuture<'a, ()> {
+        Box::pin(futures::future::err(FsError::Forbidden))
+    }
+
+    fn read_bytes<'a>(&'a mut self, count: usize) -> FsFuture<'a, bytes::Bytes> {
+        async move {
+            let buf = match self.src.read_bytes(count).await {
+                Ok(buf) => buf,
+                Err(e) => {
+                    self.abandon();
+                    return Err(e);
+                },
+            };
+            self.fill(&buf).await;
+            Ok(buf)
+        }
+        .boxed()
+    }
+
+    fn seek<'a>(&'a mut self, pos: SeekFrom) -> FsFuture<'a, u64> {
+        async move {
+            if pos != SeekFrom::Start(self.pos) {
+                self.abandon();
+            }
+            self.src.seek(pos).await
+        }
+        .boxed()
+    }
+
+    fn flush<'a>(&'a mut self) -> FsFuture<'a, ()> {
+        Box::pin(futures::future::ok(()))
+    }
+}
+
+// A file that is written on the backend. When it is flushed, the metadata
+// that was cached in the meantime is out of date.
+struct WriteFile {
+    file: Box<dyn DavFile>,
+    fs:   CacheFs,
+    path: DavPath,
+}
+
+impl std::fmt::Debug for WriteFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WriteFile").field("path", &self.path).finish()
+    }
+}
+
+impl DavFile for WriteFile {
+    fn metadata<'a>(&'a mut self) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.file.metadata()
+    }
+
+    fn write_buf<'a>(&'a mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'a, ()> {
+        self.file.write_buf(buf)
+    }
+
+    fn write_bytes<'a>(&'a mut self, buf: bytes::Bytes) -> FsFuture<'a, ()> {
+        self.file.write_bytes(buf)
+    }
+
+    fn read_bytes<'a>(&'a mut self, count: usize) -> FsFuture<'a, bytes::Bytes> {
+        self.file.read_bytes(count)
+    }
+
+    fn seek<'a>(&'a mut self, pos: SeekFrom) -> FsFuture<'a, u64> {
+        self.file.seek(pos)
+    }
+
+    fn flush<'a>(&'a mut self) -> FsFuture<'a, ()> {
+        async move {
+            let res = self.file.flush().await;
+            self.fs.invalidate(&self.path, false).await;
+            res
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::testing::{tempdir, TestClient};
+    use crate::DavHandler;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // A filesystem that counts how often a file is opened for reading.
+    #[derive(Clone)]
+    struct CountFs(Box<MemFs>, Arc<AtomicUsize>);
+
+    impl DavFileSystem for CountFs {
+        fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+            if !options.write {
+                self.1.fetch_add(1, Ordering::SeqCst);
+            }
+            self.0.open(path, options)
+        }
+
+        fn read_dir<'a>(
+            &'a self,
+            path: &'a DavPath,
+            meta: ReadDirMeta,
+        ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+        {
+            self.0.read_dir(path, meta)
+        }
+
+        fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+            self.0.metadata(path)
+        }
+
+        fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+            self.0.remove_file(path)
+        }
+    }
+
+    #[tokio::test]
+    async fn cache() {
+        let dir = tempdir();
+        let opens = Arc::new(AtomicUsize::new(0));
+        let inner = Box::new(CountFs(MemFs::new(), opens.clone()));
+        let fs = CacheFs::new(inner, &dir, 10).unwrap().with_metadata_ttl(Duration::ZERO);
+        let c = TestClient::new(DavHandler::builder().filesystem(fs.clone()).build_handler());
+        let get = |path: &'static str| {
+            let c = c.clone();
+            async move { c.request("GET", path, "").await.into_body() }
+        };
+
+        c.request("PUT", "/a", "aaaaaa").await;
+        assert_eq!(get("/a").await, "aaaaaa");
+        assert_eq!(get("/a").await, "aaaaaa");
+        assert_eq!(opens.load(Ordering::SeqCst), 1);
+
+        // a write replaces the cached copy.
+        c.request("PUT", "/a", "AAAAAA").await;
+        assert_eq!(get("/a").await, "AAAAAA");
+        assert_eq!(get("/a").await, "AAAAAA");
+        assert_eq!(opens.load(Ordering::SeqCst), 2);
+
+        // there is only room for one file, "/a" is evicted.
+        c.request("PUT", "/b", "bbbbbb").await;
+        assert_eq!(get("/b").await, "bbbbbb");
+        assert_eq!(get("/a").await, "AAAAAA");
+        assert_eq!(opens.load(Ordering::SeqCst), 4);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // too large is never cached.
+        c.request("PUT", "/big", "0123456789abcdef").await;
+        assert_eq!(get("/big").await, "0123456789abcdef");
+        assert_eq!(get("/big").await, "0123456789abcdef");
+        assert_eq!(opens.load(Ordering::SeqCst), 6);
+
+        c.request("DELETE", "/a", "").await;
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(c.request("GET", "/a", "").await.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn partial_reads() {
+        let dir = tempdir();
+        let opens = Arc::new(AtomicUsize::new(0));
+        let inner = Box::new(CountFs(MemFs::new(), opens.clone()));
+        let fs = CacheFs::new(inner, &dir, 100).unwrap();
+        let c = TestClient::new(DavHandler::builder().filesystem(fs).build_handler());
+        let files = || std::fs::read_dir(&dir).unwrap().count();
+        c.request("PUT", "/a", "abcdef").await;
+
+        // HEAD and a Range request get what they asked for, and don't fill the cache.
+        let resp = c.request("HEAD", "/a", "").await;
+        assert_eq!(resp.headers()["content-length"], "6");
+        assert_eq!(files(), 0);
+        let req = http::Request::builder().uri("/a").header("Range", "bytes=2-3");
+        let resp = c.send(req.body("").unwrap()).await;
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.into_body(), "cd");
+        assert_eq!(files(), 0);
+
+        // reading it all does.
+        assert_eq!(c.request("GET", "/a", "").await.into_body(), "abcdef");
+        assert_eq!(files(), 1);
+        let n = opens.load(Ordering::SeqCst);
+        assert_eq!(c.request("GET", "/a", "").await.into_body(), "abcdef");
+        assert_eq!(opens.load(Ordering::SeqCst), n);
+    }
+}
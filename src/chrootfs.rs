@@ -0,0 +1,261 @@
+//! Filesystem that is a subdirectory of another filesystem.
+//!
+//! [`ChrootFs`] takes a filesystem and a base path, and puts the base path
+//! in front of every path before passing it on. So with a base path of
+//! `/users/alice`, the path `/notes.txt` becomes `/users/alice/notes.txt`
+//! on the inner filesystem, and nothing outside of `/users/alice` can be
+//! reached.
+//!
+//! This is on top of the URL prefix that the handler strips (see
+//! [`DavConfig::strip_prefix`](crate::DavConfig::strip_prefix)), so one
+//! backend can be served at several URLs, each with its own root.
+//!
+//! Example:
+//!
+//! ```no_run
+//! use webdav_handler::{chrootfs::ChrootFs, davpath::DavPath, localfs::LocalFs, DavHandler};
+//!
+//! let fs = LocalFs::new("/srv/users", false, false, false);
+//! let dav_server = DavHandler::builder()
+//!     .strip_prefix("/alice")
+//!     .filesystem(ChrootFs::new(fs, DavPath::new("/alice/").unwrap()))
+//!     .build_handler();
+//! ```
+//!
+use std::future::Future;
+use std::pin::Pin;
+use std::time::SystemTime;
+
+use futures::future::FutureExt;
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+/// Filesystem with its root in a directory of another filesystem.
+#[derive(Clone)]
+pub struct ChrootFs {
+    inner: Box<dyn DavFileSystem>,
+    base:  DavPath,
+}
+
+impl ChrootFs {
+    /// Create a new `ChrootFs` with `base` as the root directory.
+    ///
+    /// The URL prefix of `base`, if any, is ignored.
+    pub fn new(inner: Box<dyn DavFileSystem>, base: DavPath) -> Box<ChrootFs> {
+        let mut base = DavPath::new("/").unwrap().join(base.as_bytes());
+        base.add_slash();
+        Box::new(ChrootFs { inner, base })
+    }
+
+    // Path on the inner filesystem.
+    fn inner_path(&self, path: &DavPath) -> DavPath {
+        self.base.join(path.as_bytes())
+    }
+
+    // Path on the inner filesystem back to our path. None if
+    // it is not below the base directory.
+    fn outer_path(&self, path: &DavPath) -> Option<DavPath> {
+        let base = self.base.as_bytes();
+        let p = path.as_bytes();
+        let rel = p.strip_prefix(base).or_else(|| (p == &base[..base.len() - 1]).then(|| &b""[..]))?;
+        let mut path = DavPath::new("/").unwrap().join(rel);
+        path.add_slash_if(rel.is_empty() || rel.ends_with(b"/"));
+        Some(path)
+    }
+}
+
+impl DavFileSystem for ChrootFs {
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move { self.inner.metadata(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move { self.inner.symlink_metadata(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+    {
+        async move { self.inner.read_dir(&self.inner_path(path), meta).await }.boxed()
+    }
+
+    fn exists<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        async move { self.inner.exists(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move { self.inner.open(&self.inner_path(path), options).await }.boxed()
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move { self.inner.create_dir(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move { self.inner.remove_dir(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move { self.inner.remove_file(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move { self.inner.remove_dir_all(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move { self.inner.rename(&self.inner_path(from), &self.inner_path(to)).await }.boxed()
+    }
+
+    fn temp_path(&self, path: &DavPath) -> Option<DavPath> {
+        let tmp = self.inner.temp_path(&self.inner_path(path))?;
+        self.outer_path(&tmp)
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move { self.inner.copy(&self.inner_path(from), &self.inner_path(to)).await }.boxed()
+    }
+
+    fn bind<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move { self.inner.bind(&self.inner_path(from), &self.inner_path(to)).await }.boxed()
+    }
+
+    fn supports_bind(&self) -> bool {
+        self.inner.supports_bind()
+    }
+
+    fn capabilities(&self) -> FsCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        async move { self.inner.set_accessed(&self.inner_path(path), tm).await }.boxed()
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        async move { self.inner.set_modified(&self.inner_path(path), tm).await }.boxed()
+    }
+
+    fn have_props<'a>(&'a self, path: &'a DavPath) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        async move { self.inner.have_props(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(http::StatusCode, DavProp)>>
+    {
+        async move { self.inner.patch_props(&self.inner_path(path), patch).await }.boxed()
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        async move { self.inner.get_props(&self.inner_path(path), do_content).await }.boxed()
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        async move { self.inner.get_prop(&self.inner_path(path), prop).await }.boxed()
+    }
+
+    fn get_named_props<'a>(&'a self, path: &'a DavPath, props: Vec<DavProp>) -> FsFuture<'a, Vec<DavProp>> {
+        async move { self.inner.get_named_props(&self.inner_path(path), props).await }.boxed()
+    }
+
+    fn quota<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        async move { self.inner.quota(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn privileges<'a>(&'a self, path: &'a DavPath, principal: Option<&'a str>) -> FsFuture<'a, PrivilegeSet> {
+        async move { self.inner.privileges(&self.inner_path(path), principal).await }.boxed()
+    }
+
+    fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        async move { self.inner.content_type(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        async move { self.inner.collection_etag(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        async move { self.inner.sync_token(&self.inner_path(path)).await }.boxed()
+    }
+
+    fn changes_since<'a>(
+        &'a self,
+        path: &'a DavPath,
+        token: &'a str,
+        deep: bool,
+    ) -> FsFuture<'a, Vec<Change>> {
+        async move {
+            let changes = self.inner.changes_since(&self.inner_path(path), token, deep).await?;
+            let changes = changes
+                .into_iter()
+                .filter_map(|change| {
+                    match change {
+                        Change::Modified(p) => self.outer_path(&p).map(Change::Modified),
+                        Change::Removed(p) => self.outer_path(&p).map(Change::Removed),
+                    }
+                })
+                .collect();
+            Ok(changes)
+        }
+        .boxed()
+    }
+}
+
+impl std::fmt::Debug for ChrootFs {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ChrootFs").field("base", &self.base).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use bytes::Bytes;
+    use futures::StreamExt;
+
+    fn path(p: &str) -> DavPath {
+        DavPath::new(p).unwrap()
+    }
+
+    #[tokio::test]
+    async fn chroot() {
+        let inner = MemFs::new();
+        inner.create_dir(&path("/users/")).await.unwrap();
+        inner.create_dir(&path("/users/alice/")).await.unwrap();
+        let fs = ChrootFs::new(inner.clone(), path("/users/alice"));
+
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        let mut file = fs.open(&path("/notes.txt"), oo).await.unwrap();
+        file.write_bytes(Bytes::from("hello")).await.unwrap();
+        file.flush().await.unwrap();
+        assert!(inner.metadata(&path("/users/alice/notes.txt")).await.is_ok());
+
+        assert!(fs.metadata(&path("/")).await.unwrap().is_dir());
+        let names = fs
+            .read_dir(&path("/"), ReadDirMeta::None)
+            .await
+            .unwrap()
+            .map(|e| e.name())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(names, vec![b"notes.txt".to_vec()]);
+
+        fs.rename(&path("/notes.txt"), &path("/old.txt")).await.unwrap();
+        assert!(inner.metadata(&path("/users/alice/old.txt")).await.is_ok());
+
+        let tmp = fs.temp_path(&path("/old.txt")).unwrap();
+        assert!(tmp.as_bytes().starts_with(b"/.put-"));
+        assert!(fs.outer_path(&path("/users/bob/x")).is_none());
+        assert!(fs.outer_path(&path("/users/alicex")).is_none());
+        assert_eq!(fs.outer_path(&path("/users/alice")), Some(path("/")));
+    }
+}
@@ -0,0 +1,234 @@
+//
+// On-the-fly compression of GET responses.
+//
+// gzip needs the "compression" feature, br the "brotli" feature.
+//
+use std::io;
+#[cfg(any(feature = "compression", feature = "brotli"))]
+use std::io::Write;
+
+use bytes::Bytes;
+#[cfg(feature = "compression")]
+use flate2::write::GzEncoder;
+use http::Request;
+
+use crate::util::accept_list;
+
+// Content types that are compressed if no list was configured.
+pub(crate) const DEFAULT_TYPES: &[&str] = &[
+    "text/",
+    "application/javascript",
+    "application/json",
+    "application/xml",
+    "application/xhtml+xml",
+    "application/wasm",
+    "image/svg+xml",
+];
+
+// Brotli quality if none was configured. 11 is the best compression, but
+// it is too slow to do on the fly.
+pub(crate) const DEFAULT_BROTLI_QUALITY: u32 = 5;
+
+// Brotli window size, log2. The maximum of 24 means a 16 MB window, on our
+// side and on the client's, for every response; 20 (1 MB) is plenty for
+// the text files that we compress.
+#[cfg(feature = "brotli")]
+const BROTLI_LGWIN: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    // Was support for this encoding compiled in.
+    fn builtin(&self) -> bool {
+        match self {
+            Encoding::Brotli => cfg!(feature = "brotli"),
+            Encoding::Gzip => cfg!(feature = "compression"),
+        }
+    }
+}
+
+// The encodings in the Accept-Encoding header, with their q-value.
+fn accept_encoding(req: &Request<()>) -> Vec<(String, f32)> {
+    accept_list(req, "accept-encoding")
+}
+
+// Find the best encoding that both we and the client support.
+//
+// If the client likes `br` and `gzip` equally well, `br` wins.
+pub(crate) fn negotiate(req: &Request<()>) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for (name, q) in accept_encoding(req) {
+        let enc = match name.as_str() {
+            "br" => Encoding::Brotli,
+            "*" if Encoding::Brotli.builtin() => Encoding::Brotli,
+            "gzip" | "x-gzip" | "*" => Encoding::Gzip,
+            _ => continue,
+        };
+        let better = |(_, bq): (Encoding, f32)| q > bq || (q == bq && enc == Encoding::Brotli);
+        if q > 0.0 && enc.builtin() && best.map(better).unwrap_or(true) {
+            best = Some((enc, q));
+        }
+    }
+    best.map(|(enc, _)| enc)
+}
+
+// The q-value of `encoding` in the Accept-Encoding header, 0 if not accepted.
+pub(crate) fn accepts(req: &Request<()>, encoding: &str) -> f32 {
+    let mut star = 0.0;
+    for (name, q) in accept_encoding(req) {
+        if name == encoding || (encoding == "gzip" && name == "x-gzip") {
+            return q;
+        }
+        if name == "*" {
+            star = q;
+        }
+    }
+    star
+}
+
+// Is this content-type in the list of compressible types.
+//
+// Entries that end in a `/` match all subtypes.
+pub(crate) fn is_compressible(types: Option<&Vec<String>>, content_type: &str) -> bool {
+    let ct = content_type.split(';').next().unwrap_or("").trim();
+    let matches = |t: &str| {
+        if t.ends_with('/') {
+            ct.starts_with(t)
+        } else {
+            ct.eq_ignore_ascii_case(t)
+        }
+    };
+    match types {
+        Some(types) => types.iter().any(|t| matches(t)),
+        None => DEFAULT_TYPES.iter().any(|t| matches(t)),
+    }
+}
+
+// Streaming compressor.
+#[cfg(any(feature = "compression", feature = "brotli"))]
+pub(crate) enum Compressor {
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    #[cfg(feature = "compression")]
+    Gzip(GzEncoder<Vec<u8>>),
+}
+
+#[cfg(any(feature = "compression", feature = "brotli"))]
+impl Compressor {
+    // `brotli_quality` is 0..=11, and is only used for `Encoding::Brotli`.
+    // `enc` must be one that `negotiate` returns.
+    #[allow(unused_variables)]
+    pub fn new(enc: Encoding, brotli_quality: u32) -> Compressor {
+        match enc {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => {
+                let quality = brotli_quality.min(11);
+                let encoder = brotli::CompressorWriter::new(Vec::new(), 4096, quality, BROTLI_LGWIN);
+                Compressor::Brotli(Box::new(encoder))
+            },
+            #[cfg(feature = "compression")]
+            Encoding::Gzip => Compressor::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::default())),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("{:?} support is not compiled in", enc),
+        }
+    }
+
+    // Compress a chunk of data, and return whatever output is available.
+    pub fn compress(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let buf = match self {
+            #[cfg(feature = "brotli")]
+            Compressor::Brotli(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            },
+            #[cfg(feature = "compression")]
+            Compressor::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            },
+        };
+        Ok(Bytes::from(std::mem::take(buf)))
+    }
+
+    // Flush the compressor and return the last bit of output.
+    pub fn finish(self) -> io::Result<Bytes> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Compressor::Brotli(encoder) => Ok(Bytes::from((*encoder).into_inner())),
+            #[cfg(feature = "compression")]
+            Compressor::Gzip(encoder) => Ok(Bytes::from(encoder.finish()?)),
+        }
+    }
+}
+
+// Without any of the compression features, `negotiate` never returns an
+// encoding, so there is never a compressor.
+#[cfg(not(any(feature = "compression", feature = "brotli")))]
+pub(crate) enum Compressor {}
+
+#[cfg(not(any(feature = "compression", feature = "brotli")))]
+impl Compressor {
+    pub fn new(enc: Encoding, _brotli_quality: u32) -> Compressor {
+        unreachable!("{:?} support is not compiled in", enc)
+    }
+
+    pub fn compress(&mut self, _data: &[u8]) -> io::Result<Bytes> {
+        match *self {}
+    }
+
+    pub fn finish(self) -> io::Result<Bytes> {
+        match self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(all(feature = "compression", feature = "brotli"))]
+    fn accept_encoding() {
+        let req = |v: &str| Request::builder().header("Accept-Encoding", v).body(()).unwrap();
+        assert_eq!(negotiate(&req("gzip, deflate")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&req("gzip, deflate, br")), Some(Encoding::Brotli));
+        assert_eq!(negotiate(&req("br;q=0.5, gzip")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&req("gzip, *;q=0.1")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&req("deflate, gzip;q=0")), None);
+        assert_eq!(negotiate(&req("identity")), None);
+        assert_eq!(negotiate(&Request::new(())), None);
+    }
+
+    #[test]
+    #[cfg(feature = "brotli")]
+    fn brotli_roundtrip() {
+        let mut c = Compressor::new(Encoding::Brotli, DEFAULT_BROTLI_QUALITY);
+        let mut out = c.compress(b"hello, ").unwrap().to_vec();
+        out.extend_from_slice(&c.compress(b"world").unwrap());
+        out.extend_from_slice(&c.finish().unwrap());
+        let mut data = Vec::new();
+        let mut d = brotli::Decompressor::new(&out[..], 4096);
+        io::Read::read_to_end(&mut d, &mut data).unwrap();
+        assert_eq!(data, b"hello, world");
+    }
+
+    #[test]
+    fn compressible() {
+        assert!(is_compressible(None, "text/html; charset=utf-8"));
+        assert!(is_compressible(None, "application/json"));
+        assert!(!is_compressible(None, "image/png"));
+        let types = vec!["image/png".to_string()];
+        assert!(is_compressible(Some(&types), "image/png"));
+        assert!(!is_compressible(Some(&types), "text/plain"));
+    }
+}
@@ -5,6 +5,7 @@ use http::{Method, StatusCode};
 
 use crate::davheaders::{self, ETag};
 use crate::davpath::DavPath;
+use crate::if_header::If;
 use crate::fs::{DavFileSystem, DavMetaData};
 use crate::ls::DavLockSystem;
 
@@ -34,25 +35,33 @@ pub(crate) fn ifrange_match(
             }
         },
         &davheaders::IfRange::ETag(ref t) => {
+            // RFC 7233 3.2: If-Range requires a strong comparison, so
+            // a weak entity-tag never matches and we send the full body.
+            if t.is_weak() {
+                return false;
+            }
             match tag {
-                Some(tag) => t == tag,
+                Some(tag) => t.strong_eq(tag),
                 None => false,
             }
         },
     }
 }
 
+// If-Match uses the strong comparison, If-None-Match the weak one.
 pub(crate) fn etaglist_match(
     tags: &davheaders::ETagList,
     exists: bool,
     tag: Option<&davheaders::ETag>,
+    weak: bool,
 ) -> bool
 {
     match tags {
         &davheaders::ETagList::Star => exists,
         &davheaders::ETagList::Tags(ref t) => {
             match tag {
-                Some(tag) => t.iter().any(|x| x == tag),
+                Some(tag) if weak => t.iter().any(|x| x.weak_eq(tag)),
+                Some(tag) => t.iter().any(|x| x.strong_eq(tag)),
                 None => false,
             }
         },
@@ -65,25 +74,24 @@ pub(crate) fn http_if_match(req: &Request, meta: Option<&Box<dyn DavMetaData>>)
 
     if let Some(r) = req.headers().typed_get::<davheaders::IfMatch>() {
         let etag = meta.and_then(|m| ETag::from_meta(m));
-        if !etaglist_match(&r.0, meta.is_some(), etag.as_ref()) {
+        if !etaglist_match(&r.0, meta.is_some(), etag.as_ref(), false) {
             trace!("precondition fail: If-Match {:?}", r);
             return Some(StatusCode::PRECONDITION_FAILED);
         }
     } else if let Some(r) = req.headers().typed_get::<headers::IfUnmodifiedSince>() {
-        match file_modified {
-            None => return Some(StatusCode::PRECONDITION_FAILED),
-            Some(file_modified) => {
-                if round_time(file_modified) > round_time(r) {
-                    trace!("precondition fail: If-Unmodified-Since {:?}", r);
-                    return Some(StatusCode::PRECONDITION_FAILED);
-                }
-            },
+        // RFC 9110 13.1.4: ignored if there is no modification date,
+        // which also means it never fails for a resource that does not exist.
+        if let Some(file_modified) = file_modified {
+            if round_time(file_modified) > round_time(r) {
+                trace!("precondition fail: If-Unmodified-Since {:?}", r);
+                return Some(StatusCode::PRECONDITION_FAILED);
+            }
         }
     }
 
     if let Some(r) = req.headers().typed_get::<davheaders::IfNoneMatch>() {
         let etag = meta.and_then(|m| ETag::from_meta(m));
-        if etaglist_match(&r.0, meta.is_some(), etag.as_ref()) {
+        if etaglist_match(&r.0, meta.is_some(), etag.as_ref(), true) {
             trace!("precondition fail: If-None-Match {:?}", r);
             if req.method() == &Method::GET || req.method() == &Method::HEAD {
                 return Some(StatusCode::NOT_MODIFIED);
@@ -119,90 +127,39 @@ pub(crate) async fn dav_if_match<'a>(
     path: &'a DavPath,
 ) -> (bool, Vec<String>)
 {
-    let mut tokens: Vec<String> = Vec::new();
-    let mut any_list_ok = false;
-
-    let r = match req.headers().typed_get::<davheaders::If>() {
+    let r = match req.headers().typed_get::<If>() {
         Some(r) => r,
-        None => return (true, tokens),
+        None => return (true, Vec::new()),
     };
 
-    for iflist in r.0.iter() {
-        // save and return all statetokens that we encountered.
-        let toks = iflist.conditions.iter().filter_map(|c| {
-            match &c.item {
-                &davheaders::IfItem::StateToken(ref t) => Some(t.to_owned()),
-                _ => None,
-            }
-        });
-        tokens.extend(toks);
-
-        // skip over if a previous list already evaluated to true.
-        if any_list_ok == true {
-            continue;
-        }
+    // save and return all statetokens that we encountered.
+    let tokens = r.state_tokens().map(|t| t.to_string()).collect::<Vec<_>>();
 
+    let mut any_list_ok = false;
+    for iflist in r.0.iter() {
         // find the resource that this list is about.
-        let mut pa: Option<DavPath> = None;
-        let (p, valid) = match iflist.resource_tag {
-            Some(ref url) => {
-                match DavPath::from_str_and_prefix(url.path(), path.prefix()) {
-                    Ok(p) => {
-                        // anchor davpath in pa.
-                        let p: &DavPath = pa.get_or_insert(p);
-                        (p, true)
-                    },
-                    Err(_) => (path, false),
-                }
-            },
-            None => (path, true),
+        let p = match iflist.resource_tag {
+            Some(ref url) => DavPath::from_str_and_prefix(url.path(), path.prefix()).ok(),
+            None => Some(path.clone()),
+        };
+        // an invalid location, so always false.
+        let p = match p {
+            Some(p) => p,
+            None => continue,
         };
 
-        // now process the conditions. they must all be true.
-        let mut list_ok = false;
-        for cond in iflist.conditions.iter() {
-            let cond_ok = match cond.item {
-                davheaders::IfItem::StateToken(ref s) => {
-                    // tokens in DAV: namespace always evaluate to false (10.4.8)
-                    if !valid || s.starts_with("DAV:") {
-                        false
-                    } else {
-                        match ls {
-                            &Some(ref ls) => ls.check(p, None, true, false, vec![s]).is_ok(),
-                            &None => false,
-                        }
-                    }
-                },
-                davheaders::IfItem::ETag(ref tag) => {
-                    if !valid {
-                        // invalid location, so always false.
-                        false
-                    } else {
-                        match fs.metadata(p).await {
-                            Ok(meta) => {
-                                // exists and may have metadata ..
-                                if let Some(mtag) = ETag::from_meta(meta) {
-                                    tag == &mtag
-                                } else {
-                                    false
-                                }
-                            },
-                            Err(_) => {
-                                // metadata error, fail.
-                                false
-                            },
-                        }
-                    }
-                },
-            };
-            if cond_ok == cond.not {
-                list_ok = false;
-                break;
-            }
-            list_ok = true;
-        }
-        if list_ok {
+        let etag = if iflist.has_etag() {
+            fs.metadata(&p).await.ok().and_then(ETag::from_meta)
+        } else {
+            None
+        };
+        let token_ok = |t: &str| {
+            ls.as_ref()
+                .is_some_and(|ls| ls.check(&p, None, true, false, vec![t]).is_ok())
+        };
+        if iflist.eval(token_ok, etag.as_ref()) {
             any_list_ok = true;
+            break;
         }
     }
     if !any_list_ok {
@@ -244,3 +201,74 @@ pub(crate) async fn if_match_get_tokens<'a>(
         (false, _) => Err(StatusCode::PRECONDITION_FAILED),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn if_range() {
+        let tag = ETag::from_str(r#""1234""#).unwrap();
+        let strong = davheaders::IfRange::ETag(ETag::from_str(r#""1234""#).unwrap());
+        let weak = davheaders::IfRange::ETag(ETag::from_str(r#"W/"1234""#).unwrap());
+        let other = davheaders::IfRange::ETag(ETag::from_str(r#""5678""#).unwrap());
+        assert!(ifrange_match(&strong, Some(&tag), None));
+        assert!(!ifrange_match(&weak, Some(&tag), None));
+        assert!(!ifrange_match(&other, Some(&tag), None));
+        assert!(!ifrange_match(&strong, None, None));
+
+        let now = SystemTime::now();
+        let date = davheaders::IfRange::Date(headers::Date::from(now));
+        assert!(ifrange_match(&date, Some(&tag), Some(now)));
+        assert!(!ifrange_match(&date, Some(&tag), Some(now + Duration::from_secs(2))));
+    }
+
+    #[derive(Debug, Clone)]
+    struct Meta(SystemTime);
+
+    impl DavMetaData for Meta {
+        fn len(&self) -> u64 {
+            5
+        }
+        fn modified(&self) -> crate::fs::FsResult<SystemTime> {
+            Ok(self.0)
+        }
+        fn is_dir(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn preconditions() {
+        let meta: Box<dyn DavMetaData> = Box::new(Meta(UNIX_EPOCH + Duration::from_secs(1000000000)));
+        let check = |method: &str, hdr: &str, val: &str, exists: bool| {
+            let req = Request::builder().method(method).header(hdr, val);
+            http_if_match(&req.body(()).unwrap(), Some(&meta).filter(|_| exists))
+        };
+        let etag = format!("\"{}\"", meta.etag().unwrap());
+        let pfail = Some(StatusCode::PRECONDITION_FAILED);
+
+        let before = "Sat, 08 Sep 2001 00:00:00 GMT";
+        let after = "Mon, 10 Sep 2001 00:00:00 GMT";
+        assert_eq!(check("PUT", "If-Unmodified-Since", before, true), pfail);
+        assert_eq!(check("PUT", "If-Unmodified-Since", after, true), None);
+        assert_eq!(check("PUT", "If-Unmodified-Since", before, false), None);
+
+        assert_eq!(check("DELETE", "If-Match", &etag, true), None);
+        assert_eq!(check("DELETE", "If-Match", r#""other""#, true), pfail);
+        assert_eq!(check("PUT", "If-Match", "*", false), pfail);
+
+        assert_eq!(check("PUT", "If-None-Match", "*", true), pfail);
+        assert_eq!(check("PUT", "If-None-Match", "*", false), None);
+        let notmod = Some(StatusCode::NOT_MODIFIED);
+        assert_eq!(check("GET", "If-None-Match", &etag, true), notmod);
+
+        // If-None-Match uses the weak comparison, If-Match the strong one.
+        let weak = format!("W/{}", etag);
+        assert_eq!(check("GET", "If-None-Match", &weak, true), notmod);
+        assert_eq!(check("PUT", "If-None-Match", &weak, true), pfail);
+        assert_eq!(check("GET", "If-None-Match", r#"W/"other", "x""#, true), None);
+        assert_eq!(check("DELETE", "If-Match", &weak, true), pfail);
+    }
+}
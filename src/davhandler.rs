@@ -3,27 +3,69 @@
 // DavHandler.
 //
 use std::error::Error as StdError;
+use std::future::Future;
 use std::io;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use bytes::{self, buf::Buf};
-use futures::stream::Stream;
+use futures::{future::FutureExt, stream::Stream};
 use headers::HeaderMapExt;
 use http::{Request, Response, StatusCode};
 use http_body::Body as HttpBody;
 
+use crate::accesslog::{AccessLog, AccessLogEntry};
 use crate::body::{Body, StreamBody};
+use crate::compression;
 use crate::davheaders;
 use crate::davpath::DavPath;
 use crate::util::{dav_method, DavMethod, DavMethodSet};
 
-use crate::errors::DavError;
+use crate::errors::{DavError, ErrorMap, HandlerError};
 use crate::fs::*;
+use crate::handle_copymove::{DestinationMap, RemoteCopy};
+use crate::handle_gethead::{
+    AutoIndexTemplate, CacheControl, ContentDisposition, DirListing, Disposition, MimeResolver,
+};
+use crate::handle_report::{Report, ReportHandler};
+use crate::handle_tus::is_tus_request;
+use crate::hidefs::{HideFn, HideFs};
+use crate::if_header::{If, IfItem};
 use crate::ls::*;
+use crate::notimestampsfs::NoTimestampsFs;
+use crate::observer::DavObserver;
+use crate::propprovider::PropertyProvider;
+use crate::ratelimit::RateLimiter;
 use crate::time::UtcOffset;
+use crate::timeoutfs::TimeoutFs;
 use crate::voidfs::{is_voidfs, VoidFs};
 use crate::DavResult;
 
+pub(crate) type Authenticator = Arc<dyn Fn(&Request<()>) -> AuthResult + Send + Sync>;
+pub(crate) type ErrorHandler = Arc<dyn Fn(StatusCode, &DavPath) -> Option<Response<Body>> + Send + Sync>;
+pub(crate) type HrefRewrite = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+// The href of `path` in a response.
+pub(crate) fn href(rewrite: Option<&HrefRewrite>, path: &DavPath) -> String {
+    let href = path.with_prefix().as_url_string();
+    match rewrite {
+        Some(rewrite) => rewrite(&href),
+        None => href,
+    }
+}
+
+/// The result of the [`authenticator`](DavConfig::authenticator) hook.
+#[derive(Debug, Clone)]
+pub enum AuthResult {
+    /// The request is from this user, it is used as the principal.
+    Ok(String),
+    /// Send a `401 Unauthorized` response with this `WWW-Authenticate` header.
+    Challenge(String),
+    /// Handle the request without a principal.
+    Anonymous,
+}
+
 /// The webdav handler struct.
 ///
 /// The `new` and `build` etc methods are used to instantiate a handler.
@@ -38,24 +80,100 @@ pub struct DavHandler {
 #[derive(Default)]
 pub struct DavConfig {
     // Prefix to be stripped off when handling request.
-    pub(crate) prefix:        Option<String>,
+    pub(crate) prefix:              Option<String>,
     // Filesystem backend.
-    pub(crate) fs:            Option<Box<dyn DavFileSystem>>,
+    pub(crate) fs:                  Option<Box<dyn DavFileSystem>>,
     // Locksystem backend.
-    pub(crate) ls:            Option<Box<dyn DavLockSystem>>,
+    pub(crate) ls:                  Option<Box<dyn DavLockSystem>>,
     // Set of allowed methods (None means "all methods")
-    pub(crate) allow:         Option<DavMethodSet>,
+    pub(crate) allow:               Option<DavMethodSet>,
     // Principal is webdav speak for "user", used to give locks an owner (if a locksystem is
     // active).
-    pub(crate) principal:     Option<String>,
+    pub(crate) principal:           Option<String>,
     // Hide symbolic links? `None` maps to `true`.
-    pub(crate) hide_symlinks: Option<bool>,
+    pub(crate) hide_symlinks:       Option<bool>,
     // Does GET on a directory return indexes.
-    pub(crate) autoindex:     Option<bool>,
+    pub(crate) autoindex:           Option<bool>,
     // Localtime for directory indexes
-    pub(crate) utcoffset:     Option<UtcOffset>,
-    // index.html
-    pub(crate) indexfile:     Option<String>,
+    pub(crate) utcoffset:           Option<UtcOffset>,
+    // Template for directory indexes.
+    pub(crate) autoindex_template:  Option<AutoIndexTemplate>,
+    // index.html, in the order in which they are tried
+    pub(crate) indexfiles:          Option<Vec<String>>,
+    // Reject all methods that modify the filesystem or locks.
+    pub(crate) read_only:           Option<bool>,
+    // Compress GET responses.
+    pub(crate) compression:         Option<bool>,
+    // Content types that are compressed.
+    pub(crate) compression_types:   Option<Vec<String>>,
+    // Accept TUS resumable uploads.
+    pub(crate) tus:                 Option<bool>,
+    // Request/response hooks.
+    pub(crate) observer:            Option<Arc<dyn DavObserver>>,
+    // Content-Type lookup, before the built-in table.
+    pub(crate) mime_resolver:       Option<MimeResolver>,
+    // value of the Server: header
+    pub(crate) server_header:       Option<String>,
+    // maximum size of a PUT request body
+    pub(crate) max_upload_size:     Option<u64>,
+    // handler for REPORT requests
+    pub(crate) report_handler:      Option<ReportHandler>,
+    // custom error responses for GET and HEAD
+    pub(crate) error_handler:       Option<ErrorHandler>,
+    // write PUT bodies to a temporary file first
+    pub(crate) atomic_put:          Option<bool>,
+    // decides who the user is, or asks for credentials
+    pub(crate) authenticator:       Option<Authenticator>,
+    // extra DAV compliance classes and methods for OPTIONS
+    pub(crate) options_extra:       Option<(Vec<String>, Vec<http::Method>)>,
+    // refuse PROPFIND with a larger Depth. Some(None) is no limit, the default is 1.
+    pub(crate) propfind_max_depth:  Option<Option<u32>>,
+    // Map the Destination header to a path.
+    pub(crate) destination_map:     Option<DestinationMap>,
+    // Cache-Control header for GET/HEAD.
+    pub(crate) cache_control:       Option<CacheControl>,
+    // Timeout for filesystem operations.
+    pub(crate) fs_op_timeout:       Option<Duration>,
+    // Send timestamps, and ETags derived from them.
+    pub(crate) expose_timestamps:   Option<bool>,
+    // Redirect GET of a collection without a trailing slash.
+    pub(crate) collection_redirect: Option<bool>,
+    // Provider of dead properties outside the filesystem.
+    pub(crate) property_provider:   Option<Arc<dyn PropertyProvider>>,
+    // serve precompressed .br / .gz files
+    pub(crate) precompressed:       Option<bool>,
+    // log XML request and response bodies, up to this many bytes
+    pub(crate) log_bodies:          Option<usize>,
+    // how many filesystem operations a recursive request does at once
+    pub(crate) fs_concurrency:      Option<usize>,
+    // rewrites the hrefs in multistatus responses
+    pub(crate) href_rewrite:        Option<HrefRewrite>,
+    // COPY/MOVE to a Destination on another server.
+    pub(crate) remote_copy:         Option<RemoteCopy>,
+    // Token bucket per principal or client address.
+    pub(crate) rate_limit:          Option<Arc<RateLimiter>>,
+    // Address of the client.
+    pub(crate) remote_addr:         Option<SocketAddr>,
+    // Combined Log Format access log.
+    pub(crate) access_log:          Option<AccessLog>,
+    // Upper limit for the timeout of a lock.
+    pub(crate) max_lock_timeout:    Option<Duration>,
+    // Content-Disposition of GET responses.
+    pub(crate) content_disposition: Option<ContentDisposition>,
+    // Send HTML files as attachments.
+    pub(crate) html_as_attachment:  Option<bool>,
+    // Add security headers to GET responses.
+    pub(crate) security_headers:    Option<bool>,
+    // Which files and directories to hide.
+    pub(crate) hide:                Option<HideFn>,
+    // Brotli quality for compression on the fly.
+    pub(crate) brotli_quality:      Option<u32>,
+    // Map filesystem errors to HTTP statuses.
+    pub(crate) error_map:           Option<ErrorMap>,
+    // serve <name>.<lang>.<ext> variants.
+    pub(crate) negotiate_language:  Option<bool>,
+    // refuse a Destination on another host
+    pub(crate) strict_destination:  Option<bool>,
 }
 
 impl DavConfig {
@@ -107,6 +225,42 @@ impl DavConfig {
         this
     }
 
+    /// Set the address of the client.
+    ///
+    /// The handler can't know this by itself, so this is set per request,
+    /// in the config that is passed to `handle_with`. Instead, the address
+    /// can also be put in the extensions of the request, as a `SocketAddr`.
+    /// That is what the adapters for actix, warp and axum do (for axum, if
+    /// the server was started with `into_make_service_with_connect_info`).
+    ///
+    /// The address is used by the [`rate_limit`](Self::rate_limit) when there
+    /// is no principal, and in the [`access_log`](Self::access_log). It is
+    /// passed to [`DavObserver::on_request_from`], and the authenticator can
+    /// find it in the extensions of the request.
+    pub fn remote_addr(self, addr: SocketAddr) -> Self {
+        let mut this = self;
+        this.remote_addr = Some(addr);
+        this
+    }
+
+    /// Authenticate requests.
+    ///
+    /// The function is called for every request, before anything else is
+    /// done. It looks at the request headers (`Authorization:`, usually)
+    /// and decides who the user is, see [`AuthResult`]. That way it is in
+    /// one place instead of in every server or framework adapter.
+    ///
+    /// A principal set with `handle_with` is replaced by the one returned
+    /// by the authenticator, if it returns `AuthResult::Ok`. The address of
+    /// the client, if known (see [`remote_addr`](Self::remote_addr)), is in
+    /// the extensions of the request, as a `SocketAddr`.
+    pub fn authenticator<F>(self, auth: F) -> Self
+    where F: Fn(&Request<()>) -> AuthResult + Send + Sync + 'static {
+        let mut this = self;
+        this.authenticator = Some(Arc::new(auth));
+        this
+    }
+
     /// Hide symbolic links (default is true)
     pub fn hide_symlinks(self, hide: bool) -> Self {
         let mut this = self;
@@ -114,6 +268,24 @@ impl DavConfig {
         this
     }
 
+    /// Redirect a `GET` of a collection without a trailing slash (default is true).
+    ///
+    /// Normally a `GET` of `/dir` is answered with a `301 Moved Permanently`
+    /// to `/dir/`. Older versions sent a `302 Found`; clients may cache
+    /// the 301, so only leave this on if the collection stays one. If this
+    /// is set to false, the index file or directory listing is served at
+    /// `/dir` without a redirect, for clients that do not handle redirects
+    /// well. Note that relative links in that index then
+    /// resolve against the parent, which is what the redirect avoids.
+    ///
+    /// `PROPFIND` and friends never redirect, they return the path with
+    /// the slash in the `Content-Location` header.
+    pub fn collection_redirect(self, redirect: bool) -> Self {
+        let mut this = self;
+        this.collection_redirect = Some(redirect);
+        this
+    }
+
     /// Does a GET on a directory produce a directory index.
     ///
     /// If you want the timestamp of the files to be in the local
@@ -125,24 +297,653 @@ impl DavConfig {
         this
     }
 
+    /// Render directory indexes with a template function instead of
+    /// the built-in HTML.
+    ///
+    /// The function is called once for every directory listing, and
+    /// the string it returns is sent as the response body as-is.
+    pub fn autoindex_template<F>(self, template: F) -> Self
+    where F: Fn(&DirListing) -> String + Send + Sync + 'static {
+        let mut this = self;
+        this.autoindex_template = Some(Arc::new(template));
+        this
+    }
+
+    /// Determine the Content-Type of files with a function.
+    ///
+    /// The function is consulted first for GET and HEAD responses and
+    /// for the `getcontenttype` property. If it returns `None`, the
+    /// built-in table, based on the file extension, is used.
+    pub fn mime_resolver<F>(self, resolver: F) -> Self
+    where F: Fn(&DavPath, &dyn DavMetaData) -> Option<mime::Mime> + Send + Sync + 'static {
+        let mut this = self;
+        this.mime_resolver = Some(Arc::new(resolver));
+        this
+    }
+
+    /// Map the `Destination` header of `COPY` and `MOVE` requests to a path.
+    ///
+    /// The function is called with the request and the value of the
+    /// `Destination` header, which is a path or an absolute URL. It returns
+    /// the (percent-encoded) path that the destination is on this server,
+    /// or `None` if it is not on this server, which is a `502 Bad Gateway`.
+    /// The prefix is then stripped from the path, just like for the
+    /// request path.
+    ///
+    /// This is useful behind a reverse proxy, where the client sends the
+    /// external URL, with a `Host` and maybe a path that are not the
+    /// ones that the handler sees.
+    ///
+    /// Without a function, the path of a `Destination` URL is used as-is,
+    /// and the host is ignored, unless [`strict_destination`](Self::strict_destination)
+    /// is set.
+    ///
+    /// Note that this function replaces that host check. If it accepts
+    /// any host, a client that wanted to copy or move a resource to
+    /// another server ends up overwriting the resource at that path on
+    /// this server. And if several sites with their own `Host` share this
+    /// handler, a client of one site can write into the others. So only
+    /// accept the hosts that the proxy actually forwards to this handler.
+    pub fn destination_map<F>(self, map: F) -> Self
+    where F: Fn(&Request<()>, &str) -> Option<String> + Send + Sync + 'static {
+        let mut this = self;
+        this.destination_map = Some(Arc::new(map));
+        this
+    }
+
+    /// Refuse a `Destination` URL that has another host than the request.
+    ///
+    /// RFC4918 says that a `COPY` or `MOVE` to another server is a `502 Bad
+    /// Gateway`. That is off by default, because behind a proxy or with
+    /// several names for one server, the `Host` of the request is often not
+    /// the host that the client puts in the `Destination`. A default port
+    /// (`:80` for http, `:443` for https) is not a different host.
+    pub fn strict_destination(self, strict: bool) -> Self {
+        let mut this = self;
+        this.strict_destination = Some(strict);
+        this
+    }
+
+    /// Handle `COPY` and `MOVE` to a `Destination` on another server.
+    ///
+    /// With [`strict_destination`](Self::strict_destination), a `Destination`
+    /// URL with another host (or one that the [`destination_map`](Self::destination_map)
+    /// function refuses) is a `502 Bad Gateway`. Setting this function turns
+    /// on `strict_destination`. With this function, the handler checks the
+    /// conditional headers and locks of the source as usual and then calls
+    /// it with the source path and the `Destination` URL. Transferring
+    /// the resource is up to the function, which returns the status of the
+    /// copy, usually `201 Created` or `204 No Content`.
+    ///
+    /// For a `MOVE`, if that status is a success, the handler then deletes
+    /// the source. The `Depth` and `Overwrite` headers are not passed on,
+    /// a `COPY` or `MOVE` to another server is always `Depth: infinity`.
+    pub fn remote_copy<F>(self, copy: F) -> Self
+    where F: Fn(DavPath, http::Uri) -> FsFuture<'static, StatusCode> + Send + Sync + 'static {
+        let mut this = self;
+        this.remote_copy = Some(Arc::new(copy));
+        this
+    }
+
+    /// Rewrite the `href`s in multistatus responses with a function.
+    ///
+    /// The function gets the href as the handler would send it, which is
+    /// the percent-encoded path including the prefix, and returns the one
+    /// to send instead. It's called for every `href` of a resource in the
+    /// responses to `PROPFIND`, `PROPPATCH`, `REPORT` and `LOCK` (the lock
+    /// root), and in the multistatus of a `COPY`, `MOVE` or `DELETE`.
+    ///
+    /// This is the other half of [`destination_map`](Self::destination_map):
+    /// behind a proxy that serves the handler under another path, the client
+    /// gets paths that it can use, without the proxy having to rewrite the
+    /// response bodies.
+    ///
+    /// ```
+    /// use webdav_handler::DavHandler;
+    ///
+    /// let dav_server = DavHandler::builder()
+    ///     .strip_prefix("/dav")
+    ///     .href_rewrite(|href| href.replacen("/dav/", "/public/", 1))
+    ///     .build_handler();
+    /// ```
+    pub fn href_rewrite<F>(self, rewrite: F) -> Self
+    where F: Fn(&str) -> String + Send + Sync + 'static {
+        let mut this = self;
+        this.href_rewrite = Some(Arc::new(rewrite));
+        this
+    }
+
+    /// Hide files and directories.
+    ///
+    /// The function gets the name of a file or directory, and returns `true`
+    /// if it should be hidden. Those are not in directory listings, and any
+    /// access to them, or to anything below a hidden directory, gives a
+    /// `404 Not Found`. That includes index files. Deleting a collection
+    /// deletes the hidden entries in it as well, if the filesystem implements
+    /// [`remove_dir_all`](crate::fs::DavFileSystem::remove_dir_all).
+    ///
+    /// For example, to hide dotfiles and `Thumbs.db`:
+    ///
+    /// ```
+    /// use webdav_handler::DavHandler;
+    ///
+    /// let dav_server = DavHandler::builder()
+    ///     .hide(|name| name.starts_with('.') || name == "Thumbs.db")
+    ///     .build_handler();
+    /// ```
+    pub fn hide<F>(self, hide: F) -> Self
+    where F: Fn(&str) -> bool + Send + Sync + 'static {
+        let mut this = self;
+        this.hide = Some(Arc::new(hide));
+        this
+    }
+
+    /// Set the `Cache-Control` header of `GET` and `HEAD` responses with a function.
+    ///
+    /// The function is called for files (not for directory listings) if the
+    /// response is a success or `304 Not Modified`. If it returns `None`,
+    /// which is the default, there is no `Cache-Control` header.
+    ///
+    /// For example, to cache everything below `/assets/` for a day:
+    ///
+    /// ```
+    /// use http::HeaderValue;
+    /// use webdav_handler::DavHandler;
+    ///
+    /// let dav_server = DavHandler::builder()
+    ///     .cache_control(|path, _meta| {
+    ///         path.as_bytes()
+    ///             .starts_with(b"/assets/")
+    ///             .then(|| HeaderValue::from_static("public, max-age=86400"))
+    ///     })
+    ///     .build_handler();
+    /// ```
+    pub fn cache_control<F>(self, cache_control: F) -> Self
+    where F: Fn(&DavPath, &dyn DavMetaData) -> Option<http::HeaderValue> + Send + Sync + 'static {
+        let mut this = self;
+        this.cache_control = Some(Arc::new(cache_control));
+        this
+    }
+
+    /// Set the `Content-Disposition` header of `GET` and `HEAD` responses with a function.
+    ///
+    /// With [`Disposition::Attachment`] a browser downloads the file
+    /// instead of showing it. The function is called for files if the
+    /// response is a success. If it returns `None`, which is the default,
+    /// there is no `Content-Disposition` header, except for HTML files
+    /// with [`html_as_attachment`](Self::html_as_attachment).
+    ///
+    /// For example, to download everything below `/uploads/`:
+    ///
+    /// ```
+    /// use webdav_handler::{DavHandler, Disposition};
+    ///
+    /// let dav_server = DavHandler::builder()
+    ///     .content_disposition(|path, _meta| {
+    ///         path.as_bytes()
+    ///             .starts_with(b"/uploads/")
+    ///             .then(|| Disposition::Attachment(None))
+    ///     })
+    ///     .build_handler();
+    /// ```
+    pub fn content_disposition<F>(self, disposition: F) -> Self
+    where F: Fn(&DavPath, &dyn DavMetaData) -> Option<Disposition> + Send + Sync + 'static {
+        let mut this = self;
+        this.content_disposition = Some(Arc::new(disposition));
+        this
+    }
+
+    /// Make browsers download HTML files instead of showing them (default `false`).
+    ///
+    /// If users can upload files, an HTML file could run scripts in the
+    /// context of this site (stored XSS). This sends `text/html` and
+    /// `application/xhtml+xml` files with `Content-Disposition: attachment`,
+    /// unless the `content_disposition` function decides otherwise.
+    pub fn html_as_attachment(self, attachment: bool) -> Self {
+        let mut this = self;
+        this.html_as_attachment = Some(attachment);
+        this
+    }
+
+    /// Add security headers to responses to `GET` and `HEAD` (default `false`).
+    ///
+    /// These are `X-Content-Type-Options: nosniff`, `Content-Security-Policy: sandbox`
+    /// and `X-Frame-Options: DENY`. With those, a browser does not guess the type
+    /// of a file, does not run scripts in it, and does not show it in a frame on
+    /// another site. That is a good idea when serving files uploaded by users.
+    pub fn security_headers(self, security_headers: bool) -> Self {
+        let mut this = self;
+        this.security_headers = Some(security_headers);
+        this
+    }
+
+    /// Put a time limit on filesystem operations.
+    ///
+    /// If an operation like `metadata` or `open` does not finish in time, for
+    /// example because of a hung network filesystem, the request fails with
+    /// `504 Gateway Timeout` and the operation is logged. Reading or writing
+    /// the body of a file is not limited, and neither is copying a file, as
+    /// those can take long for large files.
+    pub fn fs_op_timeout(self, timeout: Duration) -> Self {
+        let mut this = self;
+        this.fs_op_timeout = Some(timeout);
+        this
+    }
+
+    /// Maximum timeout of a lock.
+    ///
+    /// Locks always time out. The timeout is the first one in the `Timeout`
+    /// header of the `LOCK` request that is not `Infinite`, up to this
+    /// maximum. Without this option, the maximum is 10 minutes for an
+    /// exclusive lock and a day for a shared lock.
+    pub fn max_lock_timeout(self, timeout: Duration) -> Self {
+        let mut this = self;
+        this.max_lock_timeout = Some(timeout);
+        this
+    }
+
+    /// Send the timestamps of files and directories (default `true`).
+    ///
+    /// If `false`, there is no `Last-Modified` header in the responses, and
+    /// the `DAV:getlastmodified` and `DAV:creationdate` properties are left
+    /// out. The ETags are normally derived from the modification time as
+    /// well, so instead they are a hash of that ETag with a secret key.
+    /// The key is random, so the ETags change when the server restarts, and
+    /// differ between servers that share the files.
+    pub fn expose_timestamps(self, expose: bool) -> Self {
+        let mut this = self;
+        this.expose_timestamps = Some(expose);
+        this
+    }
+
+    /// Handle `REPORT` requests with a function.
+    ///
+    /// The function is called with the path and a [`Report`] that has
+    /// the name of the report (the root element of the request body), so
+    /// that it can dispatch on it. Any response it returns is sent as-is.
+    ///
+    /// Without a report handler, `REPORT` returns `405 Method Not Allowed`.
+    pub fn report_handler<F, Fut>(self, handler: F) -> Self
+    where
+        F: Fn(DavPath, Report) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<Body>> + Send + 'static,
+    {
+        let mut this = self;
+        this.report_handler = Some(Arc::new(move |path, report| handler(path, report).boxed()));
+        this
+    }
+
+    /// Replace the error responses to `GET` and `HEAD` requests.
+    ///
+    /// The function is called with the status code and the path when
+    /// the response to a `GET` or `HEAD` request is a 4xx or 5xx error,
+    /// so that for example a browser can be shown a friendly 404 page.
+    /// If it returns `None`, the default response is sent.
+    ///
+    /// Other methods always get the default response, WebDAV
+    /// clients might depend on it. Headers that are part of the error,
+    /// like `WWW-Authenticate`, `Content-Range`, `Allow`, `Retry-After`
+    /// and `Location`, are copied to the custom response.
+    pub fn error_handler<F>(self, handler: F) -> Self
+    where F: Fn(StatusCode, &DavPath) -> Option<Response<Body>> + Send + Sync + 'static {
+        let mut this = self;
+        this.error_handler = Some(Arc::new(handler));
+        this
+    }
+
+    /// Choose the HTTP status for filesystem errors.
+    ///
+    /// The function is called wherever a filesystem error is turned into
+    /// a status, both for the response itself and for the entries in a
+    /// multistatus response. If it returns `None`, the default mapping
+    /// is used. For example, to not reveal that a file exists when
+    /// access to it is denied:
+    ///
+    /// ```
+    /// # use webdav_handler::DavHandler;
+    /// use http::StatusCode;
+    /// use std::io::ErrorKind;
+    ///
+    /// let config = DavHandler::builder().error_map(|e| match e.kind() {
+    ///     ErrorKind::PermissionDenied => Some(StatusCode::NOT_FOUND),
+    ///     _ => None,
+    /// });
+    /// ```
+    ///
+    /// Errors from a [`DavFileSystem`] are passed as an `io::Error` with
+    /// the matching [`ErrorKind`](std::io::ErrorKind), so `FsError::Forbidden`
+    /// is `PermissionDenied`, `FsError::NotFound` is `NotFound`, and so on.
+    pub fn error_map<F>(self, map: F) -> Self
+    where F: Fn(&io::Error) -> Option<StatusCode> + Send + Sync + 'static {
+        let mut this = self;
+        this.error_map = Some(Arc::new(map));
+        this
+    }
+
     /// Indexfile to show (index.html, usually).
+    ///
+    /// This is the same as `indexfiles` with just one name.
     pub fn indexfile(self, indexfile: impl Into<String>) -> Self {
+        self.indexfiles(vec![indexfile.into()])
+    }
+
+    /// Indexfiles to try, in order (for example `index.html`, `index.htm`).
+    ///
+    /// A `GET` of a directory serves the first one that exists. If none
+    /// of them exist, the directory index is shown if that is enabled
+    /// (see [`autoindex`](DavConfig::autoindex)), otherwise the response
+    /// is `404 Not Found`.
+    pub fn indexfiles(self, indexfiles: Vec<impl Into<String>>) -> Self {
+        let mut this = self;
+        this.indexfiles = Some(indexfiles.into_iter().map(|i| i.into()).collect());
+        this
+    }
+
+    /// Serve the filesystem read-only (default is false).
+    ///
+    /// If set, PUT, PATCH, DELETE, MKCOL, MOVE, COPY, PROPPATCH, LOCK and UNLOCK
+    /// requests are refused with `403 Forbidden` before they reach the
+    /// filesystem, and OPTIONS only advertises the read-only methods.
+    pub fn read_only(self, read_only: bool) -> Self {
+        let mut this = self;
+        this.read_only = Some(read_only);
+        this
+    }
+
+    /// Compress GET responses on the fly (default is false).
+    ///
+    /// A response is compressed with `br` or `gzip` if the client sends an
+    /// `Accept-Encoding` header that allows it, the content type is
+    /// compressible, and the whole file is sent (no `Range`). If the client
+    /// accepts both equally well, `br` is used. A compressed response is
+    /// sent without `Content-Length`, and with a weak ETag.
+    ///
+    /// `gzip` is only available with the `compression` feature, and `br`
+    /// with the `brotli` feature. Without either, nothing is compressed.
+    pub fn compression(self, compression: bool) -> Self {
+        let mut this = self;
+        this.compression = Some(compression);
+        this
+    }
+
+    /// Brotli quality for compression on the fly, 0 to 11 (default is 5).
+    ///
+    /// Higher is smaller but slower; the top levels are meant for
+    /// compressing files ahead of time, see `precompressed`. This needs
+    /// the `brotli` feature.
+    pub fn brotli_quality(self, quality: u32) -> Self {
+        let mut this = self;
+        this.brotli_quality = Some(quality);
+        this
+    }
+
+    /// Serve precompressed files (default is false).
+    ///
+    /// If the client accepts `br` or `gzip`, and there is a `<path>.br` or
+    /// `<path>.gz` next to the requested file that is not older than the
+    /// file itself, that is sent instead, with a `Content-Encoding` header
+    /// and the content type of the original file. This happens before, and
+    /// instead of, compression on the fly.
+    pub fn precompressed(self, precompressed: bool) -> Self {
+        let mut this = self;
+        this.precompressed = Some(precompressed);
+        this
+    }
+
+    /// Choose the language of a file from `Accept-Language` (default is false).
+    ///
+    /// For a `GET` or `HEAD` of `index.html`, the client's languages are
+    /// tried in order of preference, and if there is an `index.<lang>.html`
+    /// like `index.de.html`, that is sent instead, with a `Content-Language`
+    /// header. A language like `de-CH` also matches `index.de.html`. If no
+    /// variant matches, the plain file is sent. This works for index files
+    /// as well. At most 8 languages are tried. All responses to `GET` and
+    /// `HEAD` then carry `Vary: Accept-Language`.
+    pub fn negotiate_language(self, negotiate: bool) -> Self {
+        let mut this = self;
+        this.negotiate_language = Some(negotiate);
+        this
+    }
+
+    /// Content types to compress, if compression is enabled.
+    ///
+    /// Entries that end with a `/`, like `text/`, match all subtypes.
+    /// The default is `text/`, `application/javascript`, `application/json`,
+    /// `application/xml`, `application/xhtml+xml`, `application/wasm`
+    /// and `image/svg+xml`.
+    pub fn compression_types(self, types: Vec<impl Into<String>>) -> Self {
+        let mut this = self;
+        this.compression_types = Some(types.into_iter().map(|t| t.into()).collect());
+        this
+    }
+
+    /// Accept TUS 1.0 resumable uploads (default is false).
+    ///
+    /// With this enabled, `POST`, `PATCH` and `HEAD` requests that have a
+    /// `Tus-Resumable` header are handled as [TUS](https://tus.io/) requests.
+    /// The upload URL is the URL of the resource itself: `POST` starts an
+    /// upload, `PATCH` appends to it, and `HEAD` returns the current offset.
+    /// The partial upload is kept in dotfiles next to the resource, and
+    /// renamed into place when it is complete. Regular `PUT` is not affected.
+    pub fn tus(self, tus: bool) -> Self {
+        let mut this = self;
+        this.tus = Some(tus);
+        this
+    }
+
+    /// Set an observer that is called at the start and end of every request.
+    ///
+    /// This can be used to collect metrics, like the number of requests
+    /// per method and status, without depending on a specific metrics library.
+    pub fn observer(self, observer: Arc<dyn DavObserver>) -> Self {
+        let mut this = self;
+        this.observer = Some(observer);
+        this
+    }
+
+    /// Use `provider` for the dead properties in the namespaces it claims.
+    ///
+    /// This lets an application keep properties of its own, for example in
+    /// a database, whatever the filesystem is. See [`PropertyProvider`].
+    pub fn property_provider(self, provider: Arc<dyn PropertyProvider>) -> Self {
+        let mut this = self;
+        this.property_provider = Some(provider);
+        this
+    }
+
+    /// Set the value of the `Server:` header that is sent with every response.
+    ///
+    /// If this is `None` (the default), no `Server:` header is sent.
+    pub fn server_header(self, server: Option<String>) -> Self {
+        let mut this = self;
+        this.server_header = server;
+        this
+    }
+
+    /// Set the maximum size of the body of a PUT request.
+    ///
+    /// A request with a larger `Content-Length` is refused right away.
+    /// If the body turns out to be larger while it is being written, the
+    /// upload is aborted, and the partial file is removed if the `PUT`
+    /// created it; a file that existed before is left truncated. That is
+    /// also how a chunked upload, without a `Content-Length`, is limited.
+    /// In both cases the response is `413 Payload Too Large`.
+    ///
+    /// With [`tus`](Self::tus), this is also the largest `Upload-Length`
+    /// that a new upload can have. It is advertised as `Tus-Max-Size`.
+    pub fn max_upload_size(self, size: u64) -> Self {
+        let mut this = self;
+        this.max_upload_size = Some(size);
+        this
+    }
+
+    /// Limit the number of requests per second of each client.
+    ///
+    /// Every client gets a bucket of `burst` requests, that fills up again
+    /// at `requests_per_sec`. A request that finds the bucket empty gets a
+    /// `429 Too Many Requests`, with a `Retry-After` header. The client is
+    /// the [`principal`](Self::principal), from the config or from the
+    /// [`authenticator`](Self::authenticator), so that users behind one address
+    /// don't share a bucket. Without a principal it is the
+    /// [`remote_addr`](Self::remote_addr); that is also where failed logins
+    /// count. Requests with neither are not limited.
+    ///
+    /// The buckets are shared by all clones of the handler. Set this on the
+    /// handler, not in a config for `handle_with`, that would start out
+    /// with new, full buckets for every request.
+    pub fn rate_limit(self, requests_per_sec: f64, burst: u32) -> Self {
+        let mut this = self;
+        this.rate_limit = Some(Arc::new(RateLimiter::new(requests_per_sec, burst)));
+        this
+    }
+
+    /// Write the body of a PUT request to a temporary file, and rename it
+    /// over the destination only once the upload is complete. If the upload
+    /// fails halfway, the existing file is left alone.
+    ///
+    /// The temporary file is a dotfile in the same directory, see
+    /// [`DavFileSystem::temp_path`]; `LocalFs` and `MemFs` have one. Partial
+    /// updates are still written in place, and so are uploads to a filesystem
+    /// without temporary paths, and to a file with more than one binding.
+    /// The properties of the file are kept. The default is `false`.
+    pub fn atomic_put(self, atomic: bool) -> Self {
+        let mut this = self;
+        this.atomic_put = Some(atomic);
+        this
+    }
+
+    /// Advertise extra DAV compliance classes and methods in `OPTIONS`.
+    ///
+    /// The classes are added to the `DAV:` header, and the methods to
+    /// the `Allow:` header. This is for when another protocol is layered
+    /// on top, for example CalDAV with `calendar-access` and `MKCALENDAR`.
+    /// The requests for those methods still have to be handled by the
+    /// application.
+    pub fn options_extra(self, dav_classes: Vec<String>, methods: Vec<http::Method>) -> Self {
+        let mut this = self;
+        this.options_extra = Some((dav_classes, methods));
+        this
+    }
+
+    /// Limit the `Depth` of a `PROPFIND` request.
+    ///
+    /// A `PROPFIND` with a larger depth, or with `Depth: infinity` (which
+    /// is what no `Depth` header means), gets a `403 Forbidden` with the
+    /// `DAV:propfind-finite-depth` precondition, as described in RFC4918.
+    /// So `Some(1)` only allows a listing of one directory at a time.
+    /// The same goes for the initial sync of a `sync-collection` `REPORT`
+    /// with `sync-level` infinite.
+    ///
+    /// The default is `Some(1)`, since a `Depth: infinity` walk of a big
+    /// tree is expensive. `None` is no limit; that can also be set with
+    /// [`handle_with`](DavHandler::handle_with) to lift the limit for
+    /// some users. Even without a limit, a walk does not descend more than
+    /// 256 levels; deeper collections are reported with a `507`.
+    pub fn propfind_max_depth(self, max_depth: Option<u32>) -> Self {
+        let mut this = self;
+        this.propfind_max_depth = Some(max_depth);
+        this
+    }
+
+    /// How many directory entries a recursive `PROPFIND` or `COPY` works on
+    /// at the same time (default 1).
+    ///
+    /// On a backend with a high latency, like a remote filesystem, fetching
+    /// the metadata of several entries at once (and, for `COPY`, copying
+    /// several files at once) is a lot faster. The order of the entries in
+    /// the response stays the same.
+    pub fn fs_concurrency(self, n: usize) -> Self {
         let mut this = self;
-        this.indexfile = Some(indexfile.into());
+        this.fs_concurrency = Some(n);
+        this
+    }
+
+    /// Write an access log, one line per request in the Combined Log Format.
+    ///
+    /// That is the format of Apache and nginx, which log analyzers
+    /// understand. The user is the principal, and the host is the address
+    /// set with [`remote_addr`](Self::remote_addr), if any. A line is
+    /// written when the response body has been sent, so the size is the
+    /// number of bytes that was actually sent. The writer is not flushed,
+    /// so if it is buffered, it's up to the application to do that.
+    ///
+    /// ```no_run
+    /// use webdav_handler::{localfs::LocalFs, DavHandler};
+    ///
+    /// let log = std::fs::OpenOptions::new().append(true).create(true).open("access.log").unwrap();
+    /// let dav_server = DavHandler::builder()
+    ///     .filesystem(LocalFs::new("/tmp", false, false, false))
+    ///     .access_log(log)
+    ///     .build_handler();
+    /// ```
+    pub fn access_log<W>(self, writer: W) -> Self
+    where W: io::Write + Send + 'static {
+        let mut this = self;
+        this.access_log = Some(Arc::new(Mutex::new(Box::new(writer))));
+        this
+    }
+
+    /// Log XML request and response bodies at debug level.
+    ///
+    /// The bodies of `PROPFIND`, `PROPPATCH` and the other requests that
+    /// send XML, and of XML responses like a multistatus, are logged up
+    /// to `max_bytes`. `GET` and `PUT` bodies are not. A response is still
+    /// streamed, it is logged when it has been sent.
+    pub fn log_bodies(self, max_bytes: usize) -> Self {
+        let mut this = self;
+        this.log_bodies = Some(max_bytes);
         this
     }
 
     fn merge(&self, new: DavConfig) -> DavConfig {
         DavConfig {
-            prefix:        new.prefix.or(self.prefix.clone()),
-            fs:            new.fs.or(self.fs.clone()),
-            ls:            new.ls.or(self.ls.clone()),
-            allow:         new.allow.or(self.allow.clone()),
-            principal:     new.principal.or(self.principal.clone()),
-            hide_symlinks: new.hide_symlinks.or(self.hide_symlinks.clone()),
-            autoindex:     new.autoindex.or(self.autoindex.clone()),
-            utcoffset:     new.utcoffset,
-            indexfile:     new.indexfile.or(self.indexfile.clone()),
+            prefix:              new.prefix.or(self.prefix.clone()),
+            fs:                  new.fs.or(self.fs.clone()),
+            ls:                  new.ls.or(self.ls.clone()),
+            allow:               new.allow.or(self.allow.clone()),
+            principal:           new.principal.or(self.principal.clone()),
+            hide_symlinks:       new.hide_symlinks.or(self.hide_symlinks.clone()),
+            autoindex:           new.autoindex.or(self.autoindex.clone()),
+            utcoffset:           new.utcoffset,
+            autoindex_template:  new.autoindex_template.or(self.autoindex_template.clone()),
+            indexfiles:          new.indexfiles.or(self.indexfiles.clone()),
+            read_only:           new.read_only.or(self.read_only),
+            compression:         new.compression.or(self.compression),
+            compression_types:   new.compression_types.or(self.compression_types.clone()),
+            tus:                 new.tus.or(self.tus),
+            observer:            new.observer.or(self.observer.clone()),
+            mime_resolver:       new.mime_resolver.or(self.mime_resolver.clone()),
+            server_header:       new.server_header.or(self.server_header.clone()),
+            max_upload_size:     new.max_upload_size.or(self.max_upload_size),
+            report_handler:      new.report_handler.or(self.report_handler.clone()),
+            error_handler:       new.error_handler.or(self.error_handler.clone()),
+            atomic_put:          new.atomic_put.or(self.atomic_put),
+            authenticator:       new.authenticator.or(self.authenticator.clone()),
+            options_extra:       new.options_extra.or(self.options_extra.clone()),
+            propfind_max_depth:  new.propfind_max_depth.or(self.propfind_max_depth),
+            destination_map:     new.destination_map.or(self.destination_map.clone()),
+            cache_control:       new.cache_control.or(self.cache_control.clone()),
+            fs_op_timeout:       new.fs_op_timeout.or(self.fs_op_timeout),
+            expose_timestamps:   new.expose_timestamps.or(self.expose_timestamps),
+            collection_redirect: new.collection_redirect.or(self.collection_redirect),
+            property_provider:   new.property_provider.or(self.property_provider.clone()),
+            precompressed:       new.precompressed.or(self.precompressed),
+            log_bodies:          new.log_bodies.or(self.log_bodies),
+            fs_concurrency:      new.fs_concurrency.or(self.fs_concurrency),
+            href_rewrite:        new.href_rewrite.or(self.href_rewrite.clone()),
+            remote_copy:         new.remote_copy.or(self.remote_copy.clone()),
+            rate_limit:          new.rate_limit.or(self.rate_limit.clone()),
+            remote_addr:         new.remote_addr.or(self.remote_addr),
+            access_log:          new.access_log.or(self.access_log.clone()),
+            max_lock_timeout:    new.max_lock_timeout.or(self.max_lock_timeout),
+            content_disposition: new.content_disposition.or(self.content_disposition.clone()),
+            html_as_attachment:  new.html_as_attachment.or(self.html_as_attachment),
+            security_headers:    new.security_headers.or(self.security_headers),
+            hide:                new.hide.or(self.hide.clone()),
+            brotli_quality:      new.brotli_quality.or(self.brotli_quality),
+            error_map:           new.error_map.or(self.error_map.clone()),
+            negotiate_language:  new.negotiate_language.or(self.negotiate_language),
+            strict_destination:  new.strict_destination.or(self.strict_destination),
         }
     }
 }
@@ -152,29 +953,103 @@ impl DavConfig {
 // At the start of the request, DavConfig is used to generate
 // a DavInner struct. DavInner::handle then handles the request.
 pub(crate) struct DavInner {
-    pub prefix:        String,
-    pub fs:            Box<dyn DavFileSystem>,
-    pub ls:            Option<Box<dyn DavLockSystem>>,
-    pub allow:         Option<DavMethodSet>,
-    pub principal:     Option<String>,
-    pub hide_symlinks: Option<bool>,
-    pub autoindex:     Option<bool>,
-    pub utcoffset:     Option<UtcOffset>,
-    pub indexfile:     Option<String>,
+    pub prefix:              String,
+    pub fs:                  Box<dyn DavFileSystem>,
+    pub ls:                  Option<Box<dyn DavLockSystem>>,
+    pub allow:               Option<DavMethodSet>,
+    pub principal:           Option<String>,
+    pub hide_symlinks:       Option<bool>,
+    pub autoindex:           Option<bool>,
+    pub utcoffset:           Option<UtcOffset>,
+    pub autoindex_template:  Option<AutoIndexTemplate>,
+    pub indexfiles:          Option<Vec<String>>,
+    pub read_only:           bool,
+    pub compression:         bool,
+    pub compression_types:   Option<Vec<String>>,
+    pub tus:                 bool,
+    pub observer:            Option<Arc<dyn DavObserver>>,
+    pub mime_resolver:       Option<MimeResolver>,
+    pub server_header:       Option<String>,
+    pub max_upload_size:     Option<u64>,
+    pub report_handler:      Option<ReportHandler>,
+    pub error_handler:       Option<ErrorHandler>,
+    pub atomic_put:          bool,
+    pub authenticator:       Option<Authenticator>,
+    pub options_extra:       Option<(Vec<String>, Vec<http::Method>)>,
+    pub propfind_max_depth:  Option<u32>,
+    pub destination_map:     Option<DestinationMap>,
+    pub cache_control:       Option<CacheControl>,
+    pub fs_op_timeout:       Option<Duration>,
+    pub collection_redirect: bool,
+    pub property_provider:   Option<Arc<dyn PropertyProvider>>,
+    pub precompressed:       bool,
+    pub log_bodies:          Option<usize>,
+    pub fs_concurrency:      usize,
+    pub href_rewrite:        Option<HrefRewrite>,
+    pub remote_copy:         Option<RemoteCopy>,
+    pub rate_limit:          Option<Arc<RateLimiter>>,
+    pub remote_addr:         Option<SocketAddr>,
+    pub access_log:          Option<AccessLog>,
+    pub max_lock_timeout:    Option<Duration>,
+    pub content_disposition: Option<ContentDisposition>,
+    pub html_as_attachment:  bool,
+    pub security_headers:    bool,
+    pub brotli_quality:      u32,
+    pub error_map:           Option<ErrorMap>,
+    pub negotiate_language:  bool,
+    pub strict_destination:  bool,
 }
 
 impl From<DavConfig> for DavInner {
     fn from(cfg: DavConfig) -> Self {
+        let hide_timestamps = cfg.expose_timestamps == Some(false);
+        let fs = TimeoutFs::wrap(cfg.fs.unwrap_or(VoidFs::new()), cfg.fs_op_timeout);
         DavInner {
-            prefix:        cfg.prefix.unwrap_or("".to_string()),
-            fs:            cfg.fs.unwrap_or(VoidFs::new()),
-            ls:            cfg.ls,
-            allow:         cfg.allow,
-            principal:     cfg.principal,
-            hide_symlinks: cfg.hide_symlinks,
-            autoindex:     cfg.autoindex,
-            utcoffset:     cfg.utcoffset,
-            indexfile:     cfg.indexfile,
+            prefix:              cfg.prefix.unwrap_or("".to_string()),
+            fs:                  HideFs::wrap(NoTimestampsFs::wrap(fs, hide_timestamps), cfg.hide),
+            ls:                  cfg.ls,
+            allow:               cfg.allow,
+            principal:           cfg.principal,
+            hide_symlinks:       cfg.hide_symlinks,
+            autoindex:           cfg.autoindex,
+            utcoffset:           cfg.utcoffset,
+            autoindex_template:  cfg.autoindex_template,
+            indexfiles:          cfg.indexfiles,
+            read_only:           cfg.read_only.unwrap_or(false),
+            compression:         cfg.compression.unwrap_or(false),
+            compression_types:   cfg.compression_types,
+            tus:                 cfg.tus.unwrap_or(false),
+            observer:            cfg.observer,
+            mime_resolver:       cfg.mime_resolver,
+            server_header:       cfg.server_header,
+            max_upload_size:     cfg.max_upload_size,
+            report_handler:      cfg.report_handler,
+            error_handler:       cfg.error_handler,
+            atomic_put:          cfg.atomic_put.unwrap_or(false),
+            authenticator:       cfg.authenticator,
+            options_extra:       cfg.options_extra,
+            propfind_max_depth:  cfg.propfind_max_depth.unwrap_or(Some(1)),
+            destination_map:     cfg.destination_map,
+            cache_control:       cfg.cache_control,
+            fs_op_timeout:       cfg.fs_op_timeout,
+            collection_redirect: cfg.collection_redirect.unwrap_or(true),
+            property_provider:   cfg.property_provider,
+            precompressed:       cfg.precompressed.unwrap_or(false),
+            log_bodies:          cfg.log_bodies,
+            fs_concurrency:      cfg.fs_concurrency.unwrap_or(1).max(1),
+            href_rewrite:        cfg.href_rewrite,
+            remote_copy:         cfg.remote_copy,
+            rate_limit:          cfg.rate_limit,
+            remote_addr:         cfg.remote_addr,
+            access_log:          cfg.access_log,
+            max_lock_timeout:    cfg.max_lock_timeout,
+            content_disposition: cfg.content_disposition,
+            html_as_attachment:  cfg.html_as_attachment.unwrap_or(false),
+            security_headers:    cfg.security_headers.unwrap_or(false),
+            brotli_quality:      cfg.brotli_quality.unwrap_or(compression::DEFAULT_BROTLI_QUALITY),
+            error_map:           cfg.error_map,
+            negotiate_language:  cfg.negotiate_language.unwrap_or(false),
+            strict_destination:  cfg.strict_destination.unwrap_or(false),
         }
     }
 }
@@ -182,19 +1057,61 @@ impl From<DavConfig> for DavInner {
 impl From<&DavConfig> for DavInner {
     fn from(cfg: &DavConfig) -> Self {
         DavInner {
-            prefix:        cfg
+            prefix:              cfg
                 .prefix
                 .as_ref()
                 .map(|p| p.to_owned())
                 .unwrap_or("".to_string()),
-            fs:            cfg.fs.clone().unwrap(),
-            ls:            cfg.ls.clone(),
-            allow:         cfg.allow,
-            principal:     cfg.principal.clone(),
-            hide_symlinks: cfg.hide_symlinks.clone(),
-            autoindex:     cfg.autoindex.clone(),
-            utcoffset:     cfg.utcoffset,
-            indexfile:     cfg.indexfile.clone(),
+            fs:                  HideFs::wrap(
+                NoTimestampsFs::wrap(
+                    TimeoutFs::wrap(cfg.fs.clone().unwrap(), cfg.fs_op_timeout),
+                    cfg.expose_timestamps == Some(false),
+                ),
+                cfg.hide.clone(),
+            ),
+            ls:                  cfg.ls.clone(),
+            allow:               cfg.allow,
+            principal:           cfg.principal.clone(),
+            hide_symlinks:       cfg.hide_symlinks.clone(),
+            autoindex:           cfg.autoindex.clone(),
+            utcoffset:           cfg.utcoffset,
+            autoindex_template:  cfg.autoindex_template.clone(),
+            indexfiles:          cfg.indexfiles.clone(),
+            read_only:           cfg.read_only.unwrap_or(false),
+            compression:         cfg.compression.unwrap_or(false),
+            compression_types:   cfg.compression_types.clone(),
+            tus:                 cfg.tus.unwrap_or(false),
+            observer:            cfg.observer.clone(),
+            mime_resolver:       cfg.mime_resolver.clone(),
+            server_header:       cfg.server_header.clone(),
+            max_upload_size:     cfg.max_upload_size,
+            report_handler:      cfg.report_handler.clone(),
+            error_handler:       cfg.error_handler.clone(),
+            atomic_put:          cfg.atomic_put.unwrap_or(false),
+            authenticator:       cfg.authenticator.clone(),
+            options_extra:       cfg.options_extra.clone(),
+            propfind_max_depth:  cfg.propfind_max_depth.unwrap_or(Some(1)),
+            destination_map:     cfg.destination_map.clone(),
+            cache_control:       cfg.cache_control.clone(),
+            fs_op_timeout:       cfg.fs_op_timeout,
+            collection_redirect: cfg.collection_redirect.unwrap_or(true),
+            property_provider:   cfg.property_provider.clone(),
+            precompressed:       cfg.precompressed.unwrap_or(false),
+            log_bodies:          cfg.log_bodies,
+            fs_concurrency:      cfg.fs_concurrency.unwrap_or(1).max(1),
+            href_rewrite:        cfg.href_rewrite.clone(),
+            remote_copy:         cfg.remote_copy.clone(),
+            rate_limit:          cfg.rate_limit.clone(),
+            remote_addr:         cfg.remote_addr,
+            access_log:          cfg.access_log.clone(),
+            max_lock_timeout:    cfg.max_lock_timeout,
+            content_disposition: cfg.content_disposition.clone(),
+            html_as_attachment:  cfg.html_as_attachment.unwrap_or(false),
+            security_headers:    cfg.security_headers.unwrap_or(false),
+            brotli_quality:      cfg.brotli_quality.unwrap_or(compression::DEFAULT_BROTLI_QUALITY),
+            error_map:           cfg.error_map.clone(),
+            negotiate_language:  cfg.negotiate_language.unwrap_or(false),
+            strict_destination:  cfg.strict_destination.unwrap_or(false),
         }
     }
 }
@@ -202,15 +1119,51 @@ impl From<&DavConfig> for DavInner {
 impl Clone for DavInner {
     fn clone(&self) -> Self {
         DavInner {
-            prefix:        self.prefix.clone(),
-            fs:            self.fs.clone(),
-            ls:            self.ls.clone(),
-            allow:         self.allow.clone(),
-            principal:     self.principal.clone(),
-            hide_symlinks: self.hide_symlinks.clone(),
-            autoindex:     self.autoindex.clone(),
-            utcoffset:     self.utcoffset,
-            indexfile:     self.indexfile.clone(),
+            prefix:              self.prefix.clone(),
+            fs:                  self.fs.clone(),
+            ls:                  self.ls.clone(),
+            allow:               self.allow.clone(),
+            principal:           self.principal.clone(),
+            hide_symlinks:       self.hide_symlinks.clone(),
+            autoindex:           self.autoindex.clone(),
+            utcoffset:           self.utcoffset,
+            autoindex_template:  self.autoindex_template.clone(),
+            indexfiles:          self.indexfiles.clone(),
+            read_only:           self.read_only,
+            compression:         self.compression,
+            compression_types:   self.compression_types.clone(),
+            tus:                 self.tus,
+            observer:            self.observer.clone(),
+            mime_resolver:       self.mime_resolver.clone(),
+            server_header:       self.server_header.clone(),
+            max_upload_size:     self.max_upload_size,
+            report_handler:      self.report_handler.clone(),
+            error_handler:       self.error_handler.clone(),
+            atomic_put:          self.atomic_put,
+            authenticator:       self.authenticator.clone(),
+            options_extra:       self.options_extra.clone(),
+            propfind_max_depth:  self.propfind_max_depth,
+            destination_map:     self.destination_map.clone(),
+            cache_control:       self.cache_control.clone(),
+            fs_op_timeout:       self.fs_op_timeout,
+            collection_redirect: self.collection_redirect,
+            property_provider:   self.property_provider.clone(),
+            precompressed:       self.precompressed,
+            log_bodies:          self.log_bodies,
+            fs_concurrency:      self.fs_concurrency,
+            href_rewrite:        self.href_rewrite.clone(),
+            remote_copy:         self.remote_copy.clone(),
+            rate_limit:          self.rate_limit.clone(),
+            remote_addr:         self.remote_addr,
+            access_log:          self.access_log.clone(),
+            max_lock_timeout:    self.max_lock_timeout,
+            content_disposition: self.content_disposition.clone(),
+            html_as_attachment:  self.html_as_attachment,
+            security_headers:    self.security_headers,
+            brotli_quality:      self.brotli_quality,
+            error_map:           self.error_map.clone(),
+            negotiate_language:  self.negotiate_language,
+            strict_destination:  self.strict_destination,
         }
     }
 }
@@ -244,6 +1197,106 @@ impl DavHandler {
         inner.handle(req).await
     }
 
+    /// Handle a webdav request, and return an error instead of an error response.
+    ///
+    /// This is like `handle`, but if the request fails, you get a
+    /// [`HandlerError`] that says why, so that you can send your own
+    /// response. `HandlerError::status()` is the status that `handle`
+    /// would have sent. The [`error_handler`](DavConfig::error_handler)
+    /// is not used.
+    ///
+    /// Responses that are not an error, such as a `207 Multi-Status`
+    /// that reports failures of some of the resources of a `COPY`,
+    /// or the `401` from an authenticator challenge, are returned as `Ok`.
+    pub async fn handle_result<ReqBody, ReqData, ReqError>(
+        &self,
+        req: Request<ReqBody>,
+    ) -> Result<Response<Body>, HandlerError>
+    where
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+    {
+        let inner = DavInner::from(&*self.config);
+        match inner.dispatch(req, true).await {
+            (_, Some(error)) => Err(error),
+            (resp, None) => Ok(resp),
+        }
+    }
+
+    /// The active locks on the resource of a request.
+    ///
+    /// These are the locks in the configured locksystem that cover the
+    /// path of the request (after the prefix has been stripped). Each
+    /// lock has its token, owner, scope (`shared`), depth (`deep`) and
+    /// timeout. Together with
+    /// [`submitted_lock_tokens`](DavHandler::submitted_lock_tokens) this
+    /// can be used to check that a client holds a lock, before the
+    /// request is passed to `handle`.
+    pub fn active_locks<B>(&self, req: &Request<B>) -> Vec<DavLock> {
+        let ls = match self.config.ls {
+            Some(ref ls) => ls,
+            None => return Vec::new(),
+        };
+        let prefix = self.config.prefix.as_deref().unwrap_or("");
+        match DavPath::from_uri_and_prefix(req.uri(), prefix) {
+            Ok(path) => ls.discover(&path),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// The lock tokens that a request submits.
+    ///
+    /// These are the state tokens in the `If` header, and the token
+    /// in the `Lock-Token` header (as sent with `UNLOCK`).
+    pub fn submitted_lock_tokens<B>(&self, req: &Request<B>) -> Vec<String> {
+        let mut tokens = Vec::new();
+        if let Some(If(lists)) = req.headers().typed_get::<If>() {
+            for cond in lists.into_iter().flat_map(|l| l.conditions.into_iter()) {
+                if let IfItem::StateToken(token) = cond.item {
+                    if !cond.not && !tokens.contains(&token) {
+                        tokens.push(token);
+                    }
+                }
+            }
+        }
+        if let Some(davheaders::LockToken(t)) = req.headers().typed_get::<davheaders::LockToken>() {
+            let t = t.trim_matches(|c| c == '<' || c == '>').to_string();
+            if !tokens.contains(&t) {
+                tokens.push(t);
+            }
+        }
+        tokens
+    }
+
+    /// Handle a webdav request that has a body that is already in memory.
+    ///
+    /// This saves having to wrap the body in some `http_body::Body`
+    /// type, which is useful in tests, or when the complete body has
+    /// been read already.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> std::io::Result<()> {
+    /// use webdav_handler::{memfs::MemFs, DavHandler};
+    ///
+    /// let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+    /// let req = http::Request::builder().method("PUT").uri("/hello.txt");
+    /// let resp = dav.handle_bytes(req.body("hello").unwrap()).await;
+    /// assert_eq!(resp.status(), http::StatusCode::CREATED);
+    ///
+    /// let req = http::Request::builder().uri("/hello.txt");
+    /// let resp = dav.handle_bytes(req.body("").unwrap()).await;
+    /// assert_eq!(resp.into_body().into_bytes().await?, "hello");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn handle_bytes<B>(&self, req: Request<B>) -> Response<Body>
+    where B: Into<bytes::Bytes> {
+        let (parts, body) = req.into_parts();
+        self.handle(Request::from_parts(parts, Body::from(body.into()))).await
+    }
+
     /// Handle a webdav request, overriding parts of the config.
     ///
     /// For example, the `principal` can be set for this request.
@@ -307,8 +1360,7 @@ impl DavHandler {
 impl DavInner {
     // helper.
     pub(crate) async fn has_parent<'a>(&'a self, path: &'a DavPath) -> bool {
-        let p = path.parent();
-        self.fs.metadata(&p).await.map(|m| m.is_dir()).unwrap_or(false)
+        self.fs.exists(&path.parent()).await.unwrap_or(false)
     }
 
     // helper.
@@ -328,7 +1380,7 @@ impl DavInner {
     {
         if meta.is_dir() && !path.is_collection() {
             path.add_slash();
-            let newloc = path.with_prefix().as_url_string();
+            let newloc = href(self.href_rewrite.as_ref(), path);
             res.headers_mut()
                 .typed_insert(davheaders::ContentLocation(newloc));
         }
@@ -367,6 +1419,22 @@ impl DavInner {
 
     // internal dispatcher.
     async fn handle<ReqBody, ReqData, ReqError>(self, req: Request<ReqBody>) -> Response<Body>
+    where
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+    {
+        self.dispatch(req, false).await.0
+    }
+
+    // Handle the request. Errors are turned into a response. If `typed_error`
+    // is set, they are also returned as a HandlerError (and the error handler is
+    // not called). The observer sees the response in both cases.
+    async fn dispatch<ReqBody, ReqData, ReqError>(
+        mut self,
+        req: Request<ReqBody>,
+        typed_error: bool,
+    ) -> (Response<Body>, Option<HandlerError>)
     where
         ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
         ReqData: Buf + Send + 'static,
@@ -379,8 +1447,61 @@ impl DavInner {
             .map(|s| s.contains("Microsoft"))
             .unwrap_or(false);
 
+        let observer = self.observer.clone();
+        let server = self.server_header.as_ref().and_then(|s| http::HeaderValue::from_str(s).ok());
+        let method = req.method().clone();
+        let start = Instant::now();
+        let error_handler = self.error_handler.clone().filter(|_| !typed_error);
+        let error_map = self.error_map.clone();
+        let log_bodies = self.log_bodies;
+        let security_headers = self.security_headers;
+        let error_path = match error_handler {
+            Some(_) if method == http::Method::GET || method == http::Method::HEAD => {
+                DavPath::from_uri_and_prefix(req.uri(), &self.prefix).ok()
+            },
+            _ => None,
+        };
+        let (mut req, body) = {
+            let (parts, body) = req.into_parts();
+            (Request::from_parts(parts, ()), body)
+        };
+
+        // the address of the client, from the config or from the adapter.
+        if self.remote_addr.is_none() {
+            self.remote_addr = req.extensions().get::<SocketAddr>().copied();
+        }
+        if let Some(addr) = self.remote_addr {
+            req.extensions_mut().insert(addr);
+        }
+
+        if let Some(ref observer) = observer {
+            match self.remote_addr {
+                Some(addr) => observer.on_request_from(&method, req.uri().path(), addr),
+                None => observer.on_request(&method, req.uri().path()),
+            }
+        }
+        let access_log = self.access_log.clone().map(|log| AccessLogEntry::new(log, &req, self.remote_addr));
+        let challenge = self.authenticate(&req);
+        let challenge = match self.rate_limited() {
+            Some(resp) => Some(resp),
+            None => challenge,
+        };
+        let principal = self.principal.clone();
+
+        #[cfg(feature = "tracing")]
+        let span = crate::instrument::request_span(&req, self.principal.as_deref());
+        let fut = async move {
+            match challenge {
+                Some(resp) => resp,
+                None => self.handle2(req, body).await,
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let fut = tracing::Instrument::instrument(fut, span.clone());
+
         // Turn any DavError results into a HTTP error response.
-        match self.handle2(req).await {
+        let mut error = None;
+        let mut resp = match fut.await {
             Ok(resp) => {
                 debug!("== END REQUEST result OK");
                 resp
@@ -388,7 +1509,8 @@ impl DavInner {
             Err(err) => {
                 debug!("== END REQUEST result {:?}", err);
                 let mut resp = Response::builder();
-                if is_ms && err.statuscode() == StatusCode::NOT_FOUND {
+                let status = err.mapped_statuscode(error_map.as_ref());
+                if is_ms && status == StatusCode::NOT_FOUND {
                     // This is an attempt to convince Windows to not
                     // cache a 404 NOT_FOUND for 30-60 seconds.
                     //
@@ -404,26 +1526,154 @@ impl DavInner {
                         .header("Expires", "0")
                         .header("Vary", "*");
                 }
-                resp = resp.header("Content-Length", "0").status(err.statuscode());
+                resp = resp.header("Content-Length", "0").status(status);
                 if err.must_close() {
                     resp = resp.header("connection", "close");
                 }
+                if typed_error {
+                    error = Some(HandlerError::new(status, err.must_close()));
+                }
                 resp.body(Body::empty()).unwrap()
             },
+        };
+
+        // see if there is a custom response for this error.
+        if let (Some(handler), Some(path)) = (error_handler, error_path) {
+            let status = resp.status();
+            if status.is_client_error() || status.is_server_error() {
+                if let Some(mut custom) = handler(status, &path) {
+                    // keep what the client needs to know about the error.
+                    use http::header;
+                    let keep = [
+                        header::CONNECTION,
+                        header::WWW_AUTHENTICATE,
+                        header::CONTENT_RANGE,
+                        header::ALLOW,
+                        header::RETRY_AFTER,
+                        header::LOCATION,
+                    ];
+                    for name in &keep {
+                        for value in resp.headers().get_all(name) {
+                            custom.headers_mut().append(name, value.clone());
+                        }
+                    }
+                    if method == http::Method::HEAD {
+                        *custom.body_mut() = Body::empty();
+                    }
+                    resp = custom;
+                }
+            }
+        }
+
+        if let Some(server) = server {
+            resp.headers_mut().insert(http::header::SERVER, server);
+        }
+
+        // the response might be a file that a user uploaded.
+        if security_headers && (method == http::Method::GET || method == http::Method::HEAD) {
+            use http::{header, HeaderValue};
+            let headers = resp.headers_mut();
+            let security = [
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+                (header::CONTENT_SECURITY_POLICY, "sandbox"),
+                (header::X_FRAME_OPTIONS, "DENY"),
+            ];
+            for (name, value) in security {
+                headers.entry(name).or_insert(HeaderValue::from_static(value));
+            }
+        }
+
+        // log XML responses, if asked for.
+        if let Some(max) = log_bodies.filter(|_| log_enabled!(log::Level::Debug)) {
+            let xml = resp
+                .headers()
+                .typed_get::<davheaders::ContentType>()
+                .is_some_and(|ct| ct.0.contains("xml"));
+            if xml && method != http::Method::GET && method != http::Method::HEAD {
+                let (parts, body) = resp.into_parts();
+                let what = format!("response body {} {}", method, parts.status.as_u16());
+                resp = Response::from_parts(parts, body.logged(what, max));
+            }
         }
+
+        if let Some(entry) = access_log {
+            resp = entry.log_response(principal, resp);
+        }
+
+        #[cfg(feature = "tracing")]
+        crate::instrument::record_response(&span, &resp);
+        if let Some(ref observer) = observer {
+            let len = resp.headers().typed_get::<headers::ContentLength>().map(|l| l.0);
+            observer.on_response(&method, resp.status(), start.elapsed(), len);
+        }
+        (resp, error)
+    }
+
+    // Rate limiting, per principal, or per address if there is none. This
+    // runs after authentication, and a failed login has no principal, so
+    // those count against the address. Without either, there is no limit.
+    fn rate_limited(&self) -> Option<DavResult<Response<Body>>> {
+        let limiter = self.rate_limit.as_ref()?;
+        let key = match (&self.principal, self.remote_addr) {
+            (Some(principal), _) => format!("p:{}", principal),
+            (None, Some(addr)) => format!("a:{}", addr.ip()),
+            (None, None) => return None,
+        };
+        let wait = limiter.check(&key).err()?;
+        debug!("rate limit exceeded for {:?}", key);
+        let retry_after = wait.as_secs() + (wait.subsec_nanos() > 0) as u64;
+        let resp = Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(http::header::RETRY_AFTER, retry_after)
+            .header("Content-Length", "0")
+            .body(Body::empty())
+            .unwrap();
+        Some(Ok(resp))
+    }
+
+    // Who is this. Returns the response if the client has to authenticate first.
+    fn authenticate(&mut self, req: &Request<()>) -> Option<DavResult<Response<Body>>> {
+        let auth = self.authenticator.clone()?;
+        match auth(req) {
+            AuthResult::Ok(principal) => self.principal = Some(principal),
+            AuthResult::Anonymous => {},
+            AuthResult::Challenge(challenge) => {
+                debug!("authentication required for {} {}", req.method(), req.uri());
+                let challenge = match http::HeaderValue::from_str(&challenge) {
+                    Ok(challenge) => challenge,
+                    Err(_) => return Some(Err(DavError::Status(StatusCode::INTERNAL_SERVER_ERROR))),
+                };
+                let resp = Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(http::header::WWW_AUTHENTICATE, challenge)
+                    .header("Content-Length", "0")
+                    .body(Body::empty())
+                    .unwrap();
+                return Some(Ok(resp));
+            },
+        }
+        None
     }
 
     // internal dispatcher part 2.
-    async fn handle2<ReqBody, ReqData, ReqError>(mut self, req: Request<ReqBody>) -> DavResult<Response<Body>>
+    async fn handle2<ReqBody, ReqData, ReqError>(
+        mut self,
+        req: Request<()>,
+        body: ReqBody,
+    ) -> DavResult<Response<Body>>
     where
         ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
         ReqData: Buf + Send + 'static,
         ReqError: StdError + Send + Sync + 'static,
     {
-        let (req, body) = {
-            let (parts, body) = req.into_parts();
-            (Request::from_parts(parts, ()), body)
-        };
+        // "100-continue" is the only expectation there is (RFC7231 5.1.1).
+        // The interim response is sent by the server, see handle_put().
+        if let Some(expect) = req.headers().get(http::header::EXPECT) {
+            if !expect.as_bytes().eq_ignore_ascii_case(b"100-continue") {
+                debug!("unknown expectation {:?}", expect);
+                return Err(DavError::StatusClose(StatusCode::EXPECTATION_FAILED));
+            }
+        }
 
         // debug when running the webdav litmus tests.
         if log_enabled!(log::Level::Debug) {
@@ -432,8 +1682,11 @@ impl DavInner {
             }
         }
 
-        // translate HTTP method to Webdav method.
+        // translate HTTP method to Webdav method. A TUS upload is a
+        // partial PUT, really.
+        let is_tus = self.tus && is_tus_request(&req);
         let method = match dav_method(req.method()) {
+            _ if is_tus && req.method() != http::Method::HEAD => DavMethod::Patch,
             Ok(m) => m,
             Err(e) => {
                 debug!("refusing method {} request {}", req.method(), req.uri());
@@ -471,15 +1724,31 @@ impl DavInner {
             }
         }
 
+        // refuse anything that would modify the filesystem or locks.
+        if self.read_only && !DavMethodSet::WEBDAV_RO.contains(method) {
+            debug!("method {} refused on read-only {}", req.method(), req.uri());
+            return Err(DavError::StatusClose(StatusCode::FORBIDDEN));
+        }
+
         // make sure the request path is valid.
         let path = DavPath::from_uri_and_prefix(req.uri(), &self.prefix)?;
 
+        if is_tus {
+            debug!("== START REQUEST TUS {} {}", req.method(), path);
+            return self.handle_tus(&req, body).await;
+        }
+
         // PUT is the only handler that reads the body itself. All the
         // other handlers either expected no body, or a pre-read Vec<u8>.
         let (body_strm, body_data) = match method {
             DavMethod::Put | DavMethod::Patch => (Some(body), Vec::new()),
             _ => (None, self.read_request(body, 65536).await?),
         };
+        let log_request = !body_data.is_empty() && log_enabled!(log::Level::Debug);
+        if let Some(max) = self.log_bodies.filter(|_| log_request) {
+            let data = &body_data[..std::cmp::min(max, body_data.len())];
+            crate::body::log_body(&format!("request body {}", req.method()), data, body_data.len());
+        }
 
         // Not all methods accept a body.
         match method {
@@ -487,7 +1756,11 @@ impl DavInner {
             DavMethod::Patch |
             DavMethod::PropFind |
             DavMethod::PropPatch |
-            DavMethod::Lock => {},
+            DavMethod::Report |
+            DavMethod::Lock |
+            DavMethod::Bind |
+            DavMethod::Unbind |
+            DavMethod::Rebind => {},
             _ => {
                 if body_data.len() > 0 {
                     return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE.into());
@@ -508,7 +1781,511 @@ impl DavInner {
             DavMethod::Head | DavMethod::Get => self.handle_get(&req).await,
             DavMethod::Copy | DavMethod::Move => self.handle_copymove(&req, method).await,
             DavMethod::Put | DavMethod::Patch => self.handle_put(&req, body_strm.unwrap()).await,
+            DavMethod::Report => self.handle_report(&req, &body_data).await,
+            DavMethod::Bind | DavMethod::Unbind | DavMethod::Rebind => {
+                self.handle_bind(&req, &body_data, method).await
+            },
         };
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::HandlerErrorKind;
+    use crate::memfs::MemFs;
+
+    fn req(method: &str, path: &str, body: &'static str) -> Request<hyper::Body> {
+        Request::builder().method(method).uri(path).body(hyper::Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn security_headers() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .security_headers(true)
+            .build_handler();
+        dav.handle(req("PUT", "/file.html", "<script>alert(1)</script>")).await;
+        let resp = dav.handle(req("GET", "/file.html", "")).await;
+        assert_eq!(resp.headers()["x-content-type-options"], "nosniff");
+        assert_eq!(resp.headers()["content-security-policy"], "sandbox");
+        assert_eq!(resp.headers()["x-frame-options"], "DENY");
+        let resp = dav.handle(req("PROPFIND", "/file.html", "")).await;
+        assert!(resp.headers().get("content-security-policy").is_none());
+    }
+
+    #[tokio::test]
+    async fn server_header() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .server_header(Some("dav/1.0".to_string()))
+            .build_handler();
+        dav.handle(req("PUT", "/file", "hello")).await;
+        for (method, path, status) in [
+            ("GET", "/file", StatusCode::OK),
+            ("PROPFIND", "/file", StatusCode::MULTI_STATUS),
+            ("OPTIONS", "/", StatusCode::OK),
+            ("GET", "/missing", StatusCode::NOT_FOUND),
+        ] {
+            let resp = dav.handle(req(method, path, "")).await;
+            assert_eq!(resp.status(), status, "{}", method);
+            assert_eq!(resp.headers()["server"], "dav/1.0", "{} {}", method, path);
+        }
+
+        // the default is to leave it out.
+        let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+        let resp = dav.handle(req("OPTIONS", "/", "")).await;
+        assert!(resp.headers().get("server").is_none());
+        let config = DavConfig::new().server_header(Some("other".to_string()));
+        let resp = dav.handle_with(config, req("OPTIONS", "/", "")).await;
+        assert_eq!(resp.headers()["server"], "other");
+    }
+
+    #[tokio::test]
+    async fn read_only() {
+        let fs = MemFs::new();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .locksystem(crate::memls::MemLs::new())
+            .build_handler();
+        dav.handle(req("PUT", "/file", "hello")).await;
+        let ro = || DavConfig::new().read_only(true);
+
+        for method in ["PUT", "DELETE", "MKCOL", "MOVE", "COPY", "PROPPATCH", "LOCK", "UNLOCK", "PATCH"] {
+            let mut r = req(method, "/file", "");
+            r.headers_mut().insert("destination", "/other".parse().unwrap());
+            let resp = dav.handle_with(ro(), r).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{}", method);
+        }
+        // nothing was changed.
+        assert!(fs.exists(&DavPath::new("/file").unwrap()).await.unwrap());
+        assert!(!fs.exists(&DavPath::new("/other").unwrap()).await.unwrap());
+        assert_eq!(dav.handle_with(ro(), req("GET", "/file", "")).await.status(), StatusCode::OK);
+        let resp = dav.handle_with(ro(), req("PROPFIND", "/file", "")).await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+
+        // and OPTIONS says so.
+        let resp = dav.handle_with(ro(), req("OPTIONS", "/", "")).await;
+        assert_eq!(resp.headers()["dav"], "1,3");
+        let allow = resp.headers()["allow"].to_str().unwrap();
+        for method in ["PUT", "DELETE", "MKCOL", "MOVE", "COPY", "PROPPATCH", "LOCK", "UNLOCK", "PATCH"] {
+            assert!(!allow.split(',').any(|m| m == method), "{}", allow);
+        }
+        assert!(allow.contains("PROPFIND"), "{}", allow);
+    }
+
+    #[tokio::test]
+    async fn handle_result() {
+        let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+
+        let res = dav.handle_result(req("GET", "/missing.txt", "")).await;
+        let err = res.err().unwrap();
+        assert_eq!(err.kind(), HandlerErrorKind::NotFound);
+        assert!(!err.must_close());
+        let res = dav.handle_result(req("MKCOL", "/a/b/", "")).await;
+        assert_eq!(res.err().unwrap().kind(), HandlerErrorKind::Conflict);
+
+        // after an unknown expectation the connection must be closed.
+        let expect = || {
+            let mut r = req("PUT", "/file.txt", "hello");
+            r.headers_mut().insert("expect", "other".parse().unwrap());
+            r
+        };
+        let err = dav.handle_result(expect()).await.err().unwrap();
+        assert_eq!(err.status(), StatusCode::EXPECTATION_FAILED);
+        assert!(err.must_close());
+        assert_eq!(dav.handle(expect()).await.headers()["connection"], "close");
+
+        let res = dav.handle_result(req("PUT", "/file.txt", "hello")).await;
+        assert_eq!(res.unwrap().status(), StatusCode::CREATED);
+        let res = dav.handle_result(req("GET", "/file.txt", "")).await;
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+
+        // the plain handler still sends the error response.
+        let resp = dav.handle(req("GET", "/missing.txt", "")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn active_locks() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(crate::memls::MemLs::new())
+            .strip_prefix("/dav")
+            .build_handler();
+        let lockinfo = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:lockinfo xmlns:D="DAV:">
+              <D:lockscope><D:shared/></D:lockscope>
+              <D:locktype><D:write/></D:locktype>
+              <D:owner>me</D:owner>
+            </D:lockinfo>"#;
+        let resp = dav.handle(req("LOCK", "/dav/file.txt", lockinfo)).await;
+        let token = resp.headers()["lock-token"].to_str().unwrap().to_string();
+
+        let put = req("PUT", "/dav/file.txt", "hello");
+        let locks = dav.active_locks(&put);
+        assert_eq!(locks.len(), 1);
+        assert!(locks[0].shared);
+        assert_eq!(format!("<{}>", locks[0].token), token);
+        assert_eq!(locks[0].owner.as_ref().unwrap().get_text().unwrap(), "me");
+        assert!(dav.submitted_lock_tokens(&put).is_empty());
+
+        let mut put = req("PUT", "/dav/file.txt", "hello");
+        let hdr = format!("(<urn:uuid:fake>) (Not {})", token);
+        put.headers_mut().insert("if", hdr.parse().unwrap());
+        assert_eq!(dav.submitted_lock_tokens(&put), vec!["urn:uuid:fake".to_string()]);
+        put.headers_mut().insert("if", format!("({})", token).parse().unwrap());
+        assert_eq!(dav.submitted_lock_tokens(&put), vec![locks[0].token.clone()]);
+    }
+
+    thread_local! {
+        static CAPTURED: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+    }
+
+    // Keeps the log messages of the threads that asked for it.
+    struct CaptureLogger;
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED.with(|c| {
+                if let Some(lines) = c.borrow_mut().as_mut() {
+                    lines.push(record.args().to_string());
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[tokio::test]
+    async fn log_bodies() {
+        static LOGGER: CaptureLogger = CaptureLogger;
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+        CAPTURED.with(|c| *c.borrow_mut() = Some(Vec::new()));
+
+        let dav = DavHandler::builder().filesystem(MemFs::new()).log_bodies(10).build_handler();
+        dav.handle(req("PUT", "/file.txt", "hello")).await;
+        let resp = dav.handle(req("GET", "/file.txt", "")).await;
+        resp.into_body().into_bytes().await.unwrap();
+
+        // the response is passed through as a whole.
+        let propfind = r#"<D:propfind xmlns:D="DAV:"><D:prop><D:getcontentlength/></D:prop></D:propfind>"#;
+        let resp = dav.handle(req("PROPFIND", "/file.txt", propfind)).await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        let body = resp.into_body().into_bytes().await.unwrap();
+        let len = body.len();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.starts_with("<?xml") && body.ends_with("</D:multistatus>"));
+        assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+
+        // but only the first 10 bytes are logged, and PUT and GET not at all.
+        let lines = CAPTURED.with(|c| c.borrow_mut().take().unwrap());
+        let bodies: Vec<_> = lines.into_iter().filter(|l| l.contains(" body ")).collect();
+        let request = format!("request body PROPFIND ({} bytes): <D:propfin ...", propfind.len());
+        let response = format!("response body PROPFIND 207 ({} bytes): <?xml vers ...", len);
+        assert_eq!(bodies, vec![request, response]);
+    }
+
+    #[tokio::test]
+    async fn has_parent() {
+        let fs = MemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        dav.handle(req("PUT", "/file.txt", "hello")).await;
+        assert!(fs.exists(&DavPath::new("/file.txt").unwrap()).await.unwrap());
+        assert!(!fs.exists(&DavPath::new("/file.txt/").unwrap()).await.unwrap());
+        assert!(!fs.exists(&DavPath::new("/missing").unwrap()).await.unwrap());
+
+        // the parent must exist and be a collection.
+        let resp = dav.handle(req("MKCOL", "/file.txt/dir", "")).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let resp = dav.handle(req("MKCOL", "/dir", "")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = dav.handle(req("MKCOL", "/dir/sub", "")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn rate_limit() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .rate_limit(0.01, 2)
+            .build_handler();
+        let from = |addr: &str| {
+            let mut req = req("OPTIONS", "/", "");
+            req.extensions_mut().insert(addr.parse::<std::net::SocketAddr>().unwrap());
+            req
+        };
+
+        assert_eq!(dav.handle(from("10.0.0.1:1000")).await.status(), StatusCode::OK);
+        assert_eq!(dav.handle(from("10.0.0.1:1001")).await.status(), StatusCode::OK);
+        let resp = dav.handle(from("10.0.0.1:1002")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()["retry-after"], "100");
+        assert_eq!(dav.handle(from("10.0.0.2:1000")).await.status(), StatusCode::OK);
+
+        // the principal counts, not the address.
+        let config = || DavConfig::new().principal("user");
+        let resp = dav.handle_with(config(), from("10.0.0.1:1003")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(dav.handle_with(config(), req("OPTIONS", "/", "")).await.status(), StatusCode::OK);
+        let resp = dav.handle_with(config(), from("10.0.0.4:1000")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let config = DavConfig::new().principal("other");
+        assert_eq!(dav.handle_with(config, from("10.0.0.4:1001")).await.status(), StatusCode::OK);
+        // and without either, there is no limit.
+        for _ in 0..3 {
+            assert_eq!(dav.handle(req("OPTIONS", "/", "")).await.status(), StatusCode::OK);
+        }
+
+        // failed logins are limited as well.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .rate_limit(0.01, 2)
+            .authenticator(|req| match req.headers().get("authorization") {
+                Some(_) => AuthResult::Ok("user".to_string()),
+                None => AuthResult::Challenge("Basic".to_string()),
+            })
+            .build_handler();
+        assert_eq!(dav.handle(from("10.0.0.3:1000")).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(dav.handle(from("10.0.0.3:1001")).await.status(), StatusCode::UNAUTHORIZED);
+        let resp = dav.handle(from("10.0.0.3:1002")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // but a user that logs in from there has a bucket of its own.
+        let mut login = from("10.0.0.3:1003");
+        login.headers_mut().insert("authorization", "Basic dXNlcjo=".parse().unwrap());
+        assert_eq!(dav.handle(login).await.status(), StatusCode::OK);
+    }
+
+    #[derive(Default)]
+    struct AddrObserver(Mutex<Vec<Option<SocketAddr>>>);
+
+    impl DavObserver for AddrObserver {
+        fn on_request(&self, _method: &http::Method, _path: &str) {
+            self.0.lock().unwrap().push(None);
+        }
+
+        fn on_request_from(&self, _method: &http::Method, _path: &str, addr: SocketAddr) {
+            self.0.lock().unwrap().push(Some(addr));
+        }
+    }
+
+    #[tokio::test]
+    async fn remote_addr() {
+        let observer = Arc::new(AddrObserver::default());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let auth_seen = seen.clone();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .observer(observer.clone())
+            .rate_limit(0.01, 1)
+            .authenticator(move |req| {
+                auth_seen.lock().unwrap().push(req.extensions().get::<SocketAddr>().copied());
+                AuthResult::Anonymous
+            })
+            .build_handler();
+        let a1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let a2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+        let from = |addr: SocketAddr| {
+            let mut req = req("OPTIONS", "/", "");
+            req.extensions_mut().insert(addr);
+            req
+        };
+
+        // the address from the adapter, in the extensions.
+        assert_eq!(dav.handle(from(a1)).await.status(), StatusCode::OK);
+        // one set in the config wins, and is the rate limit key.
+        let config = DavConfig::new().remote_addr(a1);
+        let resp = dav.handle_with(config, from(a2)).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(dav.handle(from(a2)).await.status(), StatusCode::OK);
+        // without an address there is no limit.
+        assert_eq!(dav.handle(req("OPTIONS", "/", "")).await.status(), StatusCode::OK);
+
+        let expected = vec![Some(a1), Some(a1), Some(a2), None];
+        assert_eq!(*observer.0.lock().unwrap(), expected);
+        // the authenticator runs first, it might know the principal.
+        assert_eq!(*seen.lock().unwrap(), vec![Some(a1), Some(a1), Some(a2), None]);
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl DavObserver for Recorder {
+        fn on_request(&self, method: &http::Method, path: &str) {
+            self.0.lock().unwrap().push(format!("{} {}", method, path));
+        }
+
+        fn on_response(&self, method: &http::Method, status: StatusCode, _: Duration, bytes: Option<u64>) {
+            self.0.lock().unwrap().push(format!("{} {} {:?}", method, status.as_u16(), bytes));
+        }
+    }
+
+    #[tokio::test]
+    async fn observer() {
+        let recorder = Arc::new(Recorder::default());
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .observer(recorder.clone())
+            .authenticator(|req| match req.headers().get("authorization") {
+                Some(_) => AuthResult::Ok("user".to_string()),
+                None => AuthResult::Challenge("Basic realm=\"dav\"".to_string()),
+            })
+            .build_handler();
+        let authorized = |method, path, body| {
+            let mut r = req(method, path, body);
+            r.headers_mut().insert("authorization", "Basic dXNlcg==".parse().unwrap());
+            r
+        };
+
+        assert_eq!(dav.handle(authorized("PUT", "/file", "hello")).await.status(), StatusCode::CREATED);
+        assert_eq!(dav.handle(authorized("GET", "/file", "")).await.status(), StatusCode::OK);
+        let mut propfind = authorized("PROPFIND", "/", "");
+        propfind.headers_mut().insert("depth", "1".parse().unwrap());
+        assert_eq!(dav.handle(propfind).await.status(), StatusCode::MULTI_STATUS);
+        // error paths are reported too.
+        let resp = dav.handle(authorized("GET", "/missing", "")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(dav.handle(req("GET", "/file", "")).await.status(), StatusCode::UNAUTHORIZED);
+
+        let expected = [
+            "PUT /file",
+            "PUT 201 Some(0)",
+            "GET /file",
+            "GET 200 Some(5)",
+            "PROPFIND /",
+            "PROPFIND 207 None",
+            "GET /missing",
+            "GET 404 Some(0)",
+            "GET /file",
+            "GET 401 Some(0)",
+        ];
+        assert_eq!(*recorder.0.lock().unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn authenticator() {
+        let fs = MemFs::new();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .locksystem(crate::memls::MemLs::new())
+            .authenticator(|req| {
+                match req.headers().get("x-user").map(|u| u.to_str().unwrap()) {
+                    Some("guest") => AuthResult::Anonymous,
+                    Some(user) => AuthResult::Ok(user.to_string()),
+                    None => AuthResult::Challenge("Basic realm=\"dav\"".to_string()),
+                }
+            })
+            .build_handler();
+        let as_user = |user: &str, mut req: Request<hyper::Body>| {
+            req.headers_mut().insert("x-user", user.parse().unwrap());
+            req
+        };
+
+        // a challenge, and the request is not handled.
+        let resp = dav.handle(req("PUT", "/file", "hello")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers()["www-authenticate"], "Basic realm=\"dav\"");
+        assert!(!fs.exists(&DavPath::new("/file").unwrap()).await.unwrap());
+        let resp = dav.handle(as_user("guest", req("PUT", "/file", "hello"))).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // the user is the principal, so only they can use their lock.
+        let lockinfo = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope>
+            <D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+        let resp = dav.handle(as_user("alice", req("LOCK", "/file", lockinfo))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let token = resp.headers()["lock-token"].to_str().unwrap().to_string();
+        let put = |user: &str| {
+            let mut r = as_user(user, req("PUT", "/file", "hi"));
+            r.headers_mut().insert("if", format!("({})", token).parse().unwrap());
+            r
+        };
+        assert_eq!(dav.handle(put("bob")).await.status(), StatusCode::LOCKED);
+        assert_eq!(dav.handle(put("alice")).await.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn error_map() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .error_map(|e| (e.kind() == io::ErrorKind::NotFound).then_some(StatusCode::GONE))
+            .build_handler();
+        assert_eq!(dav.handle(req("GET", "/missing", "")).await.status(), StatusCode::GONE);
+        assert_eq!(dav.handle(req("DELETE", "/missing", "")).await.status(), StatusCode::GONE);
+        let res = dav.handle_result(req("GET", "/missing", "")).await;
+        assert_eq!(res.err().unwrap().kind(), HandlerErrorKind::Status(StatusCode::GONE));
+
+        // statuses that are not from the filesystem are left alone.
+        let resp = dav.handle(req("PUT", "/no/such/dir", "data")).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn error_handler() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .rate_limit(0.01, 3)
+            .error_handler(|status, path| {
+                let body = format!("<h1>{} {}</h1>", status.as_u16(), path.as_url_string());
+                Some(Response::builder().status(status).body(Body::from(body)).unwrap())
+            })
+            .build_handler();
+        let get = |range: &str| {
+            let mut req = req("GET", "/file", "");
+            req.headers_mut().insert("range", range.parse().unwrap());
+            req.extensions_mut().insert("10.0.0.1:1000".parse::<SocketAddr>().unwrap());
+            req
+        };
+        let body = |resp: Response<Body>| async move { resp.into_body().into_bytes().await.unwrap() };
+
+        let resp = dav.handle(get("bytes=0-")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body(resp).await, "<h1>404 /file</h1>");
+
+        // the headers that go with the status are kept.
+        let mut put = req("PUT", "/file", "hello");
+        put.extensions_mut().insert("10.0.0.2:1000".parse::<SocketAddr>().unwrap());
+        dav.handle(put).await;
+        let resp = dav.handle(get("bytes=100-")).await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(resp.headers()["content-range"], "bytes */5");
+        assert_eq!(body(resp).await, "<h1>416 /file</h1>");
+
+        dav.handle(get("bytes=0-")).await;
+        let resp = dav.handle(get("bytes=0-")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().contains_key("retry-after"));
+        assert_eq!(body(resp).await, "<h1>429 /file</h1>");
+
+        // other methods get the default response.
+        let resp = dav.handle(req("PROPFIND", "/missing", "")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(body(resp).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rewritten_locations() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .href_rewrite(|href| href.replacen("/dav/", "/public/", 1))
+            .build_handler();
+
+        let resp = dav.handle(req("MKCOL", "/dav/dir/", "")).await;
+        assert_eq!(resp.headers()["content-location"], "/public/dir/");
+        let resp = dav.handle(req("GET", "/dav/dir", "")).await;
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(resp.headers()["location"], "/public/dir/");
+        let mut propfind = req("PROPFIND", "/dav/dir", "");
+        propfind.headers_mut().insert("depth", "0".parse().unwrap());
+        let resp = dav.handle(propfind).await;
+        assert_eq!(resp.headers()["content-location"], "/public/dir/");
+    }
+}
@@ -6,12 +6,11 @@ use headers::Header;
 use http::header::{HeaderName, HeaderValue};
 use lazy_static::lazy_static;
 use regex::Regex;
-use url;
 
 use crate::fs::DavMetaData;
 
 lazy_static! {
-    static ref RE_URL: Regex = Regex::new(r"https?://[^/]*([^#?]+).*$").unwrap();
+    static ref RE_URL: Regex = Regex::new(r"(?i)^(https?)://([^/]*)([^#?]+).*$").unwrap();
     pub static ref DEPTH: HeaderName = HeaderName::from_static("depth");
     pub static ref TIMEOUT: HeaderName = HeaderName::from_static("timeout");
     pub static ref OVERWRITE: HeaderName = HeaderName::from_static("overwrite");
@@ -21,12 +20,11 @@ lazy_static! {
     pub static ref IF_MATCH: HeaderName = HeaderName::from_static("if-match");
     pub static ref IF_NONE_MATCH: HeaderName = HeaderName::from_static("if-none-match");
     pub static ref X_UPDATE_RANGE: HeaderName = HeaderName::from_static("x-update-range");
-    pub static ref IF: HeaderName = HeaderName::from_static("if");
     pub static ref CONTENT_LANGUAGE: HeaderName = HeaderName::from_static("content-language");
 }
 
 // helper.
-fn one<'i, I>(values: &mut I) -> Result<&'i HeaderValue, headers::Error>
+pub(crate) fn one<'i, I>(values: &mut I) -> Result<&'i HeaderValue, headers::Error>
 where I: Iterator<Item = &'i HeaderValue> {
     let v = values.next().ok_or_else(invalid)?;
     if values.next().is_some() {
@@ -37,12 +35,12 @@ where I: Iterator<Item = &'i HeaderValue> {
 }
 
 // helper
-fn invalid() -> headers::Error {
+pub(crate) fn invalid() -> headers::Error {
     headers::Error::invalid()
 }
 
 // helper
-fn map_invalid(_e: impl std::error::Error) -> headers::Error {
+pub(crate) fn map_invalid(_e: impl std::error::Error) -> headers::Error {
     headers::Error::invalid()
 }
 
@@ -185,7 +183,7 @@ impl Header for Timeout {
         let value = one(values)?;
         let mut v = Vec::new();
         let words = value.to_str().map_err(map_invalid)?.split(|c| c == ',');
-        for word in words {
+        for word in words.map(|w| w.trim()) {
             let w = match word {
                 "Infinite" => DavTimeout::Infinite,
                 _ if word.starts_with("Second-") => {
@@ -204,7 +202,7 @@ impl Header for Timeout {
 
     fn encode<E>(&self, values: &mut E)
     where E: Extend<HeaderValue> {
-        let mut first = false;
+        let mut first = true;
         let mut value = String::new();
         for s in &self.0 {
             if !first {
@@ -221,7 +219,12 @@ impl Header for Timeout {
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Destination(pub String);
+pub struct Destination {
+    // scheme, and host (and port) if it was an absolute URL.
+    pub scheme: Option<String>,
+    pub host:   Option<String>,
+    pub path:   String,
+}
 
 impl Header for Destination {
     fn name() -> &'static HeaderName {
@@ -232,11 +235,19 @@ impl Header for Destination {
     where I: Iterator<Item = &'i HeaderValue> {
         let s = one(values)?.to_str().map_err(map_invalid)?;
         if s.starts_with("/") {
-            return Ok(Destination(s.to_string()));
+            return Ok(Destination {
+                scheme: None,
+                host:   None,
+                path:   s.to_string(),
+            });
         }
         if let Some(caps) = RE_URL.captures(s) {
-            if let Some(path) = caps.get(1) {
-                return Ok(Destination(path.as_str().to_string()));
+            if let (Some(scheme), Some(host), Some(path)) = (caps.get(1), caps.get(2), caps.get(3)) {
+                return Ok(Destination {
+                    scheme: Some(scheme.as_str().to_ascii_lowercase()),
+                    host:   Some(host.as_str().to_string()),
+                    path:   path.as_str().to_string(),
+                });
             }
         }
         Err(invalid())
@@ -244,7 +255,14 @@ impl Header for Destination {
 
     fn encode<E>(&self, values: &mut E)
     where E: Extend<HeaderValue> {
-        values.extend(std::iter::once(HeaderValue::from_str(&self.0).unwrap()));
+        let value = match self.host {
+            Some(ref host) => {
+                let scheme = self.scheme.as_deref().unwrap_or("http");
+                format!("{}://{}{}", scheme, host, self.path)
+            },
+            None => self.path.clone(),
+        };
+        values.extend(std::iter::once(HeaderValue::from_str(&value).unwrap()));
     }
 }
 
@@ -260,8 +278,8 @@ impl Header for Overwrite {
     where I: Iterator<Item = &'i HeaderValue> {
         let line = one(values)?;
         match line.as_bytes() {
-            b"F" => Ok(Overwrite(false)),
-            b"T" => Ok(Overwrite(true)),
+            b"F" | b"f" => Ok(Overwrite(false)),
+            b"T" | b"t" => Ok(Overwrite(true)),
             _ => Err(invalid()),
         }
     }
@@ -305,10 +323,39 @@ impl ETag {
         })
     }
 
-    #[allow(dead_code)]
     pub fn is_weak(&self) -> bool {
         self.weak
     }
+
+    // The opaque-tag, that is without the "W/".
+    fn opaque(&self) -> &str {
+        if self.weak {
+            &self.tag[2..]
+        } else {
+            &self.tag
+        }
+    }
+
+    // RFC 7232 2.3.2: the strong comparison. Both must be strong.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.opaque() == other.opaque()
+    }
+
+    // RFC 7232 2.3.2: the weak comparison. Only the opaque-tags must match.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.opaque() == other.opaque()
+    }
+
+    // Turn this into a weak ETag.
+    pub fn into_weak(self) -> ETag {
+        if self.weak {
+            return self;
+        }
+        ETag {
+            tag:  format!("W/{}", self.tag),
+            weak: true,
+        }
+    }
 }
 
 impl FromStr for ETag {
@@ -346,9 +393,10 @@ impl Display for ETag {
     }
 }
 
+// This is the strong comparison.
 impl PartialEq for ETag {
     fn eq(&self, other: &Self) -> bool {
-        !self.weak && !other.weak && self.tag == other.tag
+        self.strong_eq(other)
     }
 }
 
@@ -543,248 +591,10 @@ impl Header for XUpdateRange {
     }
 }
 
-// The "If" header contains IfLists, of which the results are ORed.
-#[derive(Debug, Clone, PartialEq)]
-pub struct If(pub Vec<IfList>);
-
-// An IfList contains Conditions, of which the results are ANDed.
-#[derive(Debug, Clone, PartialEq)]
-pub struct IfList {
-    pub resource_tag: Option<url::Url>,
-    pub conditions:   Vec<IfCondition>,
-}
-
-// helpers.
-impl IfList {
-    fn new() -> IfList {
-        IfList {
-            resource_tag: None,
-            conditions:   Vec::new(),
-        }
-    }
-    fn add(&mut self, not: bool, item: IfItem) {
-        self.conditions.push(IfCondition { not, item });
-    }
-}
-
-// Single Condition is [NOT] State-Token | ETag
-#[derive(Debug, Clone, PartialEq)]
-pub struct IfCondition {
-    pub not:  bool,
-    pub item: IfItem,
-}
-#[derive(Debug, Clone, PartialEq)]
-pub enum IfItem {
-    StateToken(String),
-    ETag(ETag),
-}
-
-// Below stuff is for the parser state.
-#[derive(Debug, Clone, PartialEq)]
-enum IfToken {
-    ListOpen,
-    ListClose,
-    Not,
-    Word(String),
-    Pointy(String),
-    ETag(ETag),
-    End,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-enum IfState {
-    Start,
-    RTag,
-    List,
-    Not,
-    Bad,
-}
-
-// helpers.
-fn is_whitespace(c: u8) -> bool {
-    b" \t\r\n".iter().any(|&x| x == c)
-}
-fn is_special(c: u8) -> bool {
-    b"<>()[]".iter().any(|&x| x == c)
-}
-
-fn trim_left<'a>(mut out: &'a [u8]) -> &'a [u8] {
-    while !out.is_empty() && is_whitespace(out[0]) {
-        out = &out[1..];
-    }
-    out
-}
-
-// parse one token.
-fn scan_until(buf: &[u8], c: u8) -> Result<(&[u8], &[u8]), headers::Error> {
-    let mut i = 1;
-    let mut quote = false;
-    while quote || buf[i] != c {
-        if buf.is_empty() || is_whitespace(buf[i]) {
-            return Err(invalid());
-        }
-        if buf[i] == b'"' {
-            quote = !quote;
-        }
-        i += 1
-    }
-    Ok((&buf[1..i], &buf[i + 1..]))
-}
-
-// scan one word.
-fn scan_word(buf: &[u8]) -> Result<(&[u8], &[u8]), headers::Error> {
-    for (i, &c) in buf.iter().enumerate() {
-        if is_whitespace(c) || is_special(c) || c < 32 {
-            if i == 0 {
-                return Err(invalid());
-            }
-            return Ok((&buf[..i], &buf[i..]));
-        }
-    }
-    Ok((buf, b""))
-}
-
-// get next token.
-fn get_token<'a>(buf: &'a [u8]) -> Result<(IfToken, &'a [u8]), headers::Error> {
-    let buf = trim_left(buf);
-    if buf.is_empty() {
-        return Ok((IfToken::End, buf));
-    }
-    match buf[0] {
-        b'(' => Ok((IfToken::ListOpen, &buf[1..])),
-        b')' => Ok((IfToken::ListClose, &buf[1..])),
-        b'N' if buf.starts_with(b"Not") => Ok((IfToken::Not, &buf[3..])),
-        b'<' => {
-            let (tok, rest) = scan_until(buf, b'>')?;
-            let s = std::string::String::from_utf8(tok.to_vec()).map_err(map_invalid)?;
-            Ok((IfToken::Pointy(s), rest))
-        },
-        b'[' => {
-            let (tok, rest) = scan_until(buf, b']')?;
-            let s = std::str::from_utf8(tok).map_err(map_invalid)?;
-            Ok((IfToken::ETag(ETag::from_str(s)?), rest))
-        },
-        _ => {
-            let (tok, rest) = scan_word(buf)?;
-            if tok == b"Not" {
-                Ok((IfToken::Not, rest))
-            } else {
-                let s = std::string::String::from_utf8(tok.to_vec()).map_err(map_invalid)?;
-                Ok((IfToken::Word(s), rest))
-            }
-        },
-    }
-}
-
-impl Header for If {
-    fn name() -> &'static HeaderName {
-        &IF
-    }
-
-    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
-    where I: Iterator<Item = &'i HeaderValue> {
-        // one big state machine.
-        let mut if_lists = If(Vec::new());
-        let mut cur_list = IfList::new();
-
-        let mut state = IfState::Start;
-        let mut input = one(values)?.as_bytes();
-
-        loop {
-            let (tok, rest) = get_token(input)?;
-            input = rest;
-            state = match state {
-                IfState::Start => {
-                    match tok {
-                        IfToken::ListOpen => IfState::List,
-                        IfToken::Pointy(url) => {
-                            let u = url::Url::parse(&url).map_err(map_invalid)?;
-                            cur_list.resource_tag = Some(u);
-                            IfState::RTag
-                        },
-                        IfToken::End => {
-                            if if_lists.0.len() > 0 {
-                                break;
-                            }
-                            IfState::Bad
-                        },
-                        _ => IfState::Bad,
-                    }
-                },
-                IfState::RTag => {
-                    match tok {
-                        IfToken::ListOpen => IfState::List,
-                        _ => IfState::Bad,
-                    }
-                },
-                IfState::List | IfState::Not => {
-                    let not = state == IfState::Not;
-                    match tok {
-                        IfToken::Not => {
-                            if not {
-                                IfState::Bad
-                            } else {
-                                IfState::Not
-                            }
-                        },
-                        IfToken::Pointy(stok) | IfToken::Word(stok) => {
-                            // as we don't have an URI parser, just
-                            // check if there's at least one ':' in there.
-                            if !stok.contains(":") {
-                                IfState::Bad
-                            } else {
-                                cur_list.add(not, IfItem::StateToken(stok));
-                                IfState::List
-                            }
-                        },
-                        IfToken::ETag(etag) => {
-                            cur_list.add(not, IfItem::ETag(etag));
-                            IfState::List
-                        },
-                        IfToken::ListClose => {
-                            if cur_list.conditions.is_empty() {
-                                IfState::Bad
-                            } else {
-                                if_lists.0.push(cur_list);
-                                cur_list = IfList::new();
-                                IfState::Start
-                            }
-                        },
-                        _ => IfState::Bad,
-                    }
-                },
-                IfState::Bad => return Err(invalid()),
-            };
-        }
-        Ok(if_lists)
-    }
-
-    fn encode<E>(&self, values: &mut E)
-    where E: Extend<HeaderValue> {
-        let value = "[If header]";
-        values.extend(std::iter::once(HeaderValue::from_static(value)));
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn if_header() {
-        // Note that some implementations (golang net/x/webdav) also
-        // accept a "plain  word" as StateToken, instead of only
-        // a Coded-Url (<...>). We allow that as well, but I have
-        // no idea if we need to (or should!).
-        //let val = r#"  <http://x.yz/> ([W/"etag"] Not <DAV:nope> )
-        //    (Not<urn:x>[W/"bla"] plain:word:123) "#;
-        let val = r#"  <http://x.yz/> ([W/"etag"] Not <DAV:nope> ) (Not<urn:x>[W/"bla"] plain:word:123) "#;
-        let hdrval = HeaderValue::from_static(val);
-        let mut iter = std::iter::once(&hdrval);
-        let hdr = If::decode(&mut iter);
-        assert!(hdr.is_ok());
-    }
-
     #[test]
     fn etag_header() {
         let t1 = ETag::from_str(r#"W/"12345""#).unwrap();
@@ -795,4 +605,29 @@ mod tests {
         assert!(t2 != t3);
         assert!(t3 == t4);
     }
+
+    #[test]
+    fn etag_compare() {
+        // the examples from RFC 7232 2.3.2.
+        let w1 = ETag::from_str(r#"W/"1""#).unwrap();
+        let w2 = ETag::from_str(r#"W/"2""#).unwrap();
+        let s1 = ETag::from_str(r#""1""#).unwrap();
+        assert!(!w1.strong_eq(&w1) && w1.weak_eq(&w1));
+        assert!(!w1.strong_eq(&w2) && !w1.weak_eq(&w2));
+        assert!(!w1.strong_eq(&s1) && w1.weak_eq(&s1));
+        assert!(s1.strong_eq(&s1) && s1.weak_eq(&s1));
+        assert!(s1.clone().into_weak().weak_eq(&s1));
+    }
+
+    #[test]
+    fn destination() {
+        let value = HeaderValue::from_static("HTTPS://example.com:8443/a/b");
+        let dest = Destination::decode(&mut std::iter::once(&value)).unwrap();
+        assert_eq!(dest.scheme.as_deref(), Some("https"));
+        assert_eq!(dest.host.as_deref(), Some("example.com:8443"));
+        assert_eq!(dest.path, "/a/b");
+        let mut values = Vec::new();
+        dest.encode(&mut values);
+        assert_eq!(values[0], "https://example.com:8443/a/b");
+    }
 }
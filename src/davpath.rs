@@ -99,7 +99,7 @@ fn encode_path(src: &[u8]) -> Vec<u8> {
 // - make sure path is absolute
 // - remove query part (everything after ?)
 // - merge consecutive slashes
-// - process . and ..
+// - process . and .. (also when percent-encoded)
 // - decode percent encoded bytes, fail on invalid encodings.
 // - do not allow NUL or '/' in segments.
 fn normalize_path(rp: &[u8]) -> Result<Vec<u8>, ParseError> {
@@ -130,7 +130,8 @@ fn normalize_path(rp: &[u8]) -> Result<Vec<u8>, ParseError> {
     let segments = rawpath.split(|c| *c == b'/');
     let mut v: Vec<&[u8]> = Vec::new();
     for segment in segments {
-        match segment {
+        // "%2e%2e" is the same as ".." (RFC3986 6.2.2.2).
+        match pct::percent_decode(segment).collect::<Vec<u8>>().as_slice() {
             b"." | b"" => {},
             b".." => {
                 if v.len() < 2 {
@@ -139,12 +140,12 @@ fn normalize_path(rp: &[u8]) -> Result<Vec<u8>, ParseError> {
                 v.pop();
                 v.pop();
             },
-            s => {
-                if let Err(e) = valid_segment(s) {
+            _ => {
+                if let Err(e) = valid_segment(segment) {
                     Err(e)?;
                 }
                 v.push(b"/");
-                v.push(s);
+                v.push(segment);
             },
         }
     }
@@ -259,6 +260,16 @@ impl DavPath {
         self.fullpath.extend_from_slice(b);
     }
 
+    // Add the segments of `rel` (raw bytes, not encoded) to the path.
+    pub(crate) fn join(&self, rel: &[u8]) -> DavPath {
+        let mut path = self.clone();
+        for segment in rel.split(|&c| c == b'/').filter(|s| !s.is_empty()) {
+            path.push_segment(segment);
+        }
+        path.add_slash_if(rel.ends_with(b"/"));
+        path
+    }
+
     // as URL encoded string, with prefix.
     pub(crate) fn as_url_string_with_prefix_debug(&self) -> String {
         let mut p = encode_path(self.get_path());
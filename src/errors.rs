@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::io::{self, ErrorKind};
+use std::sync::Arc;
 
 use http::StatusCode;
 use xml;
@@ -7,6 +8,7 @@ use xml;
 use crate::fs::FsError;
 
 pub(crate) type DavResult<T> = Result<T, DavError>;
+pub(crate) type ErrorMap = Arc<dyn Fn(&io::Error) -> Option<StatusCode> + Send + Sync>;
 
 #[derive(Debug)]
 pub(crate) enum DavError {
@@ -26,6 +28,131 @@ pub(crate) enum DavError {
     XmlWriterError(xml::writer::Error),
 }
 
+/// Why a request failed, as returned by
+/// [`DavHandler::handle_result`](crate::DavHandler::handle_result).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerError {
+    kind:       HandlerErrorKind,
+    must_close: bool,
+}
+
+/// The kind of a [`HandlerError`].
+///
+/// Errors that do not have a variant of their own are returned
+/// as `Status`, with the HTTP status that `handle` would have sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HandlerErrorKind {
+    /// The request is not valid (400).
+    BadRequest,
+    /// Not allowed (403).
+    Forbidden,
+    /// The resource does not exist (404).
+    NotFound,
+    /// Method not allowed on this resource, or it already exists (405).
+    MethodNotAllowed,
+    /// For example, the parent collection does not exist (409).
+    Conflict,
+    /// An `If`, `If-Match` etc. header, or `Overwrite: F`, did not match (412).
+    PreconditionFailed,
+    /// The request body is too large (413).
+    PayloadTooLarge,
+    /// The request body is not in a format we understand (415).
+    UnsupportedMediaType,
+    /// Invalid `Range` or `Content-Range` (416).
+    RangeNotSatisfiable,
+    /// The resource is locked (423).
+    Locked,
+    /// Not supported by the server or the filesystem (501).
+    NotImplemented,
+    /// The filesystem is full (507).
+    InsufficientStorage,
+    /// Any other error.
+    Status(StatusCode),
+}
+
+impl HandlerError {
+    pub(crate) fn new(status: StatusCode, must_close: bool) -> HandlerError {
+        HandlerError {
+            kind: status.into(),
+            must_close,
+        }
+    }
+
+    /// What kind of error this is.
+    pub fn kind(&self) -> HandlerErrorKind {
+        self.kind
+    }
+
+    /// The HTTP status for this error.
+    pub fn status(&self) -> StatusCode {
+        self.kind.status()
+    }
+
+    /// Whether the connection should be closed after the response.
+    ///
+    /// This is the case when, for example, the request body could not be
+    /// read completely. `handle` sends a `Connection: close` header then.
+    pub fn must_close(&self) -> bool {
+        self.must_close
+    }
+}
+
+impl HandlerErrorKind {
+    /// The HTTP status for this kind of error.
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            HandlerErrorKind::BadRequest => StatusCode::BAD_REQUEST,
+            HandlerErrorKind::Forbidden => StatusCode::FORBIDDEN,
+            HandlerErrorKind::NotFound => StatusCode::NOT_FOUND,
+            HandlerErrorKind::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            HandlerErrorKind::Conflict => StatusCode::CONFLICT,
+            HandlerErrorKind::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            HandlerErrorKind::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            HandlerErrorKind::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            HandlerErrorKind::RangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            HandlerErrorKind::Locked => StatusCode::LOCKED,
+            HandlerErrorKind::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            HandlerErrorKind::InsufficientStorage => StatusCode::INSUFFICIENT_STORAGE,
+            HandlerErrorKind::Status(status) => status,
+        }
+    }
+}
+
+impl From<StatusCode> for HandlerErrorKind {
+    fn from(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST => HandlerErrorKind::BadRequest,
+            StatusCode::FORBIDDEN => HandlerErrorKind::Forbidden,
+            StatusCode::NOT_FOUND => HandlerErrorKind::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED => HandlerErrorKind::MethodNotAllowed,
+            StatusCode::CONFLICT => HandlerErrorKind::Conflict,
+            StatusCode::PRECONDITION_FAILED => HandlerErrorKind::PreconditionFailed,
+            StatusCode::PAYLOAD_TOO_LARGE => HandlerErrorKind::PayloadTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => HandlerErrorKind::UnsupportedMediaType,
+            StatusCode::RANGE_NOT_SATISFIABLE => HandlerErrorKind::RangeNotSatisfiable,
+            StatusCode::LOCKED => HandlerErrorKind::Locked,
+            StatusCode::NOT_IMPLEMENTED => HandlerErrorKind::NotImplemented,
+            StatusCode::INSUFFICIENT_STORAGE => HandlerErrorKind::InsufficientStorage,
+            status => HandlerErrorKind::Status(status),
+        }
+    }
+}
+
+impl From<DavError> for HandlerError {
+    fn from(e: DavError) -> Self {
+        HandlerError::new(e.statuscode(), e.must_close())
+    }
+}
+
+impl Error for HandlerError {}
+
+impl std::fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.status())
+    }
+}
+
 impl Error for DavError {
     fn description(&self) -> &str {
         "DAV error"
@@ -129,6 +256,7 @@ fn fserror_to_ioerror(e: FsError) -> io::Error {
         FsError::PathTooLong => io::Error::new(io::ErrorKind::Other, "PathTooLong"),
         FsError::TooLarge => io::Error::new(io::ErrorKind::Other, "TooLarge"),
         FsError::IsRemote => io::Error::new(io::ErrorKind::Other, "IsRemote"),
+        FsError::TimedOut => io::Error::new(io::ErrorKind::TimedOut, "TimedOut"),
     }
 }
 
@@ -142,7 +270,7 @@ fn ioerror_to_status(ioerror: &io::Error) -> StatusCode {
     }
 }
 
-fn fserror_to_status(e: &FsError) -> StatusCode {
+pub(crate) fn fserror_to_status(e: &FsError) -> StatusCode {
     match e {
         FsError::NotImplemented => StatusCode::NOT_IMPLEMENTED,
         FsError::GeneralFailure => StatusCode::INTERNAL_SERVER_ERROR,
@@ -154,6 +282,7 @@ fn fserror_to_status(e: &FsError) -> StatusCode {
         FsError::PathTooLong => StatusCode::URI_TOO_LONG,
         FsError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
         FsError::IsRemote => StatusCode::BAD_GATEWAY,
+        FsError::TimedOut => StatusCode::GATEWAY_TIMEOUT,
     }
 }
 
@@ -177,6 +306,17 @@ impl DavError {
         }
     }
 
+    // Like `statuscode`, but filesystem and I/O errors are passed
+    // through the `DavConfig::error_map` hook first.
+    pub(crate) fn mapped_statuscode(&self, map: Option<&ErrorMap>) -> StatusCode {
+        let mapped = match (map, self) {
+            (Some(map), DavError::FsError(e)) => map(&fserror_to_ioerror(*e)),
+            (Some(map), DavError::IoError(e)) => map(e),
+            _ => None,
+        };
+        mapped.unwrap_or_else(|| self.statuscode())
+    }
+
     pub(crate) fn must_close(&self) -> bool {
         match self {
             // non-fatal, keep the connnection open.
@@ -12,6 +12,15 @@
 //! If: headers always succeeds, and nothing is every really locked.
 //!
 //! `FakeLs` implements such a fake locksystem.
+//!
+//! If you want a little more than that, [`FakeLs::exclusive`] returns a
+//! `FakeLs` that remembers its exclusive locks, so that a second `LOCK`
+//! on the same path fails with `423 Locked` until the first lock is
+//! released or has expired. Only the path itself is checked, not its
+//! parents or children, and writes are never blocked. For real locking,
+//! use [`MemLs`](crate::memls::MemLs).
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 
 use uuid::Uuid;
@@ -20,17 +29,42 @@ use xmltree::Element;
 use crate::davpath::DavPath;
 use crate::ls::*;
 
+type Locks = HashMap<Vec<u8>, DavLock>;
+
 /// Fake locksystem implementation.
 #[derive(Debug, Clone)]
-pub struct FakeLs {}
+pub struct FakeLs {
+    // exclusive locks, by path. Only with `FakeLs::exclusive()`.
+    locks: Option<Arc<Mutex<Locks>>>,
+}
 
 impl FakeLs {
     /// Create a new "fakels" locksystem.
     pub fn new() -> Box<FakeLs> {
-        Box::new(FakeLs {})
+        Box::new(FakeLs { locks: None })
+    }
+
+    /// Create a new "fakels" locksystem that keeps track of exclusive locks.
+    ///
+    /// Locking a path that already has an exclusive lock fails. See
+    /// the [module documentation](self) for the details.
+    pub fn exclusive() -> Box<FakeLs> {
+        Box::new(FakeLs {
+            locks: Some(Arc::new(Mutex::new(HashMap::new()))),
+        })
     }
 }
 
+// locks on "/dir" and "/dir/" are the same lock.
+fn lock_key(path: &DavPath) -> &[u8] {
+    let p = path.as_bytes();
+    p.strip_suffix(b"/").unwrap_or(p)
+}
+
+fn expired(lock: &DavLock, now: SystemTime) -> bool {
+    lock.timeout_at.is_some_and(|t| t <= now)
+}
+
 fn tm_limit(d: Option<Duration>) -> Duration {
     match d {
         None => Duration::new(120, 0),
@@ -72,11 +106,32 @@ impl DavLockSystem for FakeLs {
             shared:     shared,
             deep:       deep,
         };
+        if let Some(ref locks) = self.locks {
+            let mut locks = locks.lock().unwrap();
+            let key = lock_key(path);
+            match locks.get(key) {
+                Some(l) if !expired(l, SystemTime::now()) => {
+                    debug!("lock on {} refused: locked by {}", path, l.token);
+                    return Err(l.clone());
+                },
+                _ => {},
+            }
+            if !shared {
+                locks.insert(key.to_vec(), lock.clone());
+            }
+        }
         debug!("lock {} created", &lock.token);
         Ok(lock)
     }
 
-    fn unlock(&self, _path: &DavPath, _token: &str) -> Result<(), ()> {
+    fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()> {
+        if let Some(ref locks) = self.locks {
+            let mut locks = locks.lock().unwrap();
+            let key = lock_key(path);
+            if locks.get(key).is_some_and(|l| l.token == token) {
+                locks.remove(key);
+            }
+        }
         Ok(())
     }
 
@@ -99,6 +154,15 @@ impl DavLockSystem for FakeLs {
             shared:     shared,
             deep:       deep,
         };
+        if let Some(ref locks) = self.locks {
+            let mut locks = locks.lock().unwrap();
+            let key = lock_key(path);
+            if let Some(l) = locks.get_mut(key).filter(|l| l.token == token) {
+                l.timeout_at = lock.timeout_at;
+                l.timeout = lock.timeout;
+                return Ok(l.clone());
+            }
+        }
         Ok(lock)
     }
 
@@ -114,11 +178,54 @@ impl DavLockSystem for FakeLs {
         Ok(())
     }
 
-    fn discover(&self, _path: &DavPath) -> Vec<DavLock> {
-        Vec::new()
+    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
+        let locks = match self.locks {
+            Some(ref locks) => locks.lock().unwrap(),
+            None => return Vec::new(),
+        };
+        let key = lock_key(path);
+        match locks.get(key) {
+            Some(l) if !expired(l, SystemTime::now()) => vec![l.clone()],
+            _ => Vec::new(),
+        }
     }
 
-    fn delete(&self, _path: &DavPath) -> Result<(), ()> {
+    fn delete(&self, path: &DavPath) -> Result<(), ()> {
+        if let Some(ref locks) = self.locks {
+            let key = lock_key(path);
+            let now = SystemTime::now();
+            locks.lock().unwrap().retain(|p, l| {
+                let below = p.starts_with(key) && (p.len() == key.len() || p[key.len()] == b'/');
+                !below && !expired(l, now)
+            });
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive() {
+        let path = DavPath::new("/file.txt").unwrap();
+        let ls = FakeLs::new();
+        assert!(ls.lock(&path, None, None, None, false, false).is_ok());
+        assert!(ls.lock(&path, None, None, None, false, false).is_ok());
+
+        let ls = FakeLs::exclusive();
+        let lock = ls.lock(&path, None, None, None, false, false).unwrap();
+        assert_eq!(ls.lock(&path, None, None, None, false, false).unwrap_err().token, lock.token);
+        assert!(ls.lock(&path, None, None, None, true, false).is_err());
+        assert_eq!(ls.discover(&path).len(), 1);
+        ls.unlock(&path, &lock.token).unwrap();
+        assert!(ls.lock(&path, None, None, None, false, false).is_ok());
+
+        // an expired lock is gone.
+        let dir = DavPath::new("/dir/").unwrap();
+        ls.lock(&dir, None, None, Some(Duration::from_secs(0)), false, true).unwrap();
+        assert!(ls.discover(&dir).is_empty());
+        assert!(ls.lock(&DavPath::new("/dir").unwrap(), None, None, None, false, true).is_ok());
+    }
+}
@@ -0,0 +1,238 @@
+//! Locksystem that keeps its state in a file.
+//!
+//! `MemLs` forgets all locks when the server restarts, and clients
+//! that still hold a lock get confused. `FileLs` is a `MemLs` that
+//! also writes its locks to a JSON file every time they change, and
+//! reads them back when it is created. Locks that timed out in the
+//! meantime are dropped.
+//!
+//! The file is written by writing a new file next to it and renaming
+//! that into place, so it never is half-written. The `DavLockSystem`
+//! methods are synchronous, so this is blocking I/O. If the file can
+//! not be written, the error is logged and the lock is kept in memory.
+//!
+//! Only one `FileLs` (one process) should use a file at a time.
+//!
+//! Example:
+//!
+//! ```no_run
+//! use webdav_handler::{filels::FileLs, DavHandler};
+//!
+//! let dav_server = DavHandler::builder()
+//!     .locksystem(FileLs::new("/var/lib/webdav/locks.json").unwrap())
+//!     .build_handler();
+//! ```
+//!
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use xmltree::Element;
+
+use crate::davpath::DavPath;
+use crate::ls::*;
+use crate::memls::MemLs;
+
+/// File-backed locksystem.
+#[derive(Debug, Clone)]
+pub struct FileLs(Arc<FileLsInner>);
+
+#[derive(Debug)]
+struct FileLsInner {
+    file: PathBuf,
+    ls:   Box<MemLs>,
+    // serializes changes and the writes of the file.
+    lock: Mutex<()>,
+}
+
+impl FileLs {
+    /// Create a new "filels" locksystem, and load the locks from `file`.
+    ///
+    /// If the file does not exist yet, it is created the first time
+    /// a lock is taken.
+    pub fn new(file: impl AsRef<Path>) -> io::Result<Box<FileLs>> {
+        let file = file.as_ref().to_path_buf();
+        let ls = MemLs::new();
+        match fs::read(&file) {
+            Ok(data) => {
+                let now = SystemTime::now();
+                for lock in parse_locks(&data)? {
+                    if lock.timeout_at.is_none_or(|t| t > now) {
+                        ls.insert(lock);
+                    }
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+        let inner = FileLsInner {
+            file,
+            ls,
+            lock: Mutex::new(()),
+        };
+        Ok(Box::new(FileLs(Arc::new(inner))))
+    }
+
+    // Write all locks to the file.
+    fn save(&self) {
+        let locks: Vec<Value> = self.0.ls.all_locks().iter().map(lock_to_json).collect();
+        let data = json!({ "version": 1, "locks": locks }).to_string();
+        let mut tmp = self.0.file.clone().into_os_string();
+        tmp.push(".tmp");
+        let res = fs::write(&tmp, data).and_then(|_| fs::rename(&tmp, &self.0.file));
+        if let Err(e) = res {
+            error!("FileLs: writing {:?}: {}", self.0.file, e);
+        }
+    }
+}
+
+impl DavLockSystem for FileLs {
+    fn lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        shared: bool,
+        deep: bool,
+    ) -> Result<DavLock, DavLock>
+    {
+        let _guard = self.0.lock.lock();
+        let lock = self.0.ls.lock(path, principal, owner, timeout, shared, deep)?;
+        self.save();
+        Ok(lock)
+    }
+
+    fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()> {
+        let _guard = self.0.lock.lock();
+        self.0.ls.unlock(path, token)?;
+        self.save();
+        Ok(())
+    }
+
+    fn refresh(&self, path: &DavPath, token: &str, timeout: Option<Duration>) -> Result<DavLock, ()> {
+        let _guard = self.0.lock.lock();
+        let lock = self.0.ls.refresh(path, token, timeout)?;
+        self.save();
+        Ok(lock)
+    }
+
+    fn check(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        self.0.ls.check(path, principal, ignore_principal, deep, submitted_tokens)
+    }
+
+    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
+        self.0.ls.discover(path)
+    }
+
+    fn delete(&self, path: &DavPath) -> Result<(), ()> {
+        let _guard = self.0.lock.lock();
+        self.0.ls.delete(path)?;
+        self.save();
+        Ok(())
+    }
+}
+
+fn secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn lock_to_json(lock: &DavLock) -> Value {
+    let owner = lock.owner.as_ref().and_then(|owner| {
+        let mut buf = Vec::new();
+        owner.write(&mut buf).ok()?;
+        String::from_utf8(buf).ok()
+    });
+    json!({
+        "token":      lock.token,
+        "path":       lock.path.with_prefix().as_url_string(),
+        "prefix":     lock.path.prefix(),
+        "principal":  lock.principal,
+        "owner":      owner,
+        "timeout":    lock.timeout.map(|d| d.as_secs()),
+        "timeout_at": lock.timeout_at.map(secs),
+        "shared":     lock.shared,
+        "deep":       lock.deep,
+    })
+}
+
+fn lock_from_json(v: &Value) -> Option<DavLock> {
+    let url = v["path"].as_str()?;
+    let path = match v["prefix"].as_str() {
+        None | Some("") => DavPath::new(url).ok()?,
+        Some(prefix) => DavPath::from_str_and_prefix(url, prefix).ok()?,
+    };
+    Some(DavLock {
+        token: v["token"].as_str()?.to_string(),
+        path,
+        principal: v["principal"].as_str().map(|s| s.to_string()),
+        owner: v["owner"].as_str().and_then(|o| Element::parse(o.as_bytes()).ok()),
+        timeout_at: v["timeout_at"].as_u64().map(|s| UNIX_EPOCH + Duration::from_secs(s)),
+        timeout: v["timeout"].as_u64().map(Duration::from_secs),
+        shared: v["shared"].as_bool().unwrap_or(false),
+        deep: v["deep"].as_bool().unwrap_or(false),
+    })
+}
+
+fn parse_locks(data: &[u8]) -> io::Result<Vec<DavLock>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let v: Value = serde_json::from_slice(data).map_err(|e| invalid(&e.to_string()))?;
+    if v["version"].as_u64() != Some(1) {
+        return Err(invalid("unknown version"));
+    }
+    let locks = v["locks"].as_array().ok_or_else(|| invalid("no locks"))?;
+    locks
+        .iter()
+        .map(|l| lock_from_json(l).ok_or_else(|| invalid("invalid lock")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tempdir;
+
+    #[test]
+    fn persist() {
+        let dir = tempdir();
+        let file = dir.join("locks.json");
+        let path = DavPath::new("/dir/file.txt").unwrap();
+        let owner = Element::parse(&b"<D:href xmlns:D=\"DAV:\">me</D:href>"[..]).ok();
+
+        let ls = FileLs::new(&file).unwrap();
+        let hour = Some(Duration::from_secs(3600));
+        let lock = ls.lock(&path, Some("user"), owner.as_ref(), hour, false, false).unwrap();
+        let gone = DavPath::new("/gone.txt").unwrap();
+        ls.lock(&gone, None, None, Some(Duration::from_secs(0)), false, false).unwrap();
+        drop(ls);
+
+        // reload; the expired lock is gone.
+        let ls = FileLs::new(&file).unwrap();
+        let locks = ls.discover(&path);
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].token, lock.token);
+        assert_eq!(locks[0].principal.as_deref(), Some("user"));
+        assert_eq!(locks[0].owner.as_ref().unwrap().get_text().unwrap(), "me");
+        assert!(ls.discover(&gone).is_empty());
+        assert!(ls.lock(&path, None, None, hour, false, false).is_err());
+
+        ls.unlock(&path, &lock.token).unwrap();
+        let ls = FileLs::new(&file).unwrap();
+        assert!(ls.discover(&path).is_empty());
+
+        fs::write(&file, "garbage").unwrap();
+        assert!(FileLs::new(&file).is_err());
+    }
+}
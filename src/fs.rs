@@ -28,7 +28,11 @@ macro_rules! notimplemented_fut {
 /// Errors generated by a filesystem implementation.
 ///
 /// These are more result-codes than errors, really.
+///
+/// New variants can be added in a minor release, so a `match` on
+/// an `FsError` needs a wildcard arm.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
 pub enum FsError {
     /// Operation not implemented (501)
     NotImplemented,
@@ -50,6 +54,8 @@ pub enum FsError {
     TooLarge,
     /// Trying to MOVE over a mount boundary (EXDEV) (502)
     IsRemote,
+    /// The filesystem did not respond in time (504)
+    TimedOut,
 }
 /// The Result type.
 pub type FsResult<T> = std::result::Result<T, FsError>;
@@ -67,6 +73,55 @@ pub struct DavProp {
     pub xml:       Option<Vec<u8>>,
 }
 
+/// The RFC3744 privileges that the current principal has on a resource.
+///
+/// Returned by [`DavFileSystem::privileges`], and reported in the
+/// `DAV:current-user-privilege-set` and `DAV:owner` properties.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PrivilegeSet {
+    /// `DAV:read` is granted.
+    pub read:     bool,
+    /// `DAV:write` is granted.
+    pub write:    bool,
+    /// `DAV:read-acl` is granted.
+    pub read_acl: bool,
+    /// Owner of the resource (a user name, reported as text), if known.
+    pub owner:    Option<String>,
+}
+
+/// What a filesystem supports, as returned by [`DavFileSystem::capabilities`].
+///
+/// This is what the handler advertises in its response to `OPTIONS`, in the
+/// `DAV` and `Allow` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsCapabilities {
+    /// Resources can be created, written and removed.
+    pub write:          bool,
+    /// Files can be updated in place (`PUT` with `Content-Range`, `PATCH`).
+    pub partial_update: bool,
+    /// `BIND`, `UNBIND` and `REBIND` (RFC 5842) are implemented.
+    pub bind:           bool,
+}
+
+impl Default for FsCapabilities {
+    fn default() -> FsCapabilities {
+        FsCapabilities {
+            write:          true,
+            partial_update: true,
+            bind:           false,
+        }
+    }
+}
+
+/// A change below a collection, as returned by [`DavFileSystem::changes_since`].
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// The member was created or modified.
+    Modified(DavPath),
+    /// The member was removed.
+    Removed(DavPath),
+}
+
 /// Future returned by almost all of the DavFileSystem methods.
 pub type FsFuture<'a, T> = Pin<Box<dyn Future<Output = FsResult<T>> + Send + 'a>>;
 /// Convenience alias for a boxed Stream.
@@ -117,6 +172,25 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         self.metadata(path)
     }
 
+    /// Check if a file or directory exists.
+    ///
+    /// If the path ends in a `/`, it must be a directory. Returns `Ok(false)`
+    /// if it does not exist, and an error if that could not be determined.
+    ///
+    /// Implementations can override this if there is a cheaper way
+    /// to find out than getting the metadata.
+    ///
+    /// The default implementation calls `metadata()`.
+    fn exists<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        Box::pin(async move {
+            match self.metadata(path).await {
+                Ok(meta) => Ok(!path.is_collection() || meta.is_dir()),
+                Err(FsError::NotFound) => Ok(false),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
     /// Create a directory.
     ///
     /// The default implementation returns FsError::NotImplemented.
@@ -141,6 +215,19 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         notimplemented_fut!("remove_file")
     }
 
+    /// Remove a directory and everything below it.
+    ///
+    /// `DELETE` of a collection tries this first. If it fails, it walks
+    /// over the tree and removes what's left resource by resource, so
+    /// that the client gets a status for every resource that could not
+    /// be removed. It is fine if this fails halfway through.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        notimplemented_fut!("remove_dir_all")
+    }
+
     /// Rename a file or directory.
     ///
     /// Source and destination must be the same type (file/dir).
@@ -148,23 +235,77 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
     /// should be replaced. If it is a directory it should give
     /// an error.
     ///
+    /// If source and destination are on different backends, return
+    /// FsError::IsRemote. The MOVE will then be done by copying the
+    /// source to the destination and deleting the source.
+    ///
     /// The default implementation returns FsError::NotImplemented.
     #[allow(unused_variables)]
     fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
         notimplemented_fut!("rename")
     }
 
+    /// Path of the temporary file for an atomic `PUT` to `path`.
+    ///
+    /// With [`DavConfig::atomic_put`](crate::DavConfig::atomic_put) the
+    /// upload is written to this file first, and then renamed onto `path`.
+    /// That only makes sense if `rename` is atomic.
+    ///
+    /// The default implementation returns `None`, which means the upload
+    /// is always written in place. [`temp_file_path`] is a dotfile in the
+    /// same directory, which the handler leaves out of `PROPFIND` listings.
+    #[allow(unused_variables)]
+    fn temp_path(&self, path: &DavPath) -> Option<DavPath> {
+        None
+    }
+
     /// Copy a file
     ///
     /// Should also copy the DAV properties, if properties
     /// are implemented.
     ///
     /// The default implementation returns FsError::NotImplemented.
+    /// The handler then opens both files and copies the data with
+    /// [`copy_data`], without the properties.
     #[allow(unused_variables)]
     fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
         notimplemented_fut!("copy")
     }
 
+    /// Add another binding (RFC 5842) `to` for the resource at `from`.
+    ///
+    /// This is like a hard link: afterwards, both paths refer to the same
+    /// resource. `to` does not exist yet. Return FsError::Forbidden if
+    /// `from` can't have more than one binding, for example because it
+    /// is a directory.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn bind<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        notimplemented_fut!("bind")
+    }
+
+    /// Does this filesystem implement `bind`?
+    ///
+    /// If it does, `BIND` is allowed and advertised in the response to
+    /// `OPTIONS`, unless [`capabilities`](Self::capabilities) says otherwise.
+    /// The default implementation returns false.
+    fn supports_bind(&self) -> bool {
+        false
+    }
+
+    /// What this filesystem supports.
+    ///
+    /// The default implementation returns the default `FsCapabilities`,
+    /// a writable filesystem with partial updates, and `bind` set to
+    /// what `supports_bind` returns.
+    fn capabilities(&self) -> FsCapabilities {
+        FsCapabilities {
+            bind: self.supports_bind(),
+            ..FsCapabilities::default()
+        }
+    }
+
     /// Set the access time of a file / directory.
     ///
     /// The default implementation returns FsError::NotImplemented.
@@ -220,14 +361,119 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         notimplemented_fut!("get_prop`")
     }
 
-    /// Get quota of this filesystem (used/total space).
+    /// Get a number of specific named properties of a node.
+    ///
+    /// This is used when a PROPFIND asks for properties by name, so that
+    /// a backend that has to do a lookup for every property can fetch just
+    /// these in one go. Properties that do not exist are left out.
+    ///
+    /// The default implementation calls `get_prop()` for each of them.
+    fn get_named_props<'a>(&'a self, path: &'a DavPath, props: Vec<DavProp>) -> FsFuture<'a, Vec<DavProp>> {
+        Box::pin(async move {
+            let mut res = Vec::new();
+            for mut prop in props {
+                if let Ok(xml) = self.get_prop(path, prop.clone()).await {
+                    prop.xml = Some(xml);
+                    res.push(prop);
+                }
+            }
+            Ok(res)
+        })
+    }
+
+    /// Get quota of the filesystem that `path` is on (used/total space).
     ///
     /// The first value returned is the amount of space used,
     /// the second optional value is the total amount of space
     /// (used + available).
     ///
+    /// These are returned as the RFC4331 `quota-used-bytes` and
+    /// `quota-available-bytes` properties of collections.
+    ///
+    /// The default implementation calls `get_quota()`.
+    #[allow(unused_variables, deprecated)]
+    fn quota<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        self.get_quota()
+    }
+
+    /// Get the privileges that `principal` has on a node.
+    ///
+    /// This is used for the read-only RFC3744 `current-user-privilege-set`
+    /// and `owner` properties. `principal` is the one set in the
+    /// configuration of the handler, if any.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn privileges<'a>(&'a self, path: &'a DavPath, principal: Option<&'a str>) -> FsFuture<'a, PrivilegeSet> {
+        notimplemented_fut!("privileges")
+    }
+
+    /// Get the Content-Type of a file.
+    ///
+    /// For filesystems where the type can not be derived from the
+    /// name, for example ones that are backed by a database.
+    /// `Ok(None)` means "don't know", and then the `mime_resolver`
+    /// of the handler or the file extension decides.
+    ///
+    /// The default implementation returns `Ok(None)`.
+    #[allow(unused_variables)]
+    fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        Box::pin(future::ok(None))
+    }
+
+    /// Get the ETag of a collection.
+    ///
+    /// The ETag from the metadata of a directory usually only changes
+    /// when an entry is added or removed. This one should also change
+    /// when one of the entries is modified, so that clients can tell
+    /// when they have to look at the collection again. It is used for
+    /// the `getetag` property of collections, with `prop` as well as with
+    /// `allprop`, so a listing costs an extra lookup per collection.
+    ///
+    /// The default implementation returns FsError::NotImplemented,
+    /// and then the ETag from the metadata is used.
+    #[allow(unused_variables)]
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        notimplemented_fut!("collection_etag")
+    }
+
+    /// Get the current RFC6578 sync token of a collection.
+    ///
+    /// The token is sent to the client as-is in a `sync-collection` REPORT,
+    /// so it must be a URI. The client sends it back later to ask for the
+    /// changes since then, see `changes_since()`.
+    ///
     /// The default implementation returns FsError::NotImplemented.
     #[allow(unused_variables)]
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        notimplemented_fut!("sync_token")
+    }
+
+    /// Get the changes below a collection since `token` was returned by `sync_token()`.
+    ///
+    /// If `deep` is false, only the changes to the members of `path` are
+    /// needed, otherwise those at any depth below it. Returning more is
+    /// allowed: reporting a change that did not happen is harmless,
+    /// missing one is not.
+    ///
+    /// If the token is not valid, or too old to know the changes since
+    /// then, return FsError::Forbidden. The client will then do a full sync.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn changes_since<'a>(
+        &'a self,
+        path: &'a DavPath,
+        token: &'a str,
+        deep: bool,
+    ) -> FsFuture<'a, Vec<Change>> {
+        notimplemented_fut!("changes_since")
+    }
+
+    /// Get quota of this filesystem (used/total space).
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[deprecated(note = "implement quota() instead")]
     fn get_quota<'a>(&'a self) -> FsFuture<(u64, Option<u64>)> {
         notimplemented_fut!("get_quota`")
     }
@@ -283,9 +529,19 @@ pub trait DavDirEntry: Send + Sync {
 
 /// A `DavFile` is the equivalent of `std::fs::File`, should be
 /// readable/writeable/seekable, and be able to return its metadata.
+///
+/// The body of a PUT is written as it comes in: every chunk that is
+/// received is passed to `write_bytes` or `write_buf`, and then `flush`
+/// is called once at the end. An implementation should write the data
+/// out (or at least make it independent of the request) before the
+/// future returns, and must not collect the whole body in memory, or
+/// a large upload will use as much memory as the file is big.
 pub trait DavFile: Debug + Send + Sync {
     fn metadata<'a>(&'a mut self) -> FsFuture<Box<dyn DavMetaData>>;
+    /// Write one chunk at the current position.
     fn write_buf<'a>(&'a mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<()>;
+    /// Write one chunk at the current position. Used instead of
+    /// `write_buf` if the chunk is a `Bytes`, which saves a copy.
     fn write_bytes<'a>(&'a mut self, buf: bytes::Bytes) -> FsFuture<()>;
     fn read_bytes<'a>(&'a mut self, count: usize) -> FsFuture<bytes::Bytes>;
     fn seek<'a>(&'a mut self, pos: SeekFrom) -> FsFuture<u64>;
@@ -349,6 +605,11 @@ pub trait DavMetaData: Debug + BoxCloneMd + Send + Sync {
     fn executable(&self) -> FsResult<bool> {
         notimplemented!("executable")
     }
+
+    /// Number of bindings (unix: hard links) of the resource. Default: 1.
+    fn bindings(&self) -> u64 {
+        1
+    }
 }
 
 // generic Clone, calls implementation-specific box_clone().
@@ -425,6 +686,37 @@ impl OpenOptions {
     }
 }
 
+/// A path for a temporary file next to `path`, for [`DavFileSystem::temp_path`].
+pub fn temp_file_path(path: &DavPath) -> DavPath {
+    let mut tmp = path.parent();
+    let name = format!(".put-{}.tmp", uuid::Uuid::new_v4().simple());
+    tmp.push_segment(name.as_bytes());
+    tmp
+}
+
+// Is this the name of a file from temp_file_path().
+pub(crate) fn is_temp_file(name: &[u8]) -> bool {
+    name.starts_with(b".put-") && name.ends_with(b".tmp")
+}
+
+// Size of the chunks in which copy_data() copies a file.
+const COPY_BUF_SIZE: usize = 65536;
+
+/// Copy all data from one open file to another, then flush the destination.
+///
+/// This can be used to implement `copy()`, or to copy a file between
+/// two different filesystems.
+pub async fn copy_data(from: &mut dyn DavFile, to: &mut dyn DavFile) -> FsResult<()> {
+    loop {
+        let data = from.read_bytes(COPY_BUF_SIZE).await?;
+        if data.is_empty() {
+            break;
+        }
+        to.write_bytes(data).await?;
+    }
+    to.flush().await
+}
+
 impl std::error::Error for FsError {
     fn description(&self) -> &str {
         "DavFileSystem error"
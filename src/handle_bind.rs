@@ -0,0 +1,290 @@
+//
+// BIND, UNBIND and REBIND (RFC 5842).
+//
+// The request URI is the collection the binding is in, and the body has
+// its name (the segment) and, for BIND and REBIND, the resource it is a
+// binding for. New bindings are made by `DavFileSystem::bind`. UNBIND
+// removes a binding like DELETE does, and REBIND moves one like MOVE.
+//
+use std::io::Cursor;
+
+use headers::{Header, HeaderMapExt};
+use http::{HeaderValue, Request, Response, StatusCode};
+
+use crate::async_stream::AsyncStream;
+use crate::body::Body;
+use crate::conditional::if_match_get_tokens;
+use crate::davheaders::{self, Depth};
+use crate::davpath::DavPath;
+use crate::errors::*;
+use crate::handle_copymove::same_host;
+use crate::multierror::{multi_error, MultiError};
+use crate::util::{dav_xml_error, DavMethod};
+use crate::xmltree_ext::ElementExt;
+use crate::DavResult;
+
+// The body of a BIND, UNBIND or REBIND request.
+struct BindRequest {
+    segment: Vec<u8>,
+    href:    Option<String>,
+}
+
+fn parse_request(xmldata: &[u8], method: DavMethod) -> DavResult<BindRequest> {
+    let tree = xmltree::Element::parse2(Cursor::new(xmldata))?;
+    let name = match method {
+        DavMethod::Bind => "bind",
+        DavMethod::Unbind => "unbind",
+        _ => "rebind",
+    };
+    if tree.name != name {
+        return Err(DavError::XmlParseError);
+    }
+    let mut segment = None;
+    let mut href = None;
+    for elem in tree.child_elems_iter() {
+        match elem.name.as_str() {
+            "segment" => segment = elem.get_text().map(|t| t.to_string()),
+            "href" => href = elem.get_text().map(|t| t.trim().to_string()),
+            _ => {},
+        }
+    }
+
+    // a segment is one name, not a path.
+    let segment = match segment {
+        Some(s) if !s.is_empty() && !s.contains('/') && s != "." && s != ".." => s.into_bytes(),
+        _ => return Err(DavError::XmlParseError),
+    };
+    if href.is_none() != (method == DavMethod::Unbind) {
+        return Err(DavError::XmlParseError);
+    }
+    Ok(BindRequest { segment, href })
+}
+
+// An error response with the precondition that failed.
+fn precondition(status: StatusCode, name: &str) -> DavResult<Response<Body>> {
+    let mut res = Response::new(dav_xml_error(&format!("<D:{}/>", name)));
+    *res.status_mut() = status;
+    let ct = "application/xml; charset=utf-8".to_owned();
+    res.headers_mut().typed_insert(davheaders::ContentType(ct));
+    Ok(res)
+}
+
+impl crate::DavInner {
+    // The resource that DAV:href in the request body refers to.
+    fn bind_source(&self, req: &Request<()>, href: &str) -> DavResult<DavPath> {
+        let value = HeaderValue::from_str(href).map_err(|_| DavError::XmlParseError)?;
+        let href = match davheaders::Destination::decode(&mut std::iter::once(&value)) {
+            Ok(href) => href,
+            Err(_) => return Err(DavError::XmlParseError),
+        };
+        // like COPY and MOVE, 502 if it is on another server (with strict_destination).
+        if self.check_dest_host() && !same_host(req, &href) {
+            return Err(StatusCode::BAD_GATEWAY.into());
+        }
+        Ok(DavPath::from_str_and_prefix(&href.path, &self.prefix)?)
+    }
+
+    pub(crate) async fn handle_bind(
+        self,
+        req: &Request<()>,
+        xmldata: &[u8],
+        method: DavMethod,
+    ) -> DavResult<Response<Body>>
+    {
+        let bind = parse_request(xmldata, method)?;
+        let overwrite = match req.headers().typed_try_get::<davheaders::Overwrite>() {
+            Ok(o) => o.is_none_or(|o| o.0),
+            Err(_) => return Err(StatusCode::BAD_REQUEST.into()),
+        };
+        let (into_collection, source_exists) = match method {
+            DavMethod::Bind => ("bind-into-collection", "bind-source-exists"),
+            DavMethod::Unbind => ("unbind-from-collection", "unbind-source-exists"),
+            _ => ("rebind-into-collection", "rebind-source-exists"),
+        };
+
+        // the request URI must be a collection.
+        let mut coll = self.path(req);
+        let cmeta = self.fs.metadata(&coll).await?;
+        if !cmeta.is_dir() {
+            return precondition(StatusCode::FORBIDDEN, into_collection);
+        }
+        coll.add_slash();
+
+        // the binding itself, which may or may not exist yet.
+        let mut dest = coll.clone();
+        dest.push_segment(&bind.segment);
+        let dmeta = self.fs.symlink_metadata(&dest).await.ok();
+        if let Some(ref m) = dmeta {
+            dest.add_slash_if(m.is_dir());
+        }
+
+        // the resource to bind.
+        let source = match bind.href {
+            Some(ref href) => {
+                let mut source = self.bind_source(req, href)?;
+                match self.fs.symlink_metadata(&source).await {
+                    Ok(meta) => {
+                        source.add_slash_if(meta.is_dir());
+                        Some((source, meta))
+                    },
+                    Err(_) => return precondition(StatusCode::CONFLICT, source_exists),
+                }
+            },
+            None => None,
+        };
+
+        match (method, &source) {
+            (DavMethod::Unbind, _) if dmeta.is_none() => {
+                return precondition(StatusCode::CONFLICT, source_exists);
+            },
+            (DavMethod::Bind, _) if !self.fs.capabilities().bind => {
+                return precondition(StatusCode::FORBIDDEN, "binding-allowed");
+            },
+            (_, Some((source, meta))) => {
+                if *source == dest {
+                    return Err(StatusCode::FORBIDDEN.into());
+                }
+                // a collection can't be bound below itself.
+                if meta.is_dir() && dest.as_bytes().starts_with(source.as_bytes()) {
+                    return precondition(StatusCode::FORBIDDEN, "cycle-allowed");
+                }
+                if dmeta.is_some() && !overwrite {
+                    return precondition(StatusCode::PRECONDITION_FAILED, "can-overwrite");
+                }
+            },
+            _ => {},
+        }
+
+        // check the If and If-* headers, and locks on everything we change.
+        let tokens = match if_match_get_tokens(req, Some(&cmeta), &self.fs, &self.ls, &coll).await {
+            Ok(t) => t,
+            Err(s) => return Err(s.into()),
+        };
+        if let Some(ref locksystem) = self.ls {
+            let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+            let principal = self.principal.as_deref();
+            if locksystem.check(&dest, principal, false, true, t.clone()).is_err() {
+                return Err(StatusCode::LOCKED.into());
+            }
+            if let (DavMethod::Rebind, Some((source, _))) = (method, &source) {
+                if locksystem.check(source, principal, false, true, t).is_err() {
+                    return Err(StatusCode::LOCKED.into());
+                }
+            }
+        }
+
+        let req_path = coll.clone();
+        let rewrite = self.href_rewrite.clone();
+
+        let items = AsyncStream::new(|tx| {
+            async move {
+                let mut multierror = MultiError::new(tx, self.error_map.clone());
+
+                // remove the binding that is there now, if any.
+                let replaced = dmeta.is_some();
+                if let Some(meta) = dmeta {
+                    if self.delete_items(&mut multierror, Depth::Infinity, meta, &dest).await.is_err() {
+                        return Ok(());
+                    }
+                    if let Some(ref locksystem) = self.ls {
+                        locksystem.delete(&dest).ok();
+                    }
+                }
+
+                let res = match (method, source) {
+                    (DavMethod::Bind, Some((source, _))) => self.fs.bind(&source, &dest).await,
+                    (DavMethod::Rebind, Some((source, _))) => {
+                        let res = self.fs.rename(&source, &dest).await;
+                        if let (Ok(_), Some(locksystem)) = (&res, &self.ls) {
+                            locksystem.delete(&source).ok();
+                        }
+                        res
+                    },
+                    _ => Ok(()),
+                };
+                let status = match res {
+                    Ok(()) if replaced || method == DavMethod::Unbind => StatusCode::OK,
+                    Ok(()) => StatusCode::CREATED,
+                    Err(e) => {
+                        debug!("handle_bind: {:?} {}: {:?}", method, dest, e);
+                        multierror.statuscode(&e.into())
+                    },
+                };
+                let _ = multierror.add_status(&coll, status).await;
+                Ok::<_, DavError>(())
+            }
+        });
+
+        multi_error(req_path, items, rewrite).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::localfs::LocalFs;
+    use crate::memfs::MemFs;
+    use crate::testing::{tempdir, TestClient};
+    use crate::DavHandler;
+
+    fn body(method: &str, segment: &str, href: &str) -> String {
+        let href = if href.is_empty() {
+            String::new()
+        } else {
+            format!("<D:href>{}</D:href>", href)
+        };
+        format!(
+            r#"<D:{} xmlns:D="DAV:"><D:segment>{}</D:segment>{}</D:{}>"#,
+            method, segment, href, method
+        )
+    }
+
+    #[tokio::test]
+    async fn bind() {
+        let dir = tempdir();
+        let fs = LocalFs::new(&dir, false, false, false);
+        let c = TestClient::new(DavHandler::builder().filesystem(fs).build_handler());
+        c.request("MKCOL", "/a/", "").await;
+        c.request("MKCOL", "/b/", "").await;
+        c.request("PUT", "/a/file", "hello").await;
+
+        let resp = c.request("OPTIONS", "/a/", "").await;
+        assert!(resp.headers()["dav"].to_str().unwrap().ends_with(",bind"));
+        assert!(resp.headers()["allow"].to_str().unwrap().contains("BIND,UNBIND,REBIND"));
+
+        let resp = c.request("BIND", "/b/", body("bind", "link", "/a/file")).await;
+        assert_eq!(resp.status(), 201);
+        c.request("PUT", "/b/link", "changed").await;
+        assert_eq!(c.request("GET", "/a/file", "").await.into_body(), "changed");
+
+        // overwrite, and refuse a cycle.
+        let resp = c.request("BIND", "/b/", body("bind", "link", "http://localhost/a/file")).await;
+        assert_eq!(resp.status(), 200);
+        let resp = c.request("BIND", "/a/", body("bind", "self", "/a/")).await;
+        assert_eq!(resp.status(), 403);
+        assert!(String::from_utf8_lossy(resp.body()).contains("<D:cycle-allowed/>"));
+        let resp = c.request("BIND", "/b/", body("bind", "x", "/missing")).await;
+        assert_eq!(resp.status(), 409);
+        let resp = c.request("BIND", "/a/file", body("bind", "x", "/a/file")).await;
+        assert!(String::from_utf8_lossy(resp.body()).contains("<D:bind-into-collection/>"));
+
+        // rebind moves the binding, unbind removes it.
+        let resp = c.request("REBIND", "/a/", body("rebind", "moved", "/b/link")).await;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(c.request("GET", "/b/link", "").await.status(), 404);
+        assert_eq!(c.request("UNBIND", "/a/", body("unbind", "file", "")).await.status(), 200);
+        assert_eq!(c.request("GET", "/a/moved", "").await.into_body(), "changed");
+        assert_eq!(c.request("UNBIND", "/a/", body("unbind", "file", "")).await.status(), 409);
+        assert_eq!(c.request("UNBIND", "/a/", body("unbind", "../b", "")).await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn not_supported() {
+        let c = TestClient::new(DavHandler::builder().filesystem(MemFs::new()).build_handler());
+        c.request("PUT", "/file", "hello").await;
+        let resp = c.request("OPTIONS", "/", "").await;
+        assert!(!resp.headers()["dav"].to_str().unwrap().contains("bind"));
+        let resp = c.request("BIND", "/", body("bind", "link", "/file")).await;
+        assert_eq!(resp.status(), 403);
+        assert!(String::from_utf8_lossy(resp.body()).contains("<D:binding-allowed/>"));
+    }
+}
@@ -1,4 +1,7 @@
-use futures::{future::BoxFuture, FutureExt, StreamExt};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::{Stream, StreamExt};
 use headers::HeaderMapExt;
 use http::{Request, Response, StatusCode};
 
@@ -12,6 +15,40 @@ use crate::fs::*;
 use crate::multierror::{multi_error, MultiError};
 use crate::{util::DavMethod, DavResult};
 
+// Source, destination, metadata, and for a file the result of copying it.
+type CopyEntry = (DavPath, DavPath, FsResult<Box<dyn DavMetaData>>, Option<FsResult<()>>);
+type CopyEntries<'a> = Pin<Box<dyn Stream<Item = CopyEntry> + Send + 'a>>;
+
+pub(crate) type DestinationMap = Arc<dyn Fn(&Request<()>, &str) -> Option<String> + Send + Sync>;
+pub(crate) type RemoteCopy = Arc<dyn Fn(DavPath, http::Uri) -> FsFuture<'static, StatusCode> + Send + Sync>;
+
+// Is the host of `dest` the host that this request was sent to.
+pub(crate) fn same_host(req: &Request<()>, dest: &davheaders::Destination) -> bool {
+    let host = match dest.host {
+        Some(ref host) => without_default_port(host, dest.scheme.as_deref()),
+        None => return true,
+    };
+    let ours = match req.headers().get(http::header::HOST) {
+        Some(h) => h.to_str().ok(),
+        None => req.uri().authority().map(|a| a.as_str()),
+    };
+    let scheme = req.uri().scheme_str();
+    ours.is_none_or(|h| without_default_port(h, scheme).eq_ignore_ascii_case(host))
+}
+
+// "host:80" for http is "host", and so is "host:443" for https. If we do
+// not know the scheme (the Host header doesn't say), both are stripped.
+fn without_default_port<'a>(host: &'a str, scheme: Option<&str>) -> &'a str {
+    let port = match scheme {
+        Some(s) if s.eq_ignore_ascii_case("http") => ":80",
+        Some(s) if s.eq_ignore_ascii_case("https") => ":443",
+        Some(_) => return host,
+        None if host.ends_with(":80") => ":80",
+        None => ":443",
+    };
+    host.strip_suffix(port).unwrap_or(host)
+}
+
 // map_err helper.
 async fn add_status<'a>(
     m_err: &'a mut MultiError,
@@ -20,97 +57,181 @@ async fn add_status<'a>(
 ) -> DavResult<()>
 {
     let daverror = e.into();
-    if let Err(x) = m_err.add_status(path, daverror.statuscode()).await {
+    let status = m_err.statuscode(&daverror);
+    if let Err(x) = m_err.add_status(path, status).await {
         return Err(x.into());
     }
     Err(daverror)
 }
 
 impl crate::DavInner {
-    pub(crate) fn do_copy<'a>(
-        &'a self,
-        source: &'a DavPath,
-        topdest: &'a DavPath,
-        dest: &'a DavPath,
+    // Copy a file. If the filesystem can't, copy the data ourselves.
+    async fn copy_file(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        match self.fs.copy(from, to).await {
+            Err(FsError::NotImplemented) => {
+                let mut src = self.fs.open(from, OpenOptions::read()).await?;
+                let mut oo = OpenOptions::write();
+                oo.create = true;
+                oo.truncate = true;
+                let mut dst = self.fs.open(to, oo).await?;
+                copy_data(&mut *src, &mut *dst).await
+            },
+            other => other,
+        }
+    }
+
+    // Copy one resource. For a collection this only creates the
+    // destination; returns true if its members should be copied too.
+    async fn copy_entry(
+        &self,
+        source: &DavPath,
+        topdest: &DavPath,
+        dest: &DavPath,
+        meta: &dyn DavMetaData,
         depth: Depth,
-        mut multierror: &'a mut MultiError,
-    ) -> BoxFuture<'a, DavResult<()>>
+        multierror: &mut MultiError,
+    ) -> DavResult<bool>
     {
-        async move {
-            // when doing "COPY /a/b /a/b/c make sure we don't recursively
-            // copy /a/b/c/ into /a/b/c.
-            if source == topdest {
-                return Ok(());
-            }
+        // when doing "COPY /a/b /a/b/c make sure we don't recursively
+        // copy /a/b/c/ into /a/b/c.
+        if source == topdest {
+            return Ok(false);
+        }
 
-            // source must exist.
-            let meta = match self.fs.metadata(source).await {
-                Err(e) => return add_status(&mut multierror, source, e).await,
-                Ok(m) => m,
+        // if it's a file we can overwrite it.
+        if !meta.is_dir() {
+            return match self.copy_file(source, dest).await {
+                Ok(_) => Ok(false),
+                Err(e) => {
+                    debug!("do_copy: copy_file error: {:?}", e);
+                    add_status(multierror, source, e).await.map(|_| false)
+                },
             };
+        }
 
-            // if it's a file we can overwrite it.
-            if !meta.is_dir() {
-                return match self.fs.copy(source, dest).await {
-                    Ok(_) => Ok(()),
-                    Err(e) => {
-                        debug!("do_copy: self.fs.copy error: {:?}", e);
-                        add_status(&mut multierror, source, e).await
-                    },
-                };
+        // Copying a directory onto an existing directory with Depth 0
+        // is not an error. It means "only copy properties" (which
+        // we do not do yet).
+        if let Err(e) = self.fs.create_dir(dest).await {
+            if depth != Depth::Zero || e != FsError::Exists {
+                debug!("do_copy: self.fs.create_dir({}) error: {:?}", dest, e);
+                return add_status(multierror, dest, e).await.map(|_| false);
             }
+        }
+
+        // only recurse when Depth > 0.
+        Ok(depth != Depth::Zero)
+    }
 
-            // Copying a directory onto an existing directory with Depth 0
-            // is not an error. It means "only copy properties" (which
-            // we do not do yet).
-            if let Err(e) = self.fs.create_dir(dest).await {
-                if depth != Depth::Zero || e != FsError::Exists {
-                    debug!("do_copy: self.fs.create_dir({}) error: {:?}", dest, e);
-                    return add_status(&mut multierror, dest, e).await;
+    // The entries of `src`, with their metadata. The files are copied to `dst`
+    // right away, `fs_concurrency` at a time; the result is returned in order.
+    async fn copy_read_dir<'a>(
+        &'a self,
+        src: &DavPath,
+        dst: &DavPath,
+        topdest: &'a DavPath,
+    ) -> FsResult<CopyEntries<'a>>
+    {
+        let entries = self.fs.read_dir(src, ReadDirMeta::DataSymlink).await?;
+        let (src, dst) = (src.clone(), dst.clone());
+        let entries = entries.map(move |dirent| {
+            let name = dirent.name();
+            let mut nsrc = src.clone();
+            let mut ndest = dst.clone();
+            nsrc.push_segment(&name);
+            ndest.push_segment(&name);
+            async move {
+                let meta = dirent.metadata().await;
+                let mut copied = None;
+                match meta {
+                    Ok(ref m) if m.is_dir() => {
+                        nsrc.add_slash();
+                        ndest.add_slash();
+                    },
+                    Ok(_) if nsrc != *topdest => copied = Some(self.copy_file(&nsrc, &ndest).await),
+                    _ => {},
                 }
+                (nsrc, ndest, meta, copied)
             }
+        });
+        Ok(Box::pin(entries.buffered(self.fs_concurrency)))
+    }
 
-            // only recurse when Depth > 0.
-            if depth == Depth::Zero {
-                return Ok(());
-            }
+    // Copy a resource, and if it is a collection, everything below it.
+    //
+    // This is a depth-first walk with a stack of open directories, so the
+    // memory that is used depends on how deep the tree is, not on how many
+    // entries it has. If we encounter errors, they are sent to the multistatus
+    // response right away and we keep going. The last error is returned.
+    pub(crate) async fn do_copy<'a>(
+        &'a self,
+        source: &'a DavPath,
+        topdest: &'a DavPath,
+        dest: &'a DavPath,
+        depth: Depth,
+        multierror: &'a mut MultiError,
+    ) -> DavResult<()>
+    {
+        // source must exist.
+        let meta = match self.fs.metadata(source).await {
+            Err(e) => return add_status(multierror, source, e).await,
+            Ok(m) => m,
+        };
+        if !self.copy_entry(source, topdest, dest, &*meta, depth, multierror).await? {
+            return Ok(());
+        }
 
-            let mut entries = match self.fs.read_dir(source, ReadDirMeta::DataSymlink).await {
-                Ok(entries) => entries,
-                Err(e) => {
-                    debug!("do_copy: self.fs.read_dir error: {:?}", e);
-                    return add_status(&mut multierror, source, e).await;
+        let mut stack = Vec::new();
+        match self.copy_read_dir(source, dest, topdest).await {
+            Ok(entries) => stack.push(entries),
+            Err(e) => {
+                debug!("do_copy: self.fs.read_dir error: {:?}", e);
+                return add_status(multierror, source, e).await;
+            },
+        }
+
+        let mut retval = Ok::<_, DavError>(());
+        while let Some(entries) = stack.last_mut() {
+            let (nsrc, ndest, meta, copied) = match entries.next().await {
+                Some(entry) => entry,
+                None => {
+                    stack.pop();
+                    continue;
                 },
             };
 
-            // If we encounter errors, just print them, and keep going.
-            // Last seen error is returned from function.
-            let mut retval = Ok::<_, DavError>(());
-            while let Some(dirent) = entries.next().await {
-                // NOTE: dirent.metadata() behaves like symlink_metadata()
-                let meta = match dirent.metadata().await {
-                    Ok(meta) => meta,
-                    Err(e) => return add_status(&mut multierror, source, e).await,
-                };
-                let name = dirent.name();
-                let mut nsrc = source.clone();
-                let mut ndest = dest.clone();
-                nsrc.push_segment(&name);
-                ndest.push_segment(&name);
-
-                if meta.is_dir() {
-                    nsrc.add_slash();
-                    ndest.add_slash();
-                }
-                // recurse.
-                if let Err(e) = self.do_copy(&nsrc, topdest, &ndest, depth, multierror).await {
-                    retval = Err(e);
+            // NOTE: dirent.metadata() behaves like symlink_metadata()
+            let meta = match meta {
+                Ok(meta) => meta,
+                Err(e) => {
+                    retval = add_status(multierror, &nsrc, e).await;
+                    continue;
+                },
+            };
+            // a file has been copied already.
+            if let Some(res) = copied {
+                if let Err(e) = res {
+                    debug!("do_copy: copy_file error: {:?}", e);
+                    retval = add_status(multierror, &nsrc, e).await;
                 }
+                continue;
+            }
+            match self.copy_entry(&nsrc, topdest, &ndest, &*meta, depth, multierror).await {
+                Ok(true) => {
+                    match self.copy_read_dir(&nsrc, &ndest, topdest).await {
+                        Ok(entries) => stack.push(entries),
+                        Err(e) => {
+                            debug!("do_copy: self.fs.read_dir error: {:?}", e);
+                            retval = add_status(multierror, &nsrc, e).await;
+                        },
+                    }
+                },
+                Ok(false) => {},
+                Err(e) => retval = Err(e),
             }
-
-            retval
         }
-        .boxed()
+
+        retval
     }
 
     // Right now we handle MOVE with a simple RENAME. RFC4918 #9.9.2 talks
@@ -133,24 +254,102 @@ impl crate::DavInner {
         mut multierror: &'a mut MultiError,
     ) -> DavResult<()>
     {
-        if let Err(e) = self.fs.rename(source, dest).await {
-            add_status(&mut multierror, &source, e).await
-        } else {
-            Ok(())
+        match self.fs.rename(source, dest).await {
+            Ok(()) => Ok(()),
+            Err(FsError::IsRemote) => {
+                // source and destination are on different backends,
+                // so copy everything and then delete the source.
+                debug!("do_move: {} -> {}: copy and delete", source, dest);
+                self.do_copy(source, dest, dest, Depth::Infinity, multierror).await?;
+                let meta = match self.fs.symlink_metadata(source).await {
+                    Ok(meta) => meta,
+                    Err(e) => return add_status(&mut multierror, source, e).await,
+                };
+                self.delete_items(multierror, Depth::Infinity, meta, source).await
+            },
+            Err(e) => add_status(&mut multierror, source, e).await,
         }
     }
 
+    // Do we refuse a Destination on another host.
+    pub(crate) fn check_dest_host(&self) -> bool {
+        self.strict_destination || self.remote_copy.is_some()
+    }
+
+    // COPY or MOVE to another server.
+    async fn remote_copymove(
+        self,
+        req: &Request<()>,
+        method: DavMethod,
+        dest: &str,
+    ) -> DavResult<Response<Body>>
+    {
+        // RFC4918 9.8.5: 502 if the destination is on another server.
+        let remote_copy = match self.remote_copy {
+            Some(ref remote_copy) => remote_copy.clone(),
+            None => return Err(StatusCode::BAD_GATEWAY.into()),
+        };
+        let url = match dest.parse::<http::Uri>() {
+            Ok(url) if url.authority().is_some() => url,
+            _ => return Err(StatusCode::BAD_GATEWAY.into()),
+        };
+
+        let mut path = self.path(req);
+        let meta = self.fs.symlink_metadata(&path).await?;
+        path.add_slash_if(meta.is_dir());
+
+        let tokens = match if_match_get_tokens(req, Some(&meta), &self.fs, &self.ls, &path).await {
+            Ok(t) => t,
+            Err(s) => return Err(s.into()),
+        };
+        if method == DavMethod::Move {
+            if let Some(ref locksystem) = self.ls {
+                let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+                let principal = self.principal.as_deref();
+                if let Err(_l) = locksystem.check(&path, principal, false, true, t) {
+                    return Err(StatusCode::LOCKED.into());
+                }
+            }
+        }
+
+        debug!("remote_copymove: {} -> {}", path, url);
+        let status = remote_copy(path.clone(), url).await?;
+        if method == DavMethod::Copy || !status.is_success() {
+            let mut res = Response::new(Body::empty());
+            *res.status_mut() = status;
+            return Ok(res);
+        }
+
+        // the MOVE is a COPY plus a DELETE of the source.
+        let req_path = path.clone();
+        let rewrite = self.href_rewrite.clone();
+        let items = AsyncStream::new(|tx| {
+            async move {
+                let mut multierror = MultiError::new(tx, self.error_map.clone());
+                if let Ok(()) = self.delete_items(&mut multierror, Depth::Infinity, meta, &path).await {
+                    if let Some(ref locksystem) = self.ls {
+                        locksystem.delete(&path).ok();
+                    }
+                    let _ = multierror.add_status(&path, status).await;
+                }
+                Ok::<_, DavError>(())
+            }
+        });
+        multi_error(req_path, items, rewrite).await
+    }
+
     pub(crate) async fn handle_copymove(
         self,
         req: &Request<()>,
         method: DavMethod,
     ) -> DavResult<Response<Body>>
     {
-        // get and check headers.
-        let overwrite = req
-            .headers()
-            .typed_get::<davheaders::Overwrite>()
-            .map_or(true, |o| o.0);
+        // get and check headers. A bad Overwrite header must not
+        // be mistaken for a missing one, which means "T".
+        let overwrite = match req.headers().typed_try_get::<davheaders::Overwrite>() {
+            Ok(o) => o.map_or(true, |o| o.0),
+            Err(_) => return Err(StatusCode::BAD_REQUEST.into()),
+        };
         let depth = match req.headers().typed_get::<Depth>() {
             Some(Depth::Infinity) | None => Depth::Infinity,
             Some(Depth::Zero) if method == DavMethod::Copy => Depth::Zero,
@@ -159,9 +358,20 @@ impl crate::DavInner {
 
         // decode and validate destination.
         let dest = match req.headers().typed_get::<davheaders::Destination>() {
-            Some(dest) => DavPath::from_str_and_prefix(&dest.0, &self.prefix)?,
+            Some(dest) => dest,
             None => return Err(StatusCode::BAD_REQUEST.into()),
         };
+        let value = req.headers()[&*davheaders::DESTINATION].to_str().unwrap_or_default();
+        let dest = match self.destination_map {
+            Some(ref map) => map(req, value),
+            None if self.check_dest_host() && !same_host(req, &dest) => None,
+            None => Some(dest.path),
+        };
+        let dest = match dest {
+            Some(dest) => dest,
+            None => return self.remote_copymove(req, method, value).await,
+        };
+        let dest = DavPath::from_str_and_prefix(&dest, &self.prefix)?;
 
         // for MOVE, tread with care- if the path ends in "/" but it actually
         // is a symlink, we want to move the symlink, not what it points to.
@@ -237,13 +447,18 @@ impl crate::DavInner {
         }
 
         let req_path = path.clone();
+        let rewrite = self.href_rewrite.clone();
 
         let items = AsyncStream::new(|tx| {
             async move {
-                let mut multierror = MultiError::new(tx);
+                let mut multierror = MultiError::new(tx, self.error_map.clone());
 
-                // see if we need to delete the destination first.
-                if overwrite && exists && depth != Depth::Zero && !dest_is_file {
+                // see if we need to delete the destination first. That is
+                // not needed when a file replaces a file, and when copying
+                // a collection with Depth 0 onto a collection.
+                let replace_file = dest_is_file && !meta.is_dir();
+                let merge_dir = depth == Depth::Zero && meta.is_dir() && !dest_is_file;
+                if overwrite && exists && !replace_file && !merge_dir {
                     trace!("handle_copymove: deleting destination {}", dest);
                     if let Err(_) = self
                         .delete_items(&mut multierror, Depth::Infinity, dmeta.unwrap(), &dest)
@@ -285,6 +500,270 @@ impl crate::DavInner {
             }
         });
 
-        multi_error(req_path, items).await
+        multi_error(req_path, items, rewrite).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::davpath::DavPath;
+    use crate::fs::*;
+    use crate::memfs::MemFs;
+    use crate::DavHandler;
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use http::{Request, StatusCode};
+
+    async fn req(
+        dav: &DavHandler,
+        method: &str,
+        path: &str,
+        hdrs: &[(&str, &str)],
+        body: &str,
+    ) -> (StatusCode, String)
+    {
+        let mut req = Request::builder().method(method).uri(path);
+        for (name, value) in hdrs {
+            req = req.header(*name, *value);
+        }
+        let resp = dav.handle(req.body(hyper::Body::from(body.to_string())).unwrap()).await;
+        let status = resp.status();
+        let mut body = resp.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        (status, String::from_utf8(data).unwrap())
+    }
+
+    // a handler with /file, /dir/ and /dir/member.
+    async fn setup() -> DavHandler {
+        let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+        req(&dav, "PUT", "/file", &[], "file").await;
+        req(&dav, "MKCOL", "/dir", &[], "").await;
+        req(&dav, "PUT", "/dir/member", &[], "member").await;
+        req(&dav, "PUT", "/file2", &[], "file2").await;
+        req(&dav, "MKCOL", "/dir2", &[], "").await;
+        req(&dav, "PUT", "/dir2/member2", &[], "member2").await;
+        dav
+    }
+
+    async fn copy(dav: &DavHandler, from: &str, to: &str, overwrite: &str) -> StatusCode {
+        let hdrs = [("Destination", to), ("Overwrite", overwrite)];
+        req(dav, "COPY", from, &hdrs, "").await.0
+    }
+
+    #[tokio::test]
+    async fn overwrite_false() {
+        let dav = setup().await;
+        assert_eq!(copy(&dav, "/file", "/file2", "F").await, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(copy(&dav, "/file", "/dir2", "F").await, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(copy(&dav, "/dir", "/dir2", "F").await, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(copy(&dav, "/dir/", "/dir2/", "f").await, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(copy(&dav, "/dir", "/file2", "F").await, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(copy(&dav, "/file", "/new", "F").await, StatusCode::CREATED);
+        assert_eq!(copy(&dav, "/file", "/new2", "X").await, StatusCode::BAD_REQUEST);
+
+        // nothing was changed.
+        assert_eq!(req(&dav, "GET", "/file2", &[], "").await.1, "file2");
+        assert_eq!(req(&dav, "GET", "/dir2/member2", &[], "").await.1, "member2");
+        assert_eq!(req(&dav, "GET", "/dir2/member", &[], "").await.0, StatusCode::NOT_FOUND);
+        let hdrs = [("Destination", "/dir2"), ("Overwrite", "F")];
+        assert_eq!(req(&dav, "MOVE", "/dir", &hdrs, "").await.0, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(req(&dav, "GET", "/dir/member", &[], "").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn overwrite_true() {
+        let dav = setup().await;
+        // file -> file
+        assert_eq!(copy(&dav, "/file", "/file2", "T").await, StatusCode::NO_CONTENT);
+        assert_eq!(req(&dav, "GET", "/file2", &[], "").await.1, "file");
+
+        // dir -> dir: the old members are gone.
+        assert_eq!(copy(&dav, "/dir", "/dir2", "T").await, StatusCode::NO_CONTENT);
+        assert_eq!(req(&dav, "GET", "/dir2/member", &[], "").await.1, "member");
+        assert_eq!(req(&dav, "GET", "/dir2/member2", &[], "").await.0, StatusCode::NOT_FOUND);
+
+        // file -> dir
+        assert_eq!(copy(&dav, "/file", "/dir2", "T").await, StatusCode::NO_CONTENT);
+        assert_eq!(req(&dav, "GET", "/dir2", &[], "").await.1, "file");
+
+        // dir -> file
+        assert_eq!(copy(&dav, "/dir", "/file2", "T").await, StatusCode::NO_CONTENT);
+        assert_eq!(req(&dav, "GET", "/file2/member", &[], "").await.1, "member");
+
+        // no Overwrite header means "T".
+        let hdrs = [("Destination", "/file2")];
+        assert_eq!(req(&dav, "MOVE", "/file", &hdrs, "").await.0, StatusCode::NO_CONTENT);
+        assert_eq!(req(&dav, "GET", "/file2", &[], "").await.1, "file");
+    }
+
+    // a filesystem that does not implement copy().
+    #[derive(Clone)]
+    struct NoCopyFs(Box<MemFs>);
+
+    impl DavFileSystem for NoCopyFs {
+        fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+            self.0.open(path, options)
+        }
+
+        fn read_dir<'a>(
+            &'a self,
+            path: &'a DavPath,
+            meta: ReadDirMeta,
+        ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+        {
+            self.0.read_dir(path, meta)
+        }
+
+        fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+            self.0.metadata(path)
+        }
+
+        fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+            self.0.create_dir(path)
+        }
+    }
+
+    #[tokio::test]
+    async fn copy_fallback() {
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(NoCopyFs(MemFs::new())))
+            .build_handler();
+        req(&dav, "MKCOL", "/dir", &[], "").await;
+        req(&dav, "PUT", "/dir/member", &[], "member").await;
+        req(&dav, "PUT", "/file", &[], "file").await;
+
+        assert_eq!(copy(&dav, "/file", "/copy", "F").await, StatusCode::CREATED);
+        assert_eq!(req(&dav, "GET", "/copy", &[], "").await.1, "file");
+        assert_eq!(copy(&dav, "/dir/", "/dir2/", "F").await, StatusCode::CREATED);
+        assert_eq!(req(&dav, "GET", "/dir2/member", &[], "").await.1, "member");
+    }
+
+    // 3000 files in 30 directories.
+    #[tokio::test]
+    async fn copy_large_tree() {
+        let fs = MemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        fs.create_dir(&DavPath::new("/tree/").unwrap()).await.unwrap();
+        for d in 0..30 {
+            let dir = DavPath::new(&format!("/tree/d{}/", d)).unwrap();
+            fs.create_dir(&dir).await.unwrap();
+            for f in 0..100 {
+                let mut file = fs.open(&dir.join(format!("f{}", f).as_bytes()), oo.clone()).await.unwrap();
+                file.write_bytes(Bytes::from(format!("{}/{}", d, f))).await.unwrap();
+            }
+        }
+
+        assert_eq!(copy(&dav, "/tree/", "/copy/", "F").await, StatusCode::CREATED);
+        for d in 0..30 {
+            let dir = DavPath::new(&format!("/copy/d{}/", d)).unwrap();
+            let count = fs.read_dir(&dir, ReadDirMeta::None).await.unwrap().count().await;
+            assert_eq!(count, 100);
+        }
+        assert_eq!(req(&dav, "GET", "/copy/d29/f99", &[], "").await.1, "29/99");
+    }
+
+    #[tokio::test]
+    async fn concurrency() {
+        let fs = MemFs::new();
+        let dav = |n| {
+            DavHandler::builder()
+                .filesystem(fs.clone())
+                .fs_concurrency(n)
+                .propfind_max_depth(None)
+                .build_handler()
+        };
+        req(&dav(1), "MKCOL", "/tree/", &[], "").await;
+        for d in 0..5 {
+            req(&dav(1), "MKCOL", &format!("/tree/d{}/", d), &[], "").await;
+            for f in 0..20 {
+                req(&dav(1), "PUT", &format!("/tree/d{}/f{}", d, f), &[], &format!("{}/{}", d, f)).await;
+            }
+        }
+
+        assert_eq!(copy(&dav(8), "/tree/", "/copy/", "F").await, StatusCode::CREATED);
+        assert_eq!(req(&dav(1), "GET", "/copy/d4/f19", &[], "").await.1, "4/19");
+
+        // the same listing, in the same order.
+        let body = r#"<D:propfind xmlns:D="DAV:"><D:prop><D:getcontentlength/></D:prop></D:propfind>"#;
+        let propfind = |n| {
+            let dav = dav(n);
+            async move { req(&dav, "PROPFIND", "/copy/", &[("Depth", "infinity")], body).await.1 }
+        };
+        let listing = propfind(1).await;
+        assert_eq!(listing.matches("<D:response>").count(), 106);
+        assert_eq!(propfind(8).await, listing);
+    }
+
+    #[tokio::test]
+    async fn destination_host() {
+        // by default, the host is ignored.
+        let dav = setup().await;
+        let hdrs = [("Host", "dav.example.com"), ("Destination", "http://other.example.com/new")];
+        assert_eq!(req(&dav, "COPY", "/file", &hdrs, "").await.0, StatusCode::CREATED);
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strict_destination(true)
+            .build_handler();
+        req(&dav, "PUT", "/file", &[], "file").await;
+        let hdrs = [("Host", "dav.example.com"), ("Destination", "http://other.example.com/new")];
+        assert_eq!(req(&dav, "COPY", "/file", &hdrs, "").await.0, StatusCode::BAD_GATEWAY);
+        let hdrs = [("Host", "dav.example.com"), ("Destination", "http://DAV.example.com/new")];
+        assert_eq!(req(&dav, "COPY", "/file", &hdrs, "").await.0, StatusCode::CREATED);
+        let hdrs = [("Host", "dav.example.com"), ("Destination", "http://dav.example.com:80/new2")];
+        assert_eq!(req(&dav, "COPY", "/file", &hdrs, "").await.0, StatusCode::CREATED);
+        let hdrs = [("Host", "dav.example.com:443"), ("Destination", "https://dav.example.com/new3")];
+        assert_eq!(req(&dav, "COPY", "/file", &hdrs, "").await.0, StatusCode::CREATED);
+        let hdrs = [("Host", "dav.example.com"), ("Destination", "http://dav.example.com:8080/new")];
+        assert_eq!(req(&dav, "COPY", "/file", &hdrs, "").await.0, StatusCode::BAD_GATEWAY);
+
+        // behind a proxy that serves /public/ as /dav/.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .destination_map(|_, dest| {
+                let path = dest.strip_prefix("https://www.example.com/public/")?;
+                Some(format!("/dav/{}", path))
+            })
+            .build_handler();
+        req(&dav, "PUT", "/dav/file", &[], "file").await;
+        let hdrs = [("Host", "internal:8080"), ("Destination", "https://www.example.com/public/new")];
+        assert_eq!(req(&dav, "MOVE", "/dav/file", &hdrs, "").await.0, StatusCode::CREATED);
+        assert_eq!(req(&dav, "GET", "/dav/new", &[], "").await.1, "file");
+        let hdrs = [("Destination", "/dav/file")];
+        assert_eq!(req(&dav, "MOVE", "/dav/new", &hdrs, "").await.0, StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn remote_copy() {
+        use std::sync::{Arc, Mutex};
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let calls2 = calls.clone();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .remote_copy(move |src, dest| {
+                calls2.lock().unwrap().push(format!("{} {}", src.as_url_string(), dest));
+                Box::pin(async move { Ok(StatusCode::CREATED) })
+            })
+            .build_handler();
+        req(&dav, "MKCOL", "/dir", &[], "").await;
+        req(&dav, "PUT", "/dir/file", &[], "file").await;
+
+        let hdrs = [("Host", "dav.example.com"), ("Destination", "http://other.example.com/new")];
+        assert_eq!(req(&dav, "COPY", "/dir", &hdrs, "").await.0, StatusCode::CREATED);
+        assert_eq!(req(&dav, "GET", "/dir/file", &[], "").await.1, "file");
+        assert_eq!(req(&dav, "MOVE", "/dir", &hdrs, "").await.0, StatusCode::CREATED);
+        assert_eq!(req(&dav, "GET", "/dir/file", &[], "").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(req(&dav, "MOVE", "/dir", &hdrs, "").await.0, StatusCode::NOT_FOUND);
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0], "/dir/ http://other.example.com/new");
     }
 }
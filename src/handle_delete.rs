@@ -14,7 +14,7 @@ use crate::DavResult;
 
 // map_err helper.
 async fn add_status<'a>(m_err: &'a mut MultiError, path: &'a DavPath, e: FsError) -> DavError {
-    let status = DavError::FsError(e).statuscode();
+    let status = m_err.statuscode(&DavError::FsError(e));
     if let Err(x) = m_err.add_status(path, status).await {
         return x.into();
     }
@@ -26,7 +26,7 @@ async fn add_status<'a>(m_err: &'a mut MultiError, path: &'a DavPath, e: FsError
 async fn dir_status<'a>(res: &'a mut MultiError, path: &'a DavPath, e: FsError) -> DavError {
     let status = match e {
         FsError::Exists => StatusCode::CONFLICT,
-        e => DavError::FsError(e).statuscode(),
+        e => res.statuscode(&DavError::FsError(e)),
     };
     if let Err(x) = res.add_status(path, status).await {
         return x.into();
@@ -143,15 +143,22 @@ impl crate::DavInner {
         }
 
         let req_path = path.clone();
+        let rewrite = self.href_rewrite.clone();
 
         let items = AsyncStream::new(|tx| {
             async move {
                 // turn the Sink into something easier to pass around.
-                let mut multierror = MultiError::new(tx);
-
-                // now delete the path recursively.
-                let fut = self.delete_items(&mut multierror, depth, meta, &path);
-                if let Ok(()) = fut.await {
+                let mut multierror = MultiError::new(tx, self.error_map.clone());
+
+                // a collection can often be removed in one go. If that fails,
+                // delete_items() walks over what's left and reports the errors.
+                let at_once = meta.is_dir() && depth == Depth::Infinity;
+                let res = if at_once && self.fs.remove_dir_all(&path).await.is_ok() {
+                    Ok(())
+                } else {
+                    self.delete_items(&mut multierror, depth, meta, &path).await
+                };
+                if let Ok(()) = res {
                     // Done. Now delete the path in the locksystem as well.
                     // Should really do this per resource, in case the delete partially fails. See TODO.pm
                     if let Some(ref locksystem) = self.ls {
@@ -163,6 +170,6 @@ impl crate::DavInner {
             }
         });
 
-        multi_error(req_path, items).await
+        multi_error(req_path, items, rewrite).await
     }
 }
@@ -1,41 +1,197 @@
 use std::cmp;
 use std::convert::TryInto;
-use std::io::Write;
+use std::sync::Arc;
 
 use futures::StreamExt;
 use headers::HeaderMapExt;
 use htmlescape;
-use http::{status::StatusCode, Request, Response};
+use http::{status::StatusCode, HeaderValue, Request, Response};
+use percent_encoding as pct;
 
 use bytes::Bytes;
 
 use crate::async_stream::AsyncStream;
 use crate::body::Body;
+use crate::compression::{self, Compressor};
 use crate::conditional;
 use crate::davheaders;
 use crate::davpath::DavPath;
 use crate::errors::*;
 use crate::fs::*;
 use crate::time::systemtime_to_localtime;
+use crate::util::accept_list;
 use crate::DavMethod;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 struct Range {
     start: u64,
     count: u64,
 }
 
-const BOUNDARY: &str = "BOUNDARY";
-const BOUNDARY_START: &str = "\n--BOUNDARY\n";
-const BOUNDARY_END: &str = "\n--BOUNDARY--\n";
+// More ranges than this and we ignore the Range header.
+const MAX_RANGES: usize = 100;
+
+// Language tags from Accept-Language that we look for a variant of a file for.
+const MAX_LANGUAGE_VARIANTS: usize = 8;
 
 const READ_BUF_SIZE: usize = 16384;
 
+// Sort the ranges, and merge the ones that overlap or are adjacent, so
+// that a client cannot make us send the same data over and over again.
+fn coalesce_ranges(mut ranges: Vec<Range>) -> Vec<Range> {
+    ranges.sort_by_key(|r| r.start);
+    let mut res: Vec<Range> = Vec::with_capacity(ranges.len());
+    for range in ranges.into_iter() {
+        if let Some(last) = res.last_mut() {
+            let end = last.start + last.count;
+            if range.start <= end {
+                last.count = cmp::max(end, range.start + range.count) - last.start;
+                continue;
+            }
+        }
+        res.push(range);
+    }
+    res
+}
+
+// Headers of one part of a multipart/byteranges body.
+fn part_header(boundary: &str, range: &Range, content_type: &str, len: u64) -> String {
+    format!(
+        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+        boundary,
+        content_type,
+        range.start,
+        range.start + range.count - 1,
+        len
+    )
+}
+
+// Length of the entire multipart/byteranges body.
+fn multipart_len(ranges: &[Range], boundary: &str, content_type: &str, len: u64) -> u64 {
+    let parts = ranges
+        .iter()
+        .map(|r| part_header(boundary, r, content_type, len).len() as u64 + r.count)
+        .sum::<u64>();
+    parts + format!("\r\n--{}--\r\n", boundary).len() as u64
+}
+
+/// Directory listing, passed to the [`autoindex_template`] function.
+///
+/// [`autoindex_template`]: ../struct.DavConfig.html#method.autoindex_template
+#[derive(Debug, Clone)]
+pub struct DirListing {
+    /// URL path of the directory, including the prefix.
+    pub path:    String,
+    /// Entries, directories first, then sorted by name. Dotfiles are not included.
+    pub entries: Vec<DirEntry>,
+}
+
+/// One entry in a [`DirListing`].
+///
+/// [`DirListing`]: struct.DirListing.html
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    /// Name of the entry. Directories have a trailing slash.
+    pub name:     String,
+    /// URL path of the entry, including the prefix.
+    pub href:     String,
+    /// Size in bytes.
+    pub size:     u64,
+    /// Last modification time, if known.
+    pub modified: Option<SystemTime>,
+    /// Is this a directory.
+    pub is_dir:   bool,
+}
+
+/// How a browser should present a file, see
+/// [`DavConfig::content_disposition`](crate::DavConfig::content_disposition).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Disposition {
+    /// Show it in the browser window.
+    Inline,
+    /// Download it. The filename is the name of the file, unless another one is given.
+    Attachment(Option<String>),
+}
+
+impl Disposition {
+    // The Content-Disposition header (RFC 6266). Non-ASCII filenames
+    // go in filename*, with a plain ASCII version in filename.
+    fn header_value(&self, path: &DavPath) -> HeaderValue {
+        let name = match self {
+            Disposition::Inline => return HeaderValue::from_static("inline"),
+            Disposition::Attachment(Some(name)) => name.clone(),
+            Disposition::Attachment(None) => String::from_utf8_lossy(path.file_name()).into_owned(),
+        };
+        let ascii = name
+            .chars()
+            .map(|c| {
+                match c {
+                    ' '..='~' if c != '"' && c != '\\' => c,
+                    _ => '_',
+                }
+            })
+            .collect::<String>();
+        let mut value = format!("attachment; filename=\"{}\"", ascii);
+        if ascii != name {
+            let encoded = pct::utf8_percent_encode(&name, pct::NON_ALPHANUMERIC);
+            value.push_str(&format!("; filename*=UTF-8''{}", encoded));
+        }
+        HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+    }
+}
+
+// text/html and XHTML can run scripts when shown in the browser.
+fn is_html(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    essence.eq_ignore_ascii_case("text/html") || essence.eq_ignore_ascii_case("application/xhtml+xml")
+}
+
+pub(crate) type AutoIndexTemplate = Arc<dyn Fn(&DirListing) -> String + Send + Sync>;
+pub(crate) type CacheControl = Arc<dyn Fn(&DavPath, &dyn DavMetaData) -> Option<HeaderValue> + Send + Sync>;
+pub(crate) type ContentDisposition =
+    Arc<dyn Fn(&DavPath, &dyn DavMetaData) -> Option<Disposition> + Send + Sync>;
+pub(crate) type MimeResolver = Arc<dyn Fn(&DavPath, &dyn DavMetaData) -> Option<mime::Mime> + Send + Sync>;
+
+// Content-Type of a file, from the mime_resolver or the built-in table.
+pub(crate) fn mime_type(resolver: Option<&MimeResolver>, path: &DavPath, meta: &dyn DavMetaData) -> String {
+    resolver
+        .and_then(|resolve| resolve(path, meta))
+        .map(|m| m.to_string())
+        .unwrap_or_else(|| path.get_mime_type_str().to_string())
+}
+
+// A directory entry for the autoindex.
+struct Dirent {
+    path: String,
+    name: String,
+    meta: Box<dyn DavMetaData>,
+}
+
 impl crate::DavInner {
+    // Content-Type of a file. The filesystem has the first say.
+    async fn content_type(&self, path: &DavPath, meta: &dyn DavMetaData) -> String {
+        match self.fs.content_type(path).await {
+            Ok(Some(ct)) => ct,
+            _ => mime_type(self.mime_resolver.as_ref(), path, meta),
+        }
+    }
+
+    // GET and HEAD. The headers are the same for both, HEAD only
+    // leaves out the body.
     pub(crate) async fn handle_get(&self, req: &Request<()>) -> DavResult<Response<Body>> {
         let head = req.method() == &http::Method::HEAD;
         let mut path = self.path(&req);
         let mut is_hbs = false;
 
+        // send a variant of the file in the client's language, if there is one.
+        let mut language = None;
+        if !path.is_collection() {
+            if let Some((vpath, lang)) = self.language_variant(req, &path).await {
+                path = vpath;
+                language = Some(lang);
+            }
+        }
+
         // check if it's a directory.
         let meta = self.fs.metadata(&path).await?;
         if meta.is_dir() {
@@ -45,21 +201,40 @@ impl crate::DavInner {
             // for a directory index is usually a browser.
             //
             if !path.is_collection() {
-                let mut res = Response::new(Body::empty());
                 path.add_slash();
-                res.headers_mut()
-                    .insert("Location", path.with_prefix().as_url_string().parse().unwrap());
-                res.headers_mut().typed_insert(headers::ContentLength(0));
-                *res.status_mut() = StatusCode::FOUND;
-                return Ok(res);
+                if self.collection_redirect {
+                    let mut res = Response::new(Body::empty());
+                    let location = crate::davhandler::href(self.href_rewrite.as_ref(), &path);
+                    if let Ok(location) = location.parse() {
+                        res.headers_mut().insert("Location", location);
+                    }
+                    res.headers_mut().typed_insert(headers::ContentLength(0));
+                    *res.status_mut() = StatusCode::MOVED_PERMANENTLY;
+                    return Ok(res);
+                }
             }
 
-            // If indexfile was set, use it.
-            if let Some(indexfile) = self.indexfile.as_ref() {
-                path.push_segment(indexfile.as_bytes());
+            // If indexfiles were set, use the first one that exists.
+            let mut found = false;
+            for indexfile in self.indexfiles.iter().flatten() {
+                let mut ipath = path.clone();
+                ipath.push_segment(indexfile.as_bytes());
+                if let Some((vpath, lang)) = self.language_variant(req, &ipath).await {
+                    ipath = vpath;
+                    language = Some(lang);
+                } else if !self.fs.metadata(&ipath).await.is_ok_and(|m| m.is_file()) {
+                    continue;
+                }
+                path = ipath;
                 is_hbs = indexfile.ends_with(".hbs");
-            } else {
+                found = true;
+                break;
+            }
+            if !found {
                 // Otherwise see if we need to generate a directory index.
+                if self.indexfiles.is_some() && !self.autoindex_enabled() {
+                    return Err(DavError::Status(StatusCode::NOT_FOUND));
+                }
                 return self.handle_autoindex(req, head).await;
             }
         }
@@ -78,6 +253,21 @@ impl crate::DavInner {
             meta = m;
         }
 
+        // send a precompressed file instead, if there is one.
+        let mut precompressed = None;
+        let mut orig_meta = None;
+        let mut vary = false;
+        if self.precompressed && !is_hbs {
+            let (exists, found) = self.precompressed_file(req, &path, &*meta).await;
+            if let Some((encoding, f, m)) = found {
+                trace!("handle_get: {}: sending {} file", path, encoding);
+                precompressed = Some(encoding);
+                orig_meta = Some(std::mem::replace(&mut meta, m));
+                file = f;
+            }
+            vary = exists;
+        }
+
         let len = meta.len();
         let mut curpos = 0u64;
         let file_etag = davheaders::ETag::from_meta(&meta);
@@ -113,24 +303,33 @@ impl crate::DavInner {
             do_range = false;
         }
 
+        // a 304 Not Modified must have the Cache-Control header as well.
+        let status = res.status();
+        if status.is_success() || status == StatusCode::NOT_MODIFIED {
+            if let Some(cc) = self.cache_control.as_ref().and_then(|cc| cc(&path, &*meta)) {
+                res.headers_mut().insert(http::header::CACHE_CONTROL, cc);
+            }
+        }
+
         // see if we want to get one or more ranges.
         if do_range {
             if let Some(r) = req.headers().typed_get::<headers::Range>() {
                 trace!("handle_gethead: range header {:?}", r);
                 use std::ops::Bound::*;
-                for range in r.iter() {
+                let n = r.iter().count();
+                for range in r.iter().take(MAX_RANGES) {
                     let (start, mut count, valid) = match range {
                         (Included(s), Included(e)) if e >= s => (s, e - s + 1, true),
                         (Included(s), Unbounded) if s <= len => (s, len - s, true),
                         (Unbounded, Included(n)) if n <= len => (len - n, n, true),
                         _ => (0, 0, false),
                     };
+                    // with multiple ranges, unsatisfiable ones are skipped.
                     if !valid || start >= len {
-                        let r = format!("bytes */{}", len);
-                        res.headers_mut().insert("Content-Range", r.parse().unwrap());
-                        *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                        if n > 1 {
+                            continue;
+                        }
                         ranges.clear();
-                        no_body = true;
                         break;
                     }
                     if start + count > len {
@@ -138,6 +337,18 @@ impl crate::DavInner {
                     }
                     ranges.push(Range { start, count });
                 }
+                if n > MAX_RANGES {
+                    // not going to do that, send the whole file.
+                    debug!("handle_gethead: {} ranges, ignoring Range header", n);
+                    ranges.clear();
+                } else if ranges.is_empty() {
+                    let r = format!("bytes */{}", len);
+                    res.headers_mut().insert("Content-Range", r.parse().unwrap());
+                    *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                    no_body = true;
+                } else {
+                    ranges = coalesce_ranges(ranges);
+                }
             }
         }
 
@@ -152,6 +363,7 @@ impl crate::DavInner {
             }
         }
 
+        let boundary = uuid::Uuid::new_v4().simple().to_string();
         if ranges.len() > 0 {
             curpos = ranges[0].start;
 
@@ -167,7 +379,7 @@ impl crate::DavInner {
                 res.headers_mut().insert("Content-Range", r.parse().unwrap());
             } else {
                 // add content-type header.
-                let r = format!("multipart/byteranges; boundary={}", BOUNDARY);
+                let r = format!("multipart/byteranges; boundary={}", boundary);
                 res.headers_mut().insert("Content-Type", r.parse().unwrap());
             }
         } else {
@@ -177,19 +389,74 @@ impl crate::DavInner {
 
         // set content-length and start if we're not doing multipart.
         let content_type = if is_hbs {
-            "text/html; charset=UTF-8"
+            "text/html; charset=UTF-8".to_string()
         } else {
-            path.get_mime_type_str()
+            self.content_type(&path, orig_meta.as_deref().unwrap_or(&*meta)).await
         };
+
+        // should the browser show the file, or download it.
+        if res.status().is_success() {
+            let m = orig_meta.as_deref().unwrap_or(&*meta);
+            let disposition = match self.content_disposition.as_ref().and_then(|cd| cd(&path, m)) {
+                Some(d) => Some(d),
+                None if self.html_as_attachment && is_html(&content_type) => {
+                    Some(Disposition::Attachment(None))
+                },
+                None => None,
+            };
+            if let Some(d) = disposition {
+                res.headers_mut()
+                    .insert(http::header::CONTENT_DISPOSITION, d.header_value(&path));
+            }
+        }
+
+        if vary {
+            res.headers_mut().insert("Vary", "Accept-Encoding".parse().unwrap());
+        }
+        if let Some(enc) = precompressed {
+            res.headers_mut().insert("Content-Encoding", enc.parse().unwrap());
+        }
+
+        // see if we can compress the response. Never for partial content.
+        let mut encoding = None;
+        if precompressed.is_none() &&
+            self.compression &&
+            compression::is_compressible(self.compression_types.as_ref(), &content_type)
+        {
+            res.headers_mut()
+                .insert("Vary", "Accept-Encoding".parse().unwrap());
+            if res.status() == StatusCode::OK {
+                encoding = compression::negotiate(req);
+            }
+        }
+        if let Some(enc) = encoding {
+            res.headers_mut()
+                .insert("Content-Encoding", enc.as_str().parse().unwrap());
+            // the content is transformed, so the etag is no longer strong.
+            if let Some(etag) = res.headers().typed_get::<davheaders::ETag>() {
+                res.headers_mut().typed_insert(etag.into_weak());
+            }
+        }
+
+        // what we send depends on Accept-Language, even if it was absent.
+        if self.negotiate_language {
+            res.headers_mut().append("Vary", "Accept-Language".parse().unwrap());
+        }
+        if let Some(lang) = language.as_ref().and_then(|l| HeaderValue::from_str(l).ok()) {
+            res.headers_mut().insert(http::header::CONTENT_LANGUAGE, lang);
+        }
+
         if ranges.len() <= 1 {
             res.headers_mut()
                 .typed_insert(davheaders::ContentType(content_type.to_owned()));
+        }
+        if ranges.len() <= 1 && encoding.is_none() {
+            // a 304 has the length of the body it would have had.
             let notmod = res.status() == StatusCode::NOT_MODIFIED;
-            let len = if head || !no_body || notmod {
-                ranges[0].count
-            } else {
-                0
-            };
+            let len = if !no_body || notmod { ranges[0].count } else { 0 };
+            res.headers_mut().typed_insert(headers::ContentLength(len));
+        } else if ranges.len() > 1 && !no_body {
+            let len = multipart_len(&ranges, &boundary, &content_type, len);
             res.headers_mut().typed_insert(headers::ContentLength(len));
         }
 
@@ -198,9 +465,11 @@ impl crate::DavInner {
         }
 
         // now just loop and send data.
+        let brotli_quality = self.brotli_quality;
         *res.body_mut() = Body::from(AsyncStream::new(|mut tx| {
             async move {
                 let zero = [0; 4096];
+                let mut compressor = encoding.map(|enc| Compressor::new(enc, brotli_quality));
 
                 let multipart = ranges.len() > 1;
                 for range in ranges {
@@ -216,17 +485,7 @@ impl crate::DavInner {
                     }
 
                     if multipart {
-                        let mut hdrs = Vec::new();
-                        let _ = write!(hdrs, "{}", BOUNDARY_START);
-                        let _ = writeln!(
-                            hdrs,
-                            "Content-Range: bytes {}-{}/{}",
-                            range.start,
-                            range.start + range.count - 1,
-                            len
-                        );
-                        let _ = writeln!(hdrs, "Content-Type: {}", content_type);
-                        let _ = writeln!(hdrs, "");
+                        let hdrs = part_header(&boundary, &range, &content_type, len);
                         tx.send(Bytes::from(hdrs)).await;
                     }
 
@@ -244,11 +503,22 @@ impl crate::DavInner {
                         count -= len;
                         curpos += len;
                         trace!("sending {} bytes", len);
-                        tx.send(buf).await;
+                        match compressor {
+                            Some(ref mut c) => {
+                                let buf = c.compress(&buf)?;
+                                if buf.len() > 0 {
+                                    tx.send(buf).await;
+                                }
+                            },
+                            None => tx.send(buf).await,
+                        }
                     }
                 }
                 if multipart {
-                    tx.send(Bytes::from(BOUNDARY_END)).await;
+                    tx.send(Bytes::from(format!("\r\n--{}--\r\n", boundary))).await;
+                }
+                if let Some(c) = compressor {
+                    tx.send(c.finish()?).await;
                 }
                 Ok::<(), std::io::Error>(())
             }
@@ -257,10 +527,101 @@ impl crate::DavInner {
         Ok(res)
     }
 
-    pub(crate) async fn handle_autoindex(&self, req: &Request<()>, head: bool) -> DavResult<Response<Body>> {
-        let mut res = Response::new(Body::empty());
-        let path = self.path(&req);
+    // With language negotiation enabled, find the `<name>.<lang>.<ext>` variant
+    // of a file that best matches the Accept-Language header. A language
+    // like `de-ch` falls back to `de`. Returns the path and the language
+    // of the variant that was found.
+    async fn language_variant(&self, req: &Request<()>, path: &DavPath) -> Option<(DavPath, String)> {
+        if !self.negotiate_language {
+            return None;
+        }
+        let mut langs = accept_list(req, "accept-language");
+        langs.retain(|(lang, q)| *q > 0.0 && lang != "*");
+        langs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(cmp::Ordering::Equal));
+
+        // split off the extension, but not a leading dot.
+        let name = path.file_name();
+        let (stem, ext) = match name.iter().rposition(|&b| b == b'.') {
+            Some(i) if i > 0 => name.split_at(i),
+            _ => (name, &b""[..]),
+        };
+        if stem.is_empty() {
+            return None;
+        }
+
+        let mut tried = Vec::new();
+        for (lang, _) in &langs {
+            let primary = lang.split('-').next().unwrap_or("");
+            for tag in [lang.as_str(), primary] {
+                let valid = !tag.is_empty() && tag.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+                if !valid || tried.contains(&tag) {
+                    continue;
+                }
+                // every tag costs a lookup, don't let the client make us do many.
+                if tried.len() >= MAX_LANGUAGE_VARIANTS {
+                    return None;
+                }
+                tried.push(tag);
+                let mut vname = stem.to_vec();
+                vname.push(b'.');
+                vname.extend_from_slice(tag.as_bytes());
+                vname.extend_from_slice(ext);
+                let mut vpath = path.parent();
+                vpath.push_segment(&vname);
+                if self.fs.metadata(&vpath).await.is_ok_and(|m| m.is_file()) {
+                    return Some((vpath, tag.to_string()));
+                }
+            }
+        }
+        None
+    }
+
+    // A precompressed version of `path` that the client accepts, and that
+    // is not older than `path` itself. Also returns whether there is one at
+    // all, since the response then varies with Accept-Encoding.
+    async fn precompressed_file(
+        &self,
+        req: &Request<()>,
+        path: &DavPath,
+        meta: &dyn DavMetaData,
+    ) -> (bool, Option<(&'static str, Box<dyn DavFile>, Box<dyn DavMetaData>)>)
+    {
+        let modified = match meta.modified() {
+            Ok(m) => m,
+            Err(_) => return (false, None),
+        };
+        let mut exists = false;
+        let mut best: Option<(f32, &'static str, DavPath)> = None;
+        for (encoding, ext) in [("br", ".br"), ("gzip", ".gz")] {
+            let mut name = path.file_name().to_vec();
+            name.extend_from_slice(ext.as_bytes());
+            let mut cpath = path.parent();
+            cpath.push_segment(&name);
+            match self.fs.metadata(&cpath).await {
+                Ok(m) if m.is_file() && m.modified().is_ok_and(|m| m >= modified) => {},
+                _ => continue,
+            }
+            exists = true;
+            let q = compression::accepts(req, encoding);
+            if q > 0.0 && best.as_ref().is_none_or(|b| q > b.0) {
+                best = Some((q, encoding, cpath));
+            }
+        }
+        let (encoding, cpath) = match best {
+            Some((_, encoding, cpath)) => (encoding, cpath),
+            None => return (exists, None),
+        };
+        let mut file = match self.fs.open(&cpath, OpenOptions::read()).await {
+            Ok(file) => file,
+            Err(_) => return (exists, None),
+        };
+        match file.metadata().await {
+            Ok(meta) => (exists, Some((encoding, file, meta))),
+            Err(_) => (exists, None),
+        }
+    }
 
+    fn autoindex_enabled(&self) -> bool {
         // Is PROPFIND explicitly allowed?
         let allow_propfind = self
             .allow
@@ -269,7 +630,15 @@ impl crate::DavInner {
 
         // Only allow index generation if explicitly set to true, _or_ if it was
         // unset, and PROPFIND is explicitly allowed.
-        if !self.autoindex.unwrap_or(allow_propfind) {
+        self.autoindex.unwrap_or(allow_propfind)
+    }
+
+    pub(crate) async fn handle_autoindex(&self, req: &Request<()>, head: bool) -> DavResult<Response<Body>> {
+        let mut res = Response::new(Body::empty());
+        let mut path = self.path(&req);
+        path.add_slash();
+
+        if !self.autoindex_enabled() {
             debug!("method {} not allowed on request {}", req.method(), req.uri());
             return Err(DavError::StatusClose(StatusCode::METHOD_NOT_ALLOWED));
         }
@@ -280,137 +649,146 @@ impl crate::DavInner {
         // start output
         res.headers_mut()
             .insert("Content-Type", "text/html; charset=utf-8".parse().unwrap());
-        *res.status_mut() = StatusCode::OK;
-        if head {
-            return Ok(res);
-        }
-        let utcoffset = self.utcoffset;
-
-        // now just loop and send data.
-        *res.body_mut() = Body::from(AsyncStream::new(|mut tx| {
-            async move {
-                // transform all entries into a dirent struct.
-                struct Dirent {
-                    path: String,
-                    name: String,
-                    meta: Box<dyn DavMetaData>,
-                }
 
-                let mut dirents: Vec<Dirent> = Vec::new();
-                while let Some(dirent) = entries.next().await {
-                    let mut name = dirent.name();
-                    if name.starts_with(b".") {
-                        continue;
-                    }
-                    let mut npath = path.clone();
-                    npath.push_segment(&name);
-                    if let Ok(meta) = dirent.metadata().await {
-                        if meta.is_dir() {
-                            name.push(b'/');
-                            npath.add_slash();
-                        }
-                        dirents.push(Dirent {
-                            path: npath.with_prefix().as_url_string(),
-                            name: String::from_utf8_lossy(&name).to_string(),
-                            meta: meta,
-                        });
-                    }
+        // transform all entries into a dirent struct.
+        let mut dirents: Vec<Dirent> = Vec::new();
+        while let Some(dirent) = entries.next().await {
+            let mut name = dirent.name();
+            if name.starts_with(b".") {
+                continue;
+            }
+            let mut npath = path.clone();
+            npath.push_segment(&name);
+            if let Ok(meta) = dirent.metadata().await {
+                if meta.is_dir() {
+                    name.push(b'/');
+                    npath.add_slash();
                 }
-
-                // now we can sort the dirent struct.
-                dirents.sort_by(|a, b| {
-                    let adir = a.meta.is_dir();
-                    let bdir = b.meta.is_dir();
-                    if adir && !bdir {
-                        std::cmp::Ordering::Less
-                    } else if bdir && !adir {
-                        std::cmp::Ordering::Greater
-                    } else {
-                        (a.name).cmp(&b.name)
-                    }
+                dirents.push(Dirent {
+                    path: npath.with_prefix().as_url_string(),
+                    name: String::from_utf8_lossy(&name).to_string(),
+                    meta: meta,
                 });
+            }
+        }
 
-                // and output html
-                let upath = htmlescape::encode_minimal(&path.with_prefix().as_url_string());
-                let mut w = String::new();
-                w.push_str(
-                    "\
-                    <html><head>\n\
-                    <title>Index of ",
-                );
-                w.push_str(&upath);
-                w.push_str("</title>\n");
-                w.push_str(
-                    "\
-                    <style>\n\
-                    table {\n\
-                      border-collapse: separate;\n\
-                      border-spacing: 1.5em 0.25em;\n\
-                    }\n\
-                    h1 {\n\
-                      padding-left: 0.3em;\n\
-                    }\n\
-                    a {\n\
-                      text-decoration: none;\n\
-                      color: blue;\n\
-                    }\n\
-                    .left {\n\
-                      text-align: left;\n\
-                    }\n\
-                    .mono {\n\
-                      font-family: monospace;\n\
-                    }\n\
-                    .mw20 {\n\
-                      min-width: 20em;\n\
-                    }\n\
-                    </style>\n\
-                    </head>\n\
-                    <body>\n",
-                );
-                w.push_str(&format!("<h1>Index of {}</h1>", display_path(&path)));
-                w.push_str(
-                    "\
-                    <table>\n\
-                    <tr>\n\
-                      <th class=\"left mw20\">Name</th>\n\
-                      <th class=\"left\">Last modified</th>\n\
-                      <th>Size</th>\n\
-                    </tr>\n\
-                    <tr><th colspan=\"3\"><hr></th></tr>\n\
-                    <tr>\n\
-                      <td><a href=\"..\">Parent Directory</a></td>\n\
-                      <td>&nbsp;</td>\n\
-                      <td class=\"mono\" align=\"right\">[DIR]    </td>\n\
-                    </tr>\n",
-                );
-
-                tx.send(Bytes::from(w)).await;
-
-                for dirent in &dirents {
-                    let modified = dirent.meta.modified()
-                        .map(|t| systemtime_to_localtime(t, utcoffset))
-                        .unwrap_or("".to_string());
-                    let size = match dirent.meta.is_file() {
-                        true => display_size(dirent.meta.len()),
-                        false => "[DIR]    ".to_string(),
-                    };
-                    let name = htmlescape::encode_minimal(&dirent.name);
-                    let s = format!("<tr><td><a href=\"{}\">{}</a></td><td class=\"mono\">{}</td><td class=\"mono\" align=\"right\">{}</td></tr>",
-                         dirent.path, name, modified, size);
-                    tx.send(Bytes::from(s)).await;
-                }
-
-                let mut w = String::new();
-                w.push_str("<tr><th colspan=\"3\"><hr></th></tr>");
-                w.push_str("</table></body></html>");
-                tx.send(Bytes::from(w)).await;
-
-                Ok::<_, std::io::Error>(())
+        // now we can sort the dirent struct.
+        dirents.sort_by(|a, b| {
+            let adir = a.meta.is_dir();
+            let bdir = b.meta.is_dir();
+            if adir && !bdir {
+                std::cmp::Ordering::Less
+            } else if bdir && !adir {
+                std::cmp::Ordering::Greater
+            } else {
+                (a.name).cmp(&b.name)
             }
-        }));
+        });
+
+        // if a template was configured, let it render the listing.
+        let html = if let Some(ref template) = self.autoindex_template {
+            let listing = DirListing {
+                path:    path.with_prefix().as_url_string(),
+                entries: dirents
+                    .iter()
+                    .map(|d| {
+                        DirEntry {
+                            name:     d.name.clone(),
+                            href:     d.path.clone(),
+                            size:     d.meta.len(),
+                            modified: d.meta.modified().ok(),
+                            is_dir:   d.meta.is_dir(),
+                        }
+                    })
+                    .collect(),
+            };
+            template(&listing)
+        } else {
+            self.autoindex_html(&path, &dirents)
+        };
 
+        // The listing is generated for HEAD too, so that the
+        // Content-Length is the same as that of a GET.
+        *res.status_mut() = StatusCode::OK;
+        res.headers_mut().typed_insert(headers::ContentLength(html.len() as u64));
+        if !head {
+            *res.body_mut() = Body::from(html);
+        }
         Ok(res)
     }
+
+    fn autoindex_html(&self, path: &DavPath, dirents: &[Dirent]) -> String {
+        let upath = htmlescape::encode_minimal(&path.with_prefix().as_url_string());
+        let mut w = String::new();
+        w.push_str(
+            "\
+            <html><head>\n\
+            <title>Index of ",
+        );
+        w.push_str(&upath);
+        w.push_str("</title>\n");
+        w.push_str(
+            "\
+            <style>\n\
+            table {\n\
+              border-collapse: separate;\n\
+              border-spacing: 1.5em 0.25em;\n\
+            }\n\
+            h1 {\n\
+              padding-left: 0.3em;\n\
+            }\n\
+            a {\n\
+              text-decoration: none;\n\
+              color: blue;\n\
+            }\n\
+            .left {\n\
+              text-align: left;\n\
+            }\n\
+            .mono {\n\
+              font-family: monospace;\n\
+            }\n\
+            .mw20 {\n\
+              min-width: 20em;\n\
+            }\n\
+            </style>\n\
+            </head>\n\
+            <body>\n",
+        );
+        w.push_str(&format!("<h1>Index of {}</h1>", display_path(path)));
+        w.push_str(
+            "\
+            <table>\n\
+            <tr>\n\
+              <th class=\"left mw20\">Name</th>\n\
+              <th class=\"left\">Last modified</th>\n\
+              <th>Size</th>\n\
+            </tr>\n\
+            <tr><th colspan=\"3\"><hr></th></tr>\n\
+            <tr>\n\
+              <td><a href=\"..\">Parent Directory</a></td>\n\
+              <td>&nbsp;</td>\n\
+              <td class=\"mono\" align=\"right\">[DIR]    </td>\n\
+            </tr>\n",
+        );
+
+        for dirent in dirents {
+            let modified = dirent.meta.modified()
+                .map(|t| systemtime_to_localtime(t, self.utcoffset))
+                .unwrap_or("".to_string());
+            let size = match dirent.meta.is_file() {
+                true => display_size(dirent.meta.len()),
+                false => "[DIR]    ".to_string(),
+            };
+            let name = htmlescape::encode_minimal(&dirent.name);
+            let s = format!("<tr><td><a href=\"{}\">{}</a></td><td class=\"mono\">{}</td><td class=\"mono\" align=\"right\">{}</td></tr>",
+                 dirent.path, name, modified, size);
+            w.push_str(&s);
+        }
+
+        w.push_str("<tr><th colspan=\"3\"><hr></th></tr>");
+        w.push_str("</table></body></html>");
+        w
+    }
 }
 
 fn display_size(size: u64) -> String {
@@ -600,3 +978,599 @@ impl DavFile for HbsFile {
         Box::pin(future::ready(Ok(())))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r(start: u64, count: u64) -> Range {
+        Range { start, count }
+    }
+
+    #[test]
+    fn test_coalesce_ranges() {
+        // disjoint ranges are sorted, but left alone.
+        assert_eq!(coalesce_ranges(vec![r(200, 100), r(0, 100)]), vec![r(0, 100), r(200, 100)]);
+        // overlapping and adjacent ranges are merged.
+        assert_eq!(coalesce_ranges(vec![r(0, 100), r(50, 100)]), vec![r(0, 150)]);
+        assert_eq!(coalesce_ranges(vec![r(0, 100), r(100, 10)]), vec![r(0, 110)]);
+        assert_eq!(coalesce_ranges(vec![r(0, 100), r(10, 10), r(101, 1)]), vec![r(0, 100), r(101, 1)]);
+        // the same range many times is sent only once.
+        assert_eq!(coalesce_ranges(vec![r(0, 1000); 50]), vec![r(0, 1000)]);
+    }
+
+    #[tokio::test]
+    async fn indexfiles() {
+        use crate::{memfs::MemFs, DavHandler};
+
+        let get = |dav: DavHandler, path: &'static str| {
+            async move {
+                let req = Request::builder().uri(path).body(hyper::Body::empty()).unwrap();
+                let resp = dav.handle(req).await;
+                let status = resp.status();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                (status, String::from_utf8_lossy(&body).into_owned())
+            }
+        };
+        let put = |dav: DavHandler, path: &'static str| {
+            async move {
+                let req = Request::builder().method("PUT").uri(path);
+                dav.handle(req.body(hyper::Body::from(path)).unwrap()).await.status()
+            }
+        };
+
+        let fs = MemFs::new();
+        let config = || {
+            DavHandler::builder()
+                .filesystem(fs.clone())
+                .indexfiles(vec!["index.html", "index.htm", "default.html"])
+        };
+        let dav = config().build_handler();
+        assert!(put(dav.clone(), "/index.htm").await.is_success());
+        assert!(put(dav.clone(), "/default.html").await.is_success());
+        assert_eq!(get(dav.clone(), "/").await.1, "/index.htm");
+        assert!(put(dav.clone(), "/index.html").await.is_success());
+        assert_eq!(get(dav.clone(), "/").await.1, "/index.html");
+
+        // none of them exists.
+        fs.create_dir(&DavPath::new("/dir/").unwrap()).await.unwrap();
+        assert!(put(dav.clone(), "/dir/file.txt").await.is_success());
+        assert_eq!(get(dav.clone(), "/dir/").await.0, StatusCode::NOT_FOUND);
+        let dav = config().autoindex(true, None).build_handler();
+        let (status, body) = get(dav.clone(), "/dir/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("file.txt"));
+
+        // HEAD has the same Content-Length as GET.
+        let req = Request::builder().method("HEAD").uri("/dir/").body(hyper::Body::empty()).unwrap();
+        let resp = dav.handle(req).await;
+        assert_eq!(resp.headers()["content-length"], body.len().to_string().as_str());
+        assert!(hyper::body::to_bytes(resp.into_body()).await.unwrap().is_empty());
+    }
+
+    // MemFs, but everything in /db/ is JSON.
+    #[derive(Clone)]
+    struct DbFs(Box<crate::memfs::MemFs>);
+
+    impl DavFileSystem for DbFs {
+        fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+            self.0.metadata(path)
+        }
+
+        fn read_dir<'a>(
+            &'a self,
+            path: &'a DavPath,
+            meta: ReadDirMeta,
+        ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+        {
+            self.0.read_dir(path, meta)
+        }
+
+        fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+            self.0.open(path, options)
+        }
+
+        fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+            self.0.create_dir(path)
+        }
+
+        fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+            let ct = path.as_bytes().starts_with(b"/db/").then(|| "application/json".to_string());
+            Box::pin(futures::future::ok(ct))
+        }
+    }
+
+    #[tokio::test]
+    async fn autoindex_template() {
+        use crate::{memfs::MemFs, testing::TestClient, DavHandler};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls2 = calls.clone();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .autoindex(true, None)
+            .autoindex_template(move |listing| {
+                calls2.fetch_add(1, Ordering::SeqCst);
+                let mut out = format!("{}:", listing.path);
+                for e in &listing.entries {
+                    out.push_str(&format!(" {}={},{},{}", e.name, e.href, e.size, e.is_dir));
+                    assert!(e.modified.is_some());
+                }
+                out
+            })
+            .build_handler();
+        let c = TestClient::new(dav);
+        c.request("MKCOL", "/dav/dir/", "").await;
+        c.request("MKCOL", "/dav/dir/sub/", "").await;
+        c.request("PUT", "/dav/dir/b.txt", "hello").await;
+        c.request("PUT", "/dav/dir/a.txt", "hi").await;
+        c.request("PUT", "/dav/dir/.hidden", "x").await;
+
+        // the output of the template is the body, as is.
+        let resp = c.request("GET", "/dav/dir/", "").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let expected = concat!(
+            "/dav/dir/: sub/=/dav/dir/sub/,0,true",
+            " a.txt=/dav/dir/a.txt,2,false b.txt=/dav/dir/b.txt,5,false"
+        );
+        assert_eq!(resp.into_body(), expected);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // HEAD renders it too, for the Content-Length.
+        let resp = c.request("HEAD", "/dav/dir/", "").await;
+        assert_eq!(resp.headers()["content-length"], expected.len().to_string().as_str());
+        assert!(resp.body().is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn autoindex_head() {
+        use crate::{memfs::MemFs, testing::TestClient, DavHandler};
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true, None)
+            .build_handler();
+        let c = TestClient::new(dav);
+        c.request("MKCOL", "/dir/", "").await;
+        c.request("MKCOL", "/dir/sub/", "").await;
+        c.request("PUT", "/dir/file.txt", "hello").await;
+
+        let get = c.request("GET", "/dir/", "").await;
+        assert_eq!(get.status(), StatusCode::OK);
+        let len = get.headers()["content-length"].clone();
+        assert_eq!(len, get.body().len().to_string().as_str());
+        assert!(String::from_utf8_lossy(get.body()).contains("file.txt"));
+
+        let head = c.request("HEAD", "/dir/", "").await;
+        assert_eq!(head.status(), StatusCode::OK);
+        assert_eq!(head.headers()["content-length"], len);
+        assert_eq!(head.headers()["content-type"], get.headers()["content-type"]);
+        assert!(head.body().is_empty());
+    }
+
+    #[tokio::test]
+    async fn mime_resolver() {
+        use crate::{memfs::MemFs, testing::TestClient, DavHandler};
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .mime_resolver(|path, meta| {
+                if path.as_url_string().ends_with(".wasm") {
+                    return Some("application/wasm".parse().unwrap());
+                }
+                // an empty file, whatever its name.
+                (meta.len() == 0).then_some(mime::TEXT_PLAIN)
+            })
+            .build_handler();
+        let c = TestClient::new(dav);
+        c.request("PUT", "/app.wasm", "\0asm").await;
+        c.request("PUT", "/index.html", "<html>").await;
+        c.request("PUT", "/empty.html", "").await;
+
+        let content_type = |resp: &Response<Bytes>| resp.headers()["content-type"].clone();
+        let resp = c.request("GET", "/app.wasm", "").await;
+        assert_eq!(content_type(&resp), "application/wasm");
+        assert_eq!(content_type(&c.request("HEAD", "/app.wasm", "").await), "application/wasm");
+        assert_eq!(content_type(&c.request("GET", "/empty.html", "").await), "text/plain");
+        // without an answer from the resolver, the extension decides.
+        assert_eq!(content_type(&c.request("GET", "/index.html", "").await), "text/html");
+
+        // the getcontenttype property agrees.
+        let propfind = r#"<D:propfind xmlns:D="DAV:"><D:prop><D:getcontenttype/></D:prop></D:propfind>"#;
+        let body = c.request("PROPFIND", "/app.wasm", propfind).await.into_body();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<D:getcontenttype>application/wasm</D:getcontenttype>"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn fs_content_type() {
+        use crate::DavHandler;
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(DbFs(crate::memfs::MemFs::new())))
+            .build_handler();
+        let send = |method: &str, path: &str| {
+            let body = if method == "PUT" { "{}" } else { "" };
+            let req = Request::builder().method(method).uri(path);
+            dav.handle(req.body(hyper::Body::from(body)).unwrap())
+        };
+        assert!(send("MKCOL", "/db").await.status().is_success());
+        assert!(send("PUT", "/db/record").await.status().is_success());
+        assert!(send("PUT", "/page.html").await.status().is_success());
+
+        let resp = send("GET", "/db/record").await;
+        assert_eq!(resp.headers()["content-type"], "application/json");
+        let resp = send("GET", "/page.html").await;
+        assert_eq!(resp.headers()["content-type"], "text/html");
+    }
+
+    #[tokio::test]
+    async fn cache_control() {
+        use crate::{memfs::MemFs, DavHandler};
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .cache_control(|path, _| {
+                let assets = path.as_bytes().starts_with(b"/assets/");
+                assets.then(|| HeaderValue::from_static("max-age=3600"))
+            })
+            .build_handler();
+        let send = |method: &str, path: &str, etag: Option<&HeaderValue>| {
+            let mut req = Request::builder().method(method).uri(path);
+            if let Some(etag) = etag {
+                req = req.header("If-None-Match", etag);
+            }
+            dav.handle(req.body(hyper::Body::empty()).unwrap())
+        };
+        assert!(send("MKCOL", "/assets", None).await.status().is_success());
+        assert!(send("PUT", "/assets/app.js", None).await.status().is_success());
+        assert!(send("PUT", "/api", None).await.status().is_success());
+
+        let resp = send("GET", "/assets/app.js", None).await;
+        assert_eq!(resp.headers()["cache-control"], "max-age=3600");
+        let resp = send("HEAD", "/assets/app.js", Some(&resp.headers()["etag"])).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()["cache-control"], "max-age=3600");
+        assert!(send("GET", "/api", None).await.headers().get("cache-control").is_none());
+        assert!(send("GET", "/assets/none", None).await.headers().get("cache-control").is_none());
+    }
+
+    #[tokio::test]
+    async fn content_disposition() {
+        use crate::{memfs::MemFs, DavHandler};
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .html_as_attachment(true)
+            .content_disposition(|path, _| {
+                match path.as_bytes() {
+                    b"/inline.html" => Some(Disposition::Inline),
+                    b"/report.csv" => Some(Disposition::Attachment(Some("Über \"q1\".csv".to_string()))),
+                    _ => None,
+                }
+            })
+            .build_handler();
+        let send = |method: &str, path: &str, body: &'static str| {
+            let req = Request::builder().method(method).uri(path);
+            dav.handle(req.body(hyper::Body::from(body)).unwrap())
+        };
+        for path in &["/page.html", "/inline.html", "/report.csv", "/file.txt"] {
+            assert!(send("PUT", path, "<p>hi</p>").await.status().is_success());
+        }
+        let disposition = |path: &'static str| {
+            let resp = send("GET", path, "");
+            async move { resp.await.headers().get("content-disposition").cloned() }
+        };
+        assert_eq!(disposition("/page.html").await.unwrap(), "attachment; filename=\"page.html\"");
+        assert_eq!(disposition("/inline.html").await.unwrap(), "inline");
+        assert_eq!(
+            disposition("/report.csv").await.unwrap(),
+            "attachment; filename=\"_ber _q1_.csv\"; filename*=UTF-8''%C3%9Cber%20%22q1%22%2Ecsv"
+        );
+        assert!(disposition("/file.txt").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn collection_redirect() {
+        use crate::{memfs::MemFs, DavHandler};
+
+        let fs = MemFs::new();
+        fs.create_dir(&DavPath::new("/dir/").unwrap()).await.unwrap();
+        let get = |dav: DavHandler, method: &'static str| {
+            async move {
+                let req = Request::builder().method(method).uri("/dir").header("Depth", "1");
+                dav.handle(req.body(hyper::Body::empty()).unwrap()).await
+            }
+        };
+
+        let dav = DavHandler::builder().filesystem(fs.clone()).autoindex(true, None).build_handler();
+        let resp = get(dav.clone(), "GET").await;
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(resp.headers()["location"], "/dir/");
+        let resp = get(dav.clone(), "PROPFIND").await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        assert_eq!(resp.headers()["content-location"], "/dir/");
+
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .autoindex(true, None)
+            .collection_redirect(false)
+            .build_handler();
+        let resp = get(dav.clone(), "GET").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Index of /dir/"));
+    }
+
+    #[tokio::test]
+    async fn range_not_satisfiable() {
+        use crate::{memfs::MemFs, testing::TestClient, DavHandler};
+
+        let c = TestClient::new(DavHandler::builder().filesystem(MemFs::new()).build_handler());
+        c.request("PUT", "/file", vec![b'x'; 500]).await;
+        let get = |range: &'static str| {
+            let c = c.clone();
+            async move {
+                let req = Request::builder().uri("/file").header("Range", range);
+                c.send(req.body("").unwrap()).await
+            }
+        };
+
+        let resp = c.request("GET", "/file", "").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["accept-ranges"], "bytes");
+
+        let resp = get("bytes=1000-").await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(resp.headers()["content-range"], "bytes */500");
+        assert_eq!(resp.headers()["accept-ranges"], "bytes");
+        assert!(resp.body().is_empty());
+
+        let resp = get("bytes=500-").await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        let resp = get("bytes=499-").await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers()["content-range"], "bytes 499-499/500");
+        assert_eq!(resp.headers()["accept-ranges"], "bytes");
+    }
+
+    #[tokio::test]
+    async fn compression() {
+        use crate::{memfs::MemFs, testing::TestClient, DavHandler};
+        #[cfg(any(feature = "compression", feature = "brotli"))]
+        use std::io::Read;
+
+        let dav = DavHandler::builder().filesystem(MemFs::new()).compression(true).build_handler();
+        let c = TestClient::new(dav);
+        let text = "All work and no play makes Jack a dull boy.\n".repeat(100);
+        c.request("PUT", "/file.txt", text.clone()).await;
+        c.request("PUT", "/image.png", text.clone()).await;
+        let get = |path: &'static str, accept: &'static str| {
+            let c = c.clone();
+            async move {
+                let req = Request::builder().uri(path).header("Accept-Encoding", accept);
+                c.send(req.body("").unwrap()).await
+            }
+        };
+
+        let plain = get("/file.txt", "identity").await;
+        assert!(plain.headers().get("content-encoding").is_none());
+        assert_eq!(plain.headers()["vary"], "Accept-Encoding");
+        let etag = plain.headers()["etag"].to_str().unwrap().to_string();
+        assert!(etag.starts_with('"'));
+
+        // br is preferred over gzip. The ETag is weak, and there is no length.
+        #[cfg(feature = "brotli")]
+        {
+            let resp = get("/file.txt", "gzip, br").await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()["content-encoding"], "br");
+            assert_eq!(resp.headers()["content-type"], "text/plain");
+            assert_eq!(resp.headers()["vary"], "Accept-Encoding");
+            assert_eq!(resp.headers()["etag"], format!("W/{}", etag).as_str());
+            assert!(resp.headers().get("content-length").is_none());
+            assert!(resp.body().len() < text.len() / 10);
+            let mut data = String::new();
+            brotli::Decompressor::new(&resp.body()[..], 4096).read_to_string(&mut data).unwrap();
+            assert_eq!(data, text);
+        }
+
+        #[cfg(feature = "compression")]
+        {
+            let resp = get("/file.txt", "gzip, br;q=0.5").await;
+            assert_eq!(resp.headers()["content-encoding"], "gzip");
+            assert_eq!(resp.headers()["etag"], format!("W/{}", etag).as_str());
+            let mut data = String::new();
+            flate2::read::GzDecoder::new(&resp.body()[..]).read_to_string(&mut data).unwrap();
+            assert_eq!(data, text);
+        }
+
+        // not for types that do not compress, and not for ranges.
+        let resp = get("/image.png", "br").await;
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert!(resp.headers().get("vary").is_none());
+        let req = Request::builder()
+            .uri("/file.txt")
+            .header("Accept-Encoding", "br")
+            .header("Range", "bytes=0-3");
+        let resp = c.send(req.body("").unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(resp.into_body(), "All ");
+    }
+
+    #[tokio::test]
+    async fn precompressed() {
+        use crate::{memfs::MemFs, testing::TestClient, DavHandler};
+
+        let dav = DavHandler::builder().filesystem(MemFs::new()).precompressed(true).build_handler();
+        let c = TestClient::new(dav);
+        let get = |accept: &'static str| {
+            let c = c.clone();
+            async move {
+                let req = Request::builder().uri("/app.js").header("Accept-Encoding", accept);
+                c.send(req.body("").unwrap()).await
+            }
+        };
+        c.request("PUT", "/app.js", "plain").await;
+        c.request("PUT", "/app.js.gz", "gzipped").await;
+        c.request("PUT", "/app.js.br", "brotli").await;
+
+        let resp = get("gzip, br").await;
+        assert_eq!(resp.headers()["content-encoding"], "br");
+        assert_eq!(resp.headers()["content-type"], "text/javascript");
+        assert_eq!(resp.headers()["vary"], "Accept-Encoding");
+        assert_eq!(resp.into_body(), "brotli");
+        let resp = get("gzip, br;q=0.5").await;
+        assert_eq!(resp.headers()["content-encoding"], "gzip");
+        assert_eq!(resp.into_body(), "gzipped");
+        let resp = get("identity").await;
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(resp.headers()["vary"], "Accept-Encoding");
+        assert_eq!(resp.into_body(), "plain");
+
+        // older than the file itself, so not used.
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        c.request("PUT", "/app.js", "newer").await;
+        let resp = get("gzip, br").await;
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(resp.into_body(), "newer");
+    }
+
+    #[tokio::test]
+    async fn not_modified() {
+        use crate::{memfs::MemFs, DavHandler};
+
+        let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+        let send = |method: &str, hdrs: &[(&str, &str)]| {
+            let mut req = Request::builder().method(method).uri("/file");
+            for (k, v) in hdrs {
+                req = req.header(*k, *v);
+            }
+            let body = if method == "PUT" { "0123456789" } else { "" };
+            dav.handle(req.body(hyper::Body::from(body)).unwrap())
+        };
+        assert!(send("PUT", &[]).await.status().is_success());
+        let resp = send("GET", &[]).await;
+        let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+        let modified = resp.headers()["last-modified"].to_str().unwrap().to_string();
+
+        for hdrs in [[("If-None-Match", &*etag)], [("If-Modified-Since", &*modified)]] {
+            for method in ["GET", "HEAD"] {
+                let resp = send(method, &hdrs).await;
+                assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+                assert_eq!(resp.headers()["etag"], &*etag);
+                assert_eq!(resp.headers()["last-modified"], &*modified);
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                assert!(body.is_empty());
+            }
+        }
+
+        // If-None-Match takes precedence over If-Modified-Since.
+        let hdrs = [("If-None-Match", "\"other\""), ("If-Modified-Since", &*modified)];
+        assert_eq!(send("GET", &hdrs).await.status(), StatusCode::OK);
+
+        // a range request for a cached copy is a 304 as well, without a range.
+        let hdrs = [("If-None-Match", &*etag), ("Range", "bytes=2-4")];
+        let resp = send("GET", &hdrs).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert!(resp.headers().get("content-range").is_none());
+        let old = "Thu, 01 Jan 2004 00:00:00 GMT";
+        let hdrs = [("If-Modified-Since", old), ("Range", "bytes=2-4")];
+        let resp = send("GET", &hdrs).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "234");
+    }
+
+    #[tokio::test]
+    async fn head_is_get() {
+        use crate::{memfs::MemFs, testing::TestClient, DavHandler};
+
+        let dav = DavHandler::builder().filesystem(MemFs::new()).autoindex(true, None).build_handler();
+        let c = TestClient::new(dav);
+        c.request("PUT", "/file.txt", "hello, world").await;
+        c.request("MKCOL", "/dir", "").await;
+        c.request("PUT", "/dir/x", "x").await;
+
+        let check = |path: &'static str, range: Option<&'static str>, status: StatusCode| {
+            let c = c.clone();
+            async move {
+                let req = |method: &str| {
+                    let mut req = Request::builder().method(method).uri(path);
+                    if let Some(range) = range {
+                        req = req.header("Range", range);
+                    }
+                    req.body("").unwrap()
+                };
+                let get = c.send(req("GET")).await;
+                let head = c.send(req("HEAD")).await;
+                assert_eq!(get.status(), status);
+                assert_eq!(head.status(), status);
+                assert_eq!(head.headers(), get.headers());
+                assert!(head.body().is_empty());
+            }
+        };
+        check("/file.txt", None, StatusCode::OK).await;
+        check("/file.txt", Some("bytes=0-4"), StatusCode::PARTIAL_CONTENT).await;
+        check("/file.txt", Some("bytes=100-"), StatusCode::RANGE_NOT_SATISFIABLE).await;
+        check("/dir/", None, StatusCode::OK).await;
+        check("/dir", None, StatusCode::MOVED_PERMANENTLY).await;
+    }
+
+    #[tokio::test]
+    async fn negotiate_language() {
+        use crate::{memfs::MemFs, testing::TestClient, DavHandler};
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .indexfile("index.html")
+            .negotiate_language(true)
+            .build_handler();
+        let c = TestClient::new(dav);
+        c.request("PUT", "/index.html", "plain").await;
+        c.request("PUT", "/index.en.html", "hello").await;
+        c.request("PUT", "/index.de.html", "hallo").await;
+        c.request("MKCOL", "/de", "").await;
+        c.request("PUT", "/de/index.de.html", "nur deutsch").await;
+
+        let get = |path: &'static str, accept: &'static str| {
+            let c = c.clone();
+            async move {
+                let req = Request::builder().uri(path).header("Accept-Language", accept);
+                c.send(req.body("").unwrap()).await
+            }
+        };
+        let resp = get("/index.html", "de-CH, en;q=0.5").await;
+        assert_eq!(resp.headers()["content-language"], "de");
+        assert_eq!(resp.headers()["vary"], "Accept-Language");
+        assert_eq!(resp.into_body(), "hallo");
+        assert_eq!(get("/", "fr, en;q=0.8").await.into_body(), "hello");
+
+        // no match, or no preference: the plain file.
+        let resp = get("/index.html", "fr").await;
+        assert!(resp.headers().get("content-language").is_none());
+        assert_eq!(resp.into_body(), "plain");
+        assert_eq!(get("/index.html", "de;q=0, *").await.into_body(), "plain");
+
+        // Vary, also if there was nothing to negotiate.
+        let resp = c.request("GET", "/index.html", "").await;
+        assert_eq!(resp.headers()["vary"], "Accept-Language");
+        assert_eq!(resp.into_body(), "plain");
+        let resp = get("/index.html", "*").await;
+        assert_eq!(resp.headers()["vary"], "Accept-Language");
+
+        // only the first few languages are tried.
+        let many = "a1, a2, a3, a4, a5, a6, a7, a8, de";
+        assert_eq!(get("/index.html", many).await.into_body(), "plain");
+        let resp = get("/index.html", &many[4..]).await;
+        assert_eq!(resp.headers()["vary"], "Accept-Language");
+        assert_eq!(resp.into_body(), "hallo");
+
+        // a variant works as an index file even without the plain file.
+        assert_eq!(get("/de/", "de").await.into_body(), "nur deutsch");
+        assert_eq!(get("/de/", "en").await.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -9,6 +9,7 @@ use xmltree::{self, Element};
 
 use crate::body::Body;
 use crate::conditional::{dav_if_match, if_match};
+use crate::davhandler::{href, HrefRewrite};
 use crate::davheaders::{self, DavTimeout};
 use crate::davpath::DavPath;
 use crate::errors::*;
@@ -45,20 +46,21 @@ impl crate::DavInner {
 
             // try refresh
             // FIXME: you can refresh a lock owned by someone else. is that OK?
-            let timeout = get_timeout(&req, true, false);
+            let timeout = get_timeout(&req, false, self.max_lock_timeout);
             let lock = match locksystem.refresh(&path, &tokens[0], timeout) {
                 Ok(lock) => lock,
                 Err(_) => return Err(SC::PRECONDITION_FAILED.into()),
             };
 
             // output result
-            let prop = build_lock_prop(&lock, true);
+            let prop = build_lock_prop(&lock, true, self.href_rewrite.as_ref());
             let mut emitter = xmltree_ext::emitter(MemBuffer::new())?;
             prop.write_ev(&mut emitter)?;
             let buffer = emitter.into_inner().take();
 
             let ct = "application/xml; charset=utf-8".to_owned();
             res.headers_mut().typed_insert(davheaders::ContentType(ct));
+            res.headers_mut().typed_insert(timeout_header(&lock));
             *res.body_mut() = Body::from(buffer);
             return Ok(res);
         }
@@ -137,7 +139,7 @@ impl crate::DavInner {
         let shared = shared.unwrap();
 
         // create lock
-        let timeout = get_timeout(&req, false, shared);
+        let timeout = get_timeout(&req, shared, self.max_lock_timeout);
         let principal = self.principal.as_ref().map(|s| s.as_str());
         let lock = match locksystem.lock(&path, principal, owner.as_ref(), timeout, shared, deep) {
             Ok(lock) => lock,
@@ -169,14 +171,21 @@ impl crate::DavInner {
         let ct = "application/xml; charset=utf-8".to_owned();
         res.headers_mut().typed_insert(davheaders::LockToken(lt));
         res.headers_mut().typed_insert(davheaders::ContentType(ct));
+        res.headers_mut().typed_insert(timeout_header(&lock));
         if let None = meta {
             *res.status_mut() = SC::CREATED;
         } else {
             *res.status_mut() = SC::OK;
         }
 
+        // the new lock, and any other (shared) locks on the resource.
+        let rewrite = self.href_rewrite.as_ref();
+        let mut actlocks = vec![build_lock_prop(&lock, false, rewrite)];
+        for l in locksystem.discover(&path).iter().filter(|l| l.token != lock.token) {
+            actlocks.push(build_lock_prop(l, false, rewrite));
+        }
         let mut emitter = xmltree_ext::emitter(MemBuffer::new())?;
-        let prop = build_lock_prop(&lock, true);
+        let prop = lockdiscovery_prop(actlocks);
         prop.write_ev(&mut emitter)?;
         let buffer = emitter.into_inner().take();
 
@@ -215,7 +224,12 @@ impl crate::DavInner {
     }
 }
 
-pub(crate) fn list_lockdiscovery(ls: Option<&Box<dyn DavLockSystem>>, path: &DavPath) -> Element {
+pub(crate) fn list_lockdiscovery(
+    ls: Option<&Box<dyn DavLockSystem>>,
+    path: &DavPath,
+    rewrite: Option<&HrefRewrite>,
+) -> Element
+{
     let mut elem = Element::new2("D:lockdiscovery");
 
     // must have a locksystem or bail
@@ -227,7 +241,7 @@ pub(crate) fn list_lockdiscovery(ls: Option<&Box<dyn DavLockSystem>>, path: &Dav
     // list the locks.
     let locks = locksystem.discover(path);
     for lock in &locks {
-        elem.push_element(build_lock_prop(lock, false));
+        elem.push_element(build_lock_prop(lock, false, rewrite));
     }
     elem
 }
@@ -261,31 +275,37 @@ pub(crate) fn list_supportedlock(ls: Option<&Box<dyn DavLockSystem>>) -> Element
     elem
 }
 
-// process timeout header
-fn get_timeout(req: &Request<()>, refresh: bool, shared: bool) -> Option<Duration> {
-    let max_timeout = if shared {
-        Duration::new(86400, 0)
-    } else {
-        Duration::new(600, 0)
+// process timeout header. Locks always time out, so the first value
+// in the list that is not "Infinite" is used, up to the maximum. If
+// there is no such value, the maximum is used.
+fn get_timeout(req: &Request<()>, shared: bool, max_timeout: Option<Duration>) -> Option<Duration> {
+    let max_timeout = match max_timeout {
+        Some(max) => max,
+        None if shared => Duration::new(86400, 0),
+        None => Duration::new(600, 0),
     };
-    match req.headers().typed_get::<davheaders::Timeout>() {
-        Some(davheaders::Timeout(ref vec)) if vec.len() > 0 => {
-            match vec[0] {
-                DavTimeout::Infinite => {
-                    if refresh {
-                        None
-                    } else {
-                        Some(max_timeout)
-                    }
-                },
-                DavTimeout::Seconds(n) => Some(cmp::min(max_timeout, Duration::new(n as u64, 0))),
-            }
-        },
-        _ => None,
-    }
+    let timeouts = match req.headers().typed_get::<davheaders::Timeout>() {
+        Some(davheaders::Timeout(vec)) => vec,
+        None => Vec::new(),
+    };
+    let seconds = timeouts.iter().find_map(|t| {
+        match t {
+            DavTimeout::Seconds(n) => Some(Duration::new(*n as u64, 0)),
+            DavTimeout::Infinite => None,
+        }
+    });
+    Some(seconds.map_or(max_timeout, |s| cmp::min(max_timeout, s)))
+}
+
+// The timeout that was granted, for the response header.
+fn timeout_header(lock: &DavLock) -> davheaders::Timeout {
+    davheaders::Timeout(vec![match lock.timeout {
+        None => DavTimeout::Infinite,
+        Some(d) => DavTimeout::Seconds(cmp::min(d.as_secs(), u32::MAX as u64) as u32),
+    }])
 }
 
-fn build_lock_prop(lock: &DavLock, full: bool) -> Element {
+fn build_lock_prop(lock: &DavLock, full: bool, rewrite: Option<&HrefRewrite>) -> Element {
     let mut actlock = Element::new2("D:activelock");
 
     let mut elem = Element::new2("D:lockscope");
@@ -318,7 +338,7 @@ fn build_lock_prop(lock: &DavLock, full: bool) -> Element {
     actlock.push_element(locktokenelem);
 
     let mut lockroot = Element::new2("D:lockroot");
-    lockroot.push_element(Element::new2("D:href").text(lock.path.with_prefix().as_url_string()));
+    lockroot.push_element(Element::new2("D:href").text(href(rewrite, &lock.path)));
     actlock.push_element(lockroot);
 
     if let Some(ref o) = lock.owner {
@@ -328,11 +348,107 @@ fn build_lock_prop(lock: &DavLock, full: bool) -> Element {
     if !full {
         return actlock;
     }
+    lockdiscovery_prop(vec![actlock])
+}
 
+fn lockdiscovery_prop(actlocks: Vec<Element>) -> Element {
     let mut ldis = Element::new2("D:lockdiscovery");
-    ldis.push_element(actlock);
+    for actlock in actlocks.into_iter() {
+        ldis.push_element(actlock);
+    }
     let mut prop = Element::new2("D:prop").ns("D", "DAV:");
     prop.push_element(ldis);
 
     prop
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+    use crate::DavHandler;
+    use http::{Request, StatusCode};
+
+    const LOCKINFO: &str = r#"<D:lockinfo xmlns:D="DAV:">
+        <D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+
+    async fn req(dav: &DavHandler, method: &str, path: &str, hdrs: &[(&str, &str)], body: &str) -> StatusCode
+    {
+        let mut req = Request::builder().method(method).uri(path);
+        for (k, v) in hdrs {
+            req = req.header(*k, *v);
+        }
+        dav.handle(req.body(hyper::Body::from(body.to_string())).unwrap()).await.status()
+    }
+
+    // Take an exclusive lock, and return its token.
+    async fn lock(dav: &DavHandler, path: &str, depth: &str) -> String {
+        let req = Request::builder().method("LOCK").uri(path).header("Depth", depth);
+        let resp = dav.handle(req.body(hyper::Body::from(LOCKINFO)).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let token = resp.headers()["lock-token"].to_str().unwrap();
+        token.trim_matches(|c| c == '<' || c == '>').to_string()
+    }
+
+    #[tokio::test]
+    async fn write_needs_token() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        req(&dav, "MKCOL", "/dir", &[], "").await;
+        req(&dav, "PUT", "/file", &[], "x").await;
+        req(&dav, "PUT", "/dir/file", &[], "x").await;
+
+        let token = lock(&dav, "/file", "0").await;
+        let dtoken = lock(&dav, "/dir", "infinity").await;
+
+        let locked = StatusCode::LOCKED;
+        let (good, tagged) = (format!("(<{}>)", token), format!("</file> (<{}>)", token));
+        let wrong = "(<urn:uuid:00000000-0000-0000-0000-000000000000>)";
+        assert_eq!(req(&dav, "PUT", "/file", &[], "y").await, locked);
+        assert_eq!(req(&dav, "PUT", "/file", &[("If", wrong)], "y").await, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(req(&dav, "PUT", "/file", &[("If", &good)], "y").await, StatusCode::NO_CONTENT);
+        assert_eq!(req(&dav, "PUT", "/file", &[("If", &tagged)], "y").await, StatusCode::NO_CONTENT);
+        assert_eq!(req(&dav, "DELETE", "/file", &[], "").await, locked);
+        assert_eq!(req(&dav, "MOVE", "/file", &[("Destination", "/other")], "").await, locked);
+        assert_eq!(req(&dav, "COPY", "/dir/file", &[("Destination", "/file")], "").await, locked);
+        // copying a locked resource is fine.
+        let copy = req(&dav, "COPY", "/file", &[("Destination", "/other")], "").await;
+        assert_eq!(copy, StatusCode::CREATED);
+
+        // the depth infinity lock on /dir covers everything below it.
+        assert_eq!(req(&dav, "PUT", "/dir/new", &[], "y").await, locked);
+        assert_eq!(req(&dav, "MKCOL", "/dir/sub", &[], "").await, locked);
+        assert_eq!(req(&dav, "DELETE", "/dir/file", &[], "").await, locked);
+        assert_eq!(req(&dav, "MOVE", "/other", &[("Destination", "/dir/other")], "").await, locked);
+        let dgood = format!("(<{}>)", dtoken);
+        assert_eq!(req(&dav, "PUT", "/dir/new", &[("If", &dgood)], "y").await, StatusCode::CREATED);
+        assert_eq!(req(&dav, "MKCOL", "/dir/sub", &[("If", &dgood)], "").await, StatusCode::CREATED);
+        assert_eq!(req(&dav, "DELETE", "/dir", &[], "").await, locked);
+        assert_eq!(req(&dav, "DELETE", "/dir", &[("If", &dgood)], "").await, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .max_lock_timeout(std::time::Duration::from_secs(3600))
+            .build_handler();
+        let lock = |path: &str, timeout: &str| {
+            let req = Request::builder().method("LOCK").uri(path).header("Timeout", timeout);
+            dav.handle(req.body(hyper::Body::from(LOCKINFO)).unwrap())
+        };
+
+        let resp = lock("/a", "Infinite, Second-4100000000").await;
+        assert_eq!(resp.headers()["timeout"], "Second-3600");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<D:timeout>Second-3600</D:timeout>"));
+
+        let resp = lock("/b", "Second-60,Infinite").await;
+        assert_eq!(resp.headers()["timeout"], "Second-60");
+        let resp = lock("/c", "Infinite").await;
+        assert_eq!(resp.headers()["timeout"], "Second-3600");
+    }
+}
@@ -28,6 +28,11 @@ impl crate::DavInner {
             }
         }
 
+        // RFC 4918 9.3.1: 409 Conflict if the parent does not exist.
+        if meta.is_err() && !self.has_parent(&path).await {
+            return Err(DavError::Status(StatusCode::CONFLICT));
+        }
+
         let mut res = Response::new(Body::empty());
 
         match self.fs.create_dir(&path).await {
@@ -38,8 +43,8 @@ impl crate::DavInner {
             Ok(()) => {
                 if path.is_collection() {
                     path.add_slash();
-                    res.headers_mut()
-                        .typed_insert(davheaders::ContentLocation(path.with_prefix().as_url_string()));
+                    let location = crate::davhandler::href(self.href_rewrite.as_ref(), &path);
+                    res.headers_mut().typed_insert(davheaders::ContentLocation(location));
                 }
                 *res.status_mut() = StatusCode::CREATED;
             },
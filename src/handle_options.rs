@@ -1,8 +1,10 @@
 use headers::HeaderMapExt;
-use http::{Request, Response};
+use http::{Request, Response, StatusCode};
 
 use crate::body::Body;
-use crate::util::{dav_method, DavMethod};
+use crate::errors::DavError;
+use crate::handle_tus::{TUS_EXTENSIONS, TUS_VERSION};
+use crate::util::{dav_method, DavMethod, DavMethodSet};
 use crate::DavResult;
 
 impl crate::DavInner {
@@ -11,12 +13,43 @@ impl crate::DavInner {
 
         let h = res.headers_mut();
 
+        // What we advertise depends on what the filesystem supports.
+        //
         // We could simply not report webdav level 2 support if self.allow doesn't
-        // contain LOCK/UNLOCK. However we do advertise support, since there might
-        // be LOCK/UNLOCK support in another part of the URL space.
-        let dav = "1,2,3,sabredav-partialupdate";
-        h.insert("DAV", dav.parse().unwrap());
+        // contain LOCK/UNLOCK. However we do advertise support if there is a
+        // locksystem, since there might be LOCK/UNLOCK support in another part
+        // of the URL space.
+        //
+        // A read-only server can't do locking or partial updates though.
+        let caps = self.fs.capabilities();
+        let read_only = self.read_only || !caps.write;
+        let mut dav = "1".to_string();
+        if !read_only && self.ls.is_some() {
+            dav.push_str(",2");
+        }
+        dav.push_str(",3");
+        if !read_only && caps.partial_update {
+            dav.push_str(",sabredav-partialupdate");
+        }
+        if !read_only && caps.bind {
+            dav.push_str(",bind");
+        }
+        if let Some((ref classes, _)) = self.options_extra {
+            for class in classes {
+                dav.push(',');
+                dav.push_str(class);
+            }
+        }
+        h.insert("DAV", dav.parse().map_err(|_| DavError::Status(StatusCode::INTERNAL_SERVER_ERROR))?);
         h.insert("MS-Author-Via", "DAV".parse().unwrap());
+        if self.tus && !read_only && caps.partial_update {
+            h.insert("Tus-Resumable", TUS_VERSION.parse().unwrap());
+            h.insert("Tus-Version", TUS_VERSION.parse().unwrap());
+            h.insert("Tus-Extension", TUS_EXTENSIONS.parse().unwrap());
+            if let Some(max) = self.max_upload_size {
+                h.insert("Tus-Max-Size", max.into());
+            }
+        }
         h.typed_insert(headers::ContentLength(0));
 
         // Helper to add method to array if method is in fact
@@ -28,6 +61,7 @@ impl crate::DavInner {
         let mm = |v: &mut Vec<String>, m: &str, y: DavMethod| {
             if (y == DavMethod::Options || (y != method || islock(y) != islock(method))) &&
                 (!islock(y) || self.ls.is_some()) &&
+                (!read_only || DavMethodSet::WEBDAV_RO.contains(y)) &&
                 self.allow.map(|x| x.contains(y)).unwrap_or(true)
             {
                 v.push(m.to_string());
@@ -50,11 +84,16 @@ impl crate::DavInner {
             if is_file || is_star {
                 mm(&mut v, "HEAD", DavMethod::Head);
                 mm(&mut v, "GET", DavMethod::Get);
-                mm(&mut v, "PATCH", DavMethod::Patch);
+                if caps.partial_update {
+                    mm(&mut v, "PATCH", DavMethod::Patch);
+                }
                 mm(&mut v, "PUT", DavMethod::Put);
             }
             mm(&mut v, "OPTIONS", DavMethod::Options);
             mm(&mut v, "PROPFIND", DavMethod::PropFind);
+            if self.report_handler.is_some() {
+                mm(&mut v, "REPORT", DavMethod::Report);
+            }
             mm(&mut v, "COPY", DavMethod::Copy);
             if path.as_url_string() != "/" {
                 mm(&mut v, "MOVE", DavMethod::Move);
@@ -62,6 +101,18 @@ impl crate::DavInner {
             }
             mm(&mut v, "LOCK", DavMethod::Lock);
             mm(&mut v, "UNLOCK", DavMethod::Unlock);
+            if !is_file && caps.bind {
+                mm(&mut v, "BIND", DavMethod::Bind);
+                mm(&mut v, "UNBIND", DavMethod::Unbind);
+                mm(&mut v, "REBIND", DavMethod::Rebind);
+            }
+        }
+        if let Some((_, ref methods)) = self.options_extra {
+            for m in methods {
+                if !v.iter().any(|x| x == m.as_str()) {
+                    v.push(m.to_string());
+                }
+            }
         }
 
         let a = v.clone().join(",").parse().unwrap();
@@ -70,3 +121,99 @@ impl crate::DavInner {
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::davpath::DavPath;
+    use crate::fs::*;
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+    use crate::mountfs::MountFs;
+    use crate::overlayfs::OverlayFs;
+    use crate::DavHandler;
+    use http::{Method, Request};
+
+    #[tokio::test]
+    async fn options_extra() {
+        let options = || Request::builder().method("OPTIONS").uri("/").body(hyper::Body::empty()).unwrap();
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let resp = dav.handle(options()).await;
+        assert_eq!(resp.headers()["dav"], "1,2,3,sabredav-partialupdate");
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .options_extra(
+                vec!["calendar-access".to_string()],
+                vec![Method::from_bytes(b"MKCALENDAR").unwrap(), Method::from_bytes(b"REPORT").unwrap()],
+            )
+            .build_handler();
+        let resp = dav.handle(options()).await;
+        assert_eq!(resp.headers()["dav"], "1,2,3,sabredav-partialupdate,calendar-access");
+        let allow = resp.headers()["allow"].to_str().unwrap();
+        assert!(allow.starts_with("OPTIONS,PROPFIND,"));
+        assert!(allow.ends_with(",MKCALENDAR,REPORT"));
+    }
+
+    #[tokio::test]
+    async fn capabilities() {
+        #[derive(Clone)]
+        struct NoPatchFs(Box<MemFs>);
+
+        impl DavFileSystem for NoPatchFs {
+            fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+                self.0.open(path, options)
+            }
+            fn read_dir<'a>(
+                &'a self,
+                path: &'a DavPath,
+                meta: ReadDirMeta,
+            ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+            {
+                self.0.read_dir(path, meta)
+            }
+            fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+                self.0.metadata(path)
+            }
+            fn capabilities(&self) -> FsCapabilities {
+                FsCapabilities {
+                    partial_update: false,
+                    ..FsCapabilities::default()
+                }
+            }
+        }
+
+        let request = |path| Request::builder().method("OPTIONS").uri(path).body(hyper::Body::empty());
+        let fs = MemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        dav.handle(Request::put("/file").body(hyper::Body::from("x")).unwrap()).await;
+        let resp = dav.handle(request("/file").unwrap()).await;
+        assert_eq!(resp.headers()["dav"], "1,3,sabredav-partialupdate");
+        assert!(resp.headers()["allow"].to_str().unwrap().contains("PATCH"));
+        assert!(!resp.headers()["allow"].to_str().unwrap().contains("LOCK"));
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(NoPatchFs(fs)))
+            .locksystem(MemLs::new())
+            .build_handler();
+        let resp = dav.handle(request("/file").unwrap()).await;
+        assert_eq!(resp.headers()["dav"], "1,2,3");
+        assert!(!resp.headers()["allow"].to_str().unwrap().contains("PATCH"));
+        assert!(resp.headers()["allow"].to_str().unwrap().contains("LOCK"));
+
+        // through filesystems that wrap others.
+        let mounts = MountFs::new()
+            .mount("/a", Box::new(NoPatchFs(MemFs::new())))
+            .mount("/b", MemFs::new());
+        let overlay = OverlayFs::new(Box::new(NoPatchFs(MemFs::new())), MemFs::new());
+        for fs in [mounts as Box<dyn DavFileSystem>, overlay] {
+            let dav = DavHandler::builder().filesystem(fs).build_handler();
+            let resp = dav.handle(request("/").unwrap()).await;
+            assert_eq!(resp.headers()["dav"], "1,3");
+        }
+    }
+}
@@ -2,11 +2,15 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io::{self, Cursor};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "base64")]
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use bytes::Bytes;
-use futures::{future::BoxFuture, FutureExt, StreamExt};
+use futures::{future, StreamExt};
 use headers::HeaderMapExt;
-use http::{Request, Response, StatusCode};
+use http::{HeaderValue, Request, Response, StatusCode};
 
 use crate::xmltree_ext::*;
 use xml::common::XmlVersion;
@@ -18,19 +22,23 @@ use xmltree::{Element, XMLNode};
 use crate::async_stream::AsyncStream;
 use crate::body::Body;
 use crate::conditional::if_match_get_tokens;
+use crate::davhandler::{href, HrefRewrite};
 use crate::davheaders;
 use crate::davpath::*;
 use crate::errors::*;
 use crate::fs::*;
+use crate::handle_gethead::{mime_type, MimeResolver};
 use crate::handle_lock::{list_lockdiscovery, list_supportedlock};
 use crate::ls::*;
+use crate::propprovider::PropertyProvider;
 use crate::time::{systemtime_to_httpdate, systemtime_to_rfc3339};
-use crate::util::{MemBuffer, dav_xml_error};
+use crate::util::{dav_xml_error, MemBuffer};
 use crate::{DavInner, DavResult};
 
 const NS_APACHE_URI: &'static str = "http://apache.org/dav/props/";
 const NS_DAV_URI: &'static str = "DAV:";
 const NS_MS_URI: &'static str = "urn:schemas-microsoft-com:";
+const NS_DT_URI: &'static str = "urn:uuid:c2f41010-65b3-11d1-a29f-00aa00c14882/";
 
 // list returned by PROPFIND <propname/>.
 const PROPNAME_STR: &'static [&'static str] = &[
@@ -46,6 +54,8 @@ const PROPNAME_STR: &'static [&'static str] = &[
     "D:supportedlock",
     "D:quota-available-bytes",
     "D:quota-used-bytes",
+    "D:current-user-privilege-set",
+    "D:owner",
     "A:executable",
     "Z:Win32LastAccessTime",
 ];
@@ -82,12 +92,40 @@ const MS_ALLPROP_STR: &'static [&'static str] = &[
     "Z:Win32LastModifiedTime",
 ];
 
+// live properties that a PROPPATCH can't set or remove.
+const PROTECTED_STR: &'static [&'static str] = &[
+    "D:creationdate",
+    "D:getcontentlength",
+    "D:getcontenttype",
+    "D:getetag",
+    "D:getlastmodified",
+    "D:lockdiscovery",
+    "D:resourcetype",
+    "D:supportedlock",
+    "D:quota-available-bytes",
+    "D:quota-used-bytes",
+    "D:current-user-privilege-set",
+    "D:current-user-principal",
+    "D:supported-report-set",
+    "D:sync-token",
+    "D:owner",
+    "A:executable",
+];
+
 lazy_static! {
     static ref ALLPROP: Vec<Element> = init_staticprop(ALLPROP_STR);
     static ref MS_ALLPROP: Vec<Element> = init_staticprop(MS_ALLPROP_STR);
     static ref PROPNAME: Vec<Element> = init_staticprop(PROPNAME_STR);
 }
 
+// While walking a directory tree, send out what we have
+// when this much is buffered, or at least this often.
+const FLUSH_SIZE: usize = 16384;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+// Do not descend further than this when doing a Depth: infinity walk.
+const MAX_PROPFIND_NESTING: usize = 256;
+
 type Emitter = EventWriter<MemBuffer>;
 type Sender = crate::async_stream::Sender<bytes::Bytes, io::Error>;
 
@@ -96,15 +134,26 @@ struct StatusElement {
     element: Element,
 }
 
-struct PropWriter {
-    emitter:   Emitter,
-    tx:        Option<Sender>,
-    name:      String,
-    props:     Vec<Element>,
-    fs:        Box<dyn DavFileSystem>,
-    ls:        Option<Box<dyn DavLockSystem>>,
-    useragent: String,
-    q_cache:   QuotaCache,
+pub(crate) struct PropWriter {
+    emitter:       Emitter,
+    tx:            Option<Sender>,
+    name:          String,
+    props:         Vec<Element>,
+    include:       Vec<Element>,
+    // namespace -> prefix, for the namespaces declared on the root.
+    prefixes:      HashMap<String, String>,
+    fs:            Box<dyn DavFileSystem>,
+    ls:            Option<Box<dyn DavLockSystem>>,
+    useragent:     String,
+    q_cache:       QuotaCache,
+    mime_resolver: Option<MimeResolver>,
+    principal:     Option<String>,
+    minimal:       bool,
+    provider:      Option<Arc<dyn PropertyProvider>>,
+    href_rewrite:  Option<HrefRewrite>,
+    // protected properties a PROPPATCH tried to change.
+    protected:     Vec<Element>,
+    last_flush:    Instant,
 }
 
 #[derive(Default, Clone, Copy)]
@@ -146,16 +195,7 @@ impl DavInner {
         res.headers_mut().typed_insert(headers::Pragma::no_cache());
 
         let depth = match req.headers().typed_get::<davheaders::Depth>() {
-            Some(davheaders::Depth::Infinity) | None => {
-                if req.headers().typed_get::<davheaders::XLitmus>().is_none() {
-                    let ct = "application/xml; charset=utf-8".to_owned();
-                    res.headers_mut().typed_insert(davheaders::ContentType(ct));
-                    *res.status_mut() = StatusCode::FORBIDDEN;
-                    *res.body_mut() = dav_xml_error("<D:propfind-finite-depth/>");
-                    return Ok(res);
-                }
-                davheaders::Depth::Infinity
-            },
+            Some(davheaders::Depth::Infinity) | None => davheaders::Depth::Infinity,
             Some(d) => d.clone(),
         };
 
@@ -164,6 +204,24 @@ impl DavInner {
         let meta = self.fs.metadata(&path).await?;
         let meta = self.fixpath(&mut res, &mut path, meta);
 
+        // RFC4918 9.1: the server may refuse a depth it thinks is too much.
+        // For a file the depth does not matter.
+        if let Some(max_depth) = self.propfind_max_depth.filter(|_| meta.is_dir()) {
+            let too_deep = match depth {
+                davheaders::Depth::Zero => false,
+                davheaders::Depth::One => max_depth < 1,
+                davheaders::Depth::Infinity => true,
+            };
+            if too_deep {
+                debug!("propfind: depth {:?} exceeds the maximum of {}", depth, max_depth);
+                let ct = "application/xml; charset=utf-8".to_owned();
+                res.headers_mut().typed_insert(davheaders::ContentType(ct));
+                *res.status_mut() = StatusCode::FORBIDDEN;
+                *res.body_mut() = dav_xml_error("<D:propfind-finite-depth/>");
+                return Ok(res);
+            }
+        }
+
         let mut root = None;
         if xmldata.len() > 0 {
             root = match Element::parse(Cursor::new(xmldata)) {
@@ -181,8 +239,10 @@ impl DavInner {
         let (name, props) = match root {
             None => ("allprop", Vec::new()),
             Some(mut elem) => {
+                // RFC4918 14.8, but accept the "includes" that we used to expect as well.
                 let includes = elem
-                    .take_child("includes")
+                    .take_child("include")
+                    .or_else(|| elem.take_child("includes"))
                     .map_or(Vec::new(), |n| n.take_child_elems());
                 match elem
                     .child_elems_into_iter()
@@ -203,7 +263,14 @@ impl DavInner {
 
         trace!("propfind: type request: {}", name);
 
-        let mut pw = PropWriter::new(&req, &mut res, name, props, &self.fs, self.ls.as_ref())?;
+        let mut pw = self.prop_writer(req, &mut res, name, props)?;
+
+        // RFC8144 2.1: with "return=minimal", leave out the 404 propstats.
+        if prefer_minimal(req) {
+            pw.minimal = true;
+            res.headers_mut()
+                .insert("preference-applied", HeaderValue::from_static("return=minimal"));
+        }
 
         *res.body_mut() = Body::from(AsyncStream::new(|tx| {
             async move {
@@ -224,53 +291,114 @@ impl DavInner {
         Ok(res)
     }
 
-    fn propfind_directory<'a>(
-        &'a self,
-        path: &'a DavPath,
+    // A PropWriter with the settings from our config.
+    pub(crate) fn prop_writer(
+        &self,
+        req: &Request<()>,
+        res: &mut Response<Body>,
+        name: &str,
+        props: Vec<Element>,
+    ) -> DavResult<PropWriter>
+    {
+        let mut pw = PropWriter::new(req, res, name, props, &self.fs, self.ls.as_ref())?;
+        pw.mime_resolver = self.mime_resolver.clone();
+        pw.principal = self.principal.clone();
+        pw.provider = self.property_provider.clone();
+        pw.href_rewrite = self.href_rewrite.clone();
+        Ok(pw)
+    }
+
+    // Walk the tree below `path` and write the properties of every entry.
+    //
+    // This is a depth-first walk, in the same order as a recursive one would
+    // be, but it keeps a stack of open directories instead of recursing.
+    pub(crate) async fn propfind_directory(
+        &self,
+        path: &DavPath,
         depth: davheaders::Depth,
-        propwriter: &'a mut PropWriter,
-    ) -> BoxFuture<'a, DavResult<()>>
+        propwriter: &mut PropWriter,
+    ) -> DavResult<()>
     {
-        async move {
-            let readdir_meta = match self.hide_symlinks {
-                Some(true) | None => ReadDirMeta::DataSymlink,
-                Some(false) => ReadDirMeta::Data,
+        let readdir_meta = match self.hide_symlinks {
+            Some(true) | None => ReadDirMeta::DataSymlink,
+            Some(false) => ReadDirMeta::Data,
+        };
+        let mut stack = Vec::new();
+        if let Some(entries) = self.propfind_read_dir(path, readdir_meta).await {
+            stack.push((path.clone(), entries));
+        }
+
+        while let Some((dir, entries)) = stack.last_mut() {
+            let (name, meta) = match entries.next().await {
+                Some(entry) => entry,
+                None => {
+                    stack.pop();
+                    continue;
+                },
             };
-            let mut entries = match self.fs.read_dir(path, readdir_meta).await {
-                Ok(entries) => entries,
+            let mut npath = dir.clone();
+            npath.push_segment(&name);
+            let meta = match meta {
+                Ok(meta) => meta,
                 Err(e) => {
-                    // if we cannot read_dir, just skip it.
-                    error!("read_dir error {:?}", e);
-                    return Ok(());
+                    trace!("metadata error on {}. Skipping {:?}", npath, e);
+                    continue;
                 },
             };
-
-            while let Some(dirent) = entries.next().await {
-                let mut npath = path.clone();
-                npath.push_segment(&dirent.name());
-                let meta = match dirent.metadata().await {
-                    Ok(meta) => meta,
-                    Err(e) => {
-                        trace!("metadata error on {}. Skipping {:?}", npath, e);
-                        continue;
-                    },
-                };
-                if meta.is_symlink() {
-                    continue;
-                }
-                if meta.is_dir() {
-                    npath.add_slash();
-                }
-                let is_dir = meta.is_dir();
-                propwriter.write_props(&npath, meta).await?;
-                propwriter.flush().await?;
-                if depth == davheaders::Depth::Infinity && is_dir {
-                    self.propfind_directory(&npath, depth, propwriter).await?;
+            if meta.is_symlink() {
+                continue;
+            }
+            if meta.is_dir() {
+                npath.add_slash();
+            }
+            let is_dir = meta.is_dir();
+            if depth == davheaders::Depth::Infinity && is_dir && stack.len() >= MAX_PROPFIND_NESTING {
+                // RFC5323 4.2: tell the client that the result is incomplete.
+                debug!("propfind: {} is nested too deep, not descending", npath);
+                let error = Element::new2("D:number-of-matches-within-limits");
+                propwriter.write_status_error(&npath, StatusCode::INSUFFICIENT_STORAGE, Some(error))?;
+                continue;
+            }
+            propwriter.write_props(&npath, meta).await?;
+            propwriter.flush_some().await?;
+            if depth == davheaders::Depth::Infinity && is_dir {
+                if let Some(entries) = self.propfind_read_dir(&npath, readdir_meta).await {
+                    stack.push((npath, entries));
                 }
             }
-            Ok(())
         }
-        .boxed()
+        Ok(())
+    }
+
+    // The names of the entries of a directory, and their metadata. That is
+    // looked up for `fs_concurrency` entries at a time, in order.
+    async fn propfind_read_dir(
+        &self,
+        path: &DavPath,
+        readdir_meta: ReadDirMeta,
+    ) -> Option<FsStream<(Vec<u8>, FsResult<Box<dyn DavMetaData>>)>>
+    {
+        match self.fs.read_dir(path, readdir_meta).await {
+            Ok(entries) => {
+                // leave out uploads that are in progress.
+                let atomic_put = self.atomic_put;
+                let entries = entries.filter(move |dirent| {
+                    future::ready(!atomic_put || !is_temp_file(&dirent.name()))
+                });
+                let entries = entries.map(|dirent| {
+                    async move {
+                        let meta = dirent.metadata().await;
+                        (dirent.name(), meta)
+                    }
+                });
+                Some(Box::pin(entries.buffered(self.fs_concurrency)))
+            },
+            Err(e) => {
+                // if we cannot read_dir, just skip it.
+                error!("read_dir error {:?}", e);
+                None
+            },
+        }
     }
 
     // set/change a live property. returns StatusCode::CONTINUE if
@@ -372,6 +500,95 @@ impl DavInner {
         }
     }
 
+    // Make the changes of a PROPPATCH, after they were checked. It is all
+    // or nothing: if one change fails, the changes that were already made
+    // are undone, and all properties except the failed ones get a 424.
+    async fn proppatch_commit(
+        &self,
+        path: &DavPath,
+        provided: Vec<DavProp>,
+        patch: Vec<(bool, DavProp)>,
+    ) -> Vec<(StatusCode, DavProp)>
+    {
+        let name = |p: &DavProp| DavProp { xml: None, ..p.clone() };
+        let same = |a: &DavProp, b: &DavProp| a.name == b.name && a.namespace == b.namespace;
+        let mut ret = Vec::new();
+        let mut failed = false;
+
+        // first the dead properties in the filesystem, in one go. To be
+        // able to undo the changes, remember the current values.
+        let mut undo_patch = Vec::new();
+        if !patch.is_empty() {
+            let old = self.fs.get_props(path, true).await.unwrap_or_default();
+            for (_, p) in &patch {
+                match old.iter().find(|o| same(o, p)) {
+                    Some(o) => undo_patch.push((true, o.clone())),
+                    None => undo_patch.push((false, name(p))),
+                }
+            }
+            let names = patch.iter().map(|(_, p)| name(p)).collect::<Vec<_>>();
+            match self.fs.patch_props(path, patch).await {
+                Ok(deadret) => {
+                    failed = deadret.iter().any(|(s, _)| !s.is_success());
+                    ret.extend(deadret.into_iter());
+                },
+                Err(e) => {
+                    failed = true;
+                    let status = DavError::FsError(e).mapped_statuscode(self.error_map.as_ref());
+                    ret.extend(names.into_iter().map(|p| (status, p)));
+                    undo_patch.clear();
+                },
+            }
+        }
+
+        // then the properties of the property provider, one by one.
+        let mut undo_provided = Vec::new();
+        if let Some(ref provider) = self.property_provider {
+            for p in provided.into_iter() {
+                let pname = name(&p);
+                if failed {
+                    ret.push((StatusCode::FAILED_DEPENDENCY, pname));
+                    continue;
+                }
+                let res = match provider.get(path, &pname).await {
+                    Ok(old) => provider.set(path, p).await.map(|_| old),
+                    Err(e) => Err(e),
+                };
+                match res {
+                    Ok(old) => {
+                        undo_provided.push(DavProp { xml: old, ..pname.clone() });
+                        ret.push((StatusCode::OK, pname));
+                    },
+                    Err(e) => {
+                        failed = true;
+                        ret.push((DavError::FsError(e).mapped_statuscode(self.error_map.as_ref()), pname));
+                    },
+                }
+            }
+        }
+
+        if failed {
+            for p in undo_provided.into_iter().rev() {
+                if let Some(ref provider) = self.property_provider {
+                    if let Err(e) = provider.set(path, p).await {
+                        error!("proppatch {}: undo failed: {:?}", path, e);
+                    }
+                }
+            }
+            if !undo_patch.is_empty() {
+                if let Err(e) = self.fs.patch_props(path, undo_patch).await {
+                    error!("proppatch {}: undo failed: {:?}", path, e);
+                }
+            }
+            for (status, _) in ret.iter_mut() {
+                if status.is_success() {
+                    *status = StatusCode::FAILED_DEPENDENCY;
+                }
+            }
+        }
+        ret
+    }
+
     pub(crate) async fn handle_proppatch(
         self,
         req: &Request<()>,
@@ -410,6 +627,8 @@ impl DavInner {
         }
 
         let mut patch = Vec::new();
+        let mut provided = Vec::new();
+        let mut protected = Vec::new();
         let mut ret = Vec::new();
         let can_deadprop = self.fs.have_props(&path).await;
 
@@ -422,7 +641,14 @@ impl DavInner {
                 .filter(|e| e.name == "prop")
                 .flat_map(|e| e.child_elems_iter())
             {
+                if is_protected(n) && (elem.name == "set" || elem.name == "remove") {
+                    protected.push(element_to_davprop(n));
+                    continue;
+                }
+                let claimed = is_provided(&self.property_provider, &element_to_davprop(n));
                 match elem.name.as_str() {
+                    "set" if claimed => provided.push(element_to_davprop_full(n)),
+                    "remove" if claimed => provided.push(element_to_davprop(n)),
                     "set" => {
                         match self.liveprop_set(&n, can_deadprop) {
                             StatusCode::CONTINUE => patch.push((true, element_to_davprop_full(&n))),
@@ -441,7 +667,7 @@ impl DavInner {
         }
 
         // if any set/remove failed, stop processing here.
-        if ret.iter().any(|&(ref s, _)| s != &StatusCode::OK) {
+        if !protected.is_empty() || ret.iter().any(|&(ref s, _)| s != &StatusCode::OK) {
             ret = ret
                 .into_iter()
                 .map(|(s, p)| {
@@ -453,13 +679,9 @@ impl DavInner {
                 })
                 .collect::<Vec<_>>();
             ret.extend(patch.into_iter().map(|(_, p)| (StatusCode::FAILED_DEPENDENCY, p)));
-        } else if patch.len() > 0 {
-            // hmmm ... we assume nothing goes wrong here at the
-            // moment. if it does, we should roll back the earlier
-            // made changes to live props, but come on, we're not
-            // builing a transaction engine here.
-            let deadret = self.fs.patch_props(&path, patch).await?;
-            ret.extend(deadret.into_iter());
+            ret.extend(provided.into_iter().map(|p| (StatusCode::FAILED_DEPENDENCY, p)));
+        } else {
+            ret = self.proppatch_commit(&path, provided, patch).await;
         }
 
         // group by statuscode.
@@ -474,6 +696,8 @@ impl DavInner {
 
         // And reply.
         let mut pw = PropWriter::new(&req, &mut res, "propertyupdate", Vec::new(), &self.fs, None)?;
+        pw.href_rewrite = self.href_rewrite.clone();
+        pw.protected = protected.into_iter().map(davprop_to_element).collect();
         *res.body_mut() = Body::from(AsyncStream::new(|tx| {
             async move {
                 pw.set_tx(tx);
@@ -522,6 +746,9 @@ impl PropWriter {
             None => "",
         };
 
+        // with allprop, these were explicitly asked for with <include>.
+        let include = if name == "allprop" { props.clone() } else { Vec::new() };
+
         if name != "prop" && name != "propertyupdate" {
             let mut v = Vec::new();
             let iter = if name == "allprop" {
@@ -547,6 +774,7 @@ impl PropWriter {
         // check the prop namespaces to see what namespaces
         // we need to put in the preamble.
         let mut ev = XmlWEvent::start_element("D:multistatus").ns("D", NS_DAV_URI);
+        let mut prefixes = HashMap::new();
         if name != "propertyupdate" {
             let mut a = false;
             let mut m = false;
@@ -563,18 +791,39 @@ impl PropWriter {
             if m {
                 ev = ev.ns("Z", NS_MS_URI);
             }
+
+            // the other namespaces of the properties that were asked for get
+            // a prefix of our own, so that they are declared only once.
+            prefixes.insert(NS_DAV_URI.to_string(), "D".to_string());
+            let named = if name == "prop" { &props } else { &include };
+            for ns in named.iter().filter_map(|p| p.namespace.as_ref()) {
+                if ns != NS_APACHE_URI && ns != NS_MS_URI && !prefixes.contains_key(ns) {
+                    let pfx = format!("ns{}", prefixes.len() - 1);
+                    ev = ev.ns(pfx.clone(), ns.clone());
+                    prefixes.insert(ns.clone(), pfx);
+                }
+            }
         }
         emitter.write(ev)?;
 
         Ok(PropWriter {
-            emitter:   emitter,
-            tx:        None,
-            name:      name.to_string(),
-            props:     props,
-            fs:        fs.clone(),
-            ls:        ls.map(|ls| ls.clone()),
-            useragent: ua.to_string(),
-            q_cache:   Default::default(),
+            emitter:       emitter,
+            tx:            None,
+            name:          name.to_string(),
+            props:         props,
+            include:       include,
+            prefixes:      prefixes,
+            fs:            fs.clone(),
+            ls:            ls.map(|ls| ls.clone()),
+            useragent:     ua.to_string(),
+            q_cache:       Default::default(),
+            mime_resolver: None,
+            principal:     None,
+            minimal:       false,
+            provider:      None,
+            href_rewrite:  None,
+            protected:     Vec::new(),
+            last_flush:    Instant::now(),
         })
     }
 
@@ -594,7 +843,9 @@ impl PropWriter {
         };
         if content {
             let t: String = text.into();
-            if t != "" {
+            if !is_xml_text(&t) {
+                elem = base64_element(elem, t.as_bytes());
+            } else if t != "" {
                 elem.children.push(XMLNode::Text(t));
             }
         }
@@ -607,7 +858,7 @@ impl PropWriter {
     async fn get_quota<'a>(
         &'a self,
         qc: &'a mut QuotaCache,
-        _path: &'a DavPath,
+        path: &'a DavPath,
         meta: &'a dyn DavMetaData,
     ) -> FsResult<(u64, Option<u64>)>
     {
@@ -619,8 +870,7 @@ impl PropWriter {
         // do lookup only once.
         match qc.q_state {
             0 => {
-                // FIXME: get_quota should take a path.
-                match self.fs.get_quota().await {
+                match self.fs.quota(path).await {
                     Err(e) => {
                         qc.q_state = 1;
                         return Err(e);
@@ -650,6 +900,7 @@ impl PropWriter {
         path: &'a DavPath,
         meta: &'a dyn DavMetaData,
         qc: &'a mut QuotaCache,
+        deadprops: &'a HashMap<(Option<String>, String), Vec<u8>>,
         docontent: bool,
     ) -> DavResult<StatusElement>
     {
@@ -683,6 +934,13 @@ impl PropWriter {
                         try_deadprop = true;
                     },
                     "getetag" => {
+                        // the same for prop and allprop, so that clients
+                        // can compare the ETags of both.
+                        if meta.is_dir() && docontent {
+                            if let Ok(etag) = self.fs.collection_etag(path).await {
+                                return self.build_elem(docontent, pfx, prop, etag);
+                            }
+                        }
                         if let Some(etag) = meta.etag() {
                             return self.build_elem(docontent, pfx, prop, etag);
                         }
@@ -696,7 +954,11 @@ impl PropWriter {
                         return if meta.is_dir() {
                             self.build_elem(docontent, pfx, prop, "httpd/unix-directory")
                         } else {
-                            self.build_elem(docontent, pfx, prop, path.get_mime_type_str())
+                            let ct = match self.fs.content_type(path).await {
+                                Ok(Some(ct)) => ct,
+                                _ => mime_type(self.mime_resolver.as_ref(), path, meta),
+                            };
+                            self.build_elem(docontent, pfx, prop, ct)
                         };
                     },
                     "getlastmodified" => {
@@ -723,9 +985,10 @@ impl PropWriter {
                         });
                     },
                     "lockdiscovery" => {
+                        let rewrite = self.href_rewrite.as_ref();
                         return Ok(StatusElement {
                             status:  StatusCode::OK,
-                            element: list_lockdiscovery(self.ls.as_ref(), path),
+                            element: list_lockdiscovery(self.ls.as_ref(), path, rewrite),
                         });
                     },
                     "quota-available-bytes" => {
@@ -747,6 +1010,36 @@ impl PropWriter {
                             return self.build_elem(docontent, pfx, prop, used);
                         }
                     },
+                    "current-user-privilege-set" => {
+                        let principal = self.principal.as_deref();
+                        if let Ok(privs) = self.fs.privileges(path, principal).await {
+                            let mut elem = prop.clone();
+                            if docontent {
+                                let granted = [
+                                    (privs.read, "D:read"),
+                                    (privs.write, "D:write"),
+                                    (privs.read_acl, "D:read-acl"),
+                                ];
+                                for (_, name) in granted.iter().filter(|g| g.0) {
+                                    let mut p = Element::new2("D:privilege");
+                                    p.push_element(Element::new2(*name));
+                                    elem.push_element(p);
+                                }
+                            }
+                            return Ok(StatusElement {
+                                status:  StatusCode::OK,
+                                element: elem,
+                            });
+                        }
+                    },
+                    "owner" => {
+                        let principal = self.principal.as_deref();
+                        if let Ok(PrivilegeSet { owner: Some(owner), .. }) =
+                            self.fs.privileges(path, principal).await
+                        {
+                            return self.build_elem(docontent, pfx, prop, owner);
+                        }
+                    },
                     _ => {},
                 }
             },
@@ -821,16 +1114,18 @@ impl PropWriter {
             },
         }
 
-        if try_deadprop && self.name == "prop" && self.fs.have_props(path).await {
+        if try_deadprop && (self.name == "prop" || !self.include.is_empty()) {
             // asking for a specific property.
-            let dprop = element_to_davprop(prop);
-            if let Ok(xml) = self.fs.get_prop(path, dprop).await {
-                if let Ok(e) = Element::parse(Cursor::new(xml)) {
-                    return Ok(StatusElement {
-                        status:  StatusCode::OK,
-                        element: e,
-                    });
-                }
+            let key = (prop.namespace.clone(), prop.name.clone());
+            if let Some(xml) = deadprops.get(&key) {
+                let p = DavProp {
+                    xml: Some(xml.clone()),
+                    ..element_to_davprop(prop)
+                };
+                return Ok(StatusElement {
+                    status:  StatusCode::OK,
+                    element: davprop_to_element(p),
+                });
             }
         }
         let prop = if pfx != "" {
@@ -853,12 +1148,46 @@ impl PropWriter {
         // A HashMap<StatusCode, Vec<Element>> for the result.
         let mut props = HashMap::new();
 
+        // Fetch the dead properties that were asked for by name all at once.
+        let mut deadprops = HashMap::new();
+        if self.name == "prop" || !self.include.is_empty() {
+            let (provided, names): (Vec<_>, Vec<_>) = self
+                .props
+                .iter()
+                .filter(|p| maybe_deadprop(p))
+                .map(element_to_davprop)
+                .partition(|p| is_provided(&self.provider, p));
+            if let Some(ref provider) = self.provider {
+                for p in provided.into_iter() {
+                    if let Ok(Some(xml)) = provider.get(path, &p).await {
+                        deadprops.insert((p.namespace, p.name), xml);
+                    }
+                }
+            }
+            if !names.is_empty() && self.fs.have_props(path).await {
+                if let Ok(v) = self.fs.get_named_props(path, names).await {
+                    for p in v.into_iter() {
+                        if let Some(xml) = p.xml {
+                            deadprops.insert((p.namespace, p.name), xml);
+                        }
+                    }
+                }
+            }
+        }
+
         // Get properties one-by-one
         let do_content = self.name != "propname";
         let mut qc = self.q_cache;
         for p in &self.props {
-            let res = self.build_prop(p, path, &*meta, &mut qc, do_content).await?;
-            if res.status == StatusCode::OK || (self.name != "propname" && self.name != "allprop") {
+            let res = self.build_prop(p, path, &*meta, &mut qc, &deadprops, do_content).await?;
+            if res.status == StatusCode::NOT_FOUND && self.minimal {
+                continue;
+            }
+            let included = self.include.iter().any(|e| e.name == p.name && e.namespace == p.namespace);
+            if res.status == StatusCode::OK ||
+                included ||
+                (self.name != "propname" && self.name != "allprop")
+            {
                 add_sc_elem(&mut props, res.status, res.element);
             }
         }
@@ -867,11 +1196,33 @@ impl PropWriter {
         // and list the dead properties as well.
         if (self.name == "propname" || self.name == "allprop") && self.fs.have_props(path).await {
             if let Ok(v) = self.fs.get_props(path, do_content).await {
+                let included = |p: &DavProp| {
+                    let ns = p.namespace.as_deref();
+                    self.include.iter().any(|e| e.name == p.name && e.namespace.as_deref() == ns)
+                };
                 v.into_iter()
+                    .filter(|p| !is_provided(&self.provider, p) && !included(p))
                     .map(davprop_to_element)
                     .for_each(|e| add_sc_elem(&mut props, StatusCode::OK, e));
             }
         }
+        if self.name == "propname" || self.name == "allprop" {
+            if let Some(ref provider) = self.provider {
+                if let Ok(v) = provider.list(path).await {
+                    for mut p in v.into_iter().filter(|p| is_provided(&self.provider, p)) {
+                        if !do_content {
+                            p.xml = None;
+                        }
+                        add_sc_elem(&mut props, StatusCode::OK, davprop_to_element(p));
+                    }
+                }
+            }
+        }
+
+        // A response needs at least one propstat, even if it is empty.
+        if props.is_empty() && self.minimal {
+            props.insert(StatusCode::OK, Vec::new());
+        }
 
         Ok::<(), DavError>(self.write_propresponse(path, props)?)
     }
@@ -883,33 +1234,116 @@ impl PropWriter {
     ) -> Result<(), DavError>
     {
         self.emitter.write(XmlWEvent::start_element("D:response"))?;
-        let p = path.with_prefix().as_url_string();
+        let p = href(self.href_rewrite.as_ref(), path);
         Element::new2("D:href").text(p).write_ev(&mut self.emitter)?;
 
         let mut keys = props.keys().collect::<Vec<_>>();
         keys.sort();
         for status in keys {
-            let v = props.get(status).unwrap();
             self.emitter.write(XmlWEvent::start_element("D:propstat"))?;
-            self.emitter.write(XmlWEvent::start_element("D:prop"))?;
-            for i in v.iter() {
-                i.write_ev(&mut self.emitter)?;
-            }
-            self.emitter.write(XmlWEvent::end_element())?;
+            self.write_prop_elem(props.get(status).unwrap())?;
             Element::new2("D:status")
                 .text("HTTP/1.1 ".to_string() + &status.to_string())
                 .write_ev(&mut self.emitter)?;
             self.emitter.write(XmlWEvent::end_element())?;
         }
 
+        // RFC 4918 9.2: these get a propstat of their own, with the precondition.
+        if !self.protected.is_empty() {
+            let protected = std::mem::take(&mut self.protected);
+            self.emitter.write(XmlWEvent::start_element("D:propstat"))?;
+            self.write_prop_elem(&protected)?;
+            Element::new2("D:status")
+                .text("HTTP/1.1 ".to_string() + &StatusCode::FORBIDDEN.to_string())
+                .write_ev(&mut self.emitter)?;
+            let mut error = Element::new2("D:error");
+            error.push_element(Element::new2("D:cannot-modify-protected-property"));
+            error.write_ev(&mut self.emitter)?;
+            self.emitter.write(XmlWEvent::end_element())?;
+        }
+
         self.emitter.write(XmlWEvent::end_element())?; // response
 
         Ok(())
     }
 
+    // A <D:prop> element with these properties.
+    fn write_prop_elem(&mut self, v: &[Element]) -> Result<(), DavError> {
+        self.emitter.write(XmlWEvent::start_element("D:prop"))?;
+        for i in v.iter() {
+            let pfx = i.namespace.as_ref().and_then(|ns| self.prefixes.get(ns));
+            let pfx = pfx.filter(|pfx| i.prefix.as_ref() != Some(*pfx));
+            let dt = i.attributes.get("dt").is_some_and(|dt| dt == "bin.base64");
+            if pfx.is_none() && !dt {
+                i.write_ev(&mut self.emitter)?;
+                continue;
+            }
+            let mut e = i.clone();
+            if let Some(pfx) = pfx {
+                // declared on the root already. The children have
+                // their own namespace declarations, if needed.
+                e.prefix = Some(pfx.clone());
+                e.namespaces = None;
+            }
+            if dt {
+                // the parser forgets the namespace of an attribute.
+                e.attributes.remove("dt");
+                e.attributes.insert("b:dt".to_string(), "bin.base64".to_string());
+                e = e.ns("b", NS_DT_URI);
+            }
+            e.write_ev(&mut self.emitter)?;
+        }
+        self.emitter.write(XmlWEvent::end_element())?;
+        Ok(())
+    }
+
+    // Write a response with just a status, e.g. for a resource that is gone.
+    pub fn write_status(&mut self, path: &DavPath, status: StatusCode) -> DavResult<()> {
+        self.write_status_error(path, status, None)
+    }
+
+    // Like write_status, with an optional DAV:error element.
+    pub fn write_status_error(
+        &mut self,
+        path: &DavPath,
+        status: StatusCode,
+        error: Option<Element>,
+    ) -> DavResult<()>
+    {
+        self.emitter.write(XmlWEvent::start_element("D:response"))?;
+        let p = href(self.href_rewrite.as_ref(), path);
+        Element::new2("D:href").text(p).write_ev(&mut self.emitter)?;
+        Element::new2("D:status")
+            .text("HTTP/1.1 ".to_string() + &status.to_string())
+            .write_ev(&mut self.emitter)?;
+        if let Some(error) = error {
+            let mut elem = Element::new2("D:error");
+            elem.push_element(error);
+            elem.write_ev(&mut self.emitter)?;
+        }
+        self.emitter.write(XmlWEvent::end_element())?;
+        Ok(())
+    }
+
+    // Write an element at the top level of the multistatus response.
+    pub fn write_element(&mut self, elem: &Element) -> DavResult<()> {
+        elem.write_ev(&mut self.emitter)?;
+        Ok(())
+    }
+
     pub async fn flush(&mut self) -> DavResult<()> {
         let buffer = self.emitter.inner_mut().take();
         self.tx.as_mut().unwrap().send(Bytes::from(buffer)).await;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    // Like flush(), but only if enough data has been buffered
+    // or if it has been a while since the last flush.
+    pub async fn flush_some(&mut self) -> DavResult<()> {
+        if self.emitter.inner_mut().len() >= FLUSH_SIZE || self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush().await?;
+        }
         Ok(())
     }
 
@@ -919,6 +1353,28 @@ impl PropWriter {
     }
 }
 
+// Is there a "Prefer: return=minimal" header (RFC7240)?
+fn prefer_minimal(req: &Request<()>) -> bool {
+    req.headers()
+        .get_all("prefer")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|p| p.split(';').next()?.split_once('='))
+        .any(|(k, v)| {
+            let v = v.trim().trim_matches('"');
+            k.trim().eq_ignore_ascii_case("return") && v.eq_ignore_ascii_case("minimal")
+        })
+}
+
+// Is `prop` in a namespace that the property provider claims.
+fn is_provided(provider: &Option<Arc<dyn PropertyProvider>>, prop: &DavProp) -> bool {
+    match (provider, prop.namespace.as_deref()) {
+        (Some(provider), Some(ns)) => provider.claims(ns),
+        _ => false,
+    }
+}
+
 fn add_sc_elem(hm: &mut HashMap<StatusCode, Vec<Element>>, sc: StatusCode, e: Element) {
     if !hm.contains_key(&sc) {
         hm.insert(sc, Vec::new());
@@ -927,6 +1383,9 @@ fn add_sc_elem(hm: &mut HashMap<StatusCode, Vec<Element>>, sc: StatusCode, e: El
 }
 
 fn element_to_davprop_full(elem: &Element) -> DavProp {
+    // a base64 encoded value is stored as text, if it can be.
+    let decoded = decode_base64(elem);
+    let elem = decoded.as_ref().unwrap_or(elem);
     let mut emitter = EventWriter::new(Cursor::new(Vec::new()));
     elem.write_ev(&mut emitter).ok();
     let xml = emitter.into_inner().into_inner();
@@ -938,6 +1397,15 @@ fn element_to_davprop_full(elem: &Element) -> DavProp {
     }
 }
 
+// Can build_prop() fall back to the dead property database for `prop`.
+fn maybe_deadprop(prop: &Element) -> bool {
+    match prop.namespace.as_deref() {
+        Some(NS_DAV_URI) => prop.name == "displayname" || prop.name == "getcontentlanguage",
+        Some(NS_APACHE_URI) | Some(NS_MS_URI) => false,
+        _ => true,
+    }
+}
+
 fn element_to_davprop(elem: &Element) -> DavProp {
     DavProp {
         name:      elem.name.clone(),
@@ -947,9 +1415,15 @@ fn element_to_davprop(elem: &Element) -> DavProp {
     }
 }
 
-fn davprop_to_element(prop: DavProp) -> Element {
-    if let Some(xml) = prop.xml {
-        return Element::parse2(Cursor::new(xml)).unwrap();
+// The value of a property. A stored value that is not XML is sent
+// as the bytes that it is, base64 encoded (or as text, without the
+// `base64` feature).
+fn davprop_to_element(mut prop: DavProp) -> Element {
+    if let Some(xml) = prop.xml.take() {
+        match Element::parse2(Cursor::new(&xml)) {
+            Ok(elem) => return elem,
+            Err(_) => return base64_element(davprop_to_element(prop), &xml),
+        }
     }
     let mut elem = Element::new(&prop.name);
     if let Some(ref ns) = prop.namespace {
@@ -960,3 +1434,650 @@ fn davprop_to_element(prop: DavProp) -> Element {
     elem.namespace = prop.namespace.clone();
     elem
 }
+
+// Can `s` be sent as the text of an element. Control characters can't,
+// not even as a character reference (XML 1.0, section 2.2).
+fn is_xml_text(s: &str) -> bool {
+    s.chars().all(is_xml_char)
+}
+
+fn is_xml_char(c: char) -> bool {
+    (c >= ' ' && c != '\u{fffe}' && c != '\u{ffff}') || c == '\t' || c == '\n' || c == '\r'
+}
+
+// `elem` with `data` as its value, base64 encoded, and marked as such with
+// the datatype attribute that Microsoft uses: b:dt="bin.base64". The prefix
+// is added when the element is written.
+#[cfg(feature = "base64")]
+fn base64_element(elem: Element, data: &[u8]) -> Element {
+    let mut elem = elem.text(BASE64.encode(data));
+    elem.attributes.insert("dt".to_string(), "bin.base64".to_string());
+    elem
+}
+
+// Without base64, what can't be sent is replaced by U+FFFD.
+#[cfg(not(feature = "base64"))]
+fn base64_element(elem: Element, data: &[u8]) -> Element {
+    let text = String::from_utf8_lossy(data);
+    elem.text(text.chars().map(|c| if is_xml_char(c) { c } else { '\u{fffd}' }).collect::<String>())
+}
+
+// Is this one of the PROTECTED_STR properties.
+fn is_protected(prop: &Element) -> bool {
+    let pfx = match prop.namespace.as_deref() {
+        Some(NS_DAV_URI) => "D:",
+        Some(NS_APACHE_URI) => "A:",
+        _ => return false,
+    };
+    PROTECTED_STR.iter().any(|p| p.strip_prefix(pfx) == Some(prop.name.as_str()))
+}
+
+// The value of a property that was set with b:dt="bin.base64", as plain
+// text. None if it is not that, or if the value can't be text.
+#[cfg(feature = "base64")]
+fn decode_base64(elem: &Element) -> Option<Element> {
+    if elem.attributes.get("dt").map(|dt| dt.as_str()) != Some("bin.base64") || elem.has_child_elems() {
+        return None;
+    }
+    let text = elem.get_text().unwrap_or_default().split_whitespace().collect::<String>();
+    let text = String::from_utf8(BASE64.decode(text).ok()?).ok()?;
+    if !is_xml_text(&text) {
+        return None;
+    }
+    let mut elem = elem.clone();
+    elem.attributes.remove("dt");
+    Some(elem.text(text))
+}
+
+// Without base64, the value is stored as it was sent.
+#[cfg(not(feature = "base64"))]
+fn decode_base64(_elem: &Element) -> Option<Element> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::davpath::DavPath;
+    use crate::fs::{DavProp, FsFuture};
+    use crate::memfs::MemFs;
+    use crate::testing::{tempdir, TestClient};
+    use crate::DavHandler;
+    use http::{Request, StatusCode};
+
+    async fn propfind(dav: &DavHandler, depth: Option<&str>) -> (StatusCode, String) {
+        let mut req = Request::builder().method("PROPFIND").uri("/");
+        if let Some(depth) = depth {
+            req = req.header("Depth", depth);
+        }
+        let resp = TestClient::new(dav.clone()).send(req.body("").unwrap()).await;
+        (resp.status(), String::from_utf8(resp.into_body().to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn propfind_max_depth() {
+        // by default, infinity is refused.
+        let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+        assert_eq!(propfind(&dav, Some("1")).await.0, StatusCode::MULTI_STATUS);
+        assert_eq!(propfind(&dav, None).await.0, StatusCode::FORBIDDEN);
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .propfind_max_depth(Some(1))
+            .build_handler();
+        assert_eq!(propfind(&dav, Some("0")).await.0, StatusCode::MULTI_STATUS);
+        assert_eq!(propfind(&dav, Some("1")).await.0, StatusCode::MULTI_STATUS);
+        for depth in &[None, Some("infinity")] {
+            let (status, body) = propfind(&dav, *depth).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert!(body.contains("<D:propfind-finite-depth/>"));
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .propfind_max_depth(Some(0))
+            .build_handler();
+        assert_eq!(propfind(&dav, Some("1")).await.0, StatusCode::FORBIDDEN);
+
+        // the limit can be lifted again per request.
+        let config = crate::DavConfig::new().propfind_max_depth(None);
+        let req = Request::builder().method("PROPFIND").uri("/").header("Depth", "infinity");
+        let resp = dav.handle_with(config, req.body(hyper::Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+    }
+
+    #[tokio::test]
+    async fn propfind_nesting() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .propfind_max_depth(None)
+            .build_handler();
+        let c = TestClient::new(dav.clone());
+        let mut path = String::from("/");
+        for _ in 0..super::MAX_PROPFIND_NESTING + 1 {
+            path.push_str("d/");
+            c.request("MKCOL", &path, "").await;
+        }
+
+        // the walk stops, and says so, at the limit.
+        let (status, body) = propfind(&dav, Some("infinity")).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        let deepest = format!("<D:href>/{}</D:href>", "d/".repeat(super::MAX_PROPFIND_NESTING));
+        let resp = body.split(&deepest).nth(1).unwrap();
+        assert!(resp.starts_with("<D:status>HTTP/1.1 507 Insufficient Storage</D:status>"));
+        assert!(resp.contains("<D:error><D:number-of-matches-within-limits>"));
+        assert_eq!(body.matches("<D:response>").count(), super::MAX_PROPFIND_NESTING + 1);
+    }
+
+    #[tokio::test]
+    async fn prefer_minimal() {
+        let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+        let body = r#"<D:propfind xmlns:D="DAV:" xmlns:X="urn:x">
+            <D:prop><D:resourcetype/><X:missing/></D:prop></D:propfind>"#;
+        let propfind = |prefer: Option<&'static str>| {
+            let mut req = Request::builder().method("PROPFIND").uri("/").header("Depth", "0");
+            if let Some(prefer) = prefer {
+                req = req.header("Prefer", prefer);
+            }
+            dav.handle(req.body(hyper::Body::from(body)).unwrap())
+        };
+
+        let resp = propfind(None).await;
+        assert!(resp.headers().get("preference-applied").is_none());
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("404 Not Found"));
+
+        let resp = propfind(Some("handling=lenient, return=minimal")).await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        assert_eq!(resp.headers()["preference-applied"], "return=minimal");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(!body.contains("404") && !body.contains("missing"));
+        assert!(body.contains("<D:collection"));
+    }
+
+    async fn request(dav: &DavHandler, method: &str, body: &str) -> (StatusCode, String) {
+        let req = Request::builder().method(method).uri("/").header("Depth", "0");
+        let resp = TestClient::new(dav.clone()).send(req.body(body.to_string()).unwrap()).await;
+        (resp.status(), String::from_utf8(resp.into_body().to_vec()).unwrap())
+    }
+
+    // Keeps the properties in the urn:app namespace in a map.
+    #[derive(Default)]
+    struct AppProps(std::sync::Mutex<std::collections::HashMap<(String, String), Vec<u8>>>);
+
+    impl crate::PropertyProvider for AppProps {
+        fn claims(&self, namespace: &str) -> bool {
+            namespace == "urn:app"
+        }
+
+        fn get<'a>(&'a self, path: &'a DavPath, prop: &'a DavProp) -> FsFuture<'a, Option<Vec<u8>>> {
+            let key = (path.as_url_string(), prop.name.clone());
+            Box::pin(futures::future::ok(self.0.lock().unwrap().get(&key).cloned()))
+        }
+
+        fn set<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, ()> {
+            if prop.name == "locked" {
+                return Box::pin(futures::future::err(crate::fs::FsError::Forbidden));
+            }
+            let mut map = self.0.lock().unwrap();
+            let key = (path.as_url_string(), prop.name.clone());
+            match prop.xml {
+                Some(xml) => map.insert(key, xml),
+                None => map.remove(&key),
+            };
+            Box::pin(futures::future::ok(()))
+        }
+
+        fn list<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Vec<DavProp>> {
+            let map = self.0.lock().unwrap();
+            let props = map
+                .iter()
+                .filter(|((p, _), _)| *p == path.as_url_string())
+                .map(|((_, name), xml)| {
+                    DavProp {
+                        name:      name.clone(),
+                        prefix:    None,
+                        namespace: Some("urn:app".to_string()),
+                        xml:       Some(xml.clone()),
+                    }
+                })
+                .collect();
+            Box::pin(futures::future::ok(props))
+        }
+    }
+
+    #[tokio::test]
+    async fn property_provider() {
+        let provider = std::sync::Arc::new(AppProps::default());
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .property_provider(provider.clone())
+            .build_handler();
+        let proppatch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:A="urn:app" xmlns:O="urn:other">
+            <D:set><D:prop><A:color>red</A:color><O:size>big</O:size></D:prop></D:set>
+            </D:propertyupdate>"#;
+        let (status, _) = request(&dav, "PROPPATCH", proppatch).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert_eq!(provider.0.lock().unwrap().len(), 1);
+
+        let propfind = r#"<D:propfind xmlns:D="DAV:" xmlns:A="urn:app" xmlns:O="urn:other">
+            <D:prop><A:color/><O:size/></D:prop></D:propfind>"#;
+        let (_, body) = request(&dav, "PROPFIND", propfind).await;
+        assert!(body.contains(">red</"));
+        assert!(body.contains(">big</"));
+        assert!(!body.contains("404"));
+        let (_, body) = request(&dav, "PROPFIND", "").await;
+        assert!(body.contains(">red</"));
+
+        let proppatch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:A="urn:app">
+            <D:remove><D:prop><A:color/></D:prop></D:remove></D:propertyupdate>"#;
+        request(&dav, "PROPPATCH", proppatch).await;
+        assert!(provider.0.lock().unwrap().is_empty());
+        let (_, body) = request(&dav, "PROPFIND", propfind).await;
+        assert!(body.contains("404"));
+    }
+
+    #[tokio::test]
+    async fn proppatch_atomic() {
+        let provider = std::sync::Arc::new(AppProps::default());
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .property_provider(provider.clone())
+            .build_handler();
+        let propfind = r#"<D:propfind xmlns:D="DAV:" xmlns:A="urn:app" xmlns:O="urn:other">
+            <D:prop><D:displayname/><A:color/><O:size/></D:prop></D:propfind>"#;
+        let status = |body: &str, prop: &str| {
+            let r = body.split(prop).nth(1).unwrap_or_default();
+            r.split("<D:status>").nth(1).unwrap_or_default()[..12].to_string()
+        };
+
+        // a protected live property: nothing is set.
+        let proppatch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:O="urn:other">
+            <D:set><D:prop><D:displayname>name</D:displayname><O:size>big</O:size></D:prop></D:set>
+            <D:set><D:prop><D:getlastmodified>no</D:getlastmodified></D:prop></D:set>
+            </D:propertyupdate>"#;
+        let (_, body) = request(&dav, "PROPPATCH", proppatch).await;
+        assert_eq!(status(&body, "<D:getlastmodified"), "HTTP/1.1 403");
+        assert_eq!(status(&body, "<D:displayname"), "HTTP/1.1 424");
+        assert_eq!(status(&body, "<O:size"), "HTTP/1.1 424");
+        let (_, body) = request(&dav, "PROPFIND", propfind).await;
+        assert!(!body.contains(">name</") && !body.contains(">big</"));
+
+        // a property that fails when it is set: the other changes are undone.
+        let proppatch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:A="urn:app" xmlns:O="urn:other">
+            <D:set><D:prop><O:size>big</O:size><A:color>red</A:color><A:locked>x</A:locked></D:prop></D:set>
+            </D:propertyupdate>"#;
+        let (_, body) = request(&dav, "PROPPATCH", proppatch).await;
+        assert_eq!(status(&body, "<A:locked"), "HTTP/1.1 403");
+        assert_eq!(status(&body, "<A:color"), "HTTP/1.1 424");
+        assert_eq!(status(&body, "<O:size"), "HTTP/1.1 424");
+        assert!(provider.0.lock().unwrap().is_empty());
+        let (_, body) = request(&dav, "PROPFIND", propfind).await;
+        assert!(!body.contains(">red</") && !body.contains(">big</"));
+
+        // without it, everything is set.
+        let proppatch = proppatch.replace("<A:locked>x</A:locked>", "");
+        let (_, body) = request(&dav, "PROPPATCH", &proppatch).await;
+        assert!(!body.contains("424"));
+        let (_, body) = request(&dav, "PROPFIND", propfind).await;
+        assert!(body.contains(">red</") && body.contains(">big</"));
+    }
+
+    // MemFs that remembers how the dead properties were asked for.
+    #[derive(Clone)]
+    struct PropLog(Box<MemFs>, std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl crate::fs::DavFileSystem for PropLog {
+        fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn crate::fs::DavMetaData>> {
+            self.0.metadata(path)
+        }
+
+        fn read_dir<'a>(
+            &'a self,
+            path: &'a DavPath,
+            meta: crate::fs::ReadDirMeta,
+        ) -> FsFuture<'a, crate::fs::FsStream<Box<dyn crate::fs::DavDirEntry>>>
+        {
+            self.0.read_dir(path, meta)
+        }
+
+        fn open<'a>(
+            &'a self,
+            path: &'a DavPath,
+            options: crate::fs::OpenOptions,
+        ) -> FsFuture<'a, Box<dyn crate::fs::DavFile>>
+        {
+            self.0.open(path, options)
+        }
+
+        fn have_props<'a>(
+            &'a self,
+            path: &'a DavPath,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = bool> + Send + 'a>>
+        {
+            self.0.have_props(path)
+        }
+
+        fn patch_props<'a>(
+            &'a self,
+            path: &'a DavPath,
+            patch: Vec<(bool, DavProp)>,
+        ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>>
+        {
+            self.0.patch_props(path, patch)
+        }
+
+        fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+            self.1.lock().unwrap().push("get_props".to_string());
+            self.0.get_props(path, do_content)
+        }
+
+        fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+            self.1.lock().unwrap().push(format!("get_prop {}", prop.name));
+            self.0.get_prop(path, prop)
+        }
+
+        fn get_named_props<'a>(
+            &'a self,
+            path: &'a DavPath,
+            props: Vec<DavProp>,
+        ) -> FsFuture<'a, Vec<DavProp>> {
+            let names: Vec<_> = props.iter().map(|p| p.name.as_str()).collect();
+            self.1.lock().unwrap().push(format!("get_named_props {}", names.join(",")));
+            self.0.get_named_props(path, props)
+        }
+    }
+
+    #[tokio::test]
+    async fn named_props() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let fs = PropLog(MemFs::new(), log.clone());
+        let dav = DavHandler::builder().filesystem(Box::new(fs)).build_handler();
+        let proppatch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x">
+            <D:set><D:prop><X:a>1</X:a><X:b>2</X:b></D:prop></D:set></D:propertyupdate>"#;
+        let (status, _) = request(&dav, "PROPPATCH", proppatch).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        log.lock().unwrap().clear();
+
+        // only the dead properties that were named, in one call.
+        let propfind = r#"<D:propfind xmlns:D="DAV:" xmlns:X="urn:x">
+            <D:prop><X:a/><D:getetag/><X:c/></D:prop></D:propfind>"#;
+        let (status, body) = request(&dav, "PROPFIND", propfind).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert_eq!(*log.lock().unwrap(), ["get_named_props a,c"]);
+        let found = body.split("<D:propstat>").find(|p| p.contains("200 OK")).unwrap();
+        assert!(found.contains(">1</") && found.contains("<D:getetag>"), "{}", body);
+        let missing = body.split("<D:propstat>").find(|p| p.contains("404")).unwrap();
+        assert!(missing.contains(":c>") && !body.contains(">2</"), "{}", body);
+
+        // allprop still gets all of them.
+        log.lock().unwrap().clear();
+        let (_, body) = request(&dav, "PROPFIND", "").await;
+        assert_eq!(*log.lock().unwrap(), ["get_props"]);
+        assert!(body.contains(">1</") && body.contains(">2</"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn protected_props() {
+        let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+        let proppatch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:O="urn:other">
+            <D:set><D:prop><D:getetag>"x"</D:getetag><O:size>big</O:size></D:prop></D:set>
+            <D:remove><D:prop><D:resourcetype/></D:prop></D:remove>
+            </D:propertyupdate>"#;
+        let (_, body) = request(&dav, "PROPPATCH", proppatch).await;
+        let forbidden = body.split("<D:propstat>").find(|p| p.contains("403")).unwrap();
+        assert!(forbidden.contains("<D:getetag") && forbidden.contains("<D:resourcetype"));
+        assert!(forbidden.contains("<D:error><D:cannot-modify-protected-property"));
+        assert!(body.split("<D:propstat>").any(|p| p.contains("<O:size") && p.contains("424")));
+
+        let (_, body) = request(&dav, "PROPFIND", "").await;
+        assert!(!body.contains(">big</") && body.contains("<D:collection"));
+    }
+
+    #[tokio::test]
+    async fn propfind_forms() {
+        let c = TestClient::new(DavHandler::builder().filesystem(MemFs::new()).build_handler());
+        c.request("PUT", "/file", "hello").await;
+        let patch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x">
+            <D:set><D:prop><X:color>red</X:color></D:prop></D:set></D:propertyupdate>"#;
+        assert_eq!(c.request("PROPPATCH", "/file", patch).await.status(), StatusCode::MULTI_STATUS);
+        let propfind = |body: &'static str| {
+            let c = c.clone();
+            async move {
+                let body = format!(r#"<D:propfind xmlns:D="DAV:" xmlns:X="urn:x">{}</D:propfind>"#, body);
+                let resp = c.request("PROPFIND", "/file", body).await;
+                assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+                String::from_utf8(resp.into_body().to_vec()).unwrap()
+            }
+        };
+
+        // all live properties that are not expensive, and the dead ones.
+        let body = propfind("<D:allprop/>").await;
+        assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+        assert!(body.contains(">red</X:color>"));
+        assert!(!body.contains("quota-used-bytes"));
+        assert!(!body.contains("404"));
+
+        // the included ones as well, even a missing one.
+        let include = "<D:allprop/><D:include><D:quota-used-bytes/><X:color/><X:none/></D:include>";
+        let body = propfind(include).await;
+        assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+        assert!(body.contains("<D:quota-used-bytes>"));
+        assert_eq!(body.matches(">red</ns0:color>").count(), 1);
+        assert!(body.contains("<ns0:none") && body.contains("404 Not Found"));
+
+        // names only.
+        let body = propfind("<D:propname/>").await;
+        assert!(body.contains("<D:getcontentlength></D:getcontentlength>"));
+        assert!(body.contains("<X:color") && !body.contains("red"));
+
+        // just what was asked for.
+        let body = propfind("<D:prop><D:getcontentlength/><X:color/><X:none/></D:prop>").await;
+        assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+        assert!(body.contains(">red</ns0:color>"));
+        assert!(body.contains("<ns0:none") && body.contains("404 Not Found"));
+        assert!(!body.contains("getetag"));
+    }
+
+    #[tokio::test]
+    async fn namespaces() {
+        let c = TestClient::new(DavHandler::builder().filesystem(MemFs::new()).build_handler());
+        c.request("PUT", "/file", "hello").await;
+        let patch = r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop>
+            <color xmlns="urn:y">a&amp;b&lt;</color>
+            <q:n xmlns:q="urn:q"><q:sub attr="1&quot;">t</q:sub></q:n>
+            </D:prop></D:set></D:propertyupdate>"#;
+        assert_eq!(c.request("PROPPATCH", "/file", patch).await.status(), StatusCode::MULTI_STATUS);
+
+        // the same prefix for different namespaces, a default namespace, and no namespace.
+        let propfind = r#"<D:propfind xmlns:D="DAV:" xmlns:y="urn:y"><D:prop>
+            <y:color/><q:n xmlns:q="urn:q"/><D:getcontentlength/><none xmlns=""/>
+            <D:x xmlns:D="urn:clash"/><missing xmlns="urn:y"/>
+            </D:prop></D:propfind>"#;
+        let resp = c.request("PROPFIND", "/file", propfind).await;
+        let body = String::from_utf8(resp.into_body().to_vec()).unwrap();
+        let ns = r#"xmlns:D="DAV:" xmlns:ns0="urn:y" xmlns:ns1="urn:q" xmlns:ns2="urn:clash""#;
+        assert!(body.contains(&format!("<D:multistatus {}>", ns)));
+        assert!(body.contains("<ns0:color>") && body.contains("<ns2:x>"));
+
+        // parse it strictly, and see if every property is what it should be.
+        let tree = xmltree::Element::parse(body.as_bytes()).unwrap();
+        let elems = |e: &xmltree::Element| {
+            e.children.iter().filter_map(|n| n.as_element()).cloned().collect()
+        };
+        let mut found = Vec::new();
+        let propstats: Vec<xmltree::Element> = elems(tree.get_child("response").unwrap());
+        for propstat in propstats.iter().filter(|e| e.name == "propstat") {
+            assert_eq!(propstat.namespace.as_deref(), Some("DAV:"));
+            let status = propstat.get_child("status").unwrap().get_text().unwrap().to_string();
+            let props: Vec<xmltree::Element> = elems(propstat.get_child("prop").unwrap());
+            for prop in props.into_iter() {
+                let text = prop.get_text().map(|t| t.to_string()).unwrap_or_default();
+                found.push((prop.namespace.unwrap_or_default(), prop.name, text, status.clone()));
+            }
+        }
+        found.sort();
+        let ok = "HTTP/1.1 200 OK";
+        let missing = "HTTP/1.1 404 Not Found";
+        let expect = vec![
+            ("", "none", "", missing),
+            ("DAV:", "getcontentlength", "5", ok),
+            ("urn:clash", "x", "", missing),
+            ("urn:q", "n", "", ok),
+            ("urn:y", "color", "a&b<", ok),
+            ("urn:y", "missing", "", missing),
+        ];
+        let found = found.iter().map(|(a, b, c, d)| (a.as_str(), b.as_str(), c.as_str(), d.as_str()));
+        assert_eq!(found.collect::<Vec<_>>(), expect);
+        let sub = r#"<ns1:n><q:sub xmlns:q="urn:q" attr="1&quot;">t</q:sub></ns1:n>"#;
+        assert!(body.contains(sub));
+    }
+
+    #[cfg(feature = "base64")]
+    #[tokio::test]
+    async fn base64() {
+        let fs = MemFs::new();
+        let c = TestClient::new(DavHandler::builder().filesystem(fs.clone()).build_handler());
+        c.request("PUT", "/file", "hello").await;
+        let patch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x"
+          xmlns:b="urn:uuid:c2f41010-65b3-11d1-a29f-00aa00c14882/"><D:set><D:prop>
+            <X:text b:dt="bin.base64">aGVs
+            bG8=</X:text><X:bin b:dt="bin.base64">YQFi</X:bin>
+            </D:prop></D:set></D:propertyupdate>"#;
+        assert_eq!(c.request("PROPPATCH", "/file", patch).await.status(), StatusCode::MULTI_STATUS);
+
+        // a value that was stored by someone else, and is not XML.
+        let path = DavPath::new("/file").unwrap();
+        let raw = DavProp {
+            name:      "raw".to_string(),
+            prefix:    Some("X".to_string()),
+            namespace: Some("urn:x".to_string()),
+            xml:       Some(b"\x01\x02".to_vec()),
+        };
+        crate::fs::DavFileSystem::patch_props(&*fs, &path, vec![(true, raw)]).await.unwrap();
+
+        let propfind = r#"<D:propfind xmlns:D="DAV:" xmlns:X="urn:x"><D:prop>
+            <X:text/><X:bin/><X:raw/></D:prop></D:propfind>"#;
+        for body in &[propfind, ""] {
+            let resp = c.request("PROPFIND", "/file", *body).await;
+            let body = String::from_utf8(resp.into_body().to_vec()).unwrap();
+            // b: must be declared, or this fails.
+            xmltree::Element::parse(body.as_bytes()).unwrap();
+            assert!(body.contains(">hello</"));
+            assert!(body.contains(r#"b:dt="bin.base64">YQFi</"#));
+            assert!(body.contains(r#"b:dt="bin.base64">AQI=</"#));
+        }
+    }
+
+    #[tokio::test]
+    async fn href_rewrite() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(crate::memls::MemLs::new())
+            .strip_prefix("/dav")
+            .href_rewrite(|href| href.replacen("/dav/", "/public/", 1))
+            .build_handler();
+        let c = TestClient::new(dav);
+        c.request("MKCOL", "/dav/dir/", "").await;
+        c.request("PUT", "/dav/dir/a%20file", "hello").await;
+
+        let req = Request::builder().method("PROPFIND").uri("/dav/dir/").header("Depth", "1");
+        let resp = c.send(req.body("").unwrap()).await;
+        let body = String::from_utf8(resp.into_body().to_vec()).unwrap();
+        assert!(body.contains("<D:href>/public/dir/</D:href>"));
+        assert!(body.contains("<D:href>/public/dir/a%20file</D:href>"));
+        assert!(!body.contains("/dav/"));
+
+        let patch = r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop>
+            <D:displayname>x</D:displayname></D:prop></D:set></D:propertyupdate>"#;
+        let body = c.request("PROPPATCH", "/dav/dir/", patch).await.into_body();
+        assert!(String::from_utf8_lossy(&body).contains("<D:href>/public/dir/</D:href>"));
+
+        let lockinfo = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope>
+            <D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+        let body = c.request("LOCK", "/dav/dir/a%20file", lockinfo).await.into_body();
+        let lockroot = "<D:lockroot><D:href>/public/dir/a%20file</D:href></D:lockroot>";
+        assert!(String::from_utf8_lossy(&body).contains(lockroot));
+    }
+
+    #[tokio::test]
+    async fn collection_etag() {
+        let dir = tempdir();
+        std::fs::create_dir(dir.join("d")).unwrap();
+        let dav = DavHandler::builder()
+            .filesystem(crate::localfs::LocalFs::new(&dir, false, false, false))
+            .build_handler();
+        let c = TestClient::new(dav);
+        let getetag = |body: &'static str| {
+            let c = c.clone();
+            async move {
+                let req = Request::builder().method("PROPFIND").uri("/d/").header("Depth", "0");
+                let body = c.send(req.body(body).unwrap()).await.into_body();
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                let start = body.find("<D:getetag>").unwrap() + "<D:getetag>".len();
+                body[start..start + body[start..].find('<').unwrap()].to_string()
+            }
+        };
+
+        // the content based ETag, whether it was asked for by name or not.
+        let named = getetag(r#"<D:propfind xmlns:D="DAV:"><D:prop><D:getetag/></D:prop></D:propfind>"#).await;
+        assert!(!named.contains('-'), "{}", named);
+        let all = getetag(r#"<D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>"#).await;
+        assert_eq!(all, named);
+        let include = concat!(
+            r#"<D:propfind xmlns:D="DAV:"><D:allprop/>"#,
+            "<D:include><D:getetag/></D:include></D:propfind>"
+        );
+        assert_eq!(getetag(include).await, named);
+    }
+
+    #[tokio::test]
+    async fn quota() {
+        let quota = r#"<D:propfind xmlns:D="DAV:"><D:prop>
+            <D:quota-used-bytes/><D:quota-available-bytes/></D:prop></D:propfind>"#;
+        let prop = |body: &str, name: &str| {
+            let start = body.find(&format!("<D:{}>", name))? + name.len() + 4;
+            let len = body[start..].find('<')?;
+            body[start..start + len].parse::<u64>().ok()
+        };
+
+        let dav = DavHandler::builder().filesystem(MemFs::with_capacity(100)).build_handler();
+        let c = TestClient::new(dav.clone());
+        c.request("PUT", "/file", "0123456789").await;
+        let (status, body) = request(&dav, "PROPFIND", quota).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert_eq!(prop(&body, "quota-used-bytes"), Some(10), "{}", body);
+        assert_eq!(prop(&body, "quota-available-bytes"), Some(90), "{}", body);
+
+        // without a limit there is nothing available to report.
+        let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+        let (_, body) = request(&dav, "PROPFIND", quota).await;
+        assert_eq!(prop(&body, "quota-used-bytes"), Some(0), "{}", body);
+        let missing = body.split("<D:propstat>").find(|p| p.contains("quota-available-bytes")).unwrap();
+        assert!(missing.contains("404 Not Found"), "{}", body);
+
+        // LocalFs asks the filesystem that the directory is on.
+        let dir = tempdir();
+        let dav = DavHandler::builder()
+            .filesystem(crate::localfs::LocalFs::new(&dir, false, false, false))
+            .build_handler();
+        let (_, body) = request(&dav, "PROPFIND", quota).await;
+        assert!(prop(&body, "quota-used-bytes").is_some(), "{}", body);
+        assert!(prop(&body, "quota-available-bytes").is_some(), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn owner() {
+        let dir = tempdir();
+        let dav = DavHandler::builder()
+            .filesystem(crate::localfs::LocalFs::new(&dir, false, false, false))
+            .build_handler();
+        let owner = r#"<D:propfind xmlns:D="DAV:"><D:prop><D:owner/></D:prop></D:propfind>"#;
+        let (_, body) = request(&dav, "PROPFIND", owner).await;
+
+        // the owner is a name, not a principal URL.
+        let start = body.find("<D:owner>").expect(&body) + "<D:owner>".len();
+        let owner = &body[start..start + body[start..].find("</D:owner>").unwrap()];
+        assert!(!owner.is_empty() && !owner.contains('<'), "{}", body);
+    }
+}
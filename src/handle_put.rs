@@ -55,6 +55,17 @@ where E: StdError + Sync + Send + 'static {
 }
 
 impl crate::DavInner {
+    // A client that sends `Expect: 100-continue` waits for an interim
+    // "100 Continue" response before it sends the body. We can not send
+    // that ourselves, but hyper does it the first time the body is polled
+    // (other servers usually do the same). So the body must not be read
+    // until all the checks below have passed: if one of them fails, the
+    // client gets the final status right away and does not send the body.
+    //
+    // Nothing in here waits for the body before that, so there is no
+    // deadlock. But when we return early, the body (if any) was not read,
+    // and the connection can't be used for another request. So all errors
+    // before write_body() must close the connection.
     pub(crate) async fn handle_put<ReqBody, ReqData, ReqError>(
         self,
         req: &Request<()>,
@@ -78,6 +89,11 @@ impl crate::DavInner {
             count = n.0;
             have_count = true;
         }
+        if let Some(max) = self.max_upload_size {
+            if have_count && count > max {
+                return Err(DavError::StatusClose(SC::PAYLOAD_TOO_LARGE));
+            }
+        }
         let path = self.path(&req);
         let meta = self.fs.metadata(&path).await;
 
@@ -130,26 +146,35 @@ impl crate::DavInner {
         // Apache-style Content-Range header?
         match req.headers().typed_try_get::<headers::ContentRange>() {
             Ok(Some(range)) => {
-                if let Some((b, e)) = range.bytes_range() {
-                    if b > e {
+                // "bytes */len" does not tell us where to write.
+                let (b, e) = range
+                    .bytes_range()
+                    .ok_or(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE))?;
+                if b > e || range.bytes_len().is_some_and(|len| e >= len) {
+                    return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE));
+                }
+                if have_count {
+                    if e - b + 1 != count {
                         return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE));
                     }
-
-                    if have_count {
-                        if e - b + 1 != count {
-                            return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE));
-                        }
-                    } else {
-                        count = e - b + 1;
-                        have_count = true;
-                    }
-                    start = b;
-                    do_range = true;
-                    oo.truncate = false;
+                } else {
+                    count = e - b + 1;
+                    have_count = true;
                 }
+                start = b;
+                do_range = true;
+                oo.truncate = false;
             },
             Ok(None) => {},
-            Err(_) => return Err(DavError::StatusClose(SC::BAD_REQUEST)),
+            Err(_) => return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE)),
+        }
+
+        // we can overwrite and append, but not leave a hole in the file.
+        if do_range && !oo.append {
+            let len = meta.as_ref().map(|m| m.len()).unwrap_or(0);
+            if start > len {
+                return Err(DavError::StatusClose(SC::CONFLICT));
+            }
         }
 
         // check the If and If-* headers.
@@ -184,7 +209,28 @@ impl crate::DavInner {
             oo.create_new = true;
         }
 
-        let mut file = match self.fs.open(&path, oo).await {
+        // write to a temporary file first, if we can, and rename it into
+        // place when the upload is complete. Partial updates are in place.
+        // The rename would replace only this binding of a resource that has
+        // more than one, so those are written in place as well.
+        let multi_bound = meta.as_ref().is_ok_and(|m| m.bindings() > 1);
+        let tmp_path = match self.atomic_put && !do_range && !oo.append && !multi_bound {
+            true => self.fs.temp_path(&path),
+            false => None,
+        };
+        let mut open_path = &path;
+        let mut open_oo = oo;
+        if let Some(ref tmp) = tmp_path {
+            // we won't be opening `path`, so check here what we can.
+            if (!oo.create && meta.is_err()) || (oo.create_new && meta.is_ok()) {
+                return Err(DavError::StatusClose(SC::PRECONDITION_FAILED));
+            }
+            open_path = tmp;
+            open_oo.create = true;
+            open_oo.create_new = true;
+        }
+
+        let mut file = match self.fs.open(open_path, open_oo).await {
             Ok(f) => f,
             Err(FsError::NotFound) | Err(FsError::Exists) => {
                 let s = if !oo.create || oo.create_new {
@@ -194,7 +240,10 @@ impl crate::DavInner {
                 };
                 return Err(DavError::StatusClose(s));
             },
-            Err(e) => return Err(DavError::FsError(e)),
+            Err(e) => {
+                let status = DavError::FsError(e).mapped_statuscode(self.error_map.as_ref());
+                return Err(DavError::StatusClose(status));
+            },
         };
 
         if do_range {
@@ -207,8 +256,84 @@ impl crate::DavInner {
         res.headers_mut().typed_insert(headers::AcceptRanges::bytes());
 
         pin_utils::pin_mut!(body);
+        let count = if have_count { Some(count) } else { None };
+
+        if let Err(e) = self.write_body(&mut file, body, count).await {
+            drop(file);
+            let too_big = matches!(e.statuscode(), SC::PAYLOAD_TOO_LARGE | SC::INSUFFICIENT_STORAGE);
+            match tmp_path {
+                Some(ref tmp) => {
+                    let _ = self.fs.remove_file(tmp).await;
+                },
+                // remove the file only if this PUT created it. An existing
+                // file was truncated already, and is left as it is: partly
+                // overwritten for a partial update, or cut short otherwise.
+                None if meta.is_err() && too_big => {
+                    let _ = self.fs.remove_file(&path).await;
+                },
+                None => {},
+            }
+            return Err(e);
+        }
+        let fmeta = file.metadata().await;
+        drop(file);
+
+        if let Some(ref tmp) = tmp_path {
+            // keep the properties of the file that is replaced.
+            if meta.is_ok() && self.fs.have_props(&path).await {
+                if let Ok(props) = self.fs.get_props(&path, true).await {
+                    let patch = props.into_iter().map(|p| (true, p)).collect();
+                    let _ = self.fs.patch_props(tmp, patch).await;
+                }
+            }
+            if let Err(e) = self.fs.rename(tmp, &path).await {
+                let _ = self.fs.remove_file(tmp).await;
+                return Err(e.into());
+            }
+        }
+
+        // Report whether we created or updated the file. The metadata was
+        // looked up before writing, so this is about the state before the PUT.
+        *res.status_mut() = match meta {
+            Ok(_) => SC::NO_CONTENT,
+            Err(_) => {
+                let loc = crate::davhandler::href(self.href_rewrite.as_ref(), &path);
+                if let Ok(loc) = loc.parse() {
+                    res.headers_mut().insert(http::header::LOCATION, loc);
+                }
+                res.headers_mut().typed_insert(headers::ContentLength(0));
+                SC::CREATED
+            },
+        };
 
-        // loop, read body, write to file.
+        // no errors, connection may be kept open.
+        res.headers_mut().remove(http::header::CONNECTION);
+
+        if let Ok(m) = fmeta {
+            if let Some(etag) = davheaders::ETag::from_meta(&m) {
+                res.headers_mut().typed_insert(etag);
+            }
+            if let Ok(modified) = m.modified() {
+                res.headers_mut()
+                    .typed_insert(headers::LastModified::from(modified));
+            }
+        }
+        Ok(res)
+    }
+
+    // loop, read body, write to file. Every chunk is written before the
+    // next one is read, nothing is buffered here.
+    async fn write_body<ReqBody, ReqData, ReqError>(
+        &self,
+        file: &mut Box<dyn DavFile>,
+        mut body: std::pin::Pin<&mut ReqBody>,
+        count: Option<u64>,
+    ) -> DavResult<()>
+    where
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+    {
         let mut total = 0u64;
 
         while let Some(data) = body.data().await {
@@ -216,8 +341,13 @@ impl crate::DavInner {
             let buflen = buf.remaining();
             total += buflen as u64;
             // consistency check.
-            if have_count && total > count {
-                break;
+            if count.is_some_and(|count| total > count) {
+                error!("PUT file: sender is sending more bytes than expected");
+                return Err(DavError::StatusClose(SC::BAD_REQUEST));
+            }
+            if self.max_upload_size.is_some_and(|max| total > max) {
+                error!("PUT file: body exceeds the maximum upload size");
+                return Err(DavError::StatusClose(SC::PAYLOAD_TOO_LARGE));
             }
             // The `Buf` might actually be a `Bytes`.
             let b = {
@@ -233,37 +363,309 @@ impl crate::DavInner {
         }
         file.flush().await?;
 
-        if have_count && total > count {
-            error!("PUT file: sender is sending more bytes than expected");
+        if count.is_some_and(|count| total < count) {
+            error!("PUT file: premature EOF on input");
             return Err(DavError::StatusClose(SC::BAD_REQUEST));
         }
+        Ok(())
+    }
+}
 
-        if have_count && total < count {
-            error!("PUT file: premature EOF on input");
-            return Err(DavError::StatusClose(SC::BAD_REQUEST));
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::memfs::MemFs;
+    use crate::DavHandler;
+
+    // Serve one connection with hyper.
+    async fn server() -> TcpStream {
+        serve(DavHandler::builder().filesystem(MemFs::new()).build_handler()).await
+    }
+
+    async fn serve(dav: DavHandler) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let svc = hyper::service::service_fn(move |req| {
+                let dav = dav.clone();
+                async move { Ok::<_, Infallible>(dav.handle(req).await) }
+            });
+            let _ = hyper::server::conn::Http::new().serve_connection(stream, svc).await;
+        });
+        TcpStream::connect(addr).await.unwrap()
+    }
+
+    // Read a response header, without the body.
+    async fn response(stream: &mut TcpStream) -> String {
+        let mut data = Vec::new();
+        let mut byte = [0u8];
+        while !data.ends_with(b"\r\n\r\n") {
+            let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut byte))
+                .await
+                .expect("timeout waiting for a response")
+                .unwrap();
+            if n == 0 {
+                break;
+            }
+            data.push(byte[0]);
         }
+        String::from_utf8(data).unwrap().to_lowercase()
+    }
 
-        // Report whether we created or updated the file.
-        *res.status_mut() = match meta {
-            Ok(_) => SC::NO_CONTENT,
-            Err(_) => {
-                res.headers_mut().typed_insert(headers::ContentLength(0));
-                SC::CREATED
-            },
-        };
+    #[tokio::test]
+    async fn expect_100_continue() {
+        let mut stream = server().await;
+        let req = "PUT /file HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\
+                   Expect: 100-continue\r\n\r\n";
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert!(response(&mut stream).await.starts_with("http/1.1 100 continue"));
+        stream.write_all(b"hello").await.unwrap();
+        assert!(response(&mut stream).await.starts_with("http/1.1 201"));
 
-        // no errors, connection may be kept open.
-        res.headers_mut().remove(http::header::CONNECTION);
+        // the connection can be used again.
+        let req = "GET /file HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert!(response(&mut stream).await.starts_with("http/1.1 200"));
+    }
 
-        if let Ok(m) = file.metadata().await {
-            if let Some(etag) = davheaders::ETag::from_meta(&m) {
-                res.headers_mut().typed_insert(etag);
+    #[tokio::test]
+    async fn expect_100_continue_refused() {
+        // a failed precondition is the final answer, there is no 100 first.
+        let mut stream = server().await;
+        let req = "PUT /file HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\
+                   If-Match: *\r\nExpect: 100-continue\r\n\r\n";
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let resp = response(&mut stream).await;
+        assert!(resp.starts_with("http/1.1 412"));
+        assert!(resp.contains("connection: close"));
+
+        let mut stream = server().await;
+        let req = "PUT /file HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: teapot\r\n\r\n";
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert!(response(&mut stream).await.starts_with("http/1.1 417"));
+    }
+
+    #[tokio::test]
+    async fn chunked() {
+        let fs = MemFs::new();
+        let dav = || {
+            DavHandler::builder()
+                .filesystem(fs.clone())
+                .max_upload_size(10)
+                .build_handler()
+        };
+        let put = |path: &str, chunks: &[&str]| {
+            let mut req = format!(
+                "PUT {} HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n",
+                path
+            );
+            for chunk in chunks.iter().chain(std::iter::once(&"")) {
+                req.push_str(&format!("{:x}\r\n{}\r\n", chunk.len(), chunk));
             }
-            if let Ok(modified) = m.modified() {
-                res.headers_mut()
-                    .typed_insert(headers::LastModified::from(modified));
+            req
+        };
+
+        let mut stream = serve(dav()).await;
+        stream.write_all(put("/file", &["hello", " you"]).as_bytes()).await.unwrap();
+        let resp = response(&mut stream).await;
+        assert!(resp.starts_with("http/1.1 201"));
+        assert!(resp.contains("content-length: 0\r\n"));
+        stream.write_all(put("/file", &["hello"]).as_bytes()).await.unwrap();
+        assert!(response(&mut stream).await.starts_with("http/1.1 204"));
+
+        // without a length, the limit is checked while the body comes in.
+        let mut stream = serve(dav()).await;
+        stream.write_all(put("/big", &["hello", " world"]).as_bytes()).await.unwrap();
+        let resp = response(&mut stream).await;
+        assert!(resp.starts_with("http/1.1 413"));
+        assert!(resp.contains("connection: close"));
+        let path = crate::davpath::DavPath::new("/big").unwrap();
+        assert!(crate::fs::DavFileSystem::metadata(&*fs, &path).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn max_upload_size() {
+        let fs = MemFs::new();
+        let dav = || {
+            DavHandler::builder()
+                .filesystem(fs.clone())
+                .max_upload_size(10)
+                .build_handler()
+        };
+        let exists = |path: &'static str| {
+            let fs = fs.clone();
+            async move {
+                let path = crate::davpath::DavPath::new(path).unwrap();
+                crate::fs::DavFileSystem::metadata(&*fs, &path).await.is_ok()
             }
+        };
+
+        // exactly at the limit is fine.
+        let mut stream = serve(dav()).await;
+        let req = "PUT /file HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n0123456789";
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert!(response(&mut stream).await.starts_with("http/1.1 201"));
+
+        // a Content-Length over the limit is refused before the body is sent.
+        let mut stream = serve(dav()).await;
+        let req = "PUT /big HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\
+                   Expect: 100-continue\r\n\r\n";
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let resp = response(&mut stream).await;
+        assert!(resp.starts_with("http/1.1 413"), "{}", resp);
+        assert!(resp.contains("connection: close"));
+        assert!(!exists("/big").await);
+        assert!(exists("/file").await);
+    }
+
+    #[tokio::test]
+    async fn streaming() {
+        let fs = MemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        let (mut tx, body) = hyper::Body::channel();
+        let req = http::Request::put("/file").body(body).unwrap();
+        let put = tokio::spawn(async move { dav.handle(req).await.status() });
+
+        // each chunk is in the file before the next one is sent.
+        let path = crate::davpath::DavPath::new("/file").unwrap();
+        let mut want = 0;
+        for chunk in ["hello", " big", " world"].iter() {
+            tx.send_data(bytes::Bytes::from_static(chunk.as_bytes())).await.unwrap();
+            want += chunk.len() as u64;
+            let written = async {
+                loop {
+                    match crate::fs::DavFileSystem::metadata(&*fs, &path).await {
+                        Ok(m) if m.len() == want => break,
+                        _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                    }
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(5), written).await.expect("chunk was not written");
         }
-        Ok(res)
+        drop(tx);
+        assert_eq!(put.await.unwrap(), 201);
+    }
+
+    #[tokio::test]
+    async fn created_or_overwritten() {
+        use crate::testing::TestClient;
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .build_handler();
+        let c = TestClient::new(dav);
+
+        let resp = c.request("PUT", "/dav/a%20file", "hello").await;
+        assert_eq!(resp.status(), http::StatusCode::CREATED);
+        assert_eq!(resp.headers()["location"], "/dav/a%20file");
+
+        let resp = c.request("PUT", "/dav/a%20file", "world").await;
+        assert_eq!(resp.status(), http::StatusCode::NO_CONTENT);
+        assert!(resp.headers().get("location").is_none());
+
+        // the Location is rewritten like the hrefs.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .href_rewrite(|href| href.replacen("/dav/", "/public/", 1))
+            .build_handler();
+        let resp = TestClient::new(dav).request("PUT", "/dav/file", "hello").await;
+        assert_eq!(resp.headers()["location"], "/public/file");
+    }
+
+    #[tokio::test]
+    async fn atomic_put() {
+        use crate::testing::TestClient;
+        use http::{Request, StatusCode};
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .atomic_put(true)
+            .build_handler();
+        let c = TestClient::new(dav);
+        assert_eq!(c.request("PUT", "/file", "hello").await.status(), StatusCode::CREATED);
+        let proppatch = r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop>
+            <Z:color xmlns:Z="urn:test">red</Z:color></D:prop></D:set></D:propertyupdate>"#;
+        assert_eq!(c.request("PROPPATCH", "/file", proppatch).await.status(), StatusCode::MULTI_STATUS);
+
+        // a failed upload leaves the file alone.
+        let req = Request::put("/file").header("Content-Length", "10").body("world").unwrap();
+        assert_eq!(c.send(req).await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(c.request("GET", "/file", "").await.into_body(), "hello");
+
+        // a complete one replaces it, and keeps the properties.
+        assert_eq!(c.request("PUT", "/file", "world").await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(c.request("GET", "/file", "").await.into_body(), "world");
+        let body = c.request("PROPFIND", "/file", "").await.into_body();
+        assert!(String::from_utf8_lossy(&body).contains(">red</"));
+
+        // temporary files are not listed.
+        c.request("PUT", "/.put-0123.tmp", "x").await;
+        let req = Request::builder().method("PROPFIND").uri("/").header("Depth", "1");
+        let body = c.send(req.body("").unwrap()).await.into_body();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<D:href>/file</D:href>") && !body.contains(".put-"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn atomic_put_bindings() {
+        use crate::localfs::LocalFs;
+        use crate::testing::{tempdir, TestClient};
+
+        let dir = tempdir();
+        std::fs::write(dir.join("a"), "hello").unwrap();
+        std::fs::hard_link(dir.join("a"), dir.join("b")).unwrap();
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .atomic_put(true)
+            .build_handler();
+        let c = TestClient::new(dav);
+
+        // both bindings see the new content.
+        c.request("PUT", "/a", "world").await;
+        assert_eq!(c.request("GET", "/b", "").await.into_body(), "world");
+    }
+
+    #[tokio::test]
+    async fn content_range() {
+        use crate::testing::TestClient;
+        use http::{Request, StatusCode};
+
+        let c = TestClient::new(DavHandler::builder().filesystem(MemFs::new()).build_handler());
+        c.request("PUT", "/file", "hello world").await;
+        let put = |range: &str, body: &'static str| {
+            let req = Request::builder().method("PUT").uri("/file").header("Content-Range", range);
+            c.send(req.body(body).unwrap())
+        };
+
+        // overwrite in the middle, and append.
+        assert_eq!(put("bytes 6-10/11", "WORLD").await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(put("bytes 11-13/*", "!!!").await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(c.request("GET", "/file", "").await.into_body(), "hello WORLD!!!");
+
+        // invalid ranges, and a range that would leave a hole.
+        for (range, body, status) in [
+            ("bytes 4-2/14", "xxx", StatusCode::RANGE_NOT_SATISFIABLE),
+            ("bytes 12-15/14", "xxxx", StatusCode::RANGE_NOT_SATISFIABLE),
+            ("bytes */14", "xxx", StatusCode::RANGE_NOT_SATISFIABLE),
+            ("bytes 20-22/*", "xxx", StatusCode::CONFLICT),
+        ] {
+            assert_eq!(put(range, body).await.status(), status, "{}", range);
+        }
+        // the range must match the Content-Length.
+        let req = Request::builder()
+            .method("PUT")
+            .uri("/file")
+            .header("Content-Range", "bytes 0-3/*")
+            .header("Content-Length", "2");
+        assert_eq!(c.send(req.body("xx").unwrap()).await.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(c.request("GET", "/file", "").await.into_body(), "hello WORLD!!!");
     }
 }
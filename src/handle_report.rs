@@ -0,0 +1,321 @@
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use headers::HeaderMapExt;
+use http::{HeaderMap, Request, Response, StatusCode};
+use xmltree::Element;
+
+use crate::async_stream::AsyncStream;
+use crate::body::Body;
+use crate::davheaders::{self, Depth};
+use crate::davpath::DavPath;
+use crate::errors::DavError;
+use crate::fs::*;
+use crate::util::dav_xml_error;
+use crate::xmltree_ext::ElementExt;
+use crate::DavResult;
+
+pub(crate) type ReportHandler = Arc<dyn Fn(DavPath, Report) -> BoxFuture<'static, Response<Body>> + Send + Sync>;
+
+/// A `REPORT` request, as passed to the report handler.
+///
+/// See [`DavConfig::report_handler`](crate::DavConfig::report_handler).
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Name of the root element of the request body, e.g. `sync-collection`.
+    pub name:      String,
+    /// XML namespace of the root element, e.g. `DAV:`.
+    pub namespace: Option<String>,
+    /// The request headers (the report might depend on `Depth:`, for example).
+    pub headers:   HeaderMap,
+    /// The XML request body.
+    pub body:      Vec<u8>,
+}
+
+impl crate::DavInner {
+    pub(crate) async fn handle_report(self, req: &Request<()>, xmldata: &[u8]) -> DavResult<Response<Body>> {
+        // we need the name of the report.
+        let root = match Element::parse(Cursor::new(xmldata)) {
+            Ok(root) => root,
+            Err(_) => return Err(DavError::XmlParseError),
+        };
+
+        // the resource must exist.
+        let path = self.path(req);
+        let meta = self.fs.metadata(&path).await?;
+
+        // RFC6578 sync-collection, if the filesystem supports it.
+        if root.name == "sync-collection" && root.namespace.as_deref() == Some("DAV:") {
+            match self.fs.sync_token(&path).await {
+                Ok(token) => return self.handle_sync_collection(req, path, meta, root, token).await,
+                Err(FsError::NotImplemented) => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let handler = match self.report_handler {
+            Some(ref handler) => handler.clone(),
+            None => return Err(StatusCode::METHOD_NOT_ALLOWED.into()),
+        };
+        let report = Report {
+            name:      root.name,
+            namespace: root.namespace,
+            headers:   req.headers().clone(),
+            body:      xmldata.to_vec(),
+        };
+        debug!("report {:?} {:?} on {}", report.namespace, report.name, path);
+
+        Ok(handler(path, report).await)
+    }
+
+    async fn handle_sync_collection(
+        self,
+        req: &Request<()>,
+        mut path: DavPath,
+        meta: Box<dyn DavMetaData>,
+        root: Element,
+        new_token: String,
+    ) -> DavResult<Response<Body>>
+    {
+        // RFC6578 3.2: only on collections, and Depth must be 0.
+        if !meta.is_dir() {
+            return Err(StatusCode::FORBIDDEN.into());
+        }
+        path.add_slash();
+        match req.headers().typed_get::<Depth>() {
+            Some(Depth::Zero) | None => {},
+            Some(_) => return Err(StatusCode::BAD_REQUEST.into()),
+        }
+
+        let token = root.get_child("sync-token").and_then(|t| t.get_text()).unwrap_or_default();
+        let depth = match root.get_child("sync-level").and_then(|l| l.get_text()).as_deref() {
+            Some("1") | None => Depth::One,
+            Some("infinite") => Depth::Infinity,
+            Some(_) => return Err(StatusCode::BAD_REQUEST.into()),
+        };
+        let props = root
+            .get_child("prop")
+            .map_or(Vec::new(), |p| p.clone().take_child_elems());
+
+        // the initial sync walks the collection, just like PROPFIND does,
+        // so it is bound by the same limit.
+        if let Some(max_depth) = self.propfind_max_depth.filter(|_| token.is_empty()) {
+            if depth == Depth::Infinity || max_depth < 1 {
+                debug!("sync-collection: depth {:?} exceeds the maximum of {}", depth, max_depth);
+                let mut res = Response::new(dav_xml_error("<D:propfind-finite-depth/>"));
+                let ct = "application/xml; charset=utf-8".to_owned();
+                res.headers_mut().typed_insert(davheaders::ContentType(ct));
+                *res.status_mut() = StatusCode::FORBIDDEN;
+                return Ok(res);
+            }
+        }
+
+        // initial sync, or the changes since the last one.
+        let changes = if token.is_empty() {
+            None
+        } else {
+            match self.fs.changes_since(&path, &token, depth == Depth::Infinity).await {
+                Ok(changes) => Some(changes),
+                Err(FsError::Forbidden) | Err(FsError::NotImplemented) => {
+                    debug!("sync-collection: invalid sync token {}", token);
+                    let mut res = Response::new(dav_xml_error("<D:valid-sync-token/>"));
+                    let ct = "application/xml; charset=utf-8".to_owned();
+                    res.headers_mut().typed_insert(davheaders::ContentType(ct));
+                    *res.status_mut() = StatusCode::FORBIDDEN;
+                    return Ok(res);
+                },
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        let mut res = Response::new(Body::empty());
+        let mut pw = self.prop_writer(req, &mut res, "prop", props)?;
+
+        *res.body_mut() = Body::from(AsyncStream::new(|tx| {
+            async move {
+                pw.set_tx(tx);
+                match changes {
+                    None => self.propfind_directory(&path, depth, &mut pw).await?,
+                    Some(changes) => {
+                        let mut seen = HashSet::new();
+                        for change in changes.into_iter() {
+                            let mut cpath = match change {
+                                Change::Modified(p) | Change::Removed(p) => p,
+                            };
+                            let _ = cpath.set_prefix(path.prefix());
+                            // only members of this collection, and only
+                            // direct members unless sync-level is infinite.
+                            let name = cpath.as_bytes();
+                            let name = name.strip_suffix(b"/").unwrap_or(name);
+                            let rel = match name.strip_prefix(path.as_bytes()) {
+                                Some(rel) if !rel.is_empty() => rel,
+                                _ => continue,
+                            };
+                            if depth == Depth::One && rel.contains(&b'/') {
+                                continue;
+                            }
+                            if !seen.insert(name.to_vec()) {
+                                continue;
+                            }
+                            // whatever the change was, report how it is now.
+                            match self.fs.metadata(&cpath).await {
+                                Ok(meta) => {
+                                    cpath.add_slash_if(meta.is_dir());
+                                    pw.write_props(&cpath, meta).await?;
+                                },
+                                Err(_) => pw.write_status(&cpath, StatusCode::NOT_FOUND)?,
+                            }
+                            pw.flush_some().await?;
+                        }
+                    },
+                }
+                pw.write_element(&Element::new2("D:sync-token").text(new_token))?;
+                pw.close().await?;
+                Ok(())
+            }
+        }));
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use http::Response;
+
+    use crate::body::Body;
+    use crate::localfs::LocalFs;
+    use crate::memfs::MemFs;
+    use crate::testing::TestClient;
+    use crate::DavHandler;
+
+    fn sync(token: &str, level: &str) -> String {
+        format!(
+            concat!(
+                r#"<D:sync-collection xmlns:D="DAV:">"#,
+                "<D:sync-token>{}</D:sync-token><D:sync-level>{}</D:sync-level>",
+                "<D:prop><D:getetag/></D:prop></D:sync-collection>"
+            ),
+            token, level
+        )
+    }
+
+    async fn report(client: &TestClient, body: String) -> (u16, String) {
+        let resp = client.request("REPORT", "/", body).await;
+        let status = resp.status().as_u16();
+        (status, String::from_utf8_lossy(resp.body()).into_owned())
+    }
+
+    fn token(body: &str) -> String {
+        let start = body.find("<D:sync-token>").unwrap() + "<D:sync-token>".len();
+        let end = body[start..].find("</D:sync-token>").unwrap();
+        body[start..start + end].to_string()
+    }
+
+    #[tokio::test]
+    async fn sync_collection() {
+        let dir = crate::testing::tempdir();
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::write(dir.join("one.txt"), "1").unwrap();
+        std::fs::write(dir.join("two.txt"), "2").unwrap();
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .build_handler();
+        let client = TestClient::new(dav);
+
+        // initial sync: all members, and a token.
+        let (status, body) = report(&client, sync("", "1")).await;
+        assert_eq!(status, 207, "{}", body);
+        assert!(body.contains("<D:href>/one.txt</D:href>"), "{}", body);
+        assert!(body.contains("<D:href>/a/</D:href>"), "{}", body);
+        assert!(!body.contains("/a/b/"), "{}", body);
+        let first = token(&body);
+        assert!(!first.is_empty());
+
+        // an infinite initial sync walks the whole tree, and that's refused by default.
+        let (status, body) = report(&client, sync("", "infinite")).await;
+        assert_eq!(status, 403);
+        assert!(body.contains("<D:propfind-finite-depth/>"), "{}", body);
+
+        // a removed member is reported as 404.
+        let resp = client.request("DELETE", "/two.txt", "").await;
+        assert_eq!(resp.status(), 204);
+        std::fs::write(dir.join("a/b/deep.txt"), "deep").unwrap();
+        let (status, body) = report(&client, sync(&first, "1")).await;
+        assert_eq!(status, 207, "{}", body);
+        let removed = body.split("<D:response>").find(|r| r.contains("<D:href>/two.txt</D:href>"));
+        assert!(removed.unwrap().contains("404 Not Found"), "{}", body);
+        assert_ne!(token(&body), first);
+
+        // changes deeper down only with sync-level infinite.
+        assert!(!body.contains("deep.txt"), "{}", body);
+        let (status, body) = report(&client, sync(&first, "infinite")).await;
+        assert_eq!(status, 207, "{}", body);
+        assert!(body.contains("<D:href>/a/b/deep.txt</D:href>"), "{}", body);
+
+        // a token that we did not hand out.
+        let (status, body) = report(&client, sync("data:,localfs-1-2", "1")).await;
+        assert_eq!(status, 403);
+        assert!(body.contains("<D:valid-sync-token/>"), "{}", body);
+
+        // without a limit, the initial sync goes all the way down.
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .propfind_max_depth(None)
+            .build_handler();
+        let (status, body) = report(&TestClient::new(dav), sync("", "infinite")).await;
+        assert_eq!(status, 207, "{}", body);
+        assert!(body.contains("<D:href>/a/b/deep.txt</D:href>"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn report_handler() {
+        let query = r#"<C:addressbook-query xmlns:C="urn:ietf:params:xml:ns:carddav"/>"#;
+
+        // without a handler there are no reports.
+        let client = TestClient::new(DavHandler::builder().filesystem(MemFs::new()).build_handler());
+        assert_eq!(report(&client, query.to_string()).await.0, 405);
+        let allow = client.request("OPTIONS", "/", "").await.headers()["allow"].clone();
+        assert!(!allow.to_str().unwrap().contains("REPORT"));
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen2 = seen.clone();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .report_handler(move |path, report| {
+                let depth = report.headers.get("depth").map(|d| d.to_str().unwrap().to_string());
+                let (ns, name) = (&report.namespace, &report.name);
+                let what = format!("{} {:?} {} {:?}", path.as_url_string(), ns, name, depth);
+                seen2.lock().unwrap().push(what);
+                let body = String::from_utf8(report.body).unwrap();
+                async move { Response::builder().status(207).body(Body::from(body)).unwrap() }
+            })
+            .build_handler();
+        let client = TestClient::new(dav);
+
+        // the response of the handler is sent as-is.
+        let req = http::Request::builder()
+            .method("REPORT")
+            .uri("/")
+            .header("depth", "1")
+            .body(query)
+            .unwrap();
+        let resp = client.send(req).await;
+        assert_eq!(resp.status(), 207);
+        assert_eq!(resp.into_body(), query);
+        let expected = r#"/ Some("urn:ietf:params:xml:ns:carddav") addressbook-query Some("1")"#;
+        assert_eq!(*seen.lock().unwrap(), [expected]);
+        let allow = client.request("OPTIONS", "/", "").await.headers()["allow"].clone();
+        assert!(allow.to_str().unwrap().contains("REPORT"));
+
+        // the body must be XML, and the resource must exist.
+        assert_eq!(report(&client, "not xml".to_string()).await.0, 400);
+        let resp = client.request("REPORT", "/missing", query).await;
+        assert_eq!(resp.status(), 404);
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+}
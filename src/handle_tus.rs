@@ -0,0 +1,354 @@
+//
+// TUS 1.0 resumable uploads, see https://tus.io/protocols/resumable-upload
+//
+// The upload URL is the URL of the resource itself. `POST` creates the
+// upload, `HEAD` returns the current offset, and `PATCH` appends data.
+// While the upload is in progress the data is kept in a partial file
+// `.name.tus.part` next to the resource, and the total length of the
+// upload in a sidecar file `.name.tus`. When all data is in, the partial
+// file is renamed into place.
+//
+use std::error::Error as StdError;
+
+use bytes::{Buf, Bytes};
+use headers::HeaderMapExt;
+use http::StatusCode as SC;
+use http::{Request, Response};
+use http_body::Body as HttpBody;
+
+use crate::body::Body;
+use crate::conditional::if_match_get_tokens;
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::{DavError, DavResult};
+
+pub(crate) const TUS_VERSION: &str = "1.0.0";
+pub(crate) const TUS_EXTENSIONS: &str = "creation,creation-with-upload";
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+// Is this a TUS request.
+pub(crate) fn is_tus_request(req: &Request<()>) -> bool {
+    req.headers().contains_key("tus-resumable") &&
+        matches!(
+            *req.method(),
+            http::Method::POST | http::Method::PATCH | http::Method::HEAD
+        )
+}
+
+fn header_u64(req: &Request<()>, name: &str) -> DavResult<Option<u64>> {
+    match req.headers().get(name) {
+        None => Ok(None),
+        Some(v) => {
+            v.to_str()
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Some)
+                .ok_or(DavError::StatusClose(SC::BAD_REQUEST))
+        },
+    }
+}
+
+// The sidecar and partial file of a resource.
+fn tus_paths(path: &DavPath) -> DavResult<(DavPath, DavPath)> {
+    let name = path.file_name();
+    if name.is_empty() || path.is_collection() {
+        return Err(DavError::StatusClose(SC::METHOD_NOT_ALLOWED));
+    }
+    let mut sidecar = path.parent();
+    let mut name = [b".", name, b".tus"].concat();
+    sidecar.push_segment(&name);
+    let mut part = path.parent();
+    name.extend_from_slice(b".part");
+    part.push_segment(&name);
+    Ok((sidecar, part))
+}
+
+impl crate::DavInner {
+    pub(crate) async fn handle_tus<ReqBody, ReqData, ReqError>(
+        self,
+        req: &Request<()>,
+        body: ReqBody,
+    ) -> DavResult<Response<Body>>
+    where
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+    {
+        let mut res = Response::new(Body::empty());
+        res.headers_mut()
+            .insert("Tus-Resumable", TUS_VERSION.parse().unwrap());
+
+        let version = req.headers().get("tus-resumable").and_then(|v| v.to_str().ok());
+        if version != Some(TUS_VERSION) {
+            *res.status_mut() = SC::PRECONDITION_FAILED;
+            res.headers_mut()
+                .insert("Tus-Version", TUS_VERSION.parse().unwrap());
+            return Ok(res);
+        }
+
+        let path = self.path(&req);
+        let (sidecar, part) = tus_paths(&path)?;
+
+        match *req.method() {
+            http::Method::HEAD => {
+                let length = self.tus_length(&sidecar).await?;
+                let offset = self.fs.metadata(&part).await?.len();
+                let h = res.headers_mut();
+                h.insert("Upload-Offset", offset.into());
+                h.insert("Upload-Length", length.into());
+                h.typed_insert(headers::CacheControl::new().with_no_store());
+                return Ok(res);
+            },
+            http::Method::POST => {
+                let length =
+                    header_u64(req, "upload-length")?.ok_or(DavError::StatusClose(SC::BAD_REQUEST))?;
+                if self.max_upload_size.is_some_and(|max| length > max) {
+                    return Err(DavError::StatusClose(SC::PAYLOAD_TOO_LARGE));
+                }
+                self.tus_check_locks(req, &path).await?;
+                if !self.has_parent(&path).await {
+                    return Err(DavError::StatusClose(SC::CONFLICT));
+                }
+                if self.fs.metadata(&path).await.map(|m| m.is_dir()).unwrap_or(false) {
+                    return Err(DavError::StatusClose(SC::METHOD_NOT_ALLOWED));
+                }
+
+                // create the sidecar and an empty partial file.
+                let mut file = self.fs.open(&sidecar, tus_open_options()).await?;
+                file.write_bytes(Bytes::from(format!("{}\n", length))).await?;
+                file.flush().await?;
+                let mut file = self.fs.open(&part, tus_open_options()).await?;
+                file.flush().await?;
+
+                // creation-with-upload.
+                let mut offset = 0;
+                if content_type(req) == Some(OFFSET_OCTET_STREAM) {
+                    offset = self.tus_append(file, body, 0, length).await?;
+                }
+                if offset == length {
+                    self.tus_finish(&path, &sidecar, &part).await?;
+                }
+
+                *res.status_mut() = SC::CREATED;
+                let h = res.headers_mut();
+                let location = crate::davhandler::href(self.href_rewrite.as_ref(), &path);
+                h.insert("Location", location.parse().unwrap());
+                h.insert("Upload-Offset", offset.into());
+                return Ok(res);
+            },
+            _ => {},
+        }
+
+        // PATCH.
+        if content_type(req) != Some(OFFSET_OCTET_STREAM) {
+            return Err(DavError::StatusClose(SC::UNSUPPORTED_MEDIA_TYPE));
+        }
+        let offset = header_u64(req, "upload-offset")?.ok_or(DavError::StatusClose(SC::BAD_REQUEST))?;
+        self.tus_check_locks(req, &path).await?;
+        let length = self.tus_length(&sidecar).await?;
+        let current = self.fs.metadata(&part).await?.len();
+        if offset != current {
+            return Err(DavError::StatusClose(SC::CONFLICT));
+        }
+
+        let mut oo = OpenOptions::write();
+        oo.append = true;
+        let file = self.fs.open(&part, oo).await?;
+        let offset = self.tus_append(file, body, offset, length).await?;
+        if offset == length {
+            self.tus_finish(&path, &sidecar, &part).await?;
+        }
+
+        *res.status_mut() = SC::NO_CONTENT;
+        res.headers_mut().insert("Upload-Offset", offset.into());
+        Ok(res)
+    }
+
+    // Read the upload length from the sidecar.
+    async fn tus_length(&self, sidecar: &DavPath) -> DavResult<u64> {
+        let mut file = self.fs.open(sidecar, OpenOptions::read()).await?;
+        let data = file.read_bytes(64).await?;
+        std::str::from_utf8(&data)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .ok_or(DavError::Status(SC::INTERNAL_SERVER_ERROR))
+    }
+
+    // If the resource is locked, check if we hold that lock.
+    async fn tus_check_locks(&self, req: &Request<()>, path: &DavPath) -> DavResult<()> {
+        let meta = self.fs.metadata(path).await;
+        let tokens = if_match_get_tokens(req, meta.as_ref().ok(), &self.fs, &self.ls, path);
+        let tokens = tokens.await.map_err(DavError::StatusClose)?;
+        if let Some(ref locksystem) = self.ls {
+            let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+            let principal = self.principal.as_deref();
+            if locksystem.check(path, principal, false, false, t).is_err() {
+                return Err(DavError::StatusClose(SC::LOCKED));
+            }
+        }
+        Ok(())
+    }
+
+    // Append the request body to the partial file, returns the new offset.
+    //
+    // If the body is cut short, the data that did arrive is kept
+    // so that the client can resume from there.
+    async fn tus_append<ReqBody, ReqData, ReqError>(
+        &self,
+        mut file: Box<dyn DavFile>,
+        body: ReqBody,
+        mut offset: u64,
+        length: u64,
+    ) -> DavResult<u64>
+    where
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+    {
+        pin_utils::pin_mut!(body);
+        let mut result = Ok(());
+        while let Some(data) = body.data().await {
+            let mut buf = match data {
+                Ok(buf) => buf,
+                Err(_) => {
+                    result = Err(DavError::StatusClose(SC::BAD_REQUEST));
+                    break;
+                },
+            };
+            let buflen = buf.remaining() as u64;
+            if offset + buflen > length {
+                result = Err(DavError::StatusClose(SC::PAYLOAD_TOO_LARGE));
+                break;
+            }
+            let bytes = buf.copy_to_bytes(buflen as usize);
+            if let Err(e) = file.write_bytes(bytes).await {
+                result = Err(e.into());
+                break;
+            }
+            offset += buflen;
+        }
+        file.flush().await?;
+        result.map(|_| offset)
+    }
+
+    // Move the completed upload into place.
+    async fn tus_finish(&self, path: &DavPath, sidecar: &DavPath, part: &DavPath) -> DavResult<()> {
+        self.fs.rename(part, path).await?;
+        self.fs.remove_file(sidecar).await?;
+        Ok(())
+    }
+}
+
+fn tus_open_options() -> OpenOptions {
+    let mut oo = OpenOptions::write();
+    oo.create = true;
+    oo.truncate = true;
+    oo
+}
+
+fn content_type(req: &Request<()>) -> Option<&str> {
+    req.headers().get("content-type").and_then(|v| v.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Request, StatusCode};
+
+    use crate::memfs::MemFs;
+    use crate::testing::TestClient;
+    use crate::DavHandler;
+
+    fn client() -> TestClient {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .href_rewrite(|href| href.replacen("/dav/", "/public/", 1))
+            .tus(true)
+            .build_handler();
+        TestClient::new(dav)
+    }
+
+    fn tus(method: &str, offset: Option<u64>) -> http::request::Builder {
+        let mut req = Request::builder()
+            .method(method)
+            .uri("/dav/file")
+            .header("Tus-Resumable", "1.0.0");
+        if let Some(offset) = offset {
+            req = req
+                .header("Upload-Offset", offset)
+                .header("Content-Type", "application/offset+octet-stream");
+        }
+        req
+    }
+
+    #[tokio::test]
+    async fn upload() {
+        let c = client();
+        let req = tus("POST", None).header("Upload-Length", "10").body("").unwrap();
+        let resp = c.send(req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers()["location"], "/public/file");
+        assert_eq!(resp.headers()["upload-offset"], "0");
+
+        let resp = c.send(tus("PATCH", Some(0)).body("hello").unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(resp.headers()["upload-offset"], "5");
+
+        // the offset must be where the upload is.
+        let resp = c.send(tus("PATCH", Some(3)).body("world").unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let resp = c.send(tus("HEAD", None).body("").unwrap()).await;
+        assert_eq!(resp.headers()["upload-offset"], "5");
+        assert_eq!(resp.headers()["upload-length"], "10");
+        assert_eq!(c.request("GET", "/dav/file", "").await.status(), StatusCode::NOT_FOUND);
+
+        // not more than the length.
+        let resp = c.send(tus("PATCH", Some(5)).body("world!").unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // when the last byte is in, the file is there.
+        let resp = c.send(tus("PATCH", Some(5)).body("world").unwrap()).await;
+        assert_eq!(resp.headers()["upload-offset"], "10");
+        assert_eq!(c.request("GET", "/dav/file", "").await.into_body(), "helloworld");
+        let resp = c.send(tus("HEAD", None).body("").unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn creation_with_upload() {
+        let c = client();
+        let req = tus("POST", Some(0)).header("Upload-Length", "5").body("hello").unwrap();
+        let resp = c.send(req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers()["upload-offset"], "5");
+        assert_eq!(c.request("GET", "/dav/file", "").await.into_body(), "hello");
+
+        // another version of the protocol.
+        let req = Request::post("/dav/other").header("Tus-Resumable", "0.2.2");
+        let req = req.header("Upload-Length", "5").body("").unwrap();
+        assert_eq!(c.send(req).await.status(), StatusCode::PRECONDITION_FAILED);
+    }
+
+    #[tokio::test]
+    async fn max_upload_size() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .tus(true)
+            .max_upload_size(10)
+            .build_handler();
+        let c = TestClient::new(dav);
+        let resp = c.request("OPTIONS", "/file", "").await;
+        assert_eq!(resp.headers()["tus-max-size"], "10");
+
+        let req = Request::post("/file").header("Tus-Resumable", "1.0.0");
+        let resp = c.send(req.header("Upload-Length", "11").body("").unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let req = Request::head("/file").header("Tus-Resumable", "1.0.0");
+        assert_eq!(c.send(req.body("").unwrap()).await.status(), StatusCode::NOT_FOUND);
+
+        let req = Request::post("/file").header("Tus-Resumable", "1.0.0");
+        let resp = c.send(req.header("Upload-Length", "10").body("").unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+}
@@ -0,0 +1,269 @@
+//
+// Filesystem wrapper that hides files and directories, enabled with
+// `DavConfig::hide`.
+//
+// A path is hidden if the function returns true for one of its segments.
+// Hidden entries are left out of directory listings, both in PROPFIND
+// and in the autoindex, and any other access to them fails with NotFound.
+//
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use futures::{future, FutureExt, StreamExt};
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+pub(crate) type HideFn = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+// Return NotFound if one of the paths is hidden.
+macro_rules! visible {
+    ($self:ident, $($path:expr),+) => {
+        if $($self.is_hidden($path))||+ {
+            return Box::pin(future::ready(Err(FsError::NotFound)));
+        }
+    };
+}
+
+#[derive(Clone)]
+pub(crate) struct HideFs {
+    inner: Box<dyn DavFileSystem>,
+    hide:  HideFn,
+}
+
+impl HideFs {
+    pub(crate) fn wrap(fs: Box<dyn DavFileSystem>, hide: Option<HideFn>) -> Box<dyn DavFileSystem> {
+        match hide {
+            Some(hide) => Box::new(HideFs { inner: fs, hide }),
+            None => fs,
+        }
+    }
+
+    fn is_hidden(&self, path: &DavPath) -> bool {
+        path.as_bytes()
+            .split(|&b| b == b'/')
+            .filter(|s| !s.is_empty())
+            .any(|s| (self.hide)(&String::from_utf8_lossy(s)))
+    }
+}
+
+impl DavFileSystem for HideFs {
+    fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+        visible!(self, path);
+        self.inner.open(path, options)
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+    {
+        visible!(self, path);
+        async move {
+            let hide = self.hide.clone();
+            let entries = self.inner.read_dir(path, meta).await?.filter(move |entry| {
+                let hidden = hide(&String::from_utf8_lossy(&entry.name()));
+                future::ready(!hidden)
+            });
+            Ok(Box::pin(entries) as FsStream<Box<dyn DavDirEntry>>)
+        }
+        .boxed()
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        visible!(self, path);
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        visible!(self, path);
+        self.inner.symlink_metadata(path)
+    }
+
+    fn exists<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        if self.is_hidden(path) {
+            return Box::pin(future::ok(false));
+        }
+        self.inner.exists(path)
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        visible!(self, path);
+        self.inner.create_dir(path)
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        visible!(self, path);
+        self.inner.remove_dir(path)
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        visible!(self, path);
+        self.inner.remove_file(path)
+    }
+
+    // this removes the hidden entries in the directory as well.
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        visible!(self, path);
+        self.inner.remove_dir_all(path)
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        visible!(self, from, to);
+        self.inner.rename(from, to)
+    }
+
+    // if the temporary file would be hidden, write in place.
+    fn temp_path(&self, path: &DavPath) -> Option<DavPath> {
+        self.inner.temp_path(path).filter(|tmp| !self.is_hidden(tmp))
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        visible!(self, from, to);
+        self.inner.copy(from, to)
+    }
+
+    fn bind<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        visible!(self, from, to);
+        self.inner.bind(from, to)
+    }
+
+    fn supports_bind(&self) -> bool {
+        self.inner.supports_bind()
+    }
+
+    fn capabilities(&self) -> FsCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        visible!(self, path);
+        self.inner.set_accessed(path, tm)
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        visible!(self, path);
+        self.inner.set_modified(path, tm)
+    }
+
+    fn have_props<'a>(&'a self, path: &'a DavPath) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        if self.is_hidden(path) {
+            return Box::pin(future::ready(false));
+        }
+        self.inner.have_props(path)
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(http::StatusCode, DavProp)>>
+    {
+        visible!(self, path);
+        self.inner.patch_props(path, patch)
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        visible!(self, path);
+        self.inner.get_props(path, do_content)
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        visible!(self, path);
+        self.inner.get_prop(path, prop)
+    }
+
+    fn get_named_props<'a>(&'a self, path: &'a DavPath, props: Vec<DavProp>) -> FsFuture<'a, Vec<DavProp>> {
+        visible!(self, path);
+        self.inner.get_named_props(path, props)
+    }
+
+    fn quota<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        self.inner.quota(path)
+    }
+
+    fn privileges<'a>(&'a self, path: &'a DavPath, principal: Option<&'a str>) -> FsFuture<'a, PrivilegeSet> {
+        self.inner.privileges(path, principal)
+    }
+
+    fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        self.inner.content_type(path)
+    }
+
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.inner.collection_etag(path)
+    }
+
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.inner.sync_token(path)
+    }
+
+    fn changes_since<'a>(
+        &'a self,
+        path: &'a DavPath,
+        token: &'a str,
+        deep: bool,
+    ) -> FsFuture<'a, Vec<Change>> {
+        async move {
+            let changes = self.inner.changes_since(path, token, deep).await?;
+            let changes = changes.into_iter().filter(|c| {
+                match c {
+                    Change::Modified(p) | Change::Removed(p) => !self.is_hidden(p),
+                }
+            });
+            Ok(changes.collect())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use crate::memfs::MemFs;
+    use crate::testing::TestClient;
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn hidden() {
+        // put some hidden files there behind the back of the handler.
+        let fs = MemFs::new();
+        let raw = TestClient::new(DavHandler::builder().filesystem(fs.clone()).build_handler());
+        raw.request("MKCOL", "/.git", "").await;
+        raw.request("PUT", "/.git/config", "secret").await;
+        raw.request("PUT", "/.index.html", "hidden index").await;
+        raw.request("PUT", "/Thumbs.db", "thumbs").await;
+        raw.request("PUT", "/file", "hello").await;
+
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .hide(|name| name.starts_with('.') || name == "Thumbs.db")
+            .indexfile(".index.html")
+            .autoindex(true, None)
+            .build_handler();
+        let c = TestClient::new(dav);
+
+        let not_found = StatusCode::NOT_FOUND;
+        assert_eq!(c.request("GET", "/.git/config", "").await.status(), not_found);
+        assert_eq!(c.request("GET", "/Thumbs.db", "").await.status(), not_found);
+        assert_eq!(c.request("PROPFIND", "/.git", "").await.status(), not_found);
+        assert_eq!(c.request("DELETE", "/.git/config", "").await.status(), not_found);
+        assert_eq!(c.request("GET", "/file", "").await.status(), StatusCode::OK);
+
+        let req = http::Request::builder().method("PROPFIND").uri("/").header("Depth", "1");
+        let body = c.send(req.body("").unwrap()).await.into_body();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<D:href>/file</D:href>"));
+        assert!(!body.contains(".git") && !body.contains(".index") && !body.contains("Thumbs"));
+
+        // the index file is hidden too, so this is the autoindex.
+        let body = c.request("GET", "/", "").await.into_body();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("file") && !body.contains("hidden index"));
+        assert!(!body.contains(".git") && !body.contains("Thumbs"));
+    }
+}
@@ -0,0 +1,379 @@
+//
+// The WebDAV "If" header, RFC4918 10.4.
+//
+// The header has one or more lists of conditions, optionally tagged with
+// the resource they are about. The header is true if any of the lists is
+// true, and a list is true if all of its conditions are. A condition is a
+// state token (a lock token) or an ETag, possibly negated with "Not".
+//
+// Parsing is done here; evaluating against the lock system and the
+// filesystem is done by conditional::dav_if_match().
+//
+// This module is internal to the crate: the types use the private
+// davheaders::ETag, so they are not part of the public API.
+//
+use std::str::FromStr;
+
+use headers::Header;
+use http::header::{HeaderName, HeaderValue};
+use lazy_static::lazy_static;
+
+use crate::davheaders::{invalid, map_invalid, one, ETag};
+
+lazy_static! {
+    pub static ref IF: HeaderName = HeaderName::from_static("if");
+}
+
+// The "If" header contains IfLists, of which the results are ORed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct If(pub Vec<IfList>);
+
+// An IfList contains Conditions, of which the results are ANDed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfList {
+    pub resource_tag: Option<url::Url>,
+    pub conditions:   Vec<IfCondition>,
+}
+
+impl If {
+    /// All state tokens in the header, also the ones in a `Not` condition.
+    pub fn state_tokens(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().flat_map(|l| l.conditions.iter()).filter_map(|c| {
+            match c.item {
+                IfItem::StateToken(ref t) => Some(t.as_str()),
+                _ => None,
+            }
+        })
+    }
+}
+
+impl IfList {
+    /// Evaluate the conditions of this list, which must all be true.
+    ///
+    /// `token_ok` tells if a state token is the token of a lock on
+    /// the resource, and `etag` is the current ETag of the resource,
+    /// if it exists. State tokens in the `DAV:` namespace, like
+    /// `<DAV:no-lock>`, never match (RFC4918 10.4.8).
+    pub fn eval(&self, token_ok: impl Fn(&str) -> bool, etag: Option<&ETag>) -> bool {
+        self.conditions.iter().all(|cond| {
+            let ok = match cond.item {
+                IfItem::StateToken(ref t) => !t.starts_with("DAV:") && token_ok(t),
+                IfItem::ETag(ref tag) => etag.is_some_and(|etag| tag.strong_eq(etag)),
+            };
+            ok != cond.not
+        })
+    }
+
+    /// Is there an ETag condition in this list?
+    pub fn has_etag(&self) -> bool {
+        self.conditions.iter().any(|c| matches!(c.item, IfItem::ETag(_)))
+    }
+
+    fn new() -> IfList {
+        IfList {
+            resource_tag: None,
+            conditions:   Vec::new(),
+        }
+    }
+    fn add(&mut self, not: bool, item: IfItem) {
+        self.conditions.push(IfCondition { not, item });
+    }
+}
+
+// Single Condition is [NOT] State-Token | ETag
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfCondition {
+    pub not:  bool,
+    pub item: IfItem,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum IfItem {
+    StateToken(String),
+    ETag(ETag),
+}
+
+// Below stuff is for the parser state.
+#[derive(Debug, Clone, PartialEq)]
+enum IfToken {
+    ListOpen,
+    ListClose,
+    Not,
+    Word(String),
+    Pointy(String),
+    ETag(ETag),
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum IfState {
+    Start,
+    RTag,
+    List,
+    Not,
+    Bad,
+}
+
+// helpers.
+fn is_whitespace(c: u8) -> bool {
+    b" \t\r\n".iter().any(|&x| x == c)
+}
+fn is_special(c: u8) -> bool {
+    b"<>()[]".iter().any(|&x| x == c)
+}
+
+fn trim_left<'a>(mut out: &'a [u8]) -> &'a [u8] {
+    while !out.is_empty() && is_whitespace(out[0]) {
+        out = &out[1..];
+    }
+    out
+}
+
+// parse one token.
+fn scan_until(buf: &[u8], c: u8) -> Result<(&[u8], &[u8]), headers::Error> {
+    let mut i = 1;
+    let mut quote = false;
+    while quote || buf.get(i) != Some(&c) {
+        if i >= buf.len() || (!quote && is_whitespace(buf[i])) {
+            return Err(invalid());
+        }
+        if buf[i] == b'"' {
+            quote = !quote;
+        }
+        i += 1
+    }
+    Ok((&buf[1..i], &buf[i + 1..]))
+}
+
+// scan one word.
+fn scan_word(buf: &[u8]) -> Result<(&[u8], &[u8]), headers::Error> {
+    for (i, &c) in buf.iter().enumerate() {
+        if is_whitespace(c) || is_special(c) || c < 32 {
+            if i == 0 {
+                return Err(invalid());
+            }
+            return Ok((&buf[..i], &buf[i..]));
+        }
+    }
+    Ok((buf, b""))
+}
+
+// get next token.
+fn get_token<'a>(buf: &'a [u8]) -> Result<(IfToken, &'a [u8]), headers::Error> {
+    let buf = trim_left(buf);
+    if buf.is_empty() {
+        return Ok((IfToken::End, buf));
+    }
+    match buf[0] {
+        b'(' => Ok((IfToken::ListOpen, &buf[1..])),
+        b')' => Ok((IfToken::ListClose, &buf[1..])),
+        b'N' if buf.starts_with(b"Not") => Ok((IfToken::Not, &buf[3..])),
+        b'<' => {
+            let (tok, rest) = scan_until(buf, b'>')?;
+            let s = std::string::String::from_utf8(tok.to_vec()).map_err(map_invalid)?;
+            Ok((IfToken::Pointy(s), rest))
+        },
+        b'[' => {
+            let (tok, rest) = scan_until(buf, b']')?;
+            let s = std::str::from_utf8(tok).map_err(map_invalid)?;
+            Ok((IfToken::ETag(ETag::from_str(s)?), rest))
+        },
+        _ => {
+            let (tok, rest) = scan_word(buf)?;
+            if tok == b"Not" {
+                Ok((IfToken::Not, rest))
+            } else {
+                let s = std::string::String::from_utf8(tok.to_vec()).map_err(map_invalid)?;
+                Ok((IfToken::Word(s), rest))
+            }
+        },
+    }
+}
+
+impl Header for If {
+    fn name() -> &'static HeaderName {
+        &IF
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where I: Iterator<Item = &'i HeaderValue> {
+        // one big state machine.
+        let mut if_lists = If(Vec::new());
+        let mut cur_list = IfList::new();
+
+        let mut state = IfState::Start;
+        let mut input = one(values)?.as_bytes();
+
+        loop {
+            let (tok, rest) = get_token(input)?;
+            input = rest;
+            state = match state {
+                IfState::Start => {
+                    match tok {
+                        IfToken::ListOpen => IfState::List,
+                        IfToken::Pointy(url) => {
+                            // an absolute URL, or just the path (RFC4918 10.4.2).
+                            let u = match url::Url::parse(&url) {
+                                Err(url::ParseError::RelativeUrlWithoutBase) if url.starts_with('/') => {
+                                    url::Url::parse("http://localhost/").unwrap().join(&url)
+                                },
+                                u => u,
+                            };
+                            cur_list.resource_tag = Some(u.map_err(map_invalid)?);
+                            IfState::RTag
+                        },
+                        IfToken::End => {
+                            if if_lists.0.len() > 0 {
+                                break;
+                            }
+                            IfState::Bad
+                        },
+                        _ => IfState::Bad,
+                    }
+                },
+                IfState::RTag => {
+                    match tok {
+                        IfToken::ListOpen => IfState::List,
+                        _ => IfState::Bad,
+                    }
+                },
+                IfState::List | IfState::Not => {
+                    let not = state == IfState::Not;
+                    match tok {
+                        IfToken::Not => {
+                            if not {
+                                IfState::Bad
+                            } else {
+                                IfState::Not
+                            }
+                        },
+                        IfToken::Pointy(stok) | IfToken::Word(stok) => {
+                            // as we don't have an URI parser, just
+                            // check if there's at least one ':' in there.
+                            if !stok.contains(":") {
+                                IfState::Bad
+                            } else {
+                                cur_list.add(not, IfItem::StateToken(stok));
+                                IfState::List
+                            }
+                        },
+                        IfToken::ETag(etag) => {
+                            cur_list.add(not, IfItem::ETag(etag));
+                            IfState::List
+                        },
+                        IfToken::ListClose => {
+                            if cur_list.conditions.is_empty() {
+                                IfState::Bad
+                            } else {
+                                // the next list is about the same resource,
+                                // unless it has a resource tag of its own.
+                                let resource_tag = cur_list.resource_tag.clone();
+                                if_lists.0.push(cur_list);
+                                cur_list = IfList::new();
+                                cur_list.resource_tag = resource_tag;
+                                IfState::Start
+                            }
+                        },
+                        _ => IfState::Bad,
+                    }
+                },
+                IfState::Bad => return Err(invalid()),
+            };
+        }
+        Ok(if_lists)
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where E: Extend<HeaderValue> {
+        let value = "[If header]";
+        values.extend(std::iter::once(HeaderValue::from_static(value)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_header() {
+        // Note that some implementations (golang net/x/webdav) also
+        // accept a "plain  word" as StateToken, instead of only
+        // a Coded-Url (<...>). We allow that as well, but I have
+        // no idea if we need to (or should!).
+        //let val = r#"  <http://x.yz/> ([W/"etag"] Not <DAV:nope> )
+        //    (Not<urn:x>[W/"bla"] plain:word:123) "#;
+        let val = r#"  <http://x.yz/> ([W/"etag"] Not <DAV:nope> ) (Not<urn:x>[W/"bla"] plain:word:123) "#;
+        let hdrval = HeaderValue::from_static(val);
+        let mut iter = std::iter::once(&hdrval);
+        let hdr = If::decode(&mut iter);
+        assert!(hdr.is_ok());
+    }
+
+    #[test]
+    fn if_header_tagged() {
+        let hdr = decode("</a/b> (<urn:x>) (Not <DAV:no>) <http://h/c> ([\"e\"])").unwrap();
+        let paths = hdr.0.iter().map(|l| l.resource_tag.as_ref().unwrap().path()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["/a/b", "/a/b", "/c"]);
+        let hdr = decode("(<urn:x>) (<urn:y>)").unwrap();
+        assert!(hdr.0.iter().all(|l| l.resource_tag.is_none()));
+        assert!(decode("<a/b> (<urn:x>)").is_err());
+        assert!(decode("(<urn:x").is_err());
+    }
+
+    fn decode(val: &'static str) -> Result<If, headers::Error> {
+        If::decode(&mut std::iter::once(&HeaderValue::from_static(val)))
+    }
+
+    const TOKEN1: &str = "urn:uuid:181d4fae-7d8c-11d0-a765-00a0c91e6bf2";
+    const TOKEN2: &str = "urn:uuid:58f202ac-22cf-11d1-b12d-002035b29092";
+
+    #[test]
+    fn rfc_examples() {
+        let etag = ETag::from_str(r#""I am an ETag""#).unwrap();
+        let other = ETag::from_str(r#""I am another ETag""#).unwrap();
+        let tok1 = |t: &str| t == TOKEN1;
+        let none = |_: &str| false;
+
+        // 10.4.6: the first list, or the second.
+        let hdr = decode(
+            r#"(<urn:uuid:181d4fae-7d8c-11d0-a765-00a0c91e6bf2> ["I am an ETag"]) (["I am another ETag"])"#,
+        )
+        .unwrap();
+        assert_eq!(hdr.0.len(), 2);
+        assert!(hdr.0[0].eval(tok1, Some(&etag)));
+        assert!(!hdr.0[0].eval(tok1, Some(&other)));
+        assert!(!hdr.0[0].eval(none, Some(&etag)));
+        assert!(hdr.0[1].eval(none, Some(&other)));
+        assert!(!hdr.0[1].eval(none, None));
+        assert_eq!(hdr.state_tokens().collect::<Vec<_>>(), vec![TOKEN1]);
+
+        // 10.4.7: not the first token, but the second.
+        let hdr = decode(concat!(
+            "(Not <urn:uuid:181d4fae-7d8c-11d0-a765-00a0c91e6bf2> ",
+            "<urn:uuid:58f202ac-22cf-11d1-b12d-002035b29092>)"
+        ))
+        .unwrap();
+        assert!(hdr.0[0].conditions[0].not && !hdr.0[0].conditions[1].not);
+        assert!(hdr.0[0].eval(|t| t == TOKEN2, None));
+        assert!(!hdr.0[0].eval(|t| t == TOKEN1 || t == TOKEN2, None));
+
+        // 10.4.8: <DAV:no-lock> never matches, so "Not <DAV:no-lock>" always does.
+        let hdr = decode("(<urn:uuid:181d4fae-7d8c-11d0-a765-00a0c91e6bf2>) (Not <DAV:no-lock>)").unwrap();
+        assert!(!hdr.0[0].eval(none, None));
+        assert!(hdr.0[1].eval(|_| true, None));
+        assert!(!decode("(<DAV:no-lock>)").unwrap().0[0].eval(|_| true, None));
+
+        // 10.4.3: a tagged list.
+        let hdr = decode(concat!(
+            "<http://www.example.com/users/f/fielding/index.html> ",
+            "(<urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6>)"
+        ))
+        .unwrap();
+        let tag = hdr.0[0].resource_tag.as_ref().unwrap();
+        assert_eq!(tag.path(), "/users/f/fielding/index.html");
+
+        // ETags in an If header are compared with the strong comparison.
+        let hdr = decode(r#"([W/"I am an ETag"])"#).unwrap();
+        assert!(hdr.0[0].has_etag());
+        assert!(!hdr.0[0].eval(none, Some(&ETag::from_str(r#"W/"I am an ETag""#).unwrap())));
+    }
+}
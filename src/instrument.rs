@@ -0,0 +1,198 @@
+//
+// Tracing instrumentation, enabled with the `tracing` feature.
+//
+// Every request gets a span with the method, path and principal. When
+// the response is ready, the status and (if known) the length of the
+// body are recorded, and an event is emitted for the outcomes that are
+// most interesting when debugging: lock conflicts, failed preconditions,
+// and resources that were not found.
+//
+use http::{Request, Response, StatusCode};
+use tracing::field::Empty;
+use tracing::Span;
+
+use crate::body::Body;
+
+pub(crate) fn request_span<B>(req: &Request<B>, principal: Option<&str>) -> Span {
+    tracing::info_span!(
+        "webdav",
+        method = %req.method(),
+        path = %req.uri().path(),
+        principal = principal,
+        status = Empty,
+        bytes = Empty,
+    )
+}
+
+pub(crate) fn record_response(span: &Span, resp: &Response<Body>) {
+    let status = resp.status();
+    span.record("status", status.as_u16());
+    let len = resp
+        .headers()
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let Some(len) = len {
+        span.record("bytes", len);
+    }
+    span.in_scope(|| {
+        match status {
+            StatusCode::LOCKED => tracing::info!(status = status.as_u16(), "lock conflict"),
+            StatusCode::PRECONDITION_FAILED => {
+                tracing::info!(status = status.as_u16(), "precondition failed")
+            },
+            StatusCode::NOT_FOUND => tracing::debug!(status = status.as_u16(), "not found"),
+            _ => {},
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use http::StatusCode;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata};
+
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+    use crate::testing::TestClient;
+    use crate::{DavConfig, DavHandler};
+
+    #[derive(Default)]
+    struct Fields(HashMap<String, String>);
+
+    impl Fields {
+        fn get(&self, name: &str) -> String {
+            self.0.get(name).cloned().unwrap_or_default()
+        }
+    }
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value));
+        }
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    // Just enough of a subscriber to see what spans and events we emit.
+    #[derive(Clone, Default)]
+    struct Collector(Arc<State>);
+
+    #[derive(Default)]
+    struct State {
+        next:    AtomicU64,
+        current: Mutex<Vec<u64>>,
+        spans:   Mutex<Vec<Fields>>,
+        events:  Mutex<Vec<(Option<u64>, Fields)>>,
+    }
+
+    impl tracing::Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            self.0.spans.lock().unwrap().push(fields);
+            Id::from_u64(self.0.next.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.0.spans.lock().unwrap();
+            values.record(&mut spans[span.into_u64() as usize - 1]);
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            let span = self.0.current.lock().unwrap().last().copied();
+            self.0.events.lock().unwrap().push((span, fields));
+        }
+        fn enter(&self, span: &Id) {
+            self.0.current.lock().unwrap().push(span.into_u64());
+        }
+        fn exit(&self, _: &Id) {
+            self.0.current.lock().unwrap().pop();
+        }
+    }
+
+    #[tokio::test]
+    async fn spans_and_events() {
+        let collector = Collector::default();
+        let _guard = tracing::subscriber::set_default(collector.clone());
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let config = || DavConfig::new().principal("alice");
+        let send = |req: http::Request<&'static str>| {
+            let dav = dav.clone();
+            async move {
+                let (parts, body) = req.into_parts();
+                let req = http::Request::from_parts(parts, hyper::Body::from(body));
+                dav.handle_with(config(), req).await.status()
+            }
+        };
+        let req = |method: &str, path: &str| http::Request::builder().method(method).uri(path);
+
+        assert_eq!(send(req("PUT", "/file").body("hello").unwrap()).await, StatusCode::CREATED);
+        assert_eq!(send(req("GET", "/file").body("").unwrap()).await, StatusCode::OK);
+        assert_eq!(send(req("GET", "/missing").body("").unwrap()).await, StatusCode::NOT_FOUND);
+        let r = req("PUT", "/file").header("If-Match", "\"nope\"").body("x").unwrap();
+        assert_eq!(send(r).await, StatusCode::PRECONDITION_FAILED);
+        let lock = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope>
+            <D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+        assert_eq!(send(req("LOCK", "/file").body(lock).unwrap()).await, StatusCode::OK);
+        let status = TestClient::new(dav.clone()).request("DELETE", "/file", "").await.status();
+        assert_eq!(status, StatusCode::LOCKED);
+
+        let spans = collector.0.spans.lock().unwrap();
+        let summary: Vec<_> = spans
+            .iter()
+            .map(|s| {
+                format!(
+                    "{} {} {} {} {}",
+                    s.get("method"),
+                    s.get("path"),
+                    s.get("principal"),
+                    s.get("status"),
+                    s.get("bytes"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "PUT /file alice 201 0",
+                "GET /file alice 200 5",
+                "GET /missing alice 404 0",
+                "PUT /file alice 412 0",
+                "LOCK /file alice 200 ",
+                "DELETE /file  423 0",
+            ]
+        );
+
+        // the events are emitted inside the span of their request.
+        let events = collector.0.events.lock().unwrap();
+        let events: Vec<_> = events
+            .iter()
+            .map(|(span, fields)| (span.unwrap(), fields.get("message"), fields.get("status")))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (3, "not found".to_string(), "404".to_string()),
+                (4, "precondition failed".to_string(), "412".to_string()),
+                (6, "lock conflict".to_string(), "423".to_string()),
+            ]
+        );
+    }
+}
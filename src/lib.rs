@@ -30,8 +30,9 @@
 //! The handler in this library works with the standard http types
 //! from the `http` and `http_body` crates. That means that you can use it
 //! straight away with http libraries / frameworks that also work with
-//! those types, like hyper. Compatibility modules for [actix-web][actix-compat]
-//! and [warp][warp-compat] are also provided.
+//! those types, like hyper. Compatibility modules for [actix-web][actix-compat],
+//! [warp][warp-compat], [axum][axum-compat] and [poem][poem-compat] are also provided, as well as
+//! a [tower `Service`][tower-compat].
 //!
 //! ## Implemented standards.
 //!
@@ -40,7 +41,8 @@
 //! [RFC4918] webdav specification.
 //!
 //! The litmus test suite also has tests for RFC3744 "acl" and "principal",
-//! RFC5842 "bind", and RFC3253 "versioning". Those we do not support right now.
+//! RFC5842 "bind", and RFC3253 "versioning". Of those, only "bind" is supported,
+//! for filesystems that implement `DavFileSystem::bind` (such as `LocalFs`).
 //!
 //! The relevant parts of the HTTP RFCs are also implemented, such as the
 //! preconditions (If-Match, If-None-Match, If-Modified-Since, If-Unmodified-Since,
@@ -53,15 +55,23 @@
 //!
 //! ## Backends.
 //!
-//! Included are two filesystems:
+//! Included are these filesystems:
 //!
 //! - [`LocalFs`]: serves a directory on the local filesystem
 //! - [`MemFs`]: ephemeral in-memory filesystem. supports DAV properties.
+//! - [`OverlayFs`]: a writable filesystem layered on top of a read-only one.
+//! - [`ChrootFs`]: a subdirectory of another filesystem.
+//! - [`MountFs`]: several filesystems, each mounted at its own path.
+//! - [`CacheFs`]: caches the files of a slow filesystem on local disk.
+//! - [`S3Fs`]: serves an S3 bucket (with the `s3` feature).
+//! - [`SftpFs`]: serves a directory on an SFTP server (with the `sftp` feature).
 //!
-//! Also included are two locksystems:
+//! Also included are these locksystems:
 //!
 //! - [`MemLs`]: ephemeral in-memory locksystem.
 //! - [`FakeLs`]: fake locksystem. just enough LOCK/UNLOCK support for macOS/Windows.
+//! - [`RedisLs`]: locks shared between instances through Redis (with the `redis` feature).
+//! - [`FileLs`]: locks that survive a restart, kept in a file (with the `filels` feature).
 //!
 //! ## Example.
 //!
@@ -112,9 +122,20 @@
 //! [`MemLs`]: memls/index.html
 //! [`MemFs`]: memfs/index.html
 //! [`LocalFs`]: localfs/index.html
+//! [`OverlayFs`]: overlayfs/index.html
+//! [`ChrootFs`]: chrootfs/index.html
+//! [`MountFs`]: mountfs/index.html
+//! [`CacheFs`]: cachefs/index.html
+//! [`S3Fs`]: s3fs/index.html
+//! [`SftpFs`]: sftpfs/index.html
 //! [`FakeLs`]: fakels/index.html
+//! [`RedisLs`]: redisls/index.html
+//! [`FileLs`]: filels/index.html
 //! [actix-compat]: actix/index.html
 //! [warp-compat]: warp/index.html
+//! [axum-compat]: axum/index.html
+//! [poem-compat]: poem/index.html
+//! [tower-compat]: tower/index.html
 //! [README_litmus]: https://github.com/miquels/webdav-handler-rs/blob/master/README.litmus-test.md
 //! [examples]: https://github.com/miquels/webdav-handler-rs/tree/master/examples/
 //! [PUT]: https://github.com/miquels/webdav-handler-rs/tree/master/doc/Apache-PUT-with-Content-Range.md
@@ -130,11 +151,14 @@ extern crate log;
 #[macro_use]
 extern crate lazy_static;
 
+mod accesslog;
 mod async_stream;
+mod compression;
 mod conditional;
 mod davhandler;
 mod davheaders;
 mod errors;
+mod handle_bind;
 mod handle_copymove;
 mod handle_delete;
 mod handle_gethead;
@@ -143,15 +167,32 @@ mod handle_mkcol;
 mod handle_options;
 mod handle_props;
 mod handle_put;
+mod handle_report;
+mod handle_tus;
+mod hidefs;
+mod if_header;
+#[cfg(feature = "tracing")]
+mod instrument;
+mod localfs_etag;
 mod localfs_macos;
+mod localfs_metacache;
+mod localfs_sync;
 mod localfs_windows;
+mod localfs_xattr;
 mod multierror;
+mod notimestampsfs;
+mod observer;
+mod propprovider;
+mod ratelimit;
+mod timeoutfs;
 mod tree;
 mod util;
 mod voidfs;
 mod xmltree_ext;
 
 pub mod body;
+pub mod cachefs;
+pub mod chrootfs;
 pub mod davpath;
 pub mod fakels;
 pub mod fs;
@@ -159,6 +200,9 @@ pub mod localfs;
 pub mod ls;
 pub mod memfs;
 pub mod memls;
+pub mod mountfs;
+pub mod overlayfs;
+pub mod testing;
 pub mod time;
 
 #[cfg(any(docsrs, feature = "actix-compat"))]
@@ -169,9 +213,46 @@ pub mod actix;
 #[cfg_attr(docsrs, doc(cfg(feature = "warp-compat")))]
 pub mod warp;
 
+#[cfg(any(docsrs, feature = "axum-compat"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "axum-compat")))]
+pub mod axum;
+
+#[cfg(any(docsrs, feature = "poem-compat"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "poem-compat")))]
+pub mod poem;
+
+#[cfg(any(docsrs, feature = "tower"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "tower")))]
+pub mod tower;
+
+#[cfg(any(docsrs, feature = "s3"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "s3")))]
+pub mod s3fs;
+
+#[cfg(any(docsrs, feature = "sftp"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "sftp")))]
+pub mod sftpfs;
+
+#[cfg(any(docsrs, feature = "redis"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "redis")))]
+pub mod redisls;
+
+#[cfg(any(docsrs, feature = "filels"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "filels")))]
+pub mod filels;
+
+#[cfg(any(docsrs, feature = "digest-auth"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "digest-auth")))]
+pub mod auth;
+
 pub(crate) use crate::davhandler::DavInner;
 pub(crate) use crate::errors::{DavError, DavResult};
 pub(crate) use crate::fs::*;
 
-pub use crate::davhandler::{DavConfig, DavHandler};
+pub use crate::davhandler::{AuthResult, DavConfig, DavHandler};
+pub use crate::errors::{HandlerError, HandlerErrorKind};
+pub use crate::handle_gethead::{DirEntry, DirListing, Disposition};
+pub use crate::handle_report::Report;
+pub use crate::observer::DavObserver;
+pub use crate::propprovider::PropertyProvider;
 pub use crate::util::{DavMethod, DavMethodSet};
@@ -13,7 +13,7 @@ use std::os::unix::fs::DirBuilderExt;
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::fs::PermissionsExt;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
@@ -22,14 +22,21 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bytes::{Buf, Bytes, BytesMut};
 use futures::{future, future::BoxFuture, FutureExt, Stream};
+use http::StatusCode;
+use parking_lot::RwLock;
 use pin_utils::pin_mut;
 use tokio::task;
 
 use libc;
 
 use crate::davpath::DavPath;
+use crate::errors::fserror_to_status;
 use crate::fs::*;
+use crate::localfs_etag::{dir_etag, ContentEtags};
 use crate::localfs_macos::DUCacheBuilder;
+use crate::localfs_metacache::MetaCache;
+use crate::localfs_sync::{SyncJournal, MARGIN};
+use crate::localfs_xattr as xattr;
 
 const RUNTIME_TYPE_BASIC: u32 = 1;
 const RUNTIME_TYPE_THREADPOOL: u32 = 2;
@@ -79,27 +86,116 @@ where
     }
 }
 
+// Metadata, and the content based ETag if that is enabled.
 #[derive(Debug, Clone)]
-struct LocalFsMetaData(std::fs::Metadata);
+struct LocalFsMetaData(std::fs::Metadata, Option<String>);
+
+impl LocalFsMetaData {
+    // Hashing the file is sync code that does I/O, so this must be run in `blocking()`.
+    fn boxed(meta: std::fs::Metadata, etag: Option<&ContentEtag>) -> Box<dyn DavMetaData> {
+        let hash = etag.filter(|_| meta.is_file()).and_then(|ce| ce.etags.etag(&ce.path, &meta));
+        Box::new(LocalFsMetaData(meta, hash))
+    }
+}
+
+// Path and cache for a content based ETag.
+#[derive(Debug, Clone)]
+struct ContentEtag {
+    path:  PathBuf,
+    etags: Arc<ContentEtags>,
+}
+
+/// What [`LocalFs`] does with symbolic links.
+///
+/// Set with [`LocalFs::with_symlink_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Follow symbolic links, wherever they point to. This is the default.
+    Follow,
+    /// Act as if symbolic links do not exist. They are not listed, and
+    /// a path that contains one is not found.
+    Deny,
+    /// Follow symbolic links, but only to something inside the
+    /// base directory. Other symbolic links are treated as with `Deny`.
+    DenyOutsideRoot,
+}
 
 /// Local Filesystem implementation.
+///
+/// The ETag of a directory in a PROPFIND response that asks for `getetag`
+/// is a hash of the names, sizes and modification times of its entries. So
+/// it changes when a file in the directory changes, but not always when
+/// something deeper down does.
 #[derive(Clone)]
 pub struct LocalFs {
     pub(crate) inner: Arc<LocalFsInner>,
+    pub(crate) opts:  LocalFsOptions,
 }
 
 // inner struct.
 pub(crate) struct LocalFsInner {
-    pub basedir:          PathBuf,
+    pub basedir:          RwLock<Arc<BaseDir>>,
     pub public:           bool,
     pub case_insensitive: bool,
     pub macos:            bool,
     pub is_file:          bool,
     pub fs_access_guard:  Option<Box<dyn Fn() -> Box<dyn Any> + Send + Sync + 'static>>,
+    pub sync_journal:     SyncJournal,
+}
+
+// options from the with_* methods. They are not in LocalFsInner, so that
+// they can be set on a clone too.
+#[derive(Clone)]
+pub(crate) struct LocalFsOptions {
+    pub content_etags:  Option<Arc<ContentEtags>>,
+    pub meta_cache:     Option<Arc<MetaCache>>,
+    pub symlink_policy: SymlinkPolicy,
+    pub xattr_props:    bool,
+}
+
+impl Default for LocalFsOptions {
+    fn default() -> LocalFsOptions {
+        LocalFsOptions {
+            content_etags:  None,
+            meta_cache:     None,
+            symlink_policy: SymlinkPolicy::Follow,
+            xattr_props:    false,
+        }
+    }
+}
+
+// The base directory, and the same with symlinks resolved.
+pub(crate) struct BaseDir {
+    pub path: PathBuf,
+    real:     Option<PathBuf>,
+}
+
+impl BaseDir {
+    fn new(path: &Path) -> Arc<BaseDir> {
+        Arc::new(BaseDir {
+            path: path.to_path_buf(),
+            real: std::fs::canonicalize(path).ok(),
+        })
+    }
+
+    // If it didn't exist when we started, try again now.
+    fn real(&self) -> io::Result<std::borrow::Cow<'_, Path>> {
+        match self.real {
+            Some(ref real) => Ok(real.as_path().into()),
+            None => Ok(std::fs::canonicalize(&self.path)?.into()),
+        }
+    }
 }
 
 #[derive(Debug)]
-struct LocalFsFile(Option<std::fs::File>);
+struct LocalFsFile(Option<std::fs::File>, Option<ContentEtag>, Option<Invalidate>);
+
+// Metadata cache entry to drop when a file has been written to.
+#[derive(Debug)]
+struct Invalidate {
+    path:       PathBuf,
+    meta_cache: Arc<MetaCache>,
+}
 
 struct LocalFsReadDir {
     fs:        LocalFs,
@@ -121,6 +217,7 @@ enum Meta {
 struct LocalFsDirEntry {
     meta:  Meta,
     entry: std::fs::DirEntry,
+    etag:  Option<ContentEtag>,
 }
 
 impl LocalFs {
@@ -134,16 +231,18 @@ impl LocalFs {
     /// be case insensitive. Note that this has a _lot_ of overhead!
     pub fn new<P: AsRef<Path>>(base: P, public: bool, case_insensitive: bool, macos: bool) -> Box<LocalFs> {
         let inner = LocalFsInner {
-            basedir:          base.as_ref().to_path_buf(),
+            basedir:          RwLock::new(BaseDir::new(base.as_ref())),
             public:           public,
             macos:            macos,
             case_insensitive: case_insensitive,
             is_file:          false,
             fs_access_guard:  None,
+            sync_journal:     SyncJournal::new(),
         };
         Box::new({
             LocalFs {
                 inner: Arc::new(inner),
+                opts:  LocalFsOptions::default(),
             }
         })
     }
@@ -154,16 +253,18 @@ impl LocalFs {
     /// The request path is ignored.
     pub fn new_file<P: AsRef<Path>>(file: P, public: bool) -> Box<LocalFs> {
         let inner = LocalFsInner {
-            basedir:          file.as_ref().to_path_buf(),
+            basedir:          RwLock::new(BaseDir::new(file.as_ref())),
             public:           public,
             macos:            false,
             case_insensitive: false,
             is_file:          true,
             fs_access_guard:  None,
+            sync_journal:     SyncJournal::new(),
         };
         Box::new({
             LocalFs {
                 inner: Arc::new(inner),
+                opts:  LocalFsOptions::default(),
             }
         })
     }
@@ -179,38 +280,270 @@ impl LocalFs {
     ) -> Box<LocalFs>
     {
         let inner = LocalFsInner {
-            basedir:          base.as_ref().to_path_buf(),
+            basedir:          RwLock::new(BaseDir::new(base.as_ref())),
             public:           public,
             macos:            macos,
             case_insensitive: case_insensitive,
             is_file:          false,
             fs_access_guard:  fs_access_guard,
+            sync_journal:     SyncJournal::new(),
         };
         Box::new({
             LocalFs {
                 inner: Arc::new(inner),
+                opts:  LocalFsOptions::default(),
+            }
+        })
+    }
+
+    /// Use ETags based on a hash of the file contents.
+    ///
+    /// The default ETag is based on the inode, size and modification time of
+    /// a file, so it changes when a file is replaced by a copy with the same
+    /// content. With this option the ETag is a sha256 hash of the content.
+    ///
+    /// Hashing is expensive, so hashes are calculated on first use and then
+    /// kept in an LRU cache of `cache_size` entries, keyed by path, size and
+    /// modification time. Each entry takes roughly the length of the path plus
+    /// 100 bytes of memory.
+    ///
+    /// This needs the `content-etags` feature.
+    #[cfg(feature = "content-etags")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "content-etags")))]
+    pub fn with_content_etags(self: Box<Self>, cache_size: usize) -> Box<LocalFs> {
+        let mut this = self;
+        this.opts.content_etags = Some(Arc::new(ContentEtags::new(cache_size)));
+        this
+    }
+
+    /// Cache the metadata of files and directories.
+    ///
+    /// A PROPFIND needs the metadata of every entry in a directory, and
+    /// clients that sync a tree tend to repeat the same PROPFINDs often.
+    /// With this option the metadata is kept in an LRU cache of `capacity`
+    /// entries, keyed by path, for at most `ttl`.
+    ///
+    /// Entries are invalidated when the path is changed through this
+    /// `LocalFs`. Changes made outside of it are only seen when the
+    /// entry has expired, so keep `ttl` short.
+    pub fn with_meta_cache(self: Box<Self>, ttl: Duration, capacity: usize) -> Box<LocalFs> {
+        let mut this = self;
+        this.opts.meta_cache = Some(Arc::new(MetaCache::new(ttl, capacity)));
+        this
+    }
+
+    /// Set the [`SymlinkPolicy`]. The default is `SymlinkPolicy::Follow`.
+    ///
+    /// With `Follow`, a symbolic link inside the base directory can point to
+    /// anything on the system, which is a problem if the people that can
+    /// create files in the base directory are not the ones that use the
+    /// WebDAV server.
+    ///
+    /// The other policies check every path before it is used. That is not
+    /// entirely race-free: someone who can create symbolic links in the base
+    /// directory might be able to swap a directory for a symlink in between.
+    /// Files that are opened are checked again after opening, so at least
+    /// their contents can not be read or changed that way.
+    pub fn with_symlink_policy(self: Box<Self>, policy: SymlinkPolicy) -> Box<LocalFs> {
+        let mut this = self;
+        this.opts.symlink_policy = policy;
+        this
+    }
+
+    /// Store dead properties in extended attributes.
+    ///
+    /// Without this, `LocalFs` does not support dead properties, and a
+    /// `PROPPATCH` that tries to set one fails. With it, they are stored in
+    /// `user.webdav.*` attributes of the file or directory, so they survive
+    /// a restart and move along with a renamed file. A copied file gets
+    /// them as well.
+    ///
+    /// On filesystems that do not support (user) extended attributes,
+    /// it is as if this option was not set.
+    pub fn with_xattr_props(self: Box<Self>) -> Box<LocalFs> {
+        let mut this = self;
+        this.opts.xattr_props = true;
+        this
+    }
+
+    /// Serve another directory from now on.
+    ///
+    /// Requests that start after this use the new base directory, so the
+    /// content can be switched without restarting the server. Requests that
+    /// are busy at that moment might still see the old directory, or, with
+    /// a `SymlinkPolicy` other than `Follow`, fail.
+    /// Tokens of `sync-collection` reports from before the switch are
+    /// refused, so that clients do a full sync.
+    ///
+    /// To be able to call this, keep a clone of the `LocalFs` that was
+    /// passed to [`DavConfig::filesystem`](crate::DavConfig::filesystem).
+    pub fn set_basedir<P: AsRef<Path>>(&self, base: P) {
+        *self.inner.basedir.write() = BaseDir::new(base.as_ref());
+        self.inner.sync_journal.reset();
+    }
+
+    // The current base directory.
+    fn basedir(&self) -> Arc<BaseDir> {
+        self.inner.basedir.read().clone()
+    }
+
+    // Check `path` against the symlink policy. `follow` is set if the
+    // operation follows a symlink at the end of the path (like open()),
+    // and not if it acts on the link itself (like unlink()).
+    //
+    // This is sync code that does I/O, so it must be run in `blocking()`.
+    fn check_symlinks(&self, path: &Path, follow: bool) -> FsResult<()> {
+        let policy = self.opts.symlink_policy;
+        if policy == SymlinkPolicy::Follow || self.inner.is_file {
+            return Ok(());
+        }
+        // if the base directory was replaced just now, `path` can be in the old one.
+        let basedir = self.basedir();
+        let base = basedir.real()?;
+        let rel = path.strip_prefix(&basedir.path).map_err(|_| FsError::Forbidden)?;
+
+        // first the directories leading up to the last component.
+        if let Some(parent) = rel.parent().filter(|p| !p.as_os_str().is_empty()) {
+            let real = std::fs::canonicalize(basedir.path.join(parent))?;
+            let ok = match policy {
+                SymlinkPolicy::Deny => real == base.join(parent),
+                _ => real.starts_with(&base),
+            };
+            if !ok {
+                debug!("LocalFs: {:?}: symlink in path refused", path);
+                return Err(FsError::NotFound);
+            }
+        }
+
+        // then the last component itself.
+        match std::fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                if policy == SymlinkPolicy::Deny {
+                    return Err(FsError::NotFound);
+                }
+                if follow && !std::fs::canonicalize(path).is_ok_and(|real| real.starts_with(&base)) {
+                    debug!("LocalFs: {:?}: symlink to outside of root refused", path);
+                    return Err(FsError::NotFound);
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // Is this directory entry visible with the symlink policy.
+    // Must be run in `blocking()`.
+    fn allow_dirent(&self, entry: &std::fs::DirEntry) -> bool {
+        match self.opts.symlink_policy {
+            SymlinkPolicy::Follow => true,
+            _ if !entry.file_type().is_ok_and(|t| t.is_symlink()) => true,
+            SymlinkPolicy::Deny => false,
+            SymlinkPolicy::DenyOutsideRoot => {
+                let basedir = self.basedir();
+                let real = std::fs::canonicalize(entry.path());
+                matches!((basedir.real(), real), (Ok(base), Ok(real)) if real.starts_with(&base))
+            },
+        }
+    }
+
+    // After opening a file, see if it is the one that check_symlinks() approved
+    // of, and not something that a symlink that was swapped in points to.
+    // Only on Linux, where /proc/self/fd tells us what we opened.
+    //
+    // This is sync code that does I/O, so it must be run in `blocking()`.
+    fn check_opened(&self, path: &Path, file: &std::fs::File) -> FsResult<()> {
+        use std::os::unix::io::AsRawFd;
+        if self.opts.symlink_policy == SymlinkPolicy::Follow || self.inner.is_file {
+            return Ok(());
+        }
+        let real = match std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())) {
+            Ok(real) => real,
+            Err(_) => return Ok(()),
+        };
+        let basedir = self.basedir();
+        let base = basedir.real()?;
+        let ok = match self.opts.symlink_policy {
+            SymlinkPolicy::Deny => path.strip_prefix(&basedir.path).is_ok_and(|rel| real == base.join(rel)),
+            _ => real.starts_with(&base),
+        };
+        if !ok {
+            debug!("LocalFs: {:?}: opened {:?}, refused", path, real);
+            return Err(FsError::NotFound);
+        }
+        Ok(())
+    }
+
+    // std::fs::metadata() or std::fs::symlink_metadata(), through the
+    // metadata cache if it is enabled. Must be run in `blocking()`.
+    fn stat(&self, path: &Path, follow: bool) -> io::Result<std::fs::Metadata> {
+        match self.opts.meta_cache {
+            Some(ref cache) => cache.metadata(path, follow),
+            None if follow => std::fs::metadata(path),
+            None => std::fs::symlink_metadata(path),
+        }
+    }
+
+    // Drop `path` from the metadata cache. If `tree` is set,
+    // also drop everything below it.
+    fn invalidate(&self, path: &Path, tree: bool) {
+        if let Some(ref cache) = self.opts.meta_cache {
+            if tree {
+                cache.invalidate_tree(path);
+            } else {
+                cache.invalidate(path);
+            }
+        }
+    }
+
+    // ContentEtag for a path, if enabled.
+    fn content_etag(&self, path: &Path) -> Option<ContentEtag> {
+        self.opts.content_etags.as_ref().map(|etags| {
+            ContentEtag {
+                path:  path.to_path_buf(),
+                etags: etags.clone(),
             }
         })
     }
 
     fn fspath_dbg(&self, path: &DavPath) -> PathBuf {
-        let mut pathbuf = self.inner.basedir.clone();
+        let mut pathbuf = self.basedir().path.clone();
         if !self.inner.is_file {
             pathbuf.push(path.as_rel_ospath());
         }
         pathbuf
     }
 
-    fn fspath(&self, path: &DavPath) -> PathBuf {
-        if self.inner.case_insensitive {
-            crate::localfs_windows::resolve(&self.inner.basedir, &path)
+    // Map a DavPath to a path on the filesystem.
+    //
+    // DavPath already resolves ".." and refuses NUL bytes when it parses
+    // an URL, but a DavPath can be put together in other ways as well. So
+    // check again, before we hand anything to the OS: what we append to the
+    // base directory may only consist of plain names. Where symlinks lead
+    // to is a matter of the SymlinkPolicy, see check_symlinks().
+    fn fspath(&self, path: &DavPath) -> FsResult<PathBuf> {
+        let rel = path.as_rel_ospath();
+        let bad = |c: Component| match c {
+            Component::Normal(name) => name.as_bytes().contains(&0),
+            _ => true,
+        };
+        if rel.as_os_str().as_bytes().contains(&0) || rel.components().any(bad) {
+            debug!("LocalFs: refusing path {:?}", rel);
+            return Err(FsError::Forbidden);
+        }
+        let basedir = &self.basedir().path;
+        let pathbuf = if self.inner.case_insensitive {
+            crate::localfs_windows::resolve(basedir.to_path_buf(), &path)
         } else {
-            let mut pathbuf = self.inner.basedir.clone();
+            let mut pathbuf = basedir.to_path_buf();
             if !self.inner.is_file {
-                pathbuf.push(path.as_rel_ospath());
+                pathbuf.push(rel);
             }
             pathbuf
+        };
+        if !pathbuf.starts_with(basedir) {
+            return Err(FsError::Forbidden);
         }
+        Ok(pathbuf)
     }
 
     // threadpool::blocking() adapter, also runs the before/after hooks.
@@ -237,13 +570,16 @@ impl DavFileSystem for LocalFs {
             if let Some(meta) = self.is_virtual(davpath) {
                 return Ok(meta);
             }
-            let path = self.fspath(davpath);
+            let path = self.fspath(davpath)?;
             if self.is_notfound(&path) {
                 return Err(FsError::NotFound);
             }
+            let etag = self.content_etag(&path);
+            let this = self.clone();
             self.blocking(move || {
-                match std::fs::metadata(path) {
-                    Ok(meta) => Ok(Box::new(LocalFsMetaData(meta)) as Box<dyn DavMetaData>),
+                this.check_symlinks(&path, true)?;
+                match this.stat(&path, true) {
+                    Ok(meta) => Ok(LocalFsMetaData::boxed(meta, etag.as_ref())),
                     Err(e) => Err(e.into()),
                 }
             })
@@ -257,13 +593,16 @@ impl DavFileSystem for LocalFs {
             if let Some(meta) = self.is_virtual(davpath) {
                 return Ok(meta);
             }
-            let path = self.fspath(davpath);
+            let path = self.fspath(davpath)?;
             if self.is_notfound(&path) {
                 return Err(FsError::NotFound);
             }
+            let etag = self.content_etag(&path);
+            let this = self.clone();
             self.blocking(move || {
-                match std::fs::symlink_metadata(path) {
-                    Ok(meta) => Ok(Box::new(LocalFsMetaData(meta)) as Box<dyn DavMetaData>),
+                this.check_symlinks(&path, false)?;
+                match this.stat(&path, false) {
+                    Ok(meta) => Ok(LocalFsMetaData::boxed(meta, etag.as_ref())),
                     Err(e) => Err(e.into()),
                 }
             })
@@ -282,9 +621,15 @@ impl DavFileSystem for LocalFs {
     {
         async move {
             trace!("FS: read_dir {:?}", self.fspath_dbg(davpath));
-            let path = self.fspath(davpath);
+            let path = self.fspath(davpath)?;
             let path2 = path.clone();
-            let iter = self.blocking(move || std::fs::read_dir(&path)).await;
+            let this = self.clone();
+            let iter = self
+                .blocking(move || {
+                    this.check_symlinks(&path, true)?;
+                    Ok::<_, FsError>(std::fs::read_dir(&path)?)
+                })
+                .await;
             match iter {
                 Ok(iterator) => {
                     let strm = LocalFsReadDir {
@@ -297,7 +642,7 @@ impl DavFileSystem for LocalFs {
                     };
                     Ok(Box::pin(strm) as FsStream<Box<dyn DavDirEntry>>)
                 },
-                Err(e) => Err(e.into()),
+                Err(e) => Err(e),
             }
         }
         .boxed()
@@ -310,20 +655,48 @@ impl DavFileSystem for LocalFs {
                 return Err(FsError::Forbidden);
             }
             let mode = if self.inner.public { 0o644 } else { 0o600 };
-            let path = self.fspath(path);
+            let path = self.fspath(path)?;
+            let etag = self.content_etag(&path);
+            let invalidate = match self.opts.meta_cache {
+                Some(ref cache) if options.write || options.append => {
+                    Some(Invalidate {
+                        path:       path.clone(),
+                        meta_cache: cache.clone(),
+                    })
+                },
+                _ => None,
+            };
+            let this = self.clone();
             self.blocking(move || {
-                let res = std::fs::OpenOptions::new()
-                    .read(options.read)
+                this.check_symlinks(&path, true)?;
+                // with a symlink policy, truncate only after checking what we opened.
+                let checked = this.opts.symlink_policy != SymlinkPolicy::Follow;
+                let mut oo = std::fs::OpenOptions::new();
+                oo.read(options.read)
                     .write(options.write)
                     .append(options.append)
-                    .truncate(options.truncate)
+                    .truncate(options.truncate && !checked)
                     .create(options.create)
                     .create_new(options.create_new)
-                    .mode(mode)
-                    .open(path);
+                    .mode(mode);
+                if this.opts.symlink_policy == SymlinkPolicy::Deny {
+                    oo.custom_flags(libc::O_NOFOLLOW);
+                }
+                let res = oo.open(&path).map_err(FsError::from).and_then(|file| {
+                    if checked {
+                        this.check_opened(&path, &file)?;
+                        if options.truncate {
+                            file.set_len(0)?;
+                        }
+                    }
+                    Ok(file)
+                });
+                if let Some(ref i) = invalidate {
+                    i.meta_cache.invalidate(&i.path);
+                }
                 match res {
-                    Ok(file) => Ok(Box::new(LocalFsFile(Some(file))) as Box<dyn DavFile>),
-                    Err(e) => Err(e.into()),
+                    Ok(file) => Ok(Box::new(LocalFsFile(Some(file), etag, invalidate)) as Box<dyn DavFile>),
+                    Err(e) => Err(e),
                 }
             })
             .await
@@ -338,51 +711,103 @@ impl DavFileSystem for LocalFs {
                 return Err(FsError::Forbidden);
             }
             let mode = if self.inner.public { 0o755 } else { 0o700 };
-            let path = self.fspath(path);
+            let path = self.fspath(path)?;
+            let this = self.clone();
             self.blocking(move || {
-                std::fs::DirBuilder::new()
-                    .mode(mode)
-                    .create(path)
-                    .map_err(|e| e.into())
+                this.check_symlinks(&path, false)?;
+                let res = std::fs::DirBuilder::new().mode(mode).create(&path);
+                this.invalidate(&path, false);
+                res.map_err(|e| e.into())
             })
             .await
         }
         .boxed()
     }
 
-    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
+    fn remove_dir<'a>(&'a self, davpath: &'a DavPath) -> FsFuture<'a, ()> {
         async move {
-            trace!("FS: remove_dir {:?}", self.fspath_dbg(path));
-            let path = self.fspath(path);
-            self.blocking(move || std::fs::remove_dir(path).map_err(|e| e.into()))
-                .await
+            trace!("FS: remove_dir {:?}", self.fspath_dbg(davpath));
+            let path = self.fspath(davpath)?;
+            let davpath = davpath.clone();
+            let this = self.clone();
+            self.blocking(move || {
+                this.check_symlinks(&path, false)?;
+                let res = std::fs::remove_dir(&path);
+                this.invalidate(&path, true);
+                this.inner.sync_journal.removed(&davpath);
+                res.map_err(|e| e.into())
+            })
+            .await
         }
         .boxed()
     }
 
-    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
+    fn remove_file<'a>(&'a self, davpath: &'a DavPath) -> FsFuture<'a, ()> {
         async move {
-            trace!("FS: remove_file {:?}", self.fspath_dbg(path));
-            if self.is_forbidden(path) {
+            trace!("FS: remove_file {:?}", self.fspath_dbg(davpath));
+            if self.is_forbidden(davpath) {
                 return Err(FsError::Forbidden);
             }
-            let path = self.fspath(path);
-            self.blocking(move || std::fs::remove_file(path).map_err(|e| e.into()))
-                .await
+            let path = self.fspath(davpath)?;
+            let davpath = davpath.clone();
+            let this = self.clone();
+            self.blocking(move || {
+                this.check_symlinks(&path, false)?;
+                let res = std::fs::remove_file(&path);
+                this.invalidate(&path, false);
+                this.inner.sync_journal.removed(&davpath);
+                res.map_err(|e| e.into())
+            })
+            .await
         }
         .boxed()
     }
 
+    fn remove_dir_all<'a>(&'a self, davpath: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            trace!("FS: remove_dir_all {:?}", self.fspath_dbg(davpath));
+            // with macOS support some files are off-limits, and those
+            // might be anywhere in the tree.
+            if self.inner.macos {
+                return Err(FsError::NotImplemented);
+            }
+            let path = self.fspath(davpath)?;
+            let davpath = davpath.clone();
+            let this = self.clone();
+            self.blocking(move || {
+                this.check_symlinks(&path, false)?;
+                if !std::fs::symlink_metadata(&path)?.is_dir() {
+                    return Err(FsError::Forbidden);
+                }
+                let res = std::fs::remove_dir_all(&path);
+                this.invalidate(&path, true);
+                this.inner.sync_journal.removed(&davpath);
+                res.map_err(|e| e.into())
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    // rename(2) is atomic.
+    fn temp_path(&self, path: &DavPath) -> Option<DavPath> {
+        Some(temp_file_path(path))
+    }
+
     fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
         async move {
             trace!("FS: rename {:?} {:?}", self.fspath_dbg(from), self.fspath_dbg(to));
             if self.is_forbidden(from) || self.is_forbidden(to) {
                 return Err(FsError::Forbidden);
             }
-            let frompath = self.fspath(from);
-            let topath = self.fspath(to);
+            let frompath = self.fspath(from)?;
+            let topath = self.fspath(to)?;
+            let from = from.clone();
+            let this = self.clone();
             self.blocking(move || {
-                match std::fs::rename(&frompath, &topath) {
+                this.check_symlinks(&frompath, false)?;
+                this.check_symlinks(&topath, false)?;
+                let res = match std::fs::rename(&frompath, &topath) {
                     Ok(v) => Ok(v),
                     Err(e) => {
                         // webdav allows a rename from a directory to a file.
@@ -391,12 +816,16 @@ impl DavFileSystem for LocalFs {
                         if e.raw_os_error() == Some(libc::ENOTDIR) && frompath.is_dir() {
                             // remove and try again.
                             let _ = std::fs::remove_file(&topath);
-                            std::fs::rename(frompath, topath).map_err(|e| e.into())
+                            std::fs::rename(&frompath, &topath).map_err(|e| e.into())
                         } else {
                             Err(e.into())
                         }
                     },
-                }
+                };
+                this.invalidate(&frompath, true);
+                this.invalidate(&topath, true);
+                this.inner.sync_journal.removed(&from);
+                res
             })
             .await
         }
@@ -409,10 +838,25 @@ impl DavFileSystem for LocalFs {
             if self.is_forbidden(from) || self.is_forbidden(to) {
                 return Err(FsError::Forbidden);
             }
-            let path_from = self.fspath(from);
-            let path_to = self.fspath(to);
+            let path_from = self.fspath(from)?;
+            let path_to = self.fspath(to)?;
+            let this = self.clone();
 
-            match self.blocking(move || std::fs::copy(path_from, path_to)).await {
+            let res = self
+                .blocking(move || {
+                    this.check_symlinks(&path_from, true)?;
+                    this.check_symlinks(&path_to, true)?;
+                    let res = std::fs::copy(&path_from, &path_to);
+                    this.invalidate(&path_to, false);
+                    if res.is_ok() && this.opts.xattr_props && xattr::supported(&path_from) {
+                        if let Err(e) = xattr::copy(&path_from, &path_to) {
+                            debug!("copy({:?}, {:?}): properties: {}", path_from, path_to, e);
+                        }
+                    }
+                    Ok::<_, FsError>(res?)
+                })
+                .await;
+            match res {
                 Ok(_) => Ok(()),
                 Err(e) => {
                     debug!(
@@ -421,12 +865,277 @@ impl DavFileSystem for LocalFs {
                         self.fspath_dbg(to),
                         e
                     );
-                    Err(e.into())
+                    Err(e)
                 },
             }
         }
         .boxed()
     }
+
+    // A binding is a hard link, so directories can't have more than one.
+    fn bind<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            trace!("FS: bind {:?} {:?}", self.fspath_dbg(from), self.fspath_dbg(to));
+            if self.is_forbidden(from) || self.is_forbidden(to) {
+                return Err(FsError::Forbidden);
+            }
+            let path_from = self.fspath(from)?;
+            let path_to = self.fspath(to)?;
+            let this = self.clone();
+            self.blocking(move || {
+                this.check_symlinks(&path_from, false)?;
+                this.check_symlinks(&path_to, false)?;
+                if std::fs::symlink_metadata(&path_from)?.is_dir() {
+                    return Err(FsError::Forbidden);
+                }
+                let res = std::fs::hard_link(&path_from, &path_to);
+                this.invalidate(&path_to, false);
+                res.map_err(|e| e.into())
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    fn supports_bind(&self) -> bool {
+        true
+    }
+
+    fn have_props<'a>(&'a self, path: &'a DavPath) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        async move {
+            if !self.opts.xattr_props {
+                return false;
+            }
+            let path = match self.fspath(path) {
+                Ok(path) => path,
+                Err(_) => return false,
+            };
+            self.blocking(move || xattr::supported(&path)).await
+        }
+        .boxed()
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        davpath: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>>
+    {
+        async move {
+            trace!("FS: patch_props {:?}", self.fspath_dbg(davpath));
+            let path = self.fspath(davpath)?;
+            let this = self.clone();
+            self.blocking(move || {
+                this.check_symlinks(&path, true)?;
+                let mut res = Vec::new();
+                for (set, mut prop) in patch.into_iter() {
+                    if !set {
+                        prop.xml = None;
+                    }
+                    let status = match xattr::set(&path, &prop) {
+                        Ok(()) => StatusCode::OK,
+                        Err(e) => fserror_to_status(&e.into()),
+                    };
+                    prop.xml = None;
+                    res.push((status, prop));
+                }
+                Ok(res)
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    fn get_props<'a>(&'a self, davpath: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        async move {
+            trace!("FS: get_props {:?}", self.fspath_dbg(davpath));
+            let path = self.fspath(davpath)?;
+            let this = self.clone();
+            self.blocking(move || {
+                this.check_symlinks(&path, true)?;
+                Ok(xattr::list(&path, do_content)?)
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    fn get_prop<'a>(&'a self, davpath: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        async move {
+            trace!("FS: get_prop {:?}", self.fspath_dbg(davpath));
+            let path = self.fspath(davpath)?;
+            let this = self.clone();
+            self.blocking(move || {
+                this.check_symlinks(&path, true)?;
+                xattr::get(&path, &prop)?.ok_or(FsError::NotFound)
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    fn quota<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        async move {
+            trace!("FS: quota {:?}", self.fspath_dbg(path));
+            let path = self.fspath(path)?;
+            let this = self.clone();
+            self.blocking(move || {
+                this.check_symlinks(&path, true)?;
+                statvfs(&path)
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    // We can only see what the user that the server runs as may do,
+    // so the webdav principal is not used.
+    fn privileges<'a>(
+        &'a self,
+        davpath: &'a DavPath,
+        _principal: Option<&'a str>,
+    ) -> FsFuture<'a, PrivilegeSet> {
+        async move {
+            trace!("FS: privileges {:?}", self.fspath_dbg(davpath));
+            let path = self.fspath(davpath)?;
+            if self.is_notfound(&path) {
+                return Err(FsError::NotFound);
+            }
+            let this = self.clone();
+            self.blocking(move || {
+                this.check_symlinks(&path, true)?;
+                privileges(&path)
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    // Changes when an entry is added, removed or modified, but not
+    // always when something deeper down the tree changes.
+    fn collection_etag<'a>(&'a self, davpath: &'a DavPath) -> FsFuture<'a, String> {
+        async move {
+            trace!("FS: collection_etag {:?}", self.fspath_dbg(davpath));
+            let path = self.fspath(davpath)?;
+            let this = self.clone();
+            self.blocking(move || {
+                this.check_symlinks(&path, true)?;
+                Ok(dir_etag(&path)?)
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    fn sync_token<'a>(&'a self, _path: &'a DavPath) -> FsFuture<'a, String> {
+        async move { Ok(self.inner.sync_journal.token()) }.boxed()
+    }
+
+    fn changes_since<'a>(
+        &'a self,
+        davpath: &'a DavPath,
+        token: &'a str,
+        deep: bool,
+    ) -> FsFuture<'a, Vec<Change>>
+    {
+        async move {
+            trace!("FS: changes_since {:?} {}", self.fspath_dbg(davpath), token);
+            let journal = &self.inner.sync_journal;
+            let since = journal.parse(token).ok_or(FsError::Forbidden)?;
+            let mut changes = journal
+                .removed_since(davpath, since)
+                .into_iter()
+                .map(Change::Removed)
+                .collect::<Vec<_>>();
+            let path = self.fspath(davpath)?;
+            let davpath = davpath.clone();
+            let modified = self
+                .blocking(move || changed_since(&path, davpath, since - MARGIN, deep))
+                .await?;
+            changes.extend(modified.into_iter().map(Change::Modified));
+            Ok(changes)
+        }
+        .boxed()
+    }
+}
+
+// Walk the tree below `path` (or only its direct members, if not `deep`) and
+// return everything that changed since `since`.
+// This is sync code, must be run in `blocking()`.
+fn changed_since(path: &Path, davpath: DavPath, since: SystemTime, deep: bool) -> FsResult<Vec<DavPath>> {
+    let mut res = Vec::new();
+    let mut dirs = vec![(path.to_path_buf(), davpath)];
+    while let Some((dir, davdir)) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if dir == path => return Err(e.into()),
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            // NOTE: does not follow symlinks.
+            let meta = match entry.metadata() {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+            let mut davpath = davdir.clone();
+            davpath.push_segment(entry.file_name().as_bytes());
+            davpath.add_slash_if(meta.is_dir());
+            let ctime = UNIX_EPOCH + Duration::new(meta.ctime() as u64, meta.ctime_nsec() as u32);
+            let changed = ctime >= since || meta.modified().map_or(true, |m| m >= since);
+            if deep && meta.is_dir() {
+                dirs.push((entry.path(), davpath.clone()));
+            }
+            if changed {
+                res.push(davpath);
+            }
+        }
+    }
+    Ok(res)
+}
+
+// Check access for the effective uid/gid of this process.
+fn privileges(path: &Path) -> FsResult<PrivilegeSet> {
+    let meta = std::fs::metadata(path)?;
+    let cpath = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|_| FsError::NotFound)?;
+    let access =
+        |mode| unsafe { libc::faccessat(libc::AT_FDCWD, cpath.as_ptr(), mode, libc::AT_EACCESS) == 0 };
+    let read = access(libc::R_OK);
+    // to write in a directory we also need search permission.
+    let write = access(if meta.is_dir() { libc::W_OK | libc::X_OK } else { libc::W_OK });
+    Ok(PrivilegeSet {
+        read,
+        write,
+        read_acl: read,
+        owner: Some(user_name(meta.uid())),
+    })
+}
+
+// Look up the name of a user, or use the uid if it has no name.
+fn user_name(uid: u32) -> String {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+    let r = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if r != 0 || result.is_null() {
+        return uid.to_string();
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) };
+    name.to_string_lossy().into_owned()
+}
+
+// Used and total space of the filesystem that `path` is on. "Total" is
+// what is used plus what is available to us, so it doesn't count
+// the blocks that are reserved for root.
+fn statvfs(path: &Path) -> FsResult<(u64, Option<u64>)> {
+    let cpath = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|_| FsError::NotFound)?;
+    let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(cpath.as_ptr(), &mut st) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let bsize = st.f_frsize as u64;
+    let used = (st.f_blocks as u64).saturating_sub(st.f_bfree as u64) * bsize;
+    let avail = st.f_bavail as u64 * bsize;
+    Ok((used, Some(used + avail)))
 }
 
 // read_batch() result.
@@ -454,13 +1163,18 @@ fn read_batch(iterator: Option<std::fs::ReadDir>, fs: LocalFs, do_meta: ReadDirM
     };
     for _ in 0..256 {
         match iterator.next() {
+            Some(Ok(entry)) if !fs.allow_dirent(&entry) => {},
             Some(Ok(entry)) => {
                 let meta = match do_meta {
-                    ReadDirMeta::Data => Meta::Data(std::fs::metadata(entry.path())),
+                    ReadDirMeta::Data => Meta::Data(fs.stat(&entry.path(), true)),
+                    ReadDirMeta::DataSymlink if fs.opts.meta_cache.is_some() => {
+                        Meta::Data(fs.stat(&entry.path(), false))
+                    },
                     ReadDirMeta::DataSymlink => Meta::Data(entry.metadata()),
                     ReadDirMeta::None => Meta::Fs(fs.clone()),
                 };
                 let d = LocalFsDirEntry {
+                    etag:  fs.content_etag(&entry.path()),
                     meta:  meta,
                     entry: entry,
                 };
@@ -584,18 +1298,24 @@ impl LocalFsDirEntry {
 impl DavDirEntry for LocalFsDirEntry {
     fn metadata<'a>(&'a self) -> FsFuture<Box<dyn DavMetaData>> {
         match self.meta {
+            Meta::Data(Ok(ref meta)) if self.etag.is_some() && meta.is_file() => {
+                let meta = meta.clone();
+                let etag = self.etag.clone();
+                blocking(move || Ok(LocalFsMetaData::boxed(meta, etag.as_ref()))).boxed()
+            },
             Meta::Data(ref meta) => {
                 let m = match meta {
-                    Ok(meta) => Ok(Box::new(LocalFsMetaData(meta.clone())) as Box<dyn DavMetaData>),
+                    Ok(meta) => Ok(LocalFsMetaData::boxed(meta.clone(), None)),
                     Err(e) => Err(e.into()),
                 };
                 Box::pin(future::ready(m))
             },
             Meta::Fs(ref fs) => {
                 let fullpath = self.entry.path();
+                let etag = self.etag.clone();
                 fs.blocking(move || {
                     match std::fs::metadata(&fullpath) {
-                        Ok(meta) => Ok(Box::new(LocalFsMetaData(meta)) as Box<dyn DavMetaData>),
+                        Ok(meta) => Ok(LocalFsMetaData::boxed(meta, etag.as_ref())),
                         Err(e) => Err(e.into()),
                     }
                 })
@@ -625,9 +1345,14 @@ impl DavFile for LocalFsFile {
     fn metadata<'a>(&'a mut self) -> FsFuture<Box<dyn DavMetaData>> {
         async move {
             let file = self.0.take().unwrap();
-            let (meta, file) = blocking(move || (file.metadata(), file)).await;
+            let etag = self.1.clone();
+            let (meta, file) = blocking(move || {
+                let meta = file.metadata().map(|meta| LocalFsMetaData::boxed(meta, etag.as_ref()));
+                (meta, file)
+            })
+            .await;
             self.0 = Some(file);
-            Ok(Box::new(LocalFsMetaData(meta?)) as Box<dyn DavMetaData>)
+            Ok(meta?)
         }
         .boxed()
     }
@@ -698,6 +1423,9 @@ impl DavFile for LocalFsFile {
             let mut file = self.0.take().unwrap();
             let (res, file) = blocking(move || (file.flush(), file)).await;
             self.0 = Some(file);
+            if let Some(ref i) = self.2 {
+                i.meta_cache.invalidate(&i.path);
+            }
             res.map_err(|e| e.into())
         }
         .boxed()
@@ -722,6 +1450,10 @@ impl DavMetaData for LocalFsMetaData {
         Ok(UNIX_EPOCH + Duration::new(self.0.ctime() as u64, 0))
     }
 
+    fn bindings(&self) -> u64 {
+        self.0.nlink()
+    }
+
     fn is_dir(&self) -> bool {
         self.0.is_dir()
     }
@@ -738,8 +1470,11 @@ impl DavMetaData for LocalFsMetaData {
         Err(FsError::NotImplemented)
     }
 
-    // same as the default apache etag.
+    // content hash if enabled, otherwise the same as the default apache etag.
     fn etag(&self) -> Option<String> {
+        if let Some(ref tag) = self.1 {
+            return Some(tag.clone());
+        }
         let modified = self.0.modified().ok()?;
         let t = modified.duration_since(UNIX_EPOCH).ok()?;
         let t = t.as_secs() * 1000000 + t.subsec_nanos() as u64 / 1000;
@@ -789,3 +1524,235 @@ impl From<io::Error> for FsError {
         (&e).into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{tempdir, TempDir, TestClient};
+    use crate::DavHandler;
+
+    // A directory with "sub/file.txt" in it.
+    fn fixture() -> TempDir {
+        let dir = tempdir();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/file.txt"), "hello").unwrap();
+        dir
+    }
+
+    fn client(fs: Box<LocalFs>) -> TestClient {
+        TestClient::new(DavHandler::builder().filesystem(fs).build_handler())
+    }
+
+    async fn status(c: &TestClient, path: &str) -> StatusCode {
+        c.request("GET", path, "").await.status()
+    }
+
+    #[tokio::test]
+    async fn traversal() {
+        let dir = fixture();
+        let dav = client(LocalFs::new(dir.join("sub"), false, false, false));
+
+        assert_eq!(status(&dav, "/file.txt").await, StatusCode::OK);
+        assert_eq!(status(&dav, "/%2e%2e/%2e%2e/etc/passwd").await, StatusCode::FORBIDDEN);
+        assert_eq!(status(&dav, "/%2E%2e/sub/file.txt").await, StatusCode::FORBIDDEN);
+        assert_eq!(status(&dav, "/.%2e/.%2e/etc/passwd").await, StatusCode::FORBIDDEN);
+        assert_eq!(status(&dav, "/x/%2e%2e/file.txt").await, StatusCode::OK);
+        assert_eq!(status(&dav, "/..%2f..%2fetc/passwd").await, StatusCode::BAD_REQUEST);
+        assert_eq!(status(&dav, "/file.txt%00.jpg").await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn fspath_refuses_bad_components() {
+        let dir = fixture();
+        let fs = LocalFs::new(dir.join("sub"), false, false, false);
+
+        for seg in [&b".."[..], b".", b"a\0b", b"/etc"] {
+            let mut path = DavPath::new("/").unwrap();
+            path.push_segment(seg);
+            path.push_segment(b"passwd");
+            assert!(matches!(fs.fspath(&path), Err(FsError::Forbidden)), "{:?}", seg);
+            assert!(matches!(fs.metadata(&path).await, Err(FsError::Forbidden)));
+        }
+        let path = DavPath::new("/file.txt").unwrap();
+        assert_eq!(fs.fspath(&path).unwrap(), dir.join("sub/file.txt"));
+    }
+
+    #[tokio::test]
+    async fn delete_tree() {
+        let dir = fixture();
+        std::fs::create_dir_all(dir.join("sub/a/b")).unwrap();
+        std::fs::write(dir.join("sub/a/b/file.txt"), "hello").unwrap();
+        let dav = client(LocalFs::new(&dir, false, false, false));
+
+        assert_eq!(dav.request("DELETE", "/sub/", "").await.status(), StatusCode::NO_CONTENT);
+        assert!(!dir.join("sub").exists());
+
+        // a file is not a tree.
+        std::fs::write(dir.join("file.txt"), "hello").unwrap();
+        let fs = LocalFs::new(&dir, false, false, false);
+        let path = DavPath::new("/file.txt").unwrap();
+        assert!(matches!(fs.remove_dir_all(&path).await, Err(FsError::Forbidden)));
+    }
+
+    #[tokio::test]
+    async fn set_basedir() {
+        let (blue, green) = (fixture(), fixture());
+        std::fs::write(green.join("sub/file.txt"), "green").unwrap();
+        let fs = LocalFs::new(blue.join("sub"), false, false, false);
+        let dav = client(fs.clone());
+
+        assert_eq!(dav.request("GET", "/file.txt", "").await.into_body(), "hello");
+        fs.set_basedir(green.join("sub"));
+        assert_eq!(dav.request("GET", "/file.txt", "").await.into_body(), "green");
+    }
+
+    #[tokio::test]
+    async fn xattr_props() {
+        let dir = fixture();
+        if !xattr::supported(&dir) {
+            return;
+        }
+        let dav = client(LocalFs::new(dir.join("sub"), false, false, false).with_xattr_props());
+        let req = |method: &str, path: &str, body: &str| {
+            let req = http::Request::builder().method(method).uri(path).header("Destination", "/copy.txt");
+            dav.send(req.body(body.to_string()).unwrap())
+        };
+        let body = |resp: http::Response<bytes::Bytes>| async move {
+            String::from_utf8(resp.into_body().to_vec()).unwrap()
+        };
+
+        let set = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:A="urn:app">
+            <D:set><D:prop><A:color>red</A:color></D:prop></D:set></D:propertyupdate>"#;
+        let resp = req("PROPPATCH", "/file.txt", set).await;
+        assert!(body(resp).await.contains("200 OK"));
+        let propfind = r#"<D:propfind xmlns:D="DAV:" xmlns:A="urn:app">
+            <D:prop><A:color/></D:prop></D:propfind>"#;
+        assert!(body(req("PROPFIND", "/file.txt", propfind).await).await.contains(">red</"));
+
+        // a new LocalFs (a restart) still has them, and so does a copy.
+        let fs = LocalFs::new(dir.join("sub"), false, false, false).with_xattr_props();
+        let path = DavPath::new("/file.txt").unwrap();
+        assert_eq!(fs.get_props(&path, false).await.unwrap()[0].name, "color");
+        assert_eq!(req("COPY", "/file.txt", "").await.status(), StatusCode::CREATED);
+        assert!(body(req("PROPFIND", "/copy.txt", propfind).await).await.contains(">red</"));
+
+        let remove = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:A="urn:app">
+            <D:remove><D:prop><A:color/></D:prop></D:remove></D:propertyupdate>"#;
+        req("PROPPATCH", "/file.txt", remove).await;
+        assert!(body(req("PROPFIND", "/file.txt", propfind).await).await.contains("404"));
+        assert!(fs.get_props(&path, true).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn symlink_policy() {
+        use std::os::unix::fs::symlink;
+
+        let dir = fixture();
+        std::fs::create_dir_all(dir.join("sub/a")).unwrap();
+        std::fs::write(dir.join("sub/a/f.txt"), "inside").unwrap();
+        std::fs::create_dir(dir.join("outside")).unwrap();
+        std::fs::write(dir.join("outside/secret.txt"), "secret").unwrap();
+        symlink("file.txt", dir.join("sub/inlink")).unwrap();
+        symlink("../outside/secret.txt", dir.join("sub/outlink")).unwrap();
+        symlink("a", dir.join("sub/indir")).unwrap();
+        symlink("../outside", dir.join("sub/outdir")).unwrap();
+
+        // the policy can be set on a clone.
+        let fs = LocalFs::new(dir.join("sub"), false, false, false);
+        let dav = |policy| client(fs.clone().with_symlink_policy(policy));
+        let paths = ["/file.txt", "/inlink", "/outlink", "/indir/f.txt", "/outdir/secret.txt"];
+        let (ok, gone) = (StatusCode::OK, StatusCode::NOT_FOUND);
+        for (policy, expected) in [
+            (SymlinkPolicy::Follow, [ok, ok, ok, ok, ok]),
+            (SymlinkPolicy::Deny, [ok, gone, gone, gone, gone]),
+            (SymlinkPolicy::DenyOutsideRoot, [ok, ok, gone, ok, gone]),
+        ] {
+            let dav = dav(policy);
+            for (path, want) in paths.iter().zip(expected) {
+                assert_eq!(status(&dav, path).await, want, "{:?} {}", policy, path);
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "content-etags")]
+    async fn content_etags() {
+        use futures::StreamExt;
+
+        let dir = fixture();
+        std::fs::write(dir.join("sub/copy.txt"), "hello").unwrap();
+        std::fs::write(dir.join("sub/other.txt"), "world").unwrap();
+        let fs = LocalFs::new(dir.join("sub"), false, false, false).with_content_etags(10);
+
+        // same content, same ETag, whether from a stat, a listing or an open file.
+        let etag = |p: &str| {
+            let fs = fs.clone();
+            let p = DavPath::new(p).unwrap();
+            async move { fs.metadata(&p).await.unwrap().etag().unwrap() }
+        };
+        let hello = etag("/file.txt").await;
+        assert_eq!(etag("/copy.txt").await, hello);
+        assert_ne!(etag("/other.txt").await, hello);
+
+        let root = DavPath::new("/").unwrap();
+        let mut entries = fs.read_dir(&root, ReadDirMeta::Data).await.unwrap();
+        let mut listed = 0;
+        while let Some(entry) = entries.next().await {
+            if entry.name() == b"copy.txt" {
+                assert_eq!(entry.metadata().await.unwrap().etag().unwrap(), hello);
+                listed += 1;
+            }
+        }
+        assert_eq!(listed, 1);
+        let path = DavPath::new("/copy.txt").unwrap();
+        let mut file = fs.open(&path, OpenOptions::read()).await.unwrap();
+        assert_eq!(file.metadata().await.unwrap().etag().unwrap(), hello);
+    }
+
+    #[tokio::test]
+    async fn meta_cache() {
+        let dir = fixture();
+        let fs = LocalFs::new(&dir, false, false, false).with_meta_cache(Duration::from_secs(60), 100);
+        let dav = client(fs.clone());
+        let len = |p: &str| {
+            let fs = fs.clone();
+            let p = DavPath::new(p).unwrap();
+            async move { fs.metadata(&p).await.map(|m| m.len()).ok() }
+        };
+
+        // changes made behind our back are not seen until the entry expires.
+        assert_eq!(len("/sub/file.txt").await, Some(5));
+        std::fs::write(dir.join("sub/file.txt"), "hello world").unwrap();
+        assert_eq!(len("/sub/file.txt").await, Some(5));
+
+        // but our own changes are.
+        assert_eq!(dav.request("PUT", "/sub/file.txt", "hi").await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(len("/sub/file.txt").await, Some(2));
+        let req = http::Request::builder()
+            .method("MOVE")
+            .uri("/sub/file.txt")
+            .header("destination", "/sub/moved.txt")
+            .body("")
+            .unwrap();
+        assert_eq!(dav.send(req).await.status(), StatusCode::CREATED);
+        assert_eq!(len("/sub/file.txt").await, None);
+        assert_eq!(len("/sub/moved.txt").await, Some(2));
+        assert_eq!(dav.request("DELETE", "/sub/", "").await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(len("/sub/moved.txt").await, None);
+        assert_eq!(len("/sub").await, None);
+
+        // entries expire, and the least recently used are dropped when it is full.
+        let cache = crate::localfs_metacache::MetaCache::new(Duration::from_millis(50), 1);
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+        assert_eq!(cache.metadata(&a, true).unwrap().len(), 1);
+        std::fs::write(&a, "aa").unwrap();
+        assert_eq!(cache.metadata(&a, true).unwrap().len(), 1);
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.metadata(&a, true).unwrap().len(), 2);
+        std::fs::write(&a, "aaa").unwrap();
+        cache.metadata(&b, true).unwrap();
+        assert_eq!(cache.metadata(&a, true).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,141 @@
+// Content based ETags for LocalFs.
+//
+// The default LocalFs ETag is derived from the inode, size and mtime
+// of a file. That changes when a file is copied with the same content
+// but a new mtime (say, by rsync), and clients then download it again.
+//
+// An ETag based on a hash of the content does not have that problem,
+// but hashing a file is expensive. So we keep the hashes in an LRU cache,
+// keyed by (path, mtime, size). Each entry costs about the size of the
+// path plus a few dozen bytes.
+//
+// Directories get an ETag based on the names, sizes and mtimes of their
+// entries, so it changes when an entry is modified, not only when one is
+// added or removed. That is computed on demand, there is no cache.
+//
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+#[cfg(feature = "content-etags")]
+use std::fs::File;
+use std::hash::Hasher;
+use std::io;
+#[cfg(feature = "content-etags")]
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::os::unix::ffi::OsStrExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lru::LruCache;
+use parking_lot::Mutex;
+#[cfg(feature = "content-etags")]
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "content-etags")]
+const HASH_BUF_SIZE: usize = 65536;
+
+#[derive(Hash, PartialEq, Eq)]
+struct Key {
+    path:     PathBuf,
+    modified: SystemTime,
+    len:      u64,
+}
+
+pub(crate) struct ContentEtags {
+    cache: Mutex<LruCache<Key, String>>,
+}
+
+impl fmt::Debug for ContentEtags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ContentEtags").finish()
+    }
+}
+
+impl ContentEtags {
+    #[cfg(feature = "content-etags")]
+    pub fn new(size: usize) -> ContentEtags {
+        let size = std::num::NonZeroUsize::new(if size == 0 { 1 } else { size }).unwrap();
+        ContentEtags {
+            cache: Mutex::new(LruCache::new(size)),
+        }
+    }
+
+    // Get the ETag of a file from the cache, or calculate it.
+    //
+    // This is sync code that does I/O, so it must be run in `blocking()`.
+    pub fn etag(&self, path: &Path, meta: &std::fs::Metadata) -> Option<String> {
+        let key = Key {
+            path:     path.to_path_buf(),
+            modified: meta.modified().ok()?,
+            len:      meta.len(),
+        };
+        if let Some(tag) = self.cache.lock().get(&key) {
+            return Some(tag.clone());
+        }
+
+        let tag = hash_file(path).ok()?;
+
+        // if the file changed while we were reading it, don't cache.
+        let meta = std::fs::metadata(path).ok()?;
+        if meta.len() != key.len || meta.modified().ok()? != key.modified {
+            return None;
+        }
+        self.cache.lock().put(key, tag.clone());
+        Some(tag)
+    }
+}
+
+// Streaming sha256 of a file, truncated to 128 bits.
+#[cfg(feature = "content-etags")]
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; HASH_BUF_SIZE];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let hash = hasher.finalize();
+    Ok(hash[..16].iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// Without the feature, `ContentEtags` can't be created, so this is never called.
+#[cfg(not(feature = "content-etags"))]
+fn hash_file(_path: &Path) -> io::Result<String> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// ETag of a directory: a hash of its mtime and the name, size and mtime
+// of every entry. This only has to change when the directory changes,
+// nothing is hidden, so the std hasher is good enough.
+//
+// This is sync code that does I/O, so it must be run in `blocking()`.
+pub(crate) fn dir_etag(path: &Path) -> io::Result<String> {
+    let nanos = |t: io::Result<SystemTime>| {
+        t.ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos())
+    };
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let (len, modified) = match entry.metadata() {
+            Ok(meta) => (meta.len(), nanos(meta.modified())),
+            Err(_) => (0, 0),
+        };
+        entries.push((entry.file_name(), len, modified));
+    }
+    entries.sort();
+
+    let mut hasher = DefaultHasher::new();
+    hasher.write_u128(nanos(std::fs::metadata(path)?.modified()));
+    for (name, len, modified) in &entries {
+        hasher.write(name.as_bytes());
+        hasher.write_u8(0);
+        hasher.write_u64(*len);
+        hasher.write_u128(*modified);
+    }
+    Ok(format!("{:016x}", hasher.finish()))
+}
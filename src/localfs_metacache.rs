@@ -0,0 +1,99 @@
+// Metadata cache for LocalFs.
+//
+// Clients that keep a tree in sync do a PROPFIND on the same directories
+// over and over, and every time each file in them is stat()ed. This keeps
+// the result of stat() and lstat() in an LRU cache, keyed by path, for
+// a short while.
+//
+// Entries are dropped when LocalFs itself changes a path. Changes made
+// by others are only noticed when the entry expires.
+//
+use std::fmt;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use parking_lot::Mutex;
+
+#[derive(Hash, PartialEq, Eq, Clone)]
+struct Key {
+    path:   PathBuf,
+    follow: bool,
+}
+
+pub(crate) struct MetaCache {
+    ttl:   Duration,
+    cache: Mutex<LruCache<Key, (Instant, Metadata)>>,
+}
+
+impl fmt::Debug for MetaCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MetaCache").field("ttl", &self.ttl).finish()
+    }
+}
+
+impl MetaCache {
+    pub fn new(ttl: Duration, size: usize) -> MetaCache {
+        let size = std::num::NonZeroUsize::new(if size == 0 { 1 } else { size }).unwrap();
+        MetaCache {
+            ttl,
+            cache: Mutex::new(LruCache::new(size)),
+        }
+    }
+
+    // Like std::fs::metadata() (if `follow` is set) or std::fs::symlink_metadata(),
+    // but the result comes from the cache if we have a recent one.
+    //
+    // This is sync code that does I/O, so it must be run in `blocking()`.
+    pub fn metadata(&self, path: &Path, follow: bool) -> io::Result<Metadata> {
+        let key = Key {
+            path: path.to_path_buf(),
+            follow,
+        };
+        if let Some((when, meta)) = self.cache.lock().get(&key) {
+            if when.elapsed() < self.ttl {
+                return Ok(meta.clone());
+            }
+        }
+        let now = Instant::now();
+        let meta = if follow {
+            std::fs::metadata(path)?
+        } else {
+            std::fs::symlink_metadata(path)?
+        };
+        self.cache.lock().put(key, (now, meta.clone()));
+        Ok(meta)
+    }
+
+    // Forget `path` and its parent directory, whose mtime changes
+    // when an entry is added or removed.
+    pub fn invalidate(&self, path: &Path) {
+        let mut cache = self.cache.lock();
+        for p in std::iter::once(path).chain(path.parent()) {
+            for follow in [false, true] {
+                cache.pop(&Key {
+                    path: p.to_path_buf(),
+                    follow,
+                });
+            }
+        }
+    }
+
+    // Like invalidate(), but also forget everything below `path`.
+    // This has to go through the whole cache, so only use it if
+    // `path` might be a directory.
+    pub fn invalidate_tree(&self, path: &Path) {
+        self.invalidate(path);
+        let mut cache = self.cache.lock();
+        let keys = cache
+            .iter()
+            .filter(|(k, _)| k.path.starts_with(path))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for key in &keys {
+            cache.pop(key);
+        }
+    }
+}
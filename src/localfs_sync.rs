@@ -0,0 +1,126 @@
+// RFC6578 sync tokens for LocalFs.
+//
+// A sync token is a point in time. Whatever changed since then is found
+// by walking the tree and looking at the mtime and ctime of every entry
+// (the ctime also changes on a rename, or a copy with `cp -p`).
+//
+// Removals can't be found that way, so we keep a journal of the paths that
+// were removed or renamed away through this LocalFs. It only lives in memory,
+// so tokens carry the id of this instance, and tokens from before a restart
+// are refused. Once the journal is full, the oldest entries are dropped and
+// tokens from before them are refused too. The client then does a full sync.
+//
+// Removals done by others, outside of LocalFs, are not noticed.
+//
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+
+use crate::davpath::DavPath;
+
+// Number of removals we remember.
+const JOURNAL_SIZE: usize = 10000;
+
+// Timestamps on a filesystem can lag behind the clock a little, and
+// some filesystems only have a resolution of 2 seconds. Look back this
+// much further; reporting a change twice does no harm.
+pub(crate) const MARGIN: Duration = Duration::from_secs(2);
+
+const TOKEN_PREFIX: &str = "data:,localfs-";
+
+pub(crate) struct SyncJournal {
+    id:    u64,
+    state: Mutex<State>,
+}
+
+struct State {
+    // last token that we handed out, in nanoseconds since the epoch.
+    last:    u64,
+    // tokens from before this can't be used anymore.
+    horizon: u64,
+    removed: VecDeque<(u64, DavPath)>,
+}
+
+impl fmt::Debug for SyncJournal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SyncJournal").field("id", &self.id).finish()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+impl SyncJournal {
+    pub fn new() -> SyncJournal {
+        let id = now();
+        SyncJournal {
+            id,
+            state: Mutex::new(State {
+                last:    id,
+                horizon: id,
+                removed: VecDeque::new(),
+            }),
+        }
+    }
+
+    // Time in nanoseconds, strictly increasing.
+    fn next(state: &mut State) -> u64 {
+        state.last = std::cmp::max(now(), state.last + 1);
+        state.last
+    }
+
+    // A new token for "now".
+    pub fn token(&self) -> String {
+        let t = SyncJournal::next(&mut self.state.lock());
+        format!("{}{:x}-{:x}", TOKEN_PREFIX, self.id, t)
+    }
+
+    // Decode a token that we handed out. Returns the point in time
+    // it stands for, or None if it is not valid (anymore).
+    pub fn parse(&self, token: &str) -> Option<SystemTime> {
+        let mut parts = token.strip_prefix(TOKEN_PREFIX)?.splitn(2, '-');
+        let id = u64::from_str_radix(parts.next()?, 16).ok()?;
+        let t = u64::from_str_radix(parts.next()?, 16).ok()?;
+        let state = self.state.lock();
+        if id != self.id || t < state.horizon || t > state.last {
+            return None;
+        }
+        Some(UNIX_EPOCH + Duration::from_nanos(t))
+    }
+
+    // Refuse all tokens that were handed out until now.
+    pub fn reset(&self) {
+        let mut state = self.state.lock();
+        state.horizon = SyncJournal::next(&mut state);
+        state.removed.clear();
+    }
+
+    // Remember that `path` was removed.
+    pub fn removed(&self, path: &DavPath) {
+        let mut state = self.state.lock();
+        let t = SyncJournal::next(&mut state);
+        state.removed.push_back((t, path.clone()));
+        if state.removed.len() > JOURNAL_SIZE {
+            if let Some((t, _)) = state.removed.pop_front() {
+                state.horizon = t + 1;
+            }
+        }
+    }
+
+    // Paths below `dir` that were removed since `since`.
+    pub fn removed_since(&self, dir: &DavPath, since: SystemTime) -> Vec<DavPath> {
+        let since = since.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        let state = self.state.lock();
+        state
+            .removed
+            .iter()
+            .filter(|(t, p)| *t >= since && p.as_bytes().starts_with(dir.as_bytes()))
+            .map(|(_, p)| p.clone())
+            .collect()
+    }
+}
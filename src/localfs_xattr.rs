@@ -0,0 +1,280 @@
+// Dead properties for LocalFs, stored in extended attributes.
+//
+// Every property is an attribute called "user.webdav.{namespace}name",
+// and its value is the raw XML of the property. Because they are part
+// of the file, they survive a restart of the server, and move along
+// when the file is renamed.
+//
+// Not every filesystem supports extended attributes (or the "user."
+// namespace), see `supported()`. This is all blocking I/O, so it must
+// be run in `blocking()`.
+//
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::fs::DavProp;
+
+const PREFIX: &str = "user.webdav.";
+
+#[cfg(target_os = "linux")]
+const ENOATTR: i32 = libc::ENODATA;
+#[cfg(target_os = "macos")]
+const ENOATTR: i32 = libc::ENOATTR;
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const ENOATTR: i32 = -1;
+
+fn cpath(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| io::ErrorKind::InvalidInput.into())
+}
+
+fn cname(prop: &DavProp) -> io::Result<CString> {
+    let ns = prop.namespace.as_deref().unwrap_or("");
+    let name = format!("{}{{{}}}{}", PREFIX, ns, prop.name);
+    CString::new(name).map_err(|_| io::ErrorKind::InvalidInput.into())
+}
+
+// Thin wrappers around the system calls, which differ per OS.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::ffi::CString;
+    use std::io;
+
+    fn check(n: isize) -> io::Result<usize> {
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn get(path: &CString, name: &CString, buf: &mut [u8]) -> io::Result<usize> {
+        let (ptr, len) = (buf.as_mut_ptr() as *mut _, buf.len());
+        check(unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), ptr, len) })
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn get(path: &CString, name: &CString, buf: &mut [u8]) -> io::Result<usize> {
+        let (ptr, len) = (buf.as_mut_ptr() as *mut _, buf.len());
+        check(unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), ptr, len, 0, 0) })
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set(path: &CString, name: &CString, value: &[u8]) -> io::Result<()> {
+        let (ptr, len) = (value.as_ptr() as *const _, value.len());
+        check(unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), ptr, len, 0) } as isize).map(|_| ())
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn set(path: &CString, name: &CString, value: &[u8]) -> io::Result<()> {
+        let (ptr, len) = (value.as_ptr() as *const _, value.len());
+        check(unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), ptr, len, 0, 0) } as isize).map(|_| ())
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn remove(path: &CString, name: &CString) -> io::Result<()> {
+        check(unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) } as isize).map(|_| ())
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn remove(path: &CString, name: &CString) -> io::Result<()> {
+        check(unsafe { libc::removexattr(path.as_ptr(), name.as_ptr(), 0) } as isize).map(|_| ())
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn list(path: &CString, buf: &mut [u8]) -> io::Result<usize> {
+        check(unsafe { libc::listxattr(path.as_ptr(), buf.as_mut_ptr() as *mut _, buf.len()) })
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn list(path: &CString, buf: &mut [u8]) -> io::Result<usize> {
+        check(unsafe { libc::listxattr(path.as_ptr(), buf.as_mut_ptr() as *mut _, buf.len(), 0) })
+    }
+}
+
+// Elsewhere, extended attributes are not supported.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod sys {
+    use std::ffi::CString;
+    use std::io;
+
+    fn unsupported<T>() -> io::Result<T> {
+        Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+    }
+
+    pub fn get(_path: &CString, _name: &CString, _buf: &mut [u8]) -> io::Result<usize> {
+        unsupported()
+    }
+
+    pub fn set(_path: &CString, _name: &CString, _value: &[u8]) -> io::Result<()> {
+        unsupported()
+    }
+
+    pub fn remove(_path: &CString, _name: &CString) -> io::Result<()> {
+        unsupported()
+    }
+
+    pub fn list(_path: &CString, _buf: &mut [u8]) -> io::Result<usize> {
+        unsupported()
+    }
+}
+
+// Read an attribute, retrying if it grew in between.
+fn getxattr(path: &CString, name: &CString) -> io::Result<Option<Vec<u8>>> {
+    loop {
+        let len = match sys::get(path, name, &mut []) {
+            Ok(len) => len,
+            Err(e) if e.raw_os_error() == Some(ENOATTR) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut buf = vec![0u8; len];
+        match sys::get(path, name, &mut buf) {
+            Ok(n) => {
+                buf.truncate(n);
+                return Ok(Some(buf));
+            },
+            Err(e) if e.raw_os_error() == Some(libc::ERANGE) => continue,
+            Err(e) if e.raw_os_error() == Some(ENOATTR) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Names of all our attributes, without the prefix.
+fn listxattr(path: &CString) -> io::Result<Vec<String>> {
+    let buf = loop {
+        let len = sys::list(path, &mut [])?;
+        let mut buf = vec![0u8; len];
+        match sys::list(path, &mut buf) {
+            Ok(n) => {
+                buf.truncate(n);
+                break buf;
+            },
+            Err(e) if e.raw_os_error() == Some(libc::ERANGE) => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    let names = buf
+        .split(|&b| b == 0)
+        .filter_map(|n| std::str::from_utf8(n).ok())
+        .filter_map(|n| n.strip_prefix(PREFIX))
+        .map(|n| n.to_string())
+        .collect();
+    Ok(names)
+}
+
+// Turn "{namespace}name" back into a DavProp.
+fn parse_name(name: &str) -> Option<DavProp> {
+    let (ns, name) = name.strip_prefix('{')?.split_once('}')?;
+    Some(DavProp {
+        name:      name.to_string(),
+        prefix:    None,
+        namespace: if ns.is_empty() { None } else { Some(ns.to_string()) },
+        xml:       None,
+    })
+}
+
+// Does the filesystem that `path` is on support our attributes?
+pub(crate) fn supported(path: &Path) -> bool {
+    let probe = DavProp {
+        name:      "probe".to_string(),
+        prefix:    None,
+        namespace: None,
+        xml:       None,
+    };
+    match (cpath(path), cname(&probe)) {
+        (Ok(path), Ok(name)) => getxattr(&path, &name).is_ok(),
+        _ => false,
+    }
+}
+
+pub(crate) fn get(path: &Path, prop: &DavProp) -> io::Result<Option<Vec<u8>>> {
+    getxattr(&cpath(path)?, &cname(prop)?)
+}
+
+// Set a property, or remove it if `prop.xml` is None.
+pub(crate) fn set(path: &Path, prop: &DavProp) -> io::Result<()> {
+    let (path, name) = (cpath(path)?, cname(prop)?);
+    match prop.xml {
+        Some(ref xml) => sys::set(&path, &name, xml),
+        None => {
+            match sys::remove(&path, &name) {
+                Err(e) if e.raw_os_error() != Some(ENOATTR) => Err(e),
+                _ => Ok(()),
+            }
+        },
+    }
+}
+
+// All properties of `path`. Without their value if `do_content` is not set.
+pub(crate) fn list(path: &Path, do_content: bool) -> io::Result<Vec<DavProp>> {
+    let path = cpath(path)?;
+    let mut props = Vec::new();
+    for name in listxattr(&path)? {
+        let mut prop = match parse_name(&name) {
+            Some(prop) => prop,
+            None => continue,
+        };
+        if do_content {
+            match getxattr(&path, &cname(&prop)?)? {
+                Some(xml) => prop.xml = Some(xml),
+                None => continue,
+            }
+        }
+        props.push(prop);
+    }
+    Ok(props)
+}
+
+// Copy all properties of `from` to `to`.
+pub(crate) fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    for prop in list(from, true)? {
+        set(to, &prop)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tempdir;
+
+    #[test]
+    fn roundtrip() {
+        let dir = tempdir();
+        let (file, copy_to) = (dir.join("file"), dir.join("copy"));
+        std::fs::write(&file, "").unwrap();
+        std::fs::write(&copy_to, "").unwrap();
+        if !supported(&file) {
+            // tmpfs without user xattrs, for example.
+            return;
+        }
+
+        let mut prop = DavProp {
+            name:      "color".to_string(),
+            prefix:    Some("A".to_string()),
+            namespace: Some("urn:app".to_string()),
+            xml:       Some(b"<A:color xmlns:A=\"urn:app\">red</A:color>".to_vec()),
+        };
+        set(&file, &prop).unwrap();
+        assert_eq!(get(&file, &prop).unwrap(), prop.xml);
+        let props = list(&file, false).unwrap();
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].namespace.as_deref(), Some("urn:app"));
+        assert_eq!(props[0].name, "color");
+        assert!(props[0].xml.is_none());
+
+        copy(&file, &copy_to).unwrap();
+        assert_eq!(get(&copy_to, &prop).unwrap(), prop.xml);
+
+        let xml = prop.xml.take();
+        set(&file, &prop).unwrap();
+        assert_eq!(get(&file, &prop).unwrap(), None);
+        assert!(list(&file, true).unwrap().is_empty());
+        // removing it again is fine.
+        set(&file, &prop).unwrap();
+        assert_eq!(get(&copy_to, &prop).unwrap(), xml);
+    }
+}
@@ -1,11 +1,11 @@
 //! Contains the structs and traits that define a `locksystem` backend.
 //!
 //! Note that the methods DO NOT return futures, they are sychronous.
-//! This is because currently only two locksystems exist, `MemLs` and `FakeLs`.
-//! Both of them do not do any I/O, all methods return instantly.
+//! This is because `MemLs` and `FakeLs` do not do any I/O, all methods
+//! return instantly.
 //!
-//! If ever a locksystem gets built that does I/O (to a filesystem,
-//! a database, or over the network) we'll need to revisit this.
+//! `RedisLs` does network I/O, and blocks while doing so. If more
+//! locksystems like that get built, we'll need to revisit this.
 //!
 use crate::davpath::DavPath;
 use std::fmt::Debug;
@@ -6,8 +6,13 @@
 //! This means you have to create the instance once, using `MemFs::new`, store
 //! it in your handler struct, and clone() it every time you pass
 //! it to the DavHandler. As a MemFs struct is just a handle, cloning is cheap.
+//!
+//! By default a MemFs can grow until the process runs out of memory.
+//! Use `MemFs::with_capacity` to put a limit on the size of the data
+//! in the files.
 use std::collections::HashMap;
 use std::io::{Error, ErrorKind, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
@@ -27,7 +32,15 @@ type Tree = tree::Tree<Vec<u8>, MemFsNode>;
 /// Ephemeral in-memory filesystem.
 #[derive(Debug)]
 pub struct MemFs {
-    tree: Arc<Mutex<Tree>>,
+    tree:  Arc<Mutex<Tree>>,
+    usage: Arc<Usage>,
+}
+
+// Number of bytes in all the files together, and the maximum.
+#[derive(Debug)]
+struct Usage {
+    used:     AtomicU64,
+    capacity: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -63,6 +76,7 @@ struct MemFsDirEntry {
 #[derive(Debug)]
 struct MemFsFile {
     tree:    Arc<Mutex<Tree>>,
+    usage:   Arc<Usage>,
     node_id: u64,
     pos:     usize,
     append:  bool,
@@ -71,9 +85,27 @@ struct MemFsFile {
 impl MemFs {
     /// Create a new "memfs" filesystem.
     pub fn new() -> Box<MemFs> {
+        MemFs::new_with(None)
+    }
+
+    /// Create a new "memfs" filesystem that holds at most `bytes` bytes.
+    ///
+    /// Writes that would go over that fail with `507 Insufficient Storage`.
+    /// A new file that does not fit is removed again, but an existing file
+    /// that a `PUT` overwrites is left truncated. The space that is left is also what the `quota-available-bytes`
+    /// property shows.
+    pub fn with_capacity(bytes: u64) -> Box<MemFs> {
+        MemFs::new_with(Some(bytes))
+    }
+
+    fn new_with(capacity: Option<u64>) -> Box<MemFs> {
         let root = MemFsNode::new_dir();
         Box::new(MemFs {
-            tree: Arc::new(Mutex::new(Tree::new(root))),
+            tree:  Arc::new(Mutex::new(Tree::new(root))),
+            usage: Arc::new(Usage {
+                used: AtomicU64::new(0),
+                capacity,
+            }),
         })
     }
 
@@ -99,11 +131,14 @@ impl MemFs {
             return Err(FsError::Forbidden);
         }
         if options.truncate {
-            node.as_file_mut()?.data.truncate(0);
+            let data = &mut node.as_file_mut()?.data;
+            self.usage.shrink(data.len() as u64);
+            data.truncate(0);
             node.update_mtime(SystemTime::now());
         }
         Ok(Box::new(MemFsFile {
             tree:    self.tree.clone(),
+            usage:   self.usage.clone(),
             node_id: node_id,
             pos:     0,
             append:  options.append,
@@ -114,7 +149,8 @@ impl MemFs {
 impl Clone for MemFs {
     fn clone(&self) -> Self {
         MemFs {
-            tree: Arc::clone(&self.tree),
+            tree:  Arc::clone(&self.tree),
+            usage: Arc::clone(&self.usage),
         }
     }
 }
@@ -180,7 +216,9 @@ impl DavFileSystem for MemFs {
             let tree = &mut *self.tree.lock().unwrap();
             let parent_id = tree.lookup_parent(path.as_bytes())?;
             let node_id = tree.lookup(path.as_bytes())?;
+            let size = usage(tree, node_id);
             tree.delete_node(node_id)?;
+            self.usage.shrink(size);
             tree.get_node_mut(parent_id)?.update_mtime(SystemTime::now());
             Ok(())
         }
@@ -192,20 +230,30 @@ impl DavFileSystem for MemFs {
             let tree = &mut *self.tree.lock().unwrap();
             let parent_id = tree.lookup_parent(path.as_bytes())?;
             let node_id = tree.lookup(path.as_bytes())?;
+            let size = usage(tree, node_id);
             tree.delete_node(node_id)?;
+            self.usage.shrink(size);
             tree.get_node_mut(parent_id)?.update_mtime(SystemTime::now());
             Ok(())
         }
         .boxed()
     }
 
+    // a rename within one tree, so it is atomic.
+    fn temp_path(&self, path: &DavPath) -> Option<DavPath> {
+        Some(temp_file_path(path))
+    }
+
     fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
         async move {
             let tree = &mut *self.tree.lock().unwrap();
             let node_id = tree.lookup(from.as_bytes())?;
             let parent_id = tree.lookup_parent(from.as_bytes())?;
             let dst_id = tree.lookup_parent(to.as_bytes())?;
+            // an existing file at the destination is replaced.
+            let replaced = tree.lookup(to.as_bytes()).map_or(0, |id| usage(tree, id));
             tree.move_node(node_id, dst_id, file_name(to.as_bytes()), true)?;
+            self.usage.shrink(replaced);
             tree.get_node_mut(parent_id)?.update_mtime(SystemTime::now());
             tree.get_node_mut(dst_id)?.update_mtime(SystemTime::now());
             Ok(())
@@ -220,11 +268,23 @@ impl DavFileSystem for MemFs {
             // source must exist.
             let snode_id = tree.lookup(from.as_bytes())?;
 
+            // is there room.
+            let size = usage(tree, snode_id);
+            let replaced = tree.lookup(to.as_bytes()).map_or(0, |id| usage(tree, id));
+            if size > replaced {
+                self.usage.grow(size - replaced)?;
+            }
+
             // make sure destination exists, create if needed.
             {
                 let mut oo = OpenOptions::write();
                 oo.create = true;
-                self.do_open(tree, to.as_bytes(), oo)?;
+                if let Err(e) = self.do_open(tree, to.as_bytes(), oo) {
+                    if size > replaced {
+                        self.usage.shrink(size - replaced);
+                    }
+                    return Err(e);
+                }
             }
             let dnode_id = tree.lookup(to.as_bytes())?;
 
@@ -235,6 +295,9 @@ impl DavFileSystem for MemFs {
                 MemFsNode::File(ref mut f) => f.crtime = SystemTime::now(),
             }
             *tree.get_node_mut(dnode_id)? = data;
+            if replaced > size {
+                self.usage.shrink(replaced - size);
+            }
 
             Ok(())
         }
@@ -307,6 +370,63 @@ impl DavFileSystem for MemFs {
         }
         .boxed()
     }
+
+    fn get_named_props<'a>(&'a self, path: &'a DavPath, props: Vec<DavProp>) -> FsFuture<'a, Vec<DavProp>> {
+        async move {
+            let tree = &mut *self.tree.lock().unwrap();
+            let node_id = tree.lookup(path.as_bytes())?;
+            let node = tree.get_node(node_id)?;
+            let res = props
+                .iter()
+                .filter_map(|prop| node.get_props().get(&propkey(&prop.namespace, &prop.name)))
+                .filter(|p| p.xml.is_some())
+                .cloned()
+                .collect();
+            Ok(res)
+        }
+        .boxed()
+    }
+
+    fn quota<'a>(&'a self, _path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        async move {
+            Ok((self.usage.used.load(Ordering::Relaxed), self.usage.capacity))
+        }
+        .boxed()
+    }
+}
+
+// Total size of the files at and below this node.
+fn usage(tree: &Tree, node_id: u64) -> u64 {
+    let mut size = match tree.get_node(node_id) {
+        Ok(MemFsNode::File(f)) => f.data.len() as u64,
+        _ => 0,
+    };
+    if let Ok(children) = tree.get_children(node_id) {
+        for (_, child_id) in children {
+            size += usage(tree, child_id);
+        }
+    }
+    size
+}
+
+impl Usage {
+    // Make room for `n` more bytes.
+    fn grow(&self, n: u64) -> FsResult<()> {
+        let res = self.used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            let new = used.checked_add(n)?;
+            match self.capacity {
+                Some(c) if new > c => None,
+                _ => Some(new),
+            }
+        });
+        res.map(|_| ()).map_err(|_| FsError::InsufficientStorage)
+    }
+
+    fn shrink(&self, n: u64) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| Some(used.saturating_sub(n)));
+    }
 }
 
 // small helper.
@@ -377,6 +497,7 @@ impl DavFile for MemFsFile {
             }
             let end = self.pos + buf.len();
             if end > file.data.len() {
+                self.usage.grow((end - file.data.len()) as u64)?;
                 file.data.resize(end, 0);
             }
             file.data[self.pos..end].copy_from_slice(&buf);
@@ -396,6 +517,7 @@ impl DavFile for MemFsFile {
             }
             let end = self.pos + buf.remaining();
             if end > file.data.len() {
+                self.usage.grow((end - file.data.len()) as u64)?;
                 file.data.resize(end, 0);
             }
             while buf.has_remaining() {
@@ -546,16 +668,12 @@ trait TreeExt {
 impl TreeExt for Tree {
     fn lookup_segs(&self, segs: Vec<&[u8]>) -> FsResult<u64> {
         let mut node_id = tree::ROOT_ID;
-        let mut is_dir = true;
         for seg in segs.into_iter() {
-            if !is_dir {
+            // a file has no children.
+            if !self.get_node(node_id)?.is_dir() {
                 return Err(FsError::Forbidden);
             }
-            if self.get_node(node_id)?.is_dir() {
-                node_id = self.get_child(node_id, seg)?;
-            } else {
-                is_dir = false;
-            }
+            node_id = self.get_child(node_id, seg)?;
         }
         Ok(node_id)
     }
@@ -585,3 +703,59 @@ fn file_name(path: &[u8]) -> Vec<u8> {
         .unwrap_or(b"")
         .to_vec()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::localfs::LocalFs;
+    use crate::testing::{tempdir, TestClient};
+    use crate::DavHandler;
+    use http::{HeaderValue, Request, StatusCode};
+
+    async fn get(c: &TestClient, range: &str) -> (StatusCode, Option<HeaderValue>, bytes::Bytes) {
+        let req = Request::builder().uri("/file.txt").header("Range", range);
+        let resp = c.send(req.body("").unwrap()).await;
+        (resp.status(), resp.headers().get("content-range").cloned(), resp.into_body())
+    }
+
+    // Range requests are handled by the handler through seek() and
+    // read_bytes(), so MemFs must answer them just like LocalFs does.
+    #[tokio::test]
+    async fn ranges() {
+        let dir = tempdir();
+        let memfs = TestClient::new(DavHandler::builder().filesystem(super::MemFs::new()).build_handler());
+        let localfs = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .build_handler();
+        let localfs = TestClient::new(localfs);
+        for c in &[&memfs, &localfs] {
+            c.request("PUT", "/file.txt", "0123456789").await;
+        }
+
+        let (status, crange, body) = get(&memfs, "bytes=2-4").await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(crange.unwrap(), "bytes 2-4/10");
+        assert_eq!(body, "234");
+        assert_eq!(get(&memfs, "bytes=20-").await.0, StatusCode::RANGE_NOT_SATISFIABLE);
+
+        for range in &["bytes=2-4", "bytes=7-", "bytes=-3", "bytes=5-100", "bytes=20-"] {
+            assert_eq!(get(&memfs, range).await, get(&localfs, range).await, "{}", range);
+        }
+    }
+
+    #[tokio::test]
+    async fn capacity() {
+        let dav = DavHandler::builder().filesystem(super::MemFs::with_capacity(10)).build_handler();
+        let c = TestClient::new(dav);
+        assert_eq!(c.request("PUT", "/a", "0123456").await.status(), StatusCode::CREATED);
+        assert_eq!(c.request("PUT", "/b", "0123456").await.status(), StatusCode::INSUFFICIENT_STORAGE);
+        // the partial file is gone, and so is the space it took.
+        assert_eq!(c.request("GET", "/b", "").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(c.request("PUT", "/c", "012").await.status(), StatusCode::CREATED);
+        assert_eq!(c.request("PUT", "/d", "0").await.status(), StatusCode::INSUFFICIENT_STORAGE);
+        // a file that was there already is not removed.
+        assert_eq!(c.request("PUT", "/c", "0123456789").await.status(), StatusCode::INSUFFICIENT_STORAGE);
+        assert_eq!(c.request("GET", "/c", "").await.status(), StatusCode::OK);
+        assert_eq!(c.request("DELETE", "/a", "").await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(c.request("PUT", "/d", "0123456").await.status(), StatusCode::CREATED);
+    }
+}
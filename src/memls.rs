@@ -6,6 +6,9 @@
 //! This means you have to create the instance once, using `MemLs::new`, store
 //! it in your handler struct, and clone() it every time you pass
 //! it to the DavHandler. As a MemLs struct is just a handle, cloning is cheap.
+//!
+//! Locks that have timed out are removed the next time the locksystem
+//! is used, there is no background task.
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 
@@ -25,19 +28,65 @@ pub struct MemLs(Arc<Mutex<MemLsInner>>);
 
 #[derive(Debug)]
 struct MemLsInner {
-    tree:  Tree,
+    tree:        Tree,
+    // when the first lock times out.
+    next_expiry: Option<SystemTime>,
 }
 
 impl MemLs {
     /// Create a new "memls" locksystem.
     pub fn new() -> Box<MemLs> {
         let inner = MemLsInner {
-            tree:  Tree::new(Vec::new()),
+            tree:        Tree::new(Vec::new()),
+            next_expiry: None,
         };
         Box::new(MemLs(Arc::new(Mutex::new(inner))))
     }
 }
 
+#[cfg_attr(not(feature = "filels"), allow(dead_code))]
+impl MemLs {
+    // Add an existing lock, e.g. one that was saved by FileLs.
+    pub(crate) fn insert(&self, lock: DavLock) {
+        let inner = &mut *self.0.lock().unwrap();
+        if let Some(t) = lock.timeout_at {
+            inner.add_expiry(t);
+        }
+        get_or_create_path_node(&mut inner.tree, &lock.path).push(lock);
+    }
+
+    // All locks that have not timed out.
+    pub(crate) fn all_locks(&self) -> Vec<DavLock> {
+        let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
+        let mut locks = Vec::new();
+        collect_locks(&inner.tree, tree::ROOT_ID, &mut locks);
+        locks
+    }
+}
+
+impl MemLsInner {
+    // Remove the locks that have timed out.
+    fn expire(&mut self) {
+        let now = SystemTime::now();
+        if self.next_expiry.is_some_and(|t| t <= now) {
+            self.next_expiry = expire_node(&mut self.tree, tree::ROOT_ID, now);
+        }
+    }
+
+    fn add_expiry(&mut self, t: SystemTime) {
+        self.next_expiry = Some(self.next_expiry.map_or(t, |n| std::cmp::min(n, t)));
+    }
+
+    fn timeout_at(&mut self, timeout: Option<Duration>) -> Option<SystemTime> {
+        let timeout_at = timeout.map(|d| SystemTime::now() + d);
+        if let Some(t) = timeout_at {
+            self.add_expiry(t);
+        }
+        timeout_at
+    }
+}
+
 impl DavLockSystem for MemLs {
     fn lock(
         &self,
@@ -50,6 +99,7 @@ impl DavLockSystem for MemLs {
     ) -> Result<DavLock, DavLock>
     {
         let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
 
         // any locks in the path?
         let rc = check_locks_to_path(&inner.tree, path, None, true, &Vec::new(), shared);
@@ -64,11 +114,8 @@ impl DavLockSystem for MemLs {
         }
 
         // create lock.
+        let timeout_at = inner.timeout_at(timeout);
         let node = get_or_create_path_node(&mut inner.tree, path);
-        let timeout_at = match timeout {
-            None => None,
-            Some(d) => Some(SystemTime::now() + d),
-        };
         let lock = DavLock {
             token:      Uuid::new_v4().urn().to_string(),
             path:       path.clone(),
@@ -87,6 +134,7 @@ impl DavLockSystem for MemLs {
 
     fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()> {
         let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
         let node_id = match lookup_lock(&inner.tree, path, token) {
             None => {
                 trace!("unlock: {} not found at {}", token, path);
@@ -109,6 +157,7 @@ impl DavLockSystem for MemLs {
     fn refresh(&self, path: &DavPath, token: &str, timeout: Option<Duration>) -> Result<DavLock, ()> {
         trace!("refresh lock {}", token);
         let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
         let node_id = match lookup_lock(&inner.tree, path, token) {
             None => {
                 trace!("lock not found");
@@ -116,13 +165,10 @@ impl DavLockSystem for MemLs {
             },
             Some(n) => n,
         };
+        let timeout_at = inner.timeout_at(timeout);
         let node = (&mut inner.tree).get_node_mut(node_id).unwrap();
         let idx = node.iter().position(|n| n.token.as_str() == token).unwrap();
         let lock = &mut node[idx];
-        let timeout_at = match timeout {
-            None => None,
-            Some(d) => Some(SystemTime::now() + d),
-        };
         lock.timeout = timeout;
         lock.timeout_at = timeout_at;
         Ok(lock.clone())
@@ -137,7 +183,8 @@ impl DavLockSystem for MemLs {
         submitted_tokens: Vec<&str>,
     ) -> Result<(), DavLock>
     {
-        let inner = &*self.0.lock().unwrap();
+        let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
         let _st = submitted_tokens.clone();
         let rc = check_locks_to_path(
             &inner.tree,
@@ -167,7 +214,8 @@ impl DavLockSystem for MemLs {
     }
 
     fn discover(&self, path: &DavPath) -> Vec<DavLock> {
-        let inner = &*self.0.lock().unwrap();
+        let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
         list_locks(&inner.tree, path)
     }
 
@@ -304,6 +352,39 @@ fn check_locks_from_node(
     Ok(())
 }
 
+// Remove the locks that have timed out from this node and the nodes below it,
+// and the nodes that are left empty. Returns when the first remaining lock times out.
+fn expire_node(tree: &mut Tree, node_id: u64, now: SystemTime) -> Option<SystemTime> {
+    let mut next = None;
+    if let Ok(children) = tree.get_children(node_id) {
+        for (_, child_id) in children {
+            let t = expire_node(tree, child_id, now);
+            next = next.into_iter().chain(t).min();
+        }
+    }
+    let node = tree.get_node_mut(node_id).unwrap();
+    node.retain(|l| !l.timeout_at.is_some_and(|t| t <= now));
+    next = next.into_iter().chain(node.iter().filter_map(|l| l.timeout_at)).min();
+    if node.is_empty() && node_id != tree::ROOT_ID {
+        // fails if the node still has children, which is fine.
+        let _ = tree.delete_node(node_id);
+    }
+    next
+}
+
+// All locks in this node and the nodes below it.
+#[cfg_attr(not(feature = "filels"), allow(dead_code))]
+fn collect_locks(tree: &Tree, node_id: u64, locks: &mut Vec<DavLock>) {
+    if let Ok(node) = tree.get_node(node_id) {
+        locks.extend_from_slice(node);
+    }
+    if let Ok(children) = tree.get_children(node_id) {
+        for (_, child_id) in children {
+            collect_locks(tree, child_id, locks);
+        }
+    }
+}
+
 // Find or create node.
 fn get_or_create_path_node<'a>(tree: &'a mut Tree, path: &DavPath) -> &'a mut Vec<DavLock> {
     let mut node_id = tree::ROOT_ID;
@@ -353,21 +434,21 @@ fn lookup_node(tree: &Tree, path: &DavPath) -> Option<u64> {
     Some(node_id)
 }
 
-// Find all locks in a path
+// Find all locks on a path: the locks on the path itself,
+// and the deep locks on the collections above it.
 fn list_locks(tree: &Tree, path: &DavPath) -> Vec<DavLock> {
     let mut locks = Vec::new();
 
+    let segs = path_to_segs(path, true);
+    let last_seg = segs.len() - 1;
     let mut node_id = tree::ROOT_ID;
-    if let Ok(node) = tree.get_node(node_id) {
-        locks.extend_from_slice(node);
-    }
-    for seg in path_to_segs(path, false) {
-        node_id = match tree.get_child(node_id, seg) {
+    for (i, seg) in segs.into_iter().enumerate() {
+        node_id = match get_child(tree, node_id, seg) {
             Ok(n) => n,
             Err(_) => break,
         };
         if let Ok(node) = tree.get_node(node_id) {
-            locks.extend_from_slice(node);
+            locks.extend(node.iter().filter(|l| i == last_seg || l.deep).cloned());
         }
     }
     locks
@@ -388,3 +469,92 @@ fn get_child(tree: &Tree, node_id: u64, seg: &[u8]) -> FsResult<u64> {
     }
     tree.get_child(node_id, seg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::DavHandler;
+    use http::{Request, StatusCode};
+
+    async fn req(
+        dav: &DavHandler,
+        method: &str,
+        hdrs: &[(&str, &str)],
+        body: String,
+    ) -> (StatusCode, String)
+    {
+        let mut req = Request::builder().method(method).uri("/file.txt");
+        for (name, value) in hdrs {
+            req = req.header(*name, *value);
+        }
+        let resp = dav.handle(req.body(hyper::Body::from(body)).unwrap()).await;
+        let status = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn lock(dav: &DavHandler, scope: &str, timeout: &str) -> (StatusCode, String) {
+        let lockinfo = format!(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:lockinfo xmlns:D="DAV:">
+              <D:lockscope><D:{}/></D:lockscope>
+              <D:locktype><D:write/></D:locktype>
+            </D:lockinfo>"#,
+            scope
+        );
+        req(dav, "LOCK", &[("Timeout", timeout)], lockinfo).await
+    }
+
+    fn handler() -> DavHandler {
+        DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler()
+    }
+
+    #[tokio::test]
+    async fn lock_expires() {
+        let dav = handler();
+        assert!(lock(&dav, "exclusive", "Second-1").await.0.is_success());
+        assert_eq!(lock(&dav, "exclusive", "Infinite").await.0, StatusCode::LOCKED);
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(lock(&dav, "exclusive", "Infinite").await.0.is_success());
+        assert_eq!(lock(&dav, "exclusive", "Second-1").await.0, StatusCode::LOCKED);
+    }
+
+    #[tokio::test]
+    async fn shared_locks() {
+        let dav = handler();
+        assert!(lock(&dav, "shared", "Second-60").await.0.is_success());
+        let (status, body) = lock(&dav, "shared", "Second-60").await;
+        assert!(status.is_success());
+        assert_eq!(body.matches("<D:activelock>").count(), 2);
+        assert_eq!(lock(&dav, "exclusive", "Second-60").await.0, StatusCode::LOCKED);
+
+        let propfind = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:propfind xmlns:D="DAV:"><D:prop><D:lockdiscovery/></D:prop></D:propfind>"#;
+        let (status, body) = req(&dav, "PROPFIND", &[("Depth", "0")], propfind.to_string()).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert_eq!(body.matches("<D:shared").count(), 2);
+
+        // and a shared lock on an exclusive lock fails.
+        let dav = handler();
+        assert!(lock(&dav, "exclusive", "Second-60").await.0.is_success());
+        assert_eq!(lock(&dav, "shared", "Second-60").await.0, StatusCode::LOCKED);
+    }
+
+    #[test]
+    fn expire_sweeps_tree() {
+        let ls = MemLs::new();
+        let path = DavPath::new("/a/b/c").unwrap();
+        ls.lock(&path, None, None, Some(Duration::from_secs(0)), false, false).unwrap();
+        let keep = DavPath::new("/a/d").unwrap();
+        ls.lock(&keep, None, None, Some(Duration::from_secs(60)), false, false).unwrap();
+        assert!(ls.discover(&path).is_empty());
+        assert_eq!(ls.discover(&keep).len(), 1);
+        let inner = ls.0.lock().unwrap();
+        assert!(lookup_node(&inner.tree, &DavPath::new("/a/b").unwrap()).is_none());
+        assert!(inner.next_expiry.is_some());
+    }
+}
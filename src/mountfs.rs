@@ -0,0 +1,648 @@
+//! Filesystem that combines several filesystems in one tree.
+//!
+//! Each filesystem is mounted at a path, such as `/photos` or
+//! `/docs/archive`. A request is sent to the filesystem with the longest
+//! mount path that matches, with the mount path taken off the front.
+//! So `/photos/2020/cat.jpg` becomes `/2020/cat.jpg` on the filesystem
+//! that is mounted at `/photos`.
+//!
+//! Directories that lead up to a mount point, like `/` and `/docs` in the
+//! example above, show up as directories that contain the mount points,
+//! even if there is no filesystem mounted there. If there is one (for
+//! example at `/`), the mount points are added to its directory listing.
+//!
+//! A `MOVE` from one filesystem to another is done by copying and
+//! then deleting. Mount points themselves can not be moved or deleted;
+//! like `rm -rf` on a Unix mount point, a `DELETE` does remove what is in it.
+//!
+//! Example:
+//!
+//! ```no_run
+//! use webdav_handler::{localfs::LocalFs, memfs::MemFs, mountfs::MountFs, DavHandler};
+//!
+//! let fs = MountFs::new()
+//!     .mount("/photos", LocalFs::new("/srv/photos", false, false, false))
+//!     .mount("/docs", LocalFs::new("/srv/docs", false, false, false))
+//!     .mount("/scratch", MemFs::new());
+//! let dav_server = DavHandler::builder().filesystem(fs).build_handler();
+//! ```
+//!
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::SystemTime;
+
+use futures::{future::FutureExt, stream, StreamExt};
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+/// Filesystem that dispatches to other filesystems by path.
+#[derive(Clone)]
+pub struct MountFs {
+    // mount path (no trailing slash, "" for the root) -> filesystem.
+    mounts:  BTreeMap<Vec<u8>, Box<dyn DavFileSystem>>,
+    created: SystemTime,
+}
+
+// Directory that leads up to a mount point.
+#[derive(Debug, Clone)]
+struct VirtualDir {
+    modified: SystemTime,
+}
+
+// Entry in a directory listing for a mount point, or a directory leading up to one.
+struct MountEntry {
+    name: Vec<u8>,
+    fs:   Option<Box<dyn DavFileSystem>>,
+    vdir: VirtualDir,
+}
+
+// What a path resolves to: the mount path, the filesystem, and the
+// path on that filesystem.
+struct Resolved<'a> {
+    mount: &'a [u8],
+    fs:    &'a dyn DavFileSystem,
+    path:  DavPath,
+}
+
+impl MountFs {
+    /// Create a new `MountFs` without anything mounted.
+    pub fn new() -> Box<MountFs> {
+        Box::new(MountFs {
+            mounts:  BTreeMap::new(),
+            created: SystemTime::now(),
+        })
+    }
+
+    /// Mount `fs` at `path`. A filesystem that was mounted there before is replaced.
+    ///
+    /// Panics if `path` is not a valid path.
+    pub fn mount(self: Box<Self>, path: &str, fs: Box<dyn DavFileSystem>) -> Box<MountFs> {
+        let mut this = self;
+        let path = DavPath::new(path).expect("MountFs::mount: invalid path");
+        let mut key = path.as_bytes().to_vec();
+        if key.ends_with(b"/") {
+            key.pop();
+        }
+        this.mounts.insert(key, fs);
+        this
+    }
+
+    // Find the filesystem with the longest mount path that matches.
+    fn resolve(&self, path: &DavPath) -> Option<Resolved<'_>> {
+        let p = path.as_bytes();
+        // a mount path that is a prefix of another one sorts before it,
+        // so going backwards we find the longest match first.
+        self.mounts.iter().rev().find_map(|(mount, fs)| {
+            let rel = p.strip_prefix(mount.as_slice())?;
+            if !rel.is_empty() && rel[0] != b'/' {
+                return None;
+            }
+            Some(Resolved {
+                mount: mount.as_slice(),
+                fs:    &**fs,
+                path:  DavPath::new("/").unwrap().join(rel),
+            })
+        })
+    }
+
+    // Names of the mount points, and directories that lead up to them,
+    // directly below `path`.
+    fn children(&self, path: &DavPath) -> Vec<MountEntry> {
+        let p = path.as_bytes();
+        let p = p.strip_suffix(b"/").unwrap_or(p);
+        let mut entries: Vec<MountEntry> = Vec::new();
+        for (mount, fs) in self.mounts.iter() {
+            let rel = match mount.strip_prefix(p) {
+                Some(rel) if rel.len() > 1 && rel[0] == b'/' => &rel[1..],
+                _ => continue,
+            };
+            let (name, fs) = match rel.iter().position(|&c| c == b'/') {
+                None => (rel, Some(fs.clone())),
+                Some(idx) => (&rel[..idx], None),
+            };
+            if !entries.iter().any(|e| e.name == name) {
+                entries.push(MountEntry {
+                    name: name.to_vec(),
+                    fs,
+                    vdir: self.vdir(),
+                });
+            }
+        }
+        entries
+    }
+
+    // Is this a directory that leads up to a mount point.
+    fn is_virtual(&self, path: &DavPath) -> bool {
+        !self.children(path).is_empty()
+    }
+
+    // Is this a mount point, or a directory that leads up to one.
+    fn is_fixed(&self, path: &DavPath) -> bool {
+        let p = path.as_bytes();
+        let p = p.strip_suffix(b"/").unwrap_or(p);
+        self.mounts.contains_key(p) || self.is_virtual(path)
+    }
+
+    fn vdir(&self) -> VirtualDir {
+        VirtualDir {
+            modified: self.created,
+        }
+    }
+
+    async fn do_metadata(&self, path: &DavPath, symlink: bool) -> FsResult<Box<dyn DavMetaData>> {
+        let res = match self.resolve(path) {
+            Some(r) if symlink => r.fs.symlink_metadata(&r.path).await,
+            Some(r) => r.fs.metadata(&r.path).await,
+            None => Err(FsError::NotFound),
+        };
+        match res {
+            Err(FsError::NotFound) if self.is_virtual(path) => {
+                Ok(Box::new(self.vdir()) as Box<dyn DavMetaData>)
+            },
+            other => other,
+        }
+    }
+
+    async fn do_read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<Box<dyn DavDirEntry>>>
+    {
+        let children = self.children(path);
+        let mut entries = Vec::new();
+        match self.resolve(path) {
+            Some(r) => {
+                match r.fs.read_dir(&r.path, meta).await {
+                    Ok(mut strm) => {
+                        // mount points hide what is below them.
+                        let hidden = children.iter().map(|e| e.name.clone()).collect::<HashSet<_>>();
+                        while let Some(entry) = strm.next().await {
+                            if !hidden.contains(&entry.name()) {
+                                entries.push(entry);
+                            }
+                        }
+                    },
+                    Err(FsError::NotFound) if !children.is_empty() => {},
+                    Err(e) => return Err(e),
+                }
+            },
+            None if children.is_empty() => return Err(FsError::NotFound),
+            None => {},
+        }
+        for entry in children.into_iter() {
+            entries.push(Box::new(entry) as Box<dyn DavDirEntry>);
+        }
+        Ok(Box::pin(stream::iter(entries)) as FsStream<Box<dyn DavDirEntry>>)
+    }
+
+    async fn do_open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        match self.resolve(path) {
+            Some(r) if !self.is_virtual(path) => r.fs.open(&r.path, options).await,
+            _ => Err(FsError::Forbidden),
+        }
+    }
+
+    async fn do_exists(&self, path: &DavPath) -> FsResult<bool> {
+        let exists = match self.resolve(path) {
+            Some(r) => r.fs.exists(&r.path).await?,
+            None => false,
+        };
+        Ok(exists || self.is_virtual(path))
+    }
+
+    async fn do_rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        if self.is_fixed(from) || self.is_fixed(to) {
+            return Err(FsError::Forbidden);
+        }
+        match (self.resolve(from), self.resolve(to)) {
+            (Some(f), Some(t)) if f.mount == t.mount => f.fs.rename(&f.path, &t.path).await,
+            (Some(_), Some(_)) => Err(FsError::IsRemote),
+            _ => Err(FsError::Forbidden),
+        }
+    }
+
+    async fn do_bind(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        if self.is_fixed(from) || self.is_fixed(to) {
+            return Err(FsError::Forbidden);
+        }
+        match (self.resolve(from), self.resolve(to)) {
+            (Some(f), Some(t)) if f.mount == t.mount => f.fs.bind(&f.path, &t.path).await,
+            _ => Err(FsError::Forbidden),
+        }
+    }
+
+    async fn do_copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        if self.is_fixed(to) {
+            return Err(FsError::Forbidden);
+        }
+        let (f, t) = match (self.resolve(from), self.resolve(to)) {
+            (Some(f), Some(t)) => (f, t),
+            _ => return Err(FsError::Forbidden),
+        };
+        if f.mount == t.mount {
+            match f.fs.copy(&f.path, &t.path).await {
+                Err(FsError::NotImplemented) => {},
+                other => return other,
+            }
+        }
+        let mut src = f.fs.open(&f.path, OpenOptions::read()).await?;
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        oo.truncate = true;
+        let mut dst = t.fs.open(&t.path, oo).await?;
+        copy_data(&mut *src, &mut *dst).await?;
+        if f.fs.have_props(&f.path).await {
+            if let Ok(props) = f.fs.get_props(&f.path, true).await {
+                let patch = props.into_iter().map(|p| (true, p)).collect();
+                let _ = t.fs.patch_props(&t.path, patch).await;
+            }
+        }
+        Ok(())
+    }
+
+    // Path on a mounted filesystem to our path.
+    fn outer_path(mount: &[u8], path: &DavPath) -> DavPath {
+        DavPath::new("/").unwrap().join(mount).join(path.as_bytes())
+    }
+}
+
+impl DavFileSystem for MountFs {
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.do_metadata(path, false).boxed()
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.do_metadata(path, true).boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+    {
+        self.do_read_dir(path, meta).boxed()
+    }
+
+    fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+        self.do_open(path, options).boxed()
+    }
+
+    fn exists<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        self.do_exists(path).boxed()
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            match self.resolve(path) {
+                _ if self.is_fixed(path) => Err(FsError::Exists),
+                Some(r) => r.fs.create_dir(&r.path).await,
+                None => Err(FsError::Forbidden),
+            }
+        }
+        .boxed()
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            match self.resolve(path) {
+                _ if self.is_fixed(path) => Err(FsError::Forbidden),
+                Some(r) => r.fs.remove_dir(&r.path).await,
+                None => Err(FsError::NotFound),
+            }
+        }
+        .boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            match self.resolve(path) {
+                _ if self.is_fixed(path) => Err(FsError::Forbidden),
+                Some(r) => r.fs.remove_file(&r.path).await,
+                None => Err(FsError::NotFound),
+            }
+        }
+        .boxed()
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            match self.resolve(path) {
+                // the handler then removes the contents one by one.
+                _ if self.is_fixed(path) => Err(FsError::NotImplemented),
+                Some(r) => r.fs.remove_dir_all(&r.path).await,
+                None => Err(FsError::NotFound),
+            }
+        }
+        .boxed()
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.do_rename(from, to).boxed()
+    }
+
+    fn temp_path(&self, path: &DavPath) -> Option<DavPath> {
+        let r = self.resolve(path)?;
+        let tmp = r.fs.temp_path(&r.path)?;
+        Some(MountFs::outer_path(r.mount, &tmp))
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.do_copy(from, to).boxed()
+    }
+
+    fn bind<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.do_bind(from, to).boxed()
+    }
+
+    fn supports_bind(&self) -> bool {
+        self.mounts.values().any(|fs| fs.supports_bind())
+    }
+
+    fn capabilities(&self) -> FsCapabilities {
+        // clients decide once how to upload, so partial updates have to
+        // work everywhere. The rest is advertised if some mount has it.
+        let caps = self.mounts.values().map(|fs| fs.capabilities()).collect::<Vec<_>>();
+        FsCapabilities {
+            write:          caps.iter().any(|c| c.write),
+            partial_update: caps.iter().all(|c| c.partial_update),
+            bind:           caps.iter().any(|c| c.bind),
+        }
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        async move {
+            let r = self.resolve(path).ok_or(FsError::NotFound)?;
+            r.fs.set_accessed(&r.path, tm).await
+        }
+        .boxed()
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        async move {
+            let r = self.resolve(path).ok_or(FsError::NotFound)?;
+            r.fs.set_modified(&r.path, tm).await
+        }
+        .boxed()
+    }
+
+    fn have_props<'a>(&'a self, path: &'a DavPath) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        async move {
+            match self.resolve(path) {
+                Some(r) => r.fs.have_props(&r.path).await,
+                None => false,
+            }
+        }
+        .boxed()
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(http::StatusCode, DavProp)>>
+    {
+        async move {
+            let r = self.resolve(path).ok_or(FsError::Forbidden)?;
+            r.fs.patch_props(&r.path, patch).await
+        }
+        .boxed()
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        async move {
+            let r = self.resolve(path).ok_or(FsError::NotFound)?;
+            r.fs.get_props(&r.path, do_content).await
+        }
+        .boxed()
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        async move {
+            let r = self.resolve(path).ok_or(FsError::NotFound)?;
+            r.fs.get_prop(&r.path, prop).await
+        }
+        .boxed()
+    }
+
+    fn get_named_props<'a>(&'a self, path: &'a DavPath, props: Vec<DavProp>) -> FsFuture<'a, Vec<DavProp>> {
+        async move {
+            let r = self.resolve(path).ok_or(FsError::NotFound)?;
+            r.fs.get_named_props(&r.path, props).await
+        }
+        .boxed()
+    }
+
+    fn quota<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        async move {
+            let r = self.resolve(path).ok_or(FsError::NotImplemented)?;
+            r.fs.quota(&r.path).await
+        }
+        .boxed()
+    }
+
+    fn privileges<'a>(&'a self, path: &'a DavPath, principal: Option<&'a str>) -> FsFuture<'a, PrivilegeSet> {
+        async move {
+            let r = self.resolve(path).ok_or(FsError::NotImplemented)?;
+            r.fs.privileges(&r.path, principal).await
+        }
+        .boxed()
+    }
+
+    fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        async move {
+            let r = self.resolve(path).ok_or(FsError::NotFound)?;
+            r.fs.content_type(&r.path).await
+        }
+        .boxed()
+    }
+
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        async move {
+            if self.is_virtual(path) {
+                // the listing has entries that the filesystem does not know about.
+                return Err(FsError::NotImplemented);
+            }
+            let r = self.resolve(path).ok_or(FsError::NotImplemented)?;
+            r.fs.collection_etag(&r.path).await
+        }
+        .boxed()
+    }
+
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        async move {
+            if self.is_virtual(path) {
+                return Err(FsError::NotImplemented);
+            }
+            let r = self.resolve(path).ok_or(FsError::NotImplemented)?;
+            r.fs.sync_token(&r.path).await
+        }
+        .boxed()
+    }
+
+    fn changes_since<'a>(
+        &'a self,
+        path: &'a DavPath,
+        token: &'a str,
+        deep: bool,
+    ) -> FsFuture<'a, Vec<Change>> {
+        async move {
+            let r = self.resolve(path).ok_or(FsError::NotImplemented)?;
+            let changes = r.fs.changes_since(&r.path, token, deep).await?;
+            let changes = changes
+                .into_iter()
+                .map(|change| {
+                    match change {
+                        Change::Modified(p) => Change::Modified(MountFs::outer_path(r.mount, &p)),
+                        Change::Removed(p) => Change::Removed(MountFs::outer_path(r.mount, &p)),
+                    }
+                })
+                .collect();
+            Ok(changes)
+        }
+        .boxed()
+    }
+}
+
+impl std::fmt::Debug for MountFs {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mounts = self
+            .mounts
+            .keys()
+            .map(|k| String::from_utf8_lossy(k).into_owned())
+            .collect::<Vec<_>>();
+        f.debug_struct("MountFs").field("mounts", &mounts).finish()
+    }
+}
+
+impl DavMetaData for VirtualDir {
+    fn len(&self) -> u64 {
+        0
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        Ok(self.modified)
+    }
+
+    fn is_dir(&self) -> bool {
+        true
+    }
+}
+
+impl DavDirEntry for MountEntry {
+    fn name(&self) -> Vec<u8> {
+        self.name.clone()
+    }
+
+    fn metadata<'a>(&'a self) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            if let Some(ref fs) = self.fs {
+                if let Ok(meta) = fs.metadata(&DavPath::new("/").unwrap()).await {
+                    return Ok(meta);
+                }
+            }
+            Ok(Box::new(self.vdir.clone()) as Box<dyn DavMetaData>)
+        }
+        .boxed()
+    }
+
+    fn is_dir<'a>(&'a self) -> FsFuture<'a, bool> {
+        async move { Ok(true) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use bytes::Bytes;
+
+    fn path(p: &str) -> DavPath {
+        DavPath::new(p).unwrap()
+    }
+
+    async fn write(fs: &dyn DavFileSystem, p: &str, data: &'static str) {
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        let mut file = fs.open(&path(p), oo).await.unwrap();
+        file.write_bytes(Bytes::from(data)).await.unwrap();
+        file.flush().await.unwrap();
+    }
+
+    async fn list(fs: &dyn DavFileSystem, p: &str) -> Vec<String> {
+        let strm = fs.read_dir(&path(p), ReadDirMeta::None).await.unwrap();
+        let mut names = strm
+            .map(|e| String::from_utf8(e.name()).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn mounts() {
+        let photos = MemFs::new();
+        let archive = MemFs::new();
+        let fs = MountFs::new()
+            .mount("/photos", photos.clone())
+            .mount("/docs/archive/", archive.clone());
+
+        // directories leading up to the mount points.
+        assert_eq!(list(&*fs, "/").await, vec!["docs", "photos"]);
+        assert_eq!(list(&*fs, "/docs/").await, vec!["archive"]);
+        assert!(fs.metadata(&path("/docs")).await.unwrap().is_dir());
+        assert!(fs.metadata(&path("/nothing")).await.is_err());
+
+        // longest match wins, and paths are translated.
+        write(&*fs, "/photos/cat.jpg", "meow").await;
+        write(&*fs, "/docs/archive/old.txt", "old").await;
+        assert!(photos.metadata(&path("/cat.jpg")).await.is_ok());
+        assert!(archive.metadata(&path("/old.txt")).await.is_ok());
+        assert_eq!(list(&*fs, "/photos/").await, vec!["cat.jpg"]);
+
+        // mount points are fixed.
+        assert!(fs.remove_dir(&path("/photos/")).await.is_err());
+        assert!(fs.create_dir(&path("/docs/")).await.is_err());
+        assert!(fs.open(&path("/docs/new.txt"), OpenOptions::write()).await.is_err());
+
+        // between filesystems, copying works but renaming does not.
+        fs.copy(&path("/photos/cat.jpg"), &path("/docs/archive/cat.jpg"))
+            .await
+            .unwrap();
+        assert!(archive.metadata(&path("/cat.jpg")).await.is_ok());
+        assert!(matches!(
+            fs.rename(&path("/photos/cat.jpg"), &path("/docs/archive/cat2.jpg")).await,
+            Err(FsError::IsRemote)
+        ));
+    }
+
+    #[tokio::test]
+    async fn forwarded() {
+        let dir = crate::testing::tempdir();
+        std::fs::create_dir_all(dir.join("d/sub")).unwrap();
+        std::fs::write(dir.join("d/sub/f"), "f").unwrap();
+        std::fs::write(dir.join("file"), "file").unwrap();
+        let fs = MountFs::new()
+            .mount("/a/local", crate::localfs::LocalFs::new(&dir, false, false, false))
+            .mount("/mem", MemFs::new());
+
+        assert!(fs.exists(&path("/a/")).await.unwrap());
+        assert!(fs.exists(&path("/a/local/file")).await.unwrap());
+        assert!(!fs.exists(&path("/a/nothing")).await.unwrap());
+
+        // bind only within one filesystem.
+        assert!(fs.supports_bind());
+        fs.bind(&path("/a/local/file"), &path("/a/local/file2")).await.unwrap();
+        assert!(dir.join("file2").exists());
+        assert!(fs.bind(&path("/a/local/file"), &path("/mem/file")).await.is_err());
+
+        // a mount point itself is not removed at once.
+        fs.remove_dir_all(&path("/a/local/d/")).await.unwrap();
+        assert!(!dir.join("d").exists());
+        let res = fs.remove_dir_all(&path("/a/local/")).await;
+        assert_eq!(res.unwrap_err(), FsError::NotImplemented);
+    }
+}
@@ -11,17 +11,24 @@ use xml::EmitterConfig;
 
 use crate::async_stream::AsyncStream;
 use crate::body::Body;
+use crate::davhandler::{href, HrefRewrite};
 use crate::davpath::DavPath;
+use crate::errors::ErrorMap;
 use crate::util::MemBuffer;
 use crate::DavError;
 
 type Sender = crate::async_stream::Sender<(DavPath, StatusCode), DavError>;
 
-pub(crate) struct MultiError(Sender);
+pub(crate) struct MultiError(Sender, Option<ErrorMap>);
 
 impl MultiError {
-    pub fn new(sender: Sender) -> MultiError {
-        MultiError(sender)
+    pub fn new(sender: Sender, error_map: Option<ErrorMap>) -> MultiError {
+        MultiError(sender, error_map)
+    }
+
+    // The status for this error, as it would be added by `add_status`.
+    pub fn statuscode(&self, e: &DavError) -> StatusCode {
+        e.mapped_statuscode(self.1.as_ref())
     }
 
     pub async fn add_status<'a>(
@@ -30,7 +37,7 @@ impl MultiError {
         status: impl Into<DavError> + 'static,
     ) -> Result<(), futures::channel::mpsc::SendError>
     {
-        let status = status.into().statuscode();
+        let status = self.statuscode(&status.into());
         self.0.send((path.clone(), status)).await;
         Ok(())
     }
@@ -49,16 +56,26 @@ where S: Into<xml::name::Name<'b>> {
     Ok(())
 }
 
-fn write_response(mut w: &mut XmlWriter, path: &DavPath, sc: StatusCode) -> Result<(), DavError> {
+fn write_response(
+    mut w: &mut XmlWriter,
+    path: &DavPath,
+    sc: StatusCode,
+    rewrite: Option<&HrefRewrite>,
+) -> Result<(), DavError>
+{
     w.write(XmlWEvent::start_element("D:response"))?;
-    let p = path.with_prefix().as_url_string();
+    let p = href(rewrite, path);
     write_elem(&mut w, "D:href", &p)?;
     write_elem(&mut w, "D:status", &format!("HTTP/1.1 {}", sc))?;
     w.write(XmlWEvent::end_element())?;
     Ok(())
 }
 
-pub(crate) async fn multi_error<S>(req_path: DavPath, status_stream: S) -> Result<Response<Body>, DavError>
+pub(crate) async fn multi_error<S>(
+    req_path: DavPath,
+    status_stream: S,
+    rewrite: Option<HrefRewrite>,
+) -> Result<Response<Body>, DavError>
 where S: Stream<Item = Result<(DavPath, StatusCode), DavError>> + Send + 'static {
     // read the first path/status item
     let mut status_stream = Box::pin(status_stream);
@@ -124,7 +141,7 @@ where S: Stream<Item = Result<(DavPath, StatusCode), DavError>> + Send + 'static
                 } else {
                     status
                 };
-                write_response(&mut xw, &path, status)?;
+                write_response(&mut xw, &path, status, rewrite.as_ref())?;
                 let data = xw.inner_mut().take();
                 tx.send(data).await;
             }
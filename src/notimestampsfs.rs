@@ -0,0 +1,345 @@
+//
+// Filesystem wrapper that hides the timestamps of files and directories,
+// enabled with `DavConfig::expose_timestamps(false)`.
+//
+// The metadata has no modification, access, creation or change time, so
+// there is no Last-Modified header, no DAV:getlastmodified property and
+// If-Modified-Since is ignored. The default ETag is derived from the mtime,
+// so it is replaced by a hash of that ETag and a random key. It still
+// changes when the file changes, but the mtime can't be recovered from it.
+//
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io::SeekFrom;
+use std::pin::Pin;
+use std::time::SystemTime;
+
+use futures::{FutureExt, StreamExt};
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+lazy_static! {
+    // SipHash keys, random but the same for the life of the process.
+    static ref ETAG_KEY: RandomState = RandomState::new();
+}
+
+#[derive(Clone)]
+pub(crate) struct NoTimestampsFs {
+    inner: Box<dyn DavFileSystem>,
+}
+
+impl NoTimestampsFs {
+    // Wrap `fs` if the timestamps are to be hidden.
+    pub(crate) fn wrap(fs: Box<dyn DavFileSystem>, hide: bool) -> Box<dyn DavFileSystem> {
+        match hide {
+            true => Box::new(NoTimestampsFs { inner: fs }),
+            false => fs,
+        }
+    }
+}
+
+fn hide(meta: Box<dyn DavMetaData>) -> Box<dyn DavMetaData> {
+    let etag = meta.etag().map(|tag| hash(tag.as_bytes()));
+    Box::new(NoTimestamps { inner: meta, etag })
+}
+
+// Keyed hash of the data. Without the key, it can't be reversed.
+fn hash(data: &[u8]) -> String {
+    let mut hasher = ETAG_KEY.build_hasher();
+    hasher.write(data);
+    format!("{:016x}", hasher.finish())
+}
+
+#[derive(Debug, Clone)]
+struct NoTimestamps {
+    inner: Box<dyn DavMetaData>,
+    etag:  Option<String>,
+}
+
+impl DavMetaData for NoTimestamps {
+    fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        Err(FsError::NotImplemented)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.inner.is_dir()
+    }
+
+    fn etag(&self) -> Option<String> {
+        self.etag.clone()
+    }
+
+    fn is_file(&self) -> bool {
+        self.inner.is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.inner.is_symlink()
+    }
+
+    fn executable(&self) -> FsResult<bool> {
+        self.inner.executable()
+    }
+
+    fn bindings(&self) -> u64 {
+        self.inner.bindings()
+    }
+}
+
+struct NoTimestampsEntry {
+    entry: Box<dyn DavDirEntry>,
+}
+
+impl DavDirEntry for NoTimestampsEntry {
+    fn name(&self) -> Vec<u8> {
+        self.entry.name()
+    }
+
+    fn metadata<'a>(&'a self) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            Ok(hide(self.entry.metadata().await?))
+        }
+        .boxed()
+    }
+
+    fn is_dir<'a>(&'a self) -> FsFuture<'a, bool> {
+        self.entry.is_dir()
+    }
+
+    fn is_file<'a>(&'a self) -> FsFuture<'a, bool> {
+        self.entry.is_file()
+    }
+
+    fn is_symlink<'a>(&'a self) -> FsFuture<'a, bool> {
+        self.entry.is_symlink()
+    }
+}
+
+struct NoTimestampsFile {
+    file: Box<dyn DavFile>,
+}
+
+impl fmt::Debug for NoTimestampsFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.file.fmt(f)
+    }
+}
+
+impl DavFile for NoTimestampsFile {
+    fn metadata<'a>(&'a mut self) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            Ok(hide(self.file.metadata().await?))
+        }
+        .boxed()
+    }
+
+    fn write_buf<'a>(&'a mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'a, ()> {
+        self.file.write_buf(buf)
+    }
+
+    fn write_bytes<'a>(&'a mut self, buf: bytes::Bytes) -> FsFuture<'a, ()> {
+        self.file.write_bytes(buf)
+    }
+
+    fn read_bytes<'a>(&'a mut self, count: usize) -> FsFuture<'a, bytes::Bytes> {
+        self.file.read_bytes(count)
+    }
+
+    fn seek<'a>(&'a mut self, pos: SeekFrom) -> FsFuture<'a, u64> {
+        self.file.seek(pos)
+    }
+
+    fn flush<'a>(&'a mut self) -> FsFuture<'a, ()> {
+        self.file.flush()
+    }
+}
+
+impl DavFileSystem for NoTimestampsFs {
+    fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move {
+            let file = self.inner.open(path, options).await?;
+            Ok(Box::new(NoTimestampsFile { file }) as Box<dyn DavFile>)
+        }
+        .boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+    {
+        async move {
+            let entries = self.inner.read_dir(path, meta).await?;
+            let entries = entries.map(|entry| Box::new(NoTimestampsEntry { entry }) as Box<dyn DavDirEntry>);
+            Ok(Box::pin(entries) as FsStream<Box<dyn DavDirEntry>>)
+        }
+        .boxed()
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            Ok(hide(self.inner.metadata(path).await?))
+        }
+        .boxed()
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            Ok(hide(self.inner.symlink_metadata(path).await?))
+        }
+        .boxed()
+    }
+
+    fn exists<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        self.inner.exists(path)
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.inner.create_dir(path)
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.inner.remove_dir(path)
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.inner.remove_file(path)
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.inner.remove_dir_all(path)
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.inner.rename(from, to)
+    }
+
+    fn temp_path(&self, path: &DavPath) -> Option<DavPath> {
+        self.inner.temp_path(path)
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.inner.copy(from, to)
+    }
+
+    fn bind<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.inner.bind(from, to)
+    }
+
+    fn supports_bind(&self) -> bool {
+        self.inner.supports_bind()
+    }
+
+    fn capabilities(&self) -> FsCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.inner.set_accessed(path, tm)
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.inner.set_modified(path, tm)
+    }
+
+    fn have_props<'a>(&'a self, path: &'a DavPath) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        self.inner.have_props(path)
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(http::StatusCode, DavProp)>>
+    {
+        self.inner.patch_props(path, patch)
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        self.inner.get_props(path, do_content)
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        self.inner.get_prop(path, prop)
+    }
+
+    fn get_named_props<'a>(&'a self, path: &'a DavPath, props: Vec<DavProp>) -> FsFuture<'a, Vec<DavProp>> {
+        self.inner.get_named_props(path, props)
+    }
+
+    fn quota<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        self.inner.quota(path)
+    }
+
+    fn privileges<'a>(&'a self, path: &'a DavPath, principal: Option<&'a str>) -> FsFuture<'a, PrivilegeSet> {
+        self.inner.privileges(path, principal)
+    }
+
+    fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        self.inner.content_type(path)
+    }
+
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        async move { Ok(hash(self.inner.collection_etag(path).await?.as_bytes())) }.boxed()
+    }
+
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.inner.sync_token(path)
+    }
+
+    fn changes_since<'a>(
+        &'a self,
+        path: &'a DavPath,
+        token: &'a str,
+        deep: bool,
+    ) -> FsFuture<'a, Vec<Change>> {
+        self.inner.changes_since(path, token, deep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memfs::MemFs;
+    use crate::testing::TestClient;
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn hidden() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .expose_timestamps(false)
+            .build_handler();
+        let c = TestClient::new(dav);
+
+        let resp = c.request("PUT", "/a", "hello").await;
+        assert!(resp.headers().get("last-modified").is_none());
+        let etag = resp.headers()["etag"].clone();
+
+        let resp = c.request("GET", "/a", "").await;
+        assert!(resp.headers().get("last-modified").is_none());
+        assert_eq!(resp.headers()["etag"], etag);
+        let req = http::Request::get("/a").header("If-None-Match", etag.clone());
+        assert_eq!(c.send(req.body("").unwrap()).await.status(), http::StatusCode::NOT_MODIFIED);
+
+        let req = http::Request::get("/a").header("If-Modified-Since", "Fri, 01 Jan 2100 00:00:00 GMT");
+        assert_eq!(c.send(req.body("").unwrap()).await.status(), http::StatusCode::OK);
+
+        let body = c.request("PROPFIND", "/a", "").await.into_body();
+        let body = String::from_utf8_lossy(&body);
+        let etag = etag.to_str().unwrap().trim_matches('"');
+        assert!(body.contains(&format!("<D:getetag>{}</D:getetag>", etag)));
+        assert!(!body.contains("getlastmodified") && !body.contains("creationdate"));
+
+        // a new version has a new ETag.
+        c.request("PUT", "/a", "world").await;
+        assert_ne!(c.request("GET", "/a", "").await.headers()["etag"], etag);
+    }
+}
@@ -0,0 +1,39 @@
+//
+// Hooks that are called around every request, for metrics.
+//
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use http::{Method, StatusCode};
+
+/// Request/response hooks, for example to collect metrics.
+///
+/// Set with [`DavConfig::observer`](struct.DavConfig.html#method.observer).
+/// Both methods have a default implementation that does nothing.
+///
+/// `on_response` is also called if the request failed, so every
+/// `on_request` call is matched by an `on_response` call.
+pub trait DavObserver: Send + Sync {
+    /// Called when a request comes in, before anything else is done.
+    ///
+    /// `path` is the path of the request URL, still URL-encoded.
+    #[allow(unused_variables)]
+    fn on_request(&self, method: &Method, path: &str) {}
+
+    /// Called instead of `on_request` if the address of the client is
+    /// known, see [`DavConfig::remote_addr`](struct.DavConfig.html#method.remote_addr).
+    ///
+    /// The default implementation calls `on_request`.
+    #[allow(unused_variables)]
+    fn on_request_from(&self, method: &Method, path: &str, remote_addr: SocketAddr) {
+        self.on_request(method, path)
+    }
+
+    /// Called when the response is ready to be sent.
+    ///
+    /// `duration` is the time since `on_request`. `bytes` is the length
+    /// of the response body, if it is known in advance (it is not for
+    /// PROPFIND responses and compressed responses, for example).
+    #[allow(unused_variables)]
+    fn on_response(&self, method: &Method, status: StatusCode, duration: Duration, bytes: Option<u64>) {}
+}
@@ -0,0 +1,782 @@
+//! Overlay filesystem, a writable layer on top of a read-only one.
+//!
+//! This works like OverlayFS on Linux. Lookups try the `upper` layer
+//! first, and fall back to the `lower` layer. The lower layer is never
+//! written to: files that are modified are first copied to the upper
+//! layer ("copy-up"), including the directories they live in.
+//!
+//! Deleting a file or directory that exists in the lower layer creates a
+//! whiteout marker `.wh.<name>` in the upper layer, which hides the entry
+//! in the lower layer. A directory that is re-created after it was deleted
+//! gets a `.wh..wh..opq` marker, so that the old contents in the lower
+//! layer stay hidden. These markers never show up in directory listings.
+//!
+//! Example:
+//!
+//! ```no_run
+//! use webdav_handler::{localfs::LocalFs, overlayfs::OverlayFs, DavHandler};
+//!
+//! let lower = LocalFs::new("/srv/shared", false, false, false);
+//! let upper = LocalFs::new("/srv/users/alice", false, false, false);
+//! let dav_server = DavHandler::builder()
+//!     .filesystem(OverlayFs::new(upper, lower))
+//!     .build_handler();
+//! ```
+//!
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::SystemTime;
+
+use futures::{future::FutureExt, stream, StreamExt};
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+const WHITEOUT_PREFIX: &[u8] = b".wh.";
+const OPAQUE: &[u8] = b".wh..wh..opq";
+
+/// Overlay filesystem.
+#[derive(Clone)]
+pub struct OverlayFs {
+    upper: Box<dyn DavFileSystem>,
+    lower: Box<dyn DavFileSystem>,
+}
+
+impl OverlayFs {
+    /// Create a new overlay. All changes go to `upper`, `lower` is only read.
+    pub fn new(upper: Box<dyn DavFileSystem>, lower: Box<dyn DavFileSystem>) -> Box<OverlayFs> {
+        Box::new(OverlayFs { upper, lower })
+    }
+
+    // Does the entry exist in the upper layer.
+    async fn upper_meta(&self, path: &DavPath) -> FsResult<Option<Box<dyn DavMetaData>>> {
+        match self.upper.metadata(path).await {
+            Ok(meta) => Ok(Some(meta)),
+            Err(FsError::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn upper_exists(&self, path: &DavPath) -> FsResult<bool> {
+        Ok(self.upper_meta(path).await?.is_some())
+    }
+
+    // Is the lower layer visible at this path. It is not if the path, or
+    // one of its parents, has been deleted (whiteout) or replaced in
+    // the upper layer.
+    async fn lower_visible(&self, path: &DavPath) -> FsResult<bool> {
+        let ancestors = ancestors(path);
+        for (idx, p) in ancestors.iter().enumerate().skip(1) {
+            if self.upper_exists(&whiteout_path(p)).await? {
+                return Ok(false);
+            }
+            if idx == ancestors.len() - 1 {
+                break;
+            }
+            if let Some(meta) = self.upper_meta(p).await? {
+                if !meta.is_dir() || self.upper_exists(&opaque_path(p)).await? {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    async fn lower_meta(&self, path: &DavPath) -> FsResult<Option<Box<dyn DavMetaData>>> {
+        if !self.lower_visible(path).await? {
+            return Ok(None);
+        }
+        match self.lower.metadata(path).await {
+            Ok(meta) => Ok(Some(meta)),
+            Err(FsError::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Make sure all parent directories of `path` exist in the upper layer.
+    async fn copy_up_parents(&self, path: &DavPath) -> FsResult<()> {
+        let ancestors = ancestors(path);
+        for p in ancestors.iter().take(ancestors.len() - 1).skip(1) {
+            match self.upper_meta(p).await? {
+                Some(meta) if meta.is_dir() => continue,
+                Some(_) => return Err(FsError::Forbidden),
+                None => {},
+            }
+            match self.lower_meta(p).await? {
+                Some(meta) if meta.is_dir() => self.upper.create_dir(p).await?,
+                Some(_) => return Err(FsError::Forbidden),
+                None => return Err(FsError::NotFound),
+            }
+        }
+        Ok(())
+    }
+
+    // Copy a file or directory from the lower to the upper layer, if it
+    // is not there yet. The contents of a directory are not copied.
+    async fn copy_up(&self, path: &DavPath) -> FsResult<()> {
+        if self.upper_exists(path).await? {
+            return Ok(());
+        }
+        let meta = self.lower_meta(path).await?.ok_or(FsError::NotFound)?;
+        self.copy_up_parents(path).await?;
+        if meta.is_dir() {
+            self.upper.create_dir(path).await?;
+        } else {
+            copy_file(&*self.lower, path, &*self.upper, path).await?;
+        }
+        if self.lower.have_props(path).await {
+            if let Ok(props) = self.lower.get_props(path, true).await {
+                let patch = props.into_iter().map(|p| (true, p)).collect();
+                self.upper.patch_props(path, patch).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn create_whiteout(&self, path: &DavPath) -> FsResult<()> {
+        self.copy_up_parents(path).await?;
+        let mut file = self.upper.open(&whiteout_path(path), create_options()).await?;
+        file.flush().await
+    }
+
+    // Remove a whiteout, returns true if there was one.
+    async fn remove_whiteout(&self, path: &DavPath) -> FsResult<bool> {
+        match self.upper.remove_file(&whiteout_path(path)).await {
+            Ok(()) => Ok(true),
+            Err(FsError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // A directory in the upper layer that hides the lower layer.
+    async fn make_opaque(&self, path: &DavPath) -> FsResult<()> {
+        let mut file = self.upper.open(&opaque_path(path), create_options()).await?;
+        file.flush().await
+    }
+
+    // Entries of a directory in the upper layer, plus the names of
+    // whiteouts and whether the directory is opaque.
+    async fn read_upper_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<(Vec<Box<dyn DavDirEntry>>, HashSet<Vec<u8>>, bool)>
+    {
+        let mut entries = Vec::new();
+        let mut whiteouts = HashSet::new();
+        let mut opaque = false;
+        let mut strm = self.upper.read_dir(path, meta).await?;
+        while let Some(entry) = strm.next().await {
+            let name = entry.name();
+            if name == OPAQUE {
+                opaque = true;
+            } else if name.starts_with(WHITEOUT_PREFIX) {
+                whiteouts.insert(name[WHITEOUT_PREFIX.len()..].to_vec());
+            } else {
+                entries.push(entry);
+            }
+        }
+        Ok((entries, whiteouts, opaque))
+    }
+
+    async fn do_metadata(&self, path: &DavPath, symlink: bool) -> FsResult<Box<dyn DavMetaData>> {
+        if is_marker(path) {
+            return Err(FsError::NotFound);
+        }
+        let meta = match symlink {
+            true => self.upper.symlink_metadata(path).await,
+            false => self.upper.metadata(path).await,
+        };
+        match meta {
+            Err(FsError::NotFound) => {},
+            other => return other,
+        }
+        if !self.lower_visible(path).await? {
+            return Err(FsError::NotFound);
+        }
+        match symlink {
+            true => self.lower.symlink_metadata(path).await,
+            false => self.lower.metadata(path).await,
+        }
+    }
+
+    async fn do_read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<Box<dyn DavDirEntry>>>
+    {
+        let mut found = false;
+        let mut entries = Vec::new();
+        let mut names = HashSet::new();
+        let mut opaque = false;
+        if let Some(m) = self.upper_meta(path).await? {
+            if !m.is_dir() {
+                return Err(FsError::Forbidden);
+            }
+            let (e, whiteouts, o) = self.read_upper_dir(path, meta).await?;
+            names = whiteouts;
+            names.extend(e.iter().map(|e| e.name()));
+            entries = e;
+            opaque = o;
+            found = true;
+        }
+        if !opaque {
+            if let Some(m) = self.lower_meta(path).await? {
+                if m.is_dir() {
+                    let mut strm = self.lower.read_dir(path, meta).await?;
+                    while let Some(entry) = strm.next().await {
+                        if !names.contains(&entry.name()) {
+                            entries.push(entry);
+                        }
+                    }
+                    found = true;
+                } else if !found {
+                    return Err(FsError::Forbidden);
+                }
+            }
+        }
+        if !found {
+            return Err(FsError::NotFound);
+        }
+        Ok(Box::pin(stream::iter(entries)) as FsStream<Box<dyn DavDirEntry>>)
+    }
+
+    async fn do_open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        let writing =
+            options.write || options.append || options.truncate || options.create || options.create_new;
+        if is_marker(path) {
+            return Err(if writing {
+                FsError::Forbidden
+            } else {
+                FsError::NotFound
+            });
+        }
+        if self.upper_exists(path).await? {
+            return self.upper.open(path, options).await;
+        }
+        let lower = self.lower_meta(path).await?;
+        if !writing {
+            return match lower {
+                Some(_) => self.lower.open(path, options).await,
+                None => Err(FsError::NotFound),
+            };
+        }
+
+        let mut options = options;
+        match lower {
+            Some(ref meta) if meta.is_dir() => return Err(FsError::Forbidden),
+            Some(_) if options.create_new => return Err(FsError::Exists),
+            Some(_) if options.truncate => {
+                self.copy_up_parents(path).await?;
+                options.create = true;
+            },
+            Some(_) => self.copy_up(path).await?,
+            None if !options.create && !options.create_new => return Err(FsError::NotFound),
+            None => self.copy_up_parents(path).await?,
+        }
+        let file = self.upper.open(path, options).await?;
+        self.remove_whiteout(path).await?;
+        Ok(file)
+    }
+
+    async fn do_create_dir(&self, path: &DavPath) -> FsResult<()> {
+        if is_marker(path) {
+            return Err(FsError::Forbidden);
+        }
+        if self.do_metadata(path, false).await.is_ok() {
+            return Err(FsError::Exists);
+        }
+        self.copy_up_parents(path).await?;
+        self.upper.create_dir(path).await?;
+        if self.remove_whiteout(path).await? && self.lower.metadata(path).await.is_ok() {
+            self.make_opaque(path).await?;
+        }
+        Ok(())
+    }
+
+    async fn do_remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        let upper = self.upper_meta(path).await?;
+        let lower = self.lower_meta(path).await?;
+        if upper.as_ref().or(lower.as_ref()).map(|m| m.is_dir()) != Some(true) {
+            return Err(if upper.is_none() && lower.is_none() {
+                FsError::NotFound
+            } else {
+                FsError::Forbidden
+            });
+        }
+        if self
+            .do_read_dir(path, ReadDirMeta::None)
+            .await?
+            .next()
+            .await
+            .is_some()
+        {
+            return Err(FsError::Forbidden);
+        }
+        if upper.is_some() {
+            // only markers are left, remove them first.
+            let mut strm = self.upper.read_dir(path, ReadDirMeta::None).await?;
+            while let Some(entry) = strm.next().await {
+                let mut p = path.clone();
+                p.push_segment(&entry.name());
+                self.upper.remove_file(&p).await?;
+            }
+            self.upper.remove_dir(path).await?;
+        }
+        if lower.is_some() {
+            self.create_whiteout(path).await?;
+        }
+        Ok(())
+    }
+
+    async fn do_remove_file(&self, path: &DavPath) -> FsResult<()> {
+        if is_marker(path) {
+            return Err(FsError::NotFound);
+        }
+        let upper = self.upper_meta(path).await?;
+        let lower = self.lower_meta(path).await?;
+        if upper.as_ref().or(lower.as_ref()).map(|m| m.is_dir()) == Some(true) {
+            return Err(FsError::Forbidden);
+        }
+        if upper.is_none() && lower.is_none() {
+            return Err(FsError::NotFound);
+        }
+        if upper.is_some() {
+            self.upper.remove_file(path).await?;
+        }
+        if lower.is_some() {
+            self.create_whiteout(path).await?;
+        }
+        Ok(())
+    }
+
+    async fn do_rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        if is_marker(from) || is_marker(to) {
+            return Err(FsError::Forbidden);
+        }
+        let lower = self.lower_meta(from).await?;
+        if let Some(ref meta) = lower {
+            // a directory in the lower layer can only be moved by
+            // copying its contents, like EXDEV in a real overlay.
+            if meta.is_dir() {
+                return Err(FsError::IsRemote);
+            }
+            self.copy_up(from).await?;
+        } else if !self.upper_exists(from).await? {
+            return Err(FsError::NotFound);
+        }
+        self.copy_up_parents(to).await?;
+        self.upper.rename(from, to).await?;
+        self.remove_whiteout(to).await?;
+        // a directory that replaces one in the lower layer must hide it.
+        let is_dir = self.upper_meta(to).await?.map(|m| m.is_dir()) == Some(true);
+        if is_dir && self.lower.metadata(to).await.is_ok() {
+            self.make_opaque(to).await?;
+        }
+        if lower.is_some() {
+            self.create_whiteout(from).await?;
+        }
+        Ok(())
+    }
+
+    async fn do_copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        if is_marker(from) || is_marker(to) {
+            return Err(FsError::Forbidden);
+        }
+        self.copy_up_parents(to).await?;
+        if self.upper_exists(from).await? {
+            match self.upper.copy(from, to).await {
+                Err(FsError::NotImplemented) => copy_file(&*self.upper, from, &*self.upper, to).await?,
+                other => other?,
+            }
+        } else {
+            if self.lower_meta(from).await?.is_none() {
+                return Err(FsError::NotFound);
+            }
+            copy_file(&*self.lower, from, &*self.upper, to).await?;
+        }
+        self.remove_whiteout(to).await?;
+        Ok(())
+    }
+
+    async fn do_exists(&self, path: &DavPath) -> FsResult<bool> {
+        if is_marker(path) {
+            return Ok(false);
+        }
+        match self.upper_meta(path).await? {
+            Some(meta) => Ok(!path.is_collection() || meta.is_dir()),
+            None if self.lower_visible(path).await? => self.lower.exists(path).await,
+            None => Ok(false),
+        }
+    }
+
+    async fn do_remove_dir_all(&self, path: &DavPath) -> FsResult<()> {
+        if is_marker(path) {
+            return Err(FsError::NotFound);
+        }
+        let upper = self.upper_meta(path).await?;
+        let lower = self.lower_meta(path).await?;
+        match upper.as_ref().or(lower.as_ref()) {
+            Some(meta) if meta.is_dir() => {},
+            Some(_) => return Err(FsError::Forbidden),
+            None => return Err(FsError::NotFound),
+        }
+        if upper.is_some() {
+            self.upper.remove_dir_all(path).await?;
+        }
+        // one whiteout hides everything below it in the lower layer.
+        if lower.is_some() {
+            self.create_whiteout(path).await?;
+        }
+        Ok(())
+    }
+
+    async fn do_bind(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        if is_marker(from) || is_marker(to) {
+            return Err(FsError::Forbidden);
+        }
+        self.copy_up(from).await?;
+        self.copy_up_parents(to).await?;
+        self.upper.bind(from, to).await?;
+        self.remove_whiteout(to).await?;
+        Ok(())
+    }
+
+    // The only layer that has this directory. Collection ETags and sync
+    // tokens are per layer, so they can't describe a merged directory.
+    async fn single_layer(&self, path: &DavPath) -> FsResult<&dyn DavFileSystem> {
+        let upper = self.upper_meta(path).await?.is_some();
+        let opaque = upper && self.upper_exists(&opaque_path(path)).await?;
+        let lower = !opaque && self.lower_meta(path).await?.is_some();
+        match (upper, lower) {
+            (true, false) => Ok(&*self.upper),
+            (false, true) => Ok(&*self.lower),
+            (false, false) => Err(FsError::NotFound),
+            (true, true) => Err(FsError::NotImplemented),
+        }
+    }
+
+    // The layer that has this path.
+    async fn layer(&self, path: &DavPath) -> FsResult<&dyn DavFileSystem> {
+        if self.upper_exists(path).await? {
+            Ok(&*self.upper)
+        } else if self.lower_meta(path).await?.is_some() {
+            Ok(&*self.lower)
+        } else {
+            Err(FsError::NotFound)
+        }
+    }
+}
+
+impl DavFileSystem for OverlayFs {
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.do_metadata(path, false).boxed()
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.do_metadata(path, true).boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+    {
+        self.do_read_dir(path, meta).boxed()
+    }
+
+    fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+        self.do_open(path, options).boxed()
+    }
+
+    fn exists<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        self.do_exists(path).boxed()
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.do_create_dir(path).boxed()
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.do_remove_dir(path).boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.do_remove_file(path).boxed()
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.do_remove_dir_all(path).boxed()
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.do_rename(from, to).boxed()
+    }
+
+    fn temp_path(&self, path: &DavPath) -> Option<DavPath> {
+        self.upper.temp_path(path)
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.do_copy(from, to).boxed()
+    }
+
+    fn bind<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.do_bind(from, to).boxed()
+    }
+
+    fn supports_bind(&self) -> bool {
+        self.upper.supports_bind()
+    }
+
+    fn capabilities(&self) -> FsCapabilities {
+        self.upper.capabilities()
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        async move {
+            self.copy_up(path).await?;
+            self.upper.set_accessed(path, tm).await
+        }
+        .boxed()
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        async move {
+            self.copy_up(path).await?;
+            self.upper.set_modified(path, tm).await
+        }
+        .boxed()
+    }
+
+    fn have_props<'a>(&'a self, path: &'a DavPath) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        async move {
+            match self.layer(path).await {
+                Ok(layer) => layer.have_props(path).await,
+                Err(_) => false,
+            }
+        }
+        .boxed()
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(http::StatusCode, DavProp)>>
+    {
+        async move {
+            self.copy_up(path).await?;
+            self.upper.patch_props(path, patch).await
+        }
+        .boxed()
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        async move { self.layer(path).await?.get_props(path, do_content).await }.boxed()
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        async move { self.layer(path).await?.get_prop(path, prop).await }.boxed()
+    }
+
+    fn get_named_props<'a>(&'a self, path: &'a DavPath, props: Vec<DavProp>) -> FsFuture<'a, Vec<DavProp>> {
+        async move { self.layer(path).await?.get_named_props(path, props).await }.boxed()
+    }
+
+    fn quota<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        async move { self.upper.quota(&ancestors(path)[0]).await }.boxed()
+    }
+
+    fn privileges<'a>(&'a self, path: &'a DavPath, principal: Option<&'a str>) -> FsFuture<'a, PrivilegeSet> {
+        async move {
+            if self.upper_exists(path).await? {
+                return self.upper.privileges(path, principal).await;
+            }
+            // what is in the lower layer can be written after a copy-up.
+            let mut privs = self.layer(path).await?.privileges(path, principal).await?;
+            privs.write = self.upper.privileges(&ancestors(path)[0], principal).await?.write;
+            Ok(privs)
+        }
+        .boxed()
+    }
+
+    fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        async move { self.layer(path).await?.content_type(path).await }.boxed()
+    }
+
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        async move { self.single_layer(path).await?.collection_etag(path).await }.boxed()
+    }
+
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        async move { self.single_layer(path).await?.sync_token(path).await }.boxed()
+    }
+
+    fn changes_since<'a>(
+        &'a self,
+        path: &'a DavPath,
+        token: &'a str,
+        deep: bool,
+    ) -> FsFuture<'a, Vec<Change>> {
+        async move {
+            // a token from the other layer is refused, and the client starts over.
+            let changes = self.single_layer(path).await?.changes_since(path, token, deep).await?;
+            let visible = |c: &Change| {
+                match c {
+                    Change::Modified(p) | Change::Removed(p) => !is_marker(p),
+                }
+            };
+            Ok(changes.into_iter().filter(visible).collect())
+        }
+        .boxed()
+    }
+}
+
+impl std::fmt::Debug for OverlayFs {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("OverlayFs").finish()
+    }
+}
+
+// The path and all its parents, starting at the root.
+fn ancestors(path: &DavPath) -> Vec<DavPath> {
+    let mut v = vec![path.clone()];
+    let mut p = path.clone();
+    while p.as_bytes() != b"/" && !p.as_bytes().is_empty() {
+        p = p.parent();
+        v.push(p.clone());
+    }
+    v.reverse();
+    v
+}
+
+fn whiteout_path(path: &DavPath) -> DavPath {
+    let mut p = path.parent();
+    p.push_segment(&[WHITEOUT_PREFIX, path.file_name()].concat());
+    p
+}
+
+fn opaque_path(path: &DavPath) -> DavPath {
+    let mut p = path.clone();
+    p.push_segment(OPAQUE);
+    p
+}
+
+// Whiteouts and opaque markers are not visible.
+fn is_marker(path: &DavPath) -> bool {
+    path.file_name().starts_with(WHITEOUT_PREFIX)
+}
+
+fn create_options() -> OpenOptions {
+    let mut oo = OpenOptions::write();
+    oo.create = true;
+    oo.truncate = true;
+    oo
+}
+
+async fn copy_file(
+    from_fs: &dyn DavFileSystem,
+    from: &DavPath,
+    to_fs: &dyn DavFileSystem,
+    to: &DavPath,
+) -> FsResult<()>
+{
+    let mut src = from_fs.open(from, OpenOptions::read()).await?;
+    let mut dst = to_fs.open(to, create_options()).await?;
+    copy_data(&mut *src, &mut *dst).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use crate::memfs::MemFs;
+
+    fn path(p: &str) -> DavPath {
+        DavPath::new(p).unwrap()
+    }
+
+    async fn write(fs: &dyn DavFileSystem, p: &str, data: &'static str) {
+        let mut file = fs.open(&path(p), create_options()).await.unwrap();
+        file.write_bytes(Bytes::from(data)).await.unwrap();
+        file.flush().await.unwrap();
+    }
+
+    async fn read(fs: &dyn DavFileSystem, p: &str) -> FsResult<Bytes> {
+        let mut file = fs.open(&path(p), OpenOptions::read()).await?;
+        file.read_bytes(1000).await
+    }
+
+    async fn list(fs: &dyn DavFileSystem, p: &str) -> Vec<String> {
+        let strm = fs.read_dir(&path(p), ReadDirMeta::None).await.unwrap();
+        let mut names = strm
+            .map(|e| String::from_utf8(e.name()).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn overlay() {
+        let lower = MemFs::new();
+        let upper = MemFs::new();
+        lower.create_dir(&path("/dir/")).await.unwrap();
+        write(&*lower, "/dir/a", "lower a").await;
+        write(&*lower, "/dir/b", "lower b").await;
+        let fs = OverlayFs::new(upper.clone(), lower.clone());
+
+        // reads fall through, writes are copied up.
+        assert_eq!(read(&*fs, "/dir/a").await.unwrap(), "lower a");
+        write(&*fs, "/dir/a", "upper a").await;
+        write(&*fs, "/dir/c", "upper c").await;
+        assert_eq!(read(&*fs, "/dir/a").await.unwrap(), "upper a");
+        assert_eq!(read(&*lower, "/dir/a").await.unwrap(), "lower a");
+        assert_eq!(list(&*fs, "/dir/").await, vec!["a", "b", "c"]);
+
+        // deleting a lower file leaves a whiteout.
+        fs.remove_file(&path("/dir/b")).await.unwrap();
+        assert_eq!(read(&*fs, "/dir/b").await.unwrap_err(), FsError::NotFound);
+        assert!(read(&*lower, "/dir/b").await.is_ok());
+        assert_eq!(list(&*fs, "/dir/").await, vec!["a", "c"]);
+        write(&*fs, "/dir/b", "new b").await;
+        assert_eq!(list(&*fs, "/dir/").await, vec!["a", "b", "c"]);
+
+        // a re-created directory does not show the old contents.
+        for name in &["/dir/a", "/dir/b", "/dir/c"] {
+            fs.remove_file(&path(name)).await.unwrap();
+        }
+        fs.remove_dir(&path("/dir/")).await.unwrap();
+        assert_eq!(fs.metadata(&path("/dir/")).await.unwrap_err(), FsError::NotFound);
+        fs.create_dir(&path("/dir/")).await.unwrap();
+        assert!(list(&*fs, "/dir/").await.is_empty());
+        assert_eq!(list(&*lower, "/dir/").await, vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn forwarded() {
+        let dir = crate::testing::tempdir();
+        let lower = MemFs::new();
+        let upper = crate::localfs::LocalFs::new(&dir, false, false, false);
+        lower.create_dir(&path("/dir/")).await.unwrap();
+        write(&*lower, "/dir/a", "lower a").await;
+        let fs = OverlayFs::new(upper.clone(), lower.clone());
+
+        assert!(fs.exists(&path("/dir/a")).await.unwrap());
+        assert!(!fs.exists(&path("/dir/a/")).await.unwrap());
+        assert!(!fs.exists(&path("/.wh.dir")).await.unwrap());
+        assert!(fs.temp_path(&path("/dir/a")).is_some());
+
+        // removing a tree that is in both layers.
+        write(&*fs, "/dir/b", "upper b").await;
+        fs.remove_dir_all(&path("/dir/")).await.unwrap();
+        assert!(!fs.exists(&path("/dir/")).await.unwrap());
+        assert!(!fs.exists(&path("/dir/a")).await.unwrap());
+        assert!(list(&*fs, "/").await.is_empty());
+        assert_eq!(list(&*lower, "/dir/").await, vec!["a"]);
+    }
+}
@@ -0,0 +1,272 @@
+//! Adapters to use the standard `http` types with Poem.
+//!
+//! Poem 3 is built on `http` 1.x, while this crate uses `http` 0.2.
+//! The adapters in this module take care of converting between the two.
+//!
+//! The simplest way to serve webdav from poem is the `DavEndpoint`:
+//!
+//! ```no_run
+//! use poem::Route;
+//! use webdav_handler::{localfs::LocalFs, poem::DavEndpoint, DavHandler};
+//!
+//! # fn route() -> Route {
+//! let davhandler = DavHandler::builder()
+//!     .filesystem(LocalFs::new("/tmp", false, false, false))
+//!     .build_handler();
+//! Route::new().at("/dav/*path", DavEndpoint::new(davhandler))
+//! # }
+//! ```
+//!
+//! Or, when you want to do something with the request first, a
+//! handler using `DavRequest` and `DavResponse`:
+//!
+//! ```no_run
+//! use poem::{handler, web::Data};
+//! use webdav_handler::{DavConfig, DavHandler, poem::DavRequest, poem::DavResponse};
+//!
+//! #[handler]
+//! async fn dav_handler(req: DavRequest, davhandler: Data<&DavHandler>) -> DavResponse {
+//!     if let Some(prefix) = req.prefix() {
+//!         let config = DavConfig::new().strip_prefix(prefix);
+//!         davhandler.handle_with(config, req.request).await.into()
+//!     } else {
+//!         davhandler.handle(req.request).await.into()
+//!     }
+//! }
+//! ```
+//!
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::Stream;
+use poem::{Endpoint, FromRequest, IntoResponse, PathPattern, Request, RequestBody, Response};
+
+use crate::util::route_prefix;
+use crate::{localfs::LocalFs, DavConfig, DavHandler, DavMethodSet};
+
+/// http::Request compatibility.
+///
+/// Wraps `http::Request<DavBody>` and implements `poem::FromRequest`.
+pub struct DavRequest {
+    pub request: http::Request<DavBody>,
+    prefix:      Option<String>,
+}
+
+impl DavRequest {
+    /// Returns the request path minus the tail.
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+
+    fn new(req: &Request, body: poem::Body) -> DavRequest {
+        // If we are nested, the router has stripped the uri. We want the original.
+        let uri = req.original_uri().to_string();
+        let full_path = uri.split('?').next().unwrap_or("/").to_string();
+        let matched = req.data::<PathPattern>().map(|p| &*p.0);
+        let prefix = route_prefix(&full_path, req.uri().path(), matched);
+
+        let mut builder = http::Request::builder()
+            .method(http::Method::from_bytes(req.method().as_str().as_bytes()).unwrap())
+            .uri(uri)
+            .version(convert_version(req.version()));
+        for (name, value) in req.headers().iter() {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+        if let Some(addr) = req.remote_addr().as_socket_addr() {
+            builder = builder.extension(*addr);
+        }
+
+        let body = DavBody {
+            body: Box::pin(body.into_bytes_stream()),
+        };
+        DavRequest {
+            request: builder.body(body).unwrap(),
+            prefix,
+        }
+    }
+}
+
+impl<'a> FromRequest<'a> for DavRequest {
+    async fn from_request(req: &'a Request, body: &mut RequestBody) -> poem::Result<DavRequest> {
+        let body = body.take().unwrap_or_else(|_| poem::Body::empty());
+        Ok(DavRequest::new(req, body))
+    }
+}
+
+fn convert_version(version: poem::http::Version) -> http::Version {
+    match version {
+        poem::http::Version::HTTP_09 => http::Version::HTTP_09,
+        poem::http::Version::HTTP_10 => http::Version::HTTP_10,
+        poem::http::Version::HTTP_2 => http::Version::HTTP_2,
+        poem::http::Version::HTTP_3 => http::Version::HTTP_3,
+        _ => http::Version::HTTP_11,
+    }
+}
+
+/// Body type for `DavRequest`.
+///
+/// It wraps poem's `Body` and implements `http_body::Body`.
+pub struct DavBody {
+    body: Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>,
+}
+
+impl http_body::Body for DavBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>>
+    {
+        self.body.as_mut().poll_next(cx)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>>
+    {
+        Poll::Ready(Ok(None))
+    }
+}
+
+/// `http::Response` compatibility.
+///
+/// Wraps `http::Response<dav_handler::body::Body>` and implements poem::IntoResponse.
+pub struct DavResponse(pub http::Response<crate::body::Body>);
+
+impl From<http::Response<crate::body::Body>> for DavResponse {
+    fn from(resp: http::Response<crate::body::Body>) -> DavResponse {
+        DavResponse(resp)
+    }
+}
+
+impl IntoResponse for DavResponse {
+    fn into_response(self) -> Response {
+        use crate::body::BodyType;
+
+        let (parts, body) = self.0.into_parts();
+        let body = match body.inner {
+            BodyType::Bytes(None) | BodyType::Empty => poem::Body::empty(),
+            BodyType::Bytes(Some(b)) => poem::Body::from_bytes(b),
+            b @ BodyType::AsyncStream(..) => poem::Body::from_bytes_stream(crate::body::Body { inner: b }),
+        };
+        let mut resp = Response::builder()
+            .status(poem::http::StatusCode::from_u16(parts.status.as_u16()).unwrap())
+            .body(body);
+        let headers = resp.headers_mut();
+        for (name, value) in parts.headers.iter() {
+            let name = poem::http::HeaderName::from_bytes(name.as_str().as_bytes()).unwrap();
+            let value = poem::http::HeaderValue::from_bytes(value.as_bytes()).unwrap();
+            headers.append(name, value);
+        }
+        resp
+    }
+}
+
+/// A poem `Endpoint` that serves a `DavHandler`.
+///
+/// If the route has a wildcard, like `/dav/*path`, or the endpoint is
+/// nested, the matched part of the path is stripped as the prefix.
+#[derive(Clone)]
+pub struct DavEndpoint {
+    handler: DavHandler,
+}
+
+impl DavEndpoint {
+    /// Create an endpoint for `handler`.
+    pub fn new(handler: DavHandler) -> DavEndpoint {
+        DavEndpoint { handler }
+    }
+}
+
+impl Endpoint for DavEndpoint {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> poem::Result<Response> {
+        let body = req.take_body();
+        let req = DavRequest::new(&req, body);
+        let resp = match req.prefix() {
+            Some(prefix) => {
+                let config = DavConfig::new().strip_prefix(prefix);
+                self.handler.handle_with(config, req.request).await
+            },
+            None => self.handler.handle(req.request).await,
+        };
+        Ok(DavResponse(resp).into_response())
+    }
+}
+
+/// Creates an endpoint that serves a single file, ignoring the request path,
+/// like `warp::dav_file`.
+///
+/// Only `GET`, `HEAD` and `OPTIONS` are allowed.
+///
+/// ```no_run
+/// use poem::Route;
+///
+/// # fn route() -> Route {
+/// Route::new().at("/hosts", webdav_handler::poem::dav_file("/etc/hosts"))
+/// # }
+/// ```
+pub fn dav_file(file: impl AsRef<Path>) -> impl Endpoint<Output = Response> {
+    let handler = DavHandler::builder()
+        .filesystem(LocalFs::new_file(file, false))
+        .methods(DavMethodSet::HTTP_RO)
+        .build_handler();
+    poem::endpoint::make(move |mut req: Request| {
+        let handler = handler.clone();
+        async move {
+            let body = req.take_body();
+            let req = DavRequest::new(&req, body);
+            DavResponse::from(handler.handle(req.request).await).into_response()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::listener::TcpAcceptor;
+    use poem::{Route, Server};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::DavEndpoint;
+    use crate::{memfs::MemFs, DavHandler};
+
+    // send a request over a real connection, so that poem sets the original uri.
+    async fn request(addr: std::net::SocketAddr, req: &str) -> String {
+        let mut conn = tokio::net::TcpStream::connect(addr).await.unwrap();
+        conn.write_all(req.as_bytes()).await.unwrap();
+        let mut resp = String::new();
+        conn.read_to_string(&mut resp).await.unwrap();
+        resp
+    }
+
+    #[tokio::test]
+    async fn prefix() {
+        let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+        let app = Route::new()
+            .nest("/nested", DavEndpoint::new(dav.clone()))
+            .at("/dav/*path", DavEndpoint::new(dav));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = TcpAcceptor::from_tokio(listener).unwrap();
+        tokio::spawn(Server::new_with_acceptor(acceptor).run(app));
+
+        let put = "PUT /nested/file HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello";
+        assert!(request(addr, put).await.starts_with("HTTP/1.1 201"));
+
+        for path in ["/nested/", "/dav/"] {
+            let propfind = format!(
+                "PROPFIND {} HTTP/1.1\r\nHost: x\r\nDepth: 1\r\nConnection: close\r\n\r\n",
+                path
+            );
+            let resp = request(addr, &propfind).await;
+            assert!(resp.contains(&format!("<D:href>{}file</D:href>", path)), "{}", resp);
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//
+// Properties that do not live in the filesystem.
+//
+use futures::future;
+
+use crate::davpath::DavPath;
+use crate::fs::{DavProp, FsFuture};
+
+/// Provides dead properties that are not stored by the filesystem.
+///
+/// Set with [`DavConfig::property_provider`](struct.DavConfig.html#method.property_provider).
+/// For the namespaces it [`claims`](PropertyProvider::claims), the provider is
+/// used instead of the dead property store of the filesystem, both by
+/// `PROPFIND` and by `PROPPATCH`. Properties in other namespaces are
+/// handled by the filesystem as before.
+///
+/// The value of a property is its raw XML, including the property element
+/// itself, just like in [`DavProp::xml`].
+pub trait PropertyProvider: Send + Sync {
+    /// Does this provider handle the properties in `namespace`?
+    fn claims(&self, namespace: &str) -> bool;
+
+    /// Get the value of a property. `Ok(None)` if it is not set.
+    fn get<'a>(&'a self, path: &'a DavPath, prop: &'a DavProp) -> FsFuture<'a, Option<Vec<u8>>>;
+
+    /// Set a property. If `prop.xml` is `None`, remove it.
+    fn set<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, ()>;
+
+    /// All properties of `path`, for `allprop` and `propname` requests.
+    ///
+    /// The default implementation returns nothing.
+    #[allow(unused_variables)]
+    fn list<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Vec<DavProp>> {
+        Box::pin(future::ok(Vec::new()))
+    }
+}
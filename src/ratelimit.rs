@@ -0,0 +1,103 @@
+//
+// A token bucket rate limiter, with a bucket per client.
+//
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+
+// Remember this many clients. When a new one comes along, the one that
+// was seen least recently is forgotten, and starts with a full bucket.
+const MAX_BUCKETS: usize = 10000;
+
+// Never tell a client to wait longer than this.
+const MAX_WAIT: Duration = Duration::from_secs(3600);
+
+pub(crate) struct RateLimiter {
+    rate:    f64,
+    burst:   f64,
+    buckets: Mutex<LruCache<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens:  f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    // A rate that is not a positive number means the bucket never fills up.
+    pub(crate) fn new(requests_per_sec: f64, burst: u32) -> RateLimiter {
+        let rate = if requests_per_sec.is_finite() && requests_per_sec > 0.0 {
+            requests_per_sec
+        } else {
+            0.0
+        };
+        RateLimiter {
+            rate,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(LruCache::new(NonZeroUsize::new(MAX_BUCKETS).unwrap())),
+        }
+    }
+
+    // Take a token from the bucket of `key`. If it is empty, returns
+    // how long it takes before there is a token again.
+    pub(crate) fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let bucket = buckets.get_or_insert_mut(key.to_string(), || {
+            Bucket {
+                tokens:  self.burst,
+                updated: now,
+            }
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + self.rate * elapsed).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait = Duration::try_from_secs_f64((1.0 - bucket.tokens) / self.rate).unwrap_or(MAX_WAIT);
+        Err(wait.min(MAX_WAIT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket() {
+        let rl = RateLimiter::new(0.5, 2);
+        assert!(rl.check("a").is_ok());
+        assert!(rl.check("a").is_ok());
+        let wait = rl.check("a").unwrap_err();
+        assert!(wait > Duration::from_millis(1900) && wait <= Duration::from_secs(2));
+        assert!(rl.check("b").is_ok());
+    }
+
+    #[test]
+    fn odd_rates() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e-300] {
+            let rl = RateLimiter::new(rate, 1);
+            assert!(rl.check("a").is_ok());
+            assert!(rl.check("a").unwrap_err() <= MAX_WAIT);
+        }
+    }
+
+    #[test]
+    fn forget_oldest() {
+        let rl = RateLimiter::new(0.0, 1);
+        assert!(rl.check("first").is_ok());
+        for i in 0..MAX_BUCKETS {
+            assert!(rl.check(&i.to_string()).is_ok());
+        }
+        assert_eq!(rl.buckets.lock().unwrap().len(), MAX_BUCKETS);
+        // "first" was forgotten, so it gets a full bucket again.
+        assert!(rl.check("first").is_ok());
+        assert!(rl.check("first").is_err());
+    }
+}
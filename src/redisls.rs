@@ -0,0 +1,649 @@
+//! Locksystem that keeps its state in Redis.
+//!
+//! `MemLs` keeps its locks in the memory of the process, so if you
+//! run several instances of a server behind a load balancer, the
+//! instances do not know about each other's locks. `RedisLs` stores the
+//! locks in a Redis server that is shared by all instances.
+//!
+//! Every lock is stored as a hash at `<prefix>lock:<token>`, with a TTL
+//! that is the same as the timeout of the lock, so that stale locks go
+//! away by themselves. The tokens of the locks at a path are kept in
+//! a set at `<prefix>path:<path>`, and a sorted set at `<prefix>paths`
+//! contains all paths that have locks on them. Entries that refer to
+//! expired locks are cleaned up when they are found.
+//!
+//! The `DavLockSystem` methods are synchronous, so this locksystem does
+//! blocking I/O. If the Redis server can not be reached, locking and
+//! checking fail (so you get `423 Locked`), and lock discovery
+//! returns nothing.
+//!
+//! Example:
+//!
+//! ```no_run
+//! use webdav_handler::{redisls::RedisLs, DavHandler};
+//!
+//! let dav_server = DavHandler::builder()
+//!     .locksystem(RedisLs::new("redis://127.0.0.1/", Some("webdav:")).unwrap())
+//!     .build_handler();
+//! ```
+//!
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use redis::{Connection, RedisResult};
+use uuid::Uuid;
+use xmltree::Element;
+
+use crate::davpath::DavPath;
+use crate::ls::*;
+
+// How long the mutex that serializes `lock()` calls may be held,
+// and how long we wait for it.
+const MUTEX_TTL_MS: u64 = 5000;
+const MUTEX_WAIT: Duration = Duration::from_secs(5);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Release the mutex, but only if we still hold it.
+const UNLOCK_SCRIPT: &str = r#"
+if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("del", KEYS[1])
+else
+    return 0
+end
+"#;
+
+/// Redis-backed locksystem.
+#[derive(Debug, Clone)]
+pub struct RedisLs(Arc<RedisLsInner>);
+
+struct RedisLsInner {
+    client: redis::Client,
+    prefix: String,
+    conn:   Mutex<Option<Connection>>,
+}
+
+impl fmt::Debug for RedisLsInner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RedisLsInner")
+            .field("client", &self.client)
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl RedisLs {
+    /// Create a new "redisls" locksystem.
+    ///
+    /// `url` is a Redis connection string like `redis://127.0.0.1/`. All
+    /// keys are prefixed with `prefix`, if set. The connection is made
+    /// when the locksystem is first used.
+    pub fn new(url: &str, prefix: Option<&str>) -> RedisResult<Box<RedisLs>> {
+        let inner = RedisLsInner {
+            client: redis::Client::open(url)?,
+            prefix: prefix.unwrap_or("").to_string(),
+            conn:   Mutex::new(None),
+        };
+        Ok(Box::new(RedisLs(Arc::new(inner))))
+    }
+
+    fn key(&self, kind: &str, name: &[u8]) -> Vec<u8> {
+        let mut key = self.0.prefix.as_bytes().to_vec();
+        key.extend_from_slice(kind.as_bytes());
+        key.extend_from_slice(name);
+        key
+    }
+
+    // Run `f` with a connection. If the connection turns out to be
+    // broken, reconnect and try once more.
+    fn with_conn<T>(&self, mut f: impl FnMut(&mut Connection) -> RedisResult<T>) -> RedisResult<T> {
+        let mut conn = self.0.conn.lock();
+        let mut retry = true;
+        loop {
+            if conn.is_none() {
+                *conn = Some(self.0.client.get_connection_with_timeout(CONNECT_TIMEOUT)?);
+            }
+            match f(conn.as_mut().unwrap()) {
+                Err(e) if e.is_io_error() || e.is_connection_dropped() => {
+                    *conn = None;
+                    if !retry {
+                        return Err(e);
+                    }
+                    retry = false;
+                },
+                other => return other,
+            }
+        }
+    }
+
+    // Serialize `lock()` calls over all instances, so that checking
+    // for conflicts and creating the lock is atomic.
+    fn with_mutex<T>(
+        &self,
+        con: &mut Connection,
+        f: impl FnOnce(&mut Connection) -> RedisResult<T>,
+    ) -> RedisResult<T>
+    {
+        let key = self.key("mutex", b"");
+        let id = Uuid::new_v4().to_string();
+        let start = Instant::now();
+        loop {
+            let ok: Option<String> = redis::cmd("SET")
+                .arg(&key)
+                .arg(&id)
+                .arg("NX")
+                .arg("PX")
+                .arg(MUTEX_TTL_MS)
+                .query(con)?;
+            if ok.is_some() {
+                break;
+            }
+            if start.elapsed() > MUTEX_WAIT {
+                return Err((redis::ErrorKind::TryAgain, "timeout waiting for lock mutex").into());
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        let res = f(con);
+        let _: RedisResult<i32> = redis::Script::new(UNLOCK_SCRIPT).key(&key).arg(&id).invoke(con);
+        res
+    }
+
+    // Load a lock. Returns `None` if it does not exist (anymore).
+    fn load_lock(&self, con: &mut Connection, token: &str) -> RedisResult<Option<DavLock>> {
+        let fields: HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(self.key("lock:", token.as_bytes()))
+            .query(con)?;
+        Ok(lock_from_fields(token, &fields))
+    }
+
+    // Load all locks at exactly this path, and clean up expired ones.
+    fn locks_at(&self, con: &mut Connection, lockpath: &[u8]) -> RedisResult<Vec<DavLock>> {
+        let setkey = self.key("path:", lockpath);
+        let tokens: Vec<String> = redis::cmd("SMEMBERS").arg(&setkey).query(con)?;
+        let mut locks = Vec::new();
+        let mut expired = Vec::new();
+        for token in tokens {
+            match self.load_lock(con, &token)? {
+                Some(lock) => locks.push(lock),
+                None => expired.push(token),
+            }
+        }
+        if !expired.is_empty() {
+            trace!("redisls: cleaning up {} expired locks", expired.len());
+            redis::cmd("SREM").arg(&setkey).arg(&expired).query::<()>(con)?;
+            if locks.is_empty() {
+                self.remove_path_if_empty(con, lockpath)?;
+            }
+        }
+        Ok(locks)
+    }
+
+    fn remove_path_if_empty(&self, con: &mut Connection, lockpath: &[u8]) -> RedisResult<()> {
+        let count: u64 = redis::cmd("SCARD").arg(self.key("path:", lockpath)).query(con)?;
+        if count == 0 {
+            redis::cmd("ZREM")
+                .arg(self.key("paths", b""))
+                .arg(lockpath)
+                .query::<()>(con)?;
+        }
+        Ok(())
+    }
+
+    // All paths below this path that have locks on them.
+    fn paths_below(&self, con: &mut Connection, lockpath: &[u8]) -> RedisResult<Vec<Vec<u8>>> {
+        let mut base = lockpath.to_vec();
+        if !base.ends_with(b"/") {
+            base.push(b'/');
+        }
+        let mut min = b"(".to_vec();
+        min.extend_from_slice(&base);
+        let mut max = b"[".to_vec();
+        max.extend_from_slice(&base);
+        max.push(0xff);
+        redis::cmd("ZRANGEBYLEX")
+            .arg(self.key("paths", b""))
+            .arg(min)
+            .arg(max)
+            .query(con)
+    }
+
+    // All locks at this path and the paths above it, starting at the root.
+    // The boolean is true for the locks at the path itself.
+    fn locks_to_path(&self, con: &mut Connection, path: &DavPath) -> RedisResult<Vec<(bool, DavLock)>> {
+        let paths = ancestors(path);
+        let last = paths.len() - 1;
+        let mut locks = Vec::new();
+        for (i, lockpath) in paths.iter().enumerate() {
+            for lock in self.locks_at(con, lockpath)? {
+                locks.push((i == last, lock));
+            }
+        }
+        Ok(locks)
+    }
+
+    // All locks at this path and the paths below it.
+    fn locks_from_path(&self, con: &mut Connection, path: &DavPath) -> RedisResult<Vec<DavLock>> {
+        let lockpath = lock_path(path);
+        let mut locks = self.locks_at(con, &lockpath)?;
+        for p in self.paths_below(con, &lockpath)? {
+            locks.extend(self.locks_at(con, &p)?);
+        }
+        Ok(locks)
+    }
+
+    fn remove_lock(&self, con: &mut Connection, lock: &DavLock) -> RedisResult<()> {
+        let lockpath = lock_path(&lock.path);
+        redis::cmd("DEL")
+            .arg(self.key("lock:", lock.token.as_bytes()))
+            .query::<()>(con)?;
+        redis::cmd("SREM")
+            .arg(self.key("path:", &lockpath))
+            .arg(&lock.token)
+            .query::<()>(con)?;
+        self.remove_path_if_empty(con, &lockpath)
+    }
+
+    // Find a lock by token. It must be at this path or above it.
+    fn lookup_lock(&self, con: &mut Connection, path: &DavPath, token: &str) -> RedisResult<Option<DavLock>> {
+        let lock = match self.load_lock(con, token)? {
+            Some(lock) => lock,
+            None => return Ok(None),
+        };
+        let lockpath = lock_path(&lock.path);
+        if ancestors(path).iter().any(|p| p == &lockpath) {
+            Ok(Some(lock))
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn do_lock(
+        &self,
+        con: &mut Connection,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        shared: bool,
+        deep: bool,
+    ) -> RedisResult<Result<DavLock, DavLock>>
+    {
+        // any locks in the path?
+        let locks = self.locks_to_path(con, path)?;
+        if let Err(l) = check_locks_to_path(&locks, None, true, &Vec::new(), shared) {
+            trace!("lock: check_locks_to_path: {:?}", l);
+            return Ok(Err(l));
+        }
+
+        // if it's a deep lock we need to check if there are locks furter along the path.
+        if deep {
+            let locks = self.locks_from_path(con, path)?;
+            if let Err(l) = check_locks_from_path(&locks, None, true, &Vec::new(), shared) {
+                trace!("lock: check_locks_from_path: {:?}", l);
+                return Ok(Err(l));
+            }
+        }
+
+        // create lock.
+        let lock = DavLock {
+            token: Uuid::new_v4().urn().to_string(),
+            path: path.clone(),
+            principal: principal.map(|s| s.to_string()),
+            owner: owner.cloned(),
+            timeout_at: timeout.map(|d| SystemTime::now() + d),
+            timeout,
+            shared,
+            deep,
+        };
+        let lockkey = self.key("lock:", lock.token.as_bytes());
+        let lockpath = lock_path(path);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("HSET").arg(&lockkey).arg(lock_to_fields(&lock)).ignore();
+        if let Some(timeout) = timeout {
+            pipe.cmd("EXPIRE").arg(&lockkey).arg(ttl(timeout)).ignore();
+        }
+        pipe.cmd("SADD")
+            .arg(self.key("path:", &lockpath))
+            .arg(&lock.token)
+            .ignore();
+        pipe.cmd("ZADD")
+            .arg(self.key("paths", b""))
+            .arg(0)
+            .arg(&lockpath)
+            .ignore();
+        pipe.query::<()>(con)?;
+        trace!("lock {} created", &lock.token);
+        Ok(Ok(lock))
+    }
+}
+
+impl DavLockSystem for RedisLs {
+    fn lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        shared: bool,
+        deep: bool,
+    ) -> Result<DavLock, DavLock>
+    {
+        let res = self.with_conn(|con| {
+            self.with_mutex(con, |con| {
+                self.do_lock(con, path, principal, owner, timeout, shared, deep)
+            })
+        });
+        match res {
+            Ok(res) => res,
+            Err(e) => {
+                error!("redisls: lock {}: {}", path, e);
+                Err(unavailable(path))
+            },
+        }
+    }
+
+    fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()> {
+        let res = self.with_conn(|con| {
+            match self.lookup_lock(con, path, token)? {
+                Some(lock) => self.remove_lock(con, &lock).map(|_| true),
+                None => Ok(false),
+            }
+        });
+        match res {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                trace!("unlock: {} not found at {}", token, path);
+                Err(())
+            },
+            Err(e) => {
+                error!("redisls: unlock {}: {}", path, e);
+                Err(())
+            },
+        }
+    }
+
+    fn refresh(&self, path: &DavPath, token: &str, timeout: Option<Duration>) -> Result<DavLock, ()> {
+        trace!("refresh lock {}", token);
+        let res = self.with_conn(|con| {
+            let mut lock = match self.lookup_lock(con, path, token)? {
+                Some(lock) => lock,
+                None => return Ok(None),
+            };
+            lock.timeout = timeout;
+            lock.timeout_at = timeout.map(|d| SystemTime::now() + d);
+            let lockkey = self.key("lock:", token.as_bytes());
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            pipe.cmd("HSET").arg(&lockkey).arg(lock_to_fields(&lock)).ignore();
+            match timeout {
+                Some(timeout) => pipe.cmd("EXPIRE").arg(&lockkey).arg(ttl(timeout)).ignore(),
+                None => pipe.cmd("PERSIST").arg(&lockkey).ignore(),
+            };
+            pipe.query::<()>(con)?;
+            Ok(Some(lock))
+        });
+        match res {
+            Ok(Some(lock)) => Ok(lock),
+            Ok(None) => {
+                trace!("lock not found");
+                Err(())
+            },
+            Err(e) => {
+                error!("redisls: refresh {}: {}", path, e);
+                Err(())
+            },
+        }
+    }
+
+    fn check(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        let res = self.with_conn(|con| {
+            let locks = self.locks_to_path(con, path)?;
+            let rc = check_locks_to_path(&locks, principal, ignore_principal, &submitted_tokens, false);
+            trace!("check: check_lock_to_path: {:?}: {:?}", submitted_tokens, rc);
+            if rc.is_err() {
+                return Ok(rc);
+            }
+
+            // if it's a deep lock we need to check if there are locks furter along the path.
+            if deep {
+                let locks = self.locks_from_path(con, path)?;
+                let rc = check_locks_from_path(&locks, principal, ignore_principal, &submitted_tokens, false);
+                trace!("check: check_locks_from_path: {:?}", rc);
+                return Ok(rc);
+            }
+            Ok(Ok(()))
+        });
+        match res {
+            Ok(rc) => rc,
+            Err(e) => {
+                error!("redisls: check {}: {}", path, e);
+                Err(unavailable(path))
+            },
+        }
+    }
+
+    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
+        let res = self.with_conn(|con| {
+            let mut locks = Vec::new();
+            for lockpath in ancestors(path) {
+                locks.extend(self.locks_at(con, &lockpath)?);
+            }
+            Ok(locks)
+        });
+        res.unwrap_or_else(|e| {
+            error!("redisls: discover {}: {}", path, e);
+            Vec::new()
+        })
+    }
+
+    fn delete(&self, path: &DavPath) -> Result<(), ()> {
+        let res = self.with_conn(|con| {
+            for lock in self.locks_from_path(con, path)? {
+                self.remove_lock(con, &lock)?;
+            }
+            Ok(())
+        });
+        res.map_err(|e| error!("redisls: delete {}: {}", path, e))
+    }
+}
+
+// check if there are any locks along the path.
+fn check_locks_to_path(
+    locks: &[(bool, DavLock)],
+    principal: Option<&str>,
+    ignore_principal: bool,
+    submitted_tokens: &Vec<&str>,
+    shared_ok: bool,
+) -> Result<(), DavLock>
+{
+    // state
+    let mut holds_lock = false;
+    let mut first_lock_seen: Option<&DavLock> = None;
+
+    for (at_path, nl) in locks {
+        if !at_path && !nl.deep {
+            continue;
+        }
+        if submitted_tokens.iter().any(|t| &nl.token == t) &&
+            (ignore_principal || principal == nl.principal.as_deref())
+        {
+            // fine, we hold this lock.
+            holds_lock = true;
+        } else {
+            // exclusive locks are fatal.
+            if !nl.shared {
+                return Err(nl.to_owned());
+            }
+            // remember first shared lock seen.
+            if !shared_ok {
+                first_lock_seen.get_or_insert(nl);
+            }
+        }
+    }
+
+    // return conflicting lock on error.
+    match first_lock_seen {
+        Some(lock) if !holds_lock => Err(lock.to_owned()),
+        _ => Ok(()),
+    }
+}
+
+// See if there are locks at or below this path that we do not hold.
+fn check_locks_from_path(
+    locks: &[DavLock],
+    principal: Option<&str>,
+    ignore_principal: bool,
+    submitted_tokens: &Vec<&str>,
+    shared_ok: bool,
+) -> Result<(), DavLock>
+{
+    for nl in locks {
+        if (!nl.shared || !shared_ok) &&
+            (!submitted_tokens.iter().any(|t| t == &nl.token) ||
+                (!ignore_principal && principal != nl.principal.as_deref()))
+        {
+            return Err(nl.to_owned());
+        }
+    }
+    Ok(())
+}
+
+// The path as stored in Redis: no prefix, no trailing slash.
+fn lock_path(path: &DavPath) -> Vec<u8> {
+    ancestors(path).pop().unwrap()
+}
+
+// The root, all paths in between, and the path itself.
+fn ancestors(path: &DavPath) -> Vec<Vec<u8>> {
+    let mut paths = vec![b"/".to_vec()];
+    let mut p = Vec::new();
+    for seg in path.as_bytes().split(|&c| c == b'/').filter(|s| !s.is_empty()) {
+        p.push(b'/');
+        p.extend_from_slice(seg);
+        paths.push(p.clone());
+    }
+    paths
+}
+
+fn ttl(timeout: Duration) -> u64 {
+    std::cmp::max(timeout.as_secs(), 1)
+}
+
+fn lock_to_fields(lock: &DavLock) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("path", lock.path.with_prefix().as_url_string()),
+        ("prefix", lock.path.prefix().to_string()),
+        ("shared", (lock.shared as u8).to_string()),
+        ("deep", (lock.deep as u8).to_string()),
+    ];
+    if let Some(ref principal) = lock.principal {
+        fields.push(("principal", principal.clone()));
+    }
+    if let Some(ref owner) = lock.owner {
+        let mut buf = Vec::new();
+        if owner.write(&mut buf).is_ok() {
+            fields.push(("owner", String::from_utf8_lossy(&buf).into_owned()));
+        }
+    }
+    if let Some(timeout) = lock.timeout {
+        fields.push(("timeout", timeout.as_secs().to_string()));
+    }
+    if let Some(t) = lock.timeout_at.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        fields.push(("timeout_at", t.as_secs().to_string()));
+    }
+    fields
+}
+
+fn lock_from_fields(token: &str, fields: &HashMap<String, String>) -> Option<DavLock> {
+    let url = fields.get("path")?;
+    let path = match fields.get("prefix").map(|p| p.as_str()) {
+        None | Some("") => DavPath::new(url).ok()?,
+        Some(prefix) => DavPath::from_str_and_prefix(url, prefix).ok()?,
+    };
+    let secs = |name: &str| fields.get(name).and_then(|s| s.parse::<u64>().ok());
+    Some(DavLock {
+        token: token.to_string(),
+        path,
+        principal: fields.get("principal").cloned(),
+        owner: parse_owner(fields.get("owner")),
+        timeout_at: secs("timeout_at").map(|s| UNIX_EPOCH + Duration::from_secs(s)),
+        timeout: secs("timeout").map(Duration::from_secs),
+        shared: fields.get("shared").map(|s| s == "1").unwrap_or(false),
+        deep: fields.get("deep").map(|s| s == "1").unwrap_or(false),
+    })
+}
+
+fn parse_owner(owner: Option<&String>) -> Option<Element> {
+    Element::parse(owner?.as_bytes()).ok()
+}
+
+// returned as the "conflicting lock" if we cannot reach the server.
+fn unavailable(path: &DavPath) -> DavLock {
+    DavLock {
+        token:      String::new(),
+        path:       path.clone(),
+        principal:  None,
+        owner:      None,
+        timeout_at: None,
+        timeout:    None,
+        shared:     false,
+        deep:       false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_paths() {
+        let path = DavPath::new("/a/b/").unwrap();
+        assert_eq!(lock_path(&path), b"/a/b".to_vec());
+        assert_eq!(lock_path(&DavPath::new("/").unwrap()), b"/".to_vec());
+        assert_eq!(
+            ancestors(&path),
+            vec![b"/".to_vec(), b"/a".to_vec(), b"/a/b".to_vec()]
+        );
+    }
+
+    #[test]
+    fn lock_fields() {
+        let mut path = DavPath::new("/dav/some%20file").unwrap();
+        path.set_prefix("/dav").unwrap();
+        let lock = DavLock {
+            token:      "urn:uuid:1234".to_string(),
+            path:       path.clone(),
+            principal:  Some("user".to_string()),
+            owner:      Element::parse(&b"<D:href xmlns:D=\"DAV:\">me</D:href>"[..]).ok(),
+            timeout_at: Some(UNIX_EPOCH + Duration::from_secs(1000)),
+            timeout:    Some(Duration::from_secs(60)),
+            shared:     true,
+            deep:       false,
+        };
+        let fields = lock_to_fields(&lock)
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect::<HashMap<_, _>>();
+        let lock2 = lock_from_fields(&lock.token, &fields).unwrap();
+        assert!(lock2.path == path);
+        assert_eq!(lock2.path.prefix(), "/dav");
+        assert_eq!(lock2.principal, lock.principal);
+        assert_eq!(lock2.owner.unwrap().get_text().unwrap(), "me");
+        assert_eq!(lock2.timeout_at, lock.timeout_at);
+        assert_eq!(lock2.timeout, lock.timeout);
+        assert!(lock2.shared && !lock2.deep);
+    }
+}
@@ -0,0 +1,979 @@
+//! Filesystem backed by an S3 bucket.
+//!
+//! S3 does not have directories, just keys, so collections are mapped
+//! onto key prefixes: the collection `/a/b/` contains all keys that start
+//! with `a/b/` (and do not have another `/` after that). `MKCOL` creates a
+//! zero-byte marker object with a trailing slash, so that empty collections
+//! can exist.
+//!
+//! Files are read with ranged `GetObject` requests and written with a
+//! multipart upload, so that memory use stays bounded, regardless of the
+//! size of the file. Partial updates (`PUT` with `Content-Range`) are
+//! not supported.
+//!
+//! Example:
+//!
+//! ```no_run
+//! use webdav_handler::{s3fs::{Credentials, Region, S3Fs}, DavHandler};
+//!
+//! let region = "eu-central-1".parse::<Region>().unwrap();
+//! let credentials = Credentials::default().unwrap();
+//! let dav_server = DavHandler::builder()
+//!     .filesystem(S3Fs::new("my-bucket", region, credentials).unwrap())
+//!     .build_handler();
+//! ```
+//!
+use std::io::SeekFrom;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{future::FutureExt, stream};
+use s3::error::S3Error;
+use s3::serde_types::Part;
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+pub use s3::creds::Credentials;
+pub use s3::Bucket;
+pub use s3::Region;
+
+// Size of the parts of a multipart upload. S3 wants at least 5 MiB.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+// How much we read from S3 at once.
+const READ_AHEAD: u64 = 1024 * 1024;
+
+const CONTENT_TYPE: &str = "application/octet-stream";
+
+/// S3 filesystem.
+#[derive(Debug, Clone)]
+pub struct S3Fs {
+    inner: Arc<S3FsInner>,
+}
+
+#[derive(Debug)]
+struct S3FsInner {
+    bucket: Box<Bucket>,
+}
+
+#[derive(Debug, Clone)]
+struct S3MetaData {
+    len:      u64,
+    modified: Option<SystemTime>,
+    is_dir:   bool,
+    etag:     Option<String>,
+}
+
+#[derive(Debug)]
+struct S3DirEntry {
+    name: Vec<u8>,
+    meta: S3MetaData,
+}
+
+#[derive(Debug)]
+struct S3File {
+    fs:     S3Fs,
+    key:    String,
+    meta:   S3MetaData,
+    pos:    u64,
+    // read-ahead buffer, and at what offset in the file it starts.
+    rbuf:   Bytes,
+    rpos:   u64,
+    // write state. `None` if the file was opened for reading.
+    upload: Option<Upload>,
+}
+
+#[derive(Debug, Default)]
+struct Upload {
+    buf:       BytesMut,
+    upload_id: Option<String>,
+    parts:     Vec<Part>,
+    done:      bool,
+}
+
+impl S3Fs {
+    /// Create a new S3 filesystem, serving the bucket `bucket`.
+    pub fn new(bucket: &str, region: Region, credentials: Credentials) -> Result<Box<S3Fs>, S3Error> {
+        let bucket = Bucket::new(bucket, region, credentials)?;
+        Ok(S3Fs::from_bucket(bucket))
+    }
+
+    /// Create a new S3 filesystem from a configured `Bucket`.
+    ///
+    /// Use this if you need to change the defaults, for example to use
+    /// path-style requests for a minio server.
+    pub fn from_bucket(bucket: Box<Bucket>) -> Box<S3Fs> {
+        Box::new(S3Fs {
+            inner: Arc::new(S3FsInner { bucket }),
+        })
+    }
+
+    fn bucket(&self) -> &Bucket {
+        &self.inner.bucket
+    }
+
+    // Look up a key. Files are found directly, collections by
+    // listing the prefix.
+    async fn lookup(&self, key: &str) -> FsResult<S3MetaData> {
+        let key = key.trim_end_matches('/');
+        if key.is_empty() {
+            return Ok(S3MetaData::dir(None));
+        }
+        let (head, status) = self.bucket().head_object(key).await.map_err(s3_error)?;
+        match status {
+            200..=299 => {
+                return Ok(S3MetaData {
+                    len:      head.content_length.unwrap_or(0).max(0) as u64,
+                    modified: head.last_modified.as_deref().and_then(parse_httpdate),
+                    is_dir:   false,
+                    etag:     head.e_tag.as_deref().map(trim_etag),
+                });
+            },
+            404 => {},
+            status => return Err(status_error(status)),
+        }
+        self.lookup_dir(key).await
+    }
+
+    // Look up a collection. It exists if there is anything below it.
+    async fn lookup_dir(&self, key: &str) -> FsResult<S3MetaData> {
+        let key = key.trim_end_matches('/');
+        if key.is_empty() {
+            return Ok(S3MetaData::dir(None));
+        }
+        let prefix = format!("{}/", key);
+        let (list, status) = self
+            .bucket()
+            .list_page(prefix.clone(), Some("/".to_string()), None, None, Some(1))
+            .await
+            .map_err(s3_error)?;
+        if status >= 300 {
+            return Err(status_error(status));
+        }
+        let marker = list.contents.iter().find(|o| o.key == prefix);
+        if !list.contents.is_empty() || list.common_prefixes.map(|p| !p.is_empty()).unwrap_or(false) {
+            return Ok(S3MetaData::dir(
+                marker.and_then(|o| parse_rfc3339(&o.last_modified)),
+            ));
+        }
+        Err(FsError::NotFound)
+    }
+
+    // Check that the parent collection of a key exists.
+    async fn check_parent(&self, key: &str) -> FsResult<()> {
+        let key = key.trim_end_matches('/');
+        match key.rfind('/') {
+            Some(idx) => {
+                let meta = self.lookup(&key[..idx]).await?;
+                if meta.is_dir {
+                    Ok(())
+                } else {
+                    Err(FsError::Forbidden)
+                }
+            },
+            None => Ok(()),
+        }
+    }
+
+    // All keys below (and including) a collection.
+    async fn list_recursive(&self, key: &str) -> FsResult<Vec<String>> {
+        let prefix = format!("{}/", key.trim_end_matches('/'));
+        let results = self.bucket().list(prefix, None).await.map_err(s3_error)?;
+        Ok(results
+            .into_iter()
+            .flat_map(|r| r.contents.into_iter().map(|o| o.key))
+            .collect())
+    }
+
+    async fn delete_key(&self, key: &str) -> FsResult<()> {
+        let resp = self.bucket().delete_object(key).await.map_err(s3_error)?;
+        match resp.status_code() {
+            200..=299 | 404 => Ok(()),
+            status => Err(status_error(status)),
+        }
+    }
+
+    async fn copy_key(&self, from: &str, to: &str) -> FsResult<()> {
+        let status = self
+            .bucket()
+            .copy_object_internal(from, to)
+            .await
+            .map_err(s3_error)?;
+        match status {
+            200..=299 => Ok(()),
+            status => Err(status_error(status)),
+        }
+    }
+
+    async fn do_copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        let (from, to) = (s3_key(from)?, s3_key(to)?);
+        let meta = self.lookup(&from).await?;
+        self.check_parent(&to).await?;
+        if !meta.is_dir {
+            return self.copy_key(&from, &to).await;
+        }
+        let from = format!("{}/", from.trim_end_matches('/'));
+        let to = format!("{}/", to.trim_end_matches('/'));
+        let mut keys = self.list_recursive(&from).await?;
+        if !keys.iter().any(|k| k == &from) {
+            // make sure the destination exists, even if it's empty.
+            self.put_marker(&to).await?;
+        }
+        keys.sort();
+        for key in keys {
+            let dest = format!("{}{}", to, &key[from.len()..]);
+            self.copy_key(&key, &dest).await?;
+        }
+        Ok(())
+    }
+
+    async fn put_marker(&self, key: &str) -> FsResult<()> {
+        let resp = self.bucket().put_object(key, b"").await.map_err(s3_error)?;
+        match resp.status_code() {
+            200..=299 => Ok(()),
+            status => Err(status_error(status)),
+        }
+    }
+}
+
+impl DavFileSystem for S3Fs {
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            let meta = self.lookup(&s3_key(path)?).await?;
+            Ok(Box::new(meta) as Box<dyn DavMetaData>)
+        }
+        .boxed()
+    }
+
+    fn exists<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        async move {
+            // for a collection there is no need for a HEAD of the key.
+            let key = s3_key(path)?;
+            let res = if path.is_collection() {
+                self.lookup_dir(&key).await
+            } else {
+                self.lookup(&key).await
+            };
+            match res {
+                Ok(_) => Ok(true),
+                Err(FsError::NotFound) => Ok(false),
+                Err(e) => Err(e),
+            }
+        }
+        .boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        _meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+    {
+        async move {
+            let key = s3_key(path)?;
+            if !self.lookup(&key).await?.is_dir {
+                return Err(FsError::Forbidden);
+            }
+            let prefix = match key.trim_end_matches('/') {
+                "" => String::new(),
+                k => format!("{}/", k),
+            };
+            let results = self
+                .bucket()
+                .list(prefix.clone(), Some("/".to_string()))
+                .await
+                .map_err(s3_error)?;
+
+            let mut entries = Vec::new();
+            for result in results {
+                for p in result.common_prefixes.unwrap_or_default() {
+                    let name = p.prefix[prefix.len()..].trim_end_matches('/');
+                    entries.push(S3DirEntry {
+                        name: name.as_bytes().to_vec(),
+                        meta: S3MetaData::dir(None),
+                    });
+                }
+                for o in result.contents {
+                    // skip the directory marker.
+                    if o.key == prefix {
+                        continue;
+                    }
+                    entries.push(S3DirEntry {
+                        name: o.key.as_bytes()[prefix.len()..].to_vec(),
+                        meta: S3MetaData {
+                            len:      o.size,
+                            modified: parse_rfc3339(&o.last_modified),
+                            is_dir:   false,
+                            etag:     o.e_tag.as_deref().map(trim_etag),
+                        },
+                    });
+                }
+            }
+            let strm = stream::iter(entries.into_iter().map(|e| Box::new(e) as Box<dyn DavDirEntry>));
+            Ok(Box::pin(strm) as FsStream<Box<dyn DavDirEntry>>)
+        }
+        .boxed()
+    }
+
+    fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move {
+            let key = s3_key(path)?;
+            if key.is_empty() || key.ends_with('/') {
+                return Err(FsError::Forbidden);
+            }
+            let meta = match self.lookup(&key).await {
+                Ok(meta) => Some(meta),
+                Err(FsError::NotFound) => None,
+                Err(e) => return Err(e),
+            };
+            if meta.as_ref().map(|m| m.is_dir).unwrap_or(false) {
+                return Err(FsError::Forbidden);
+            }
+
+            if !options.write && !options.append {
+                let meta = meta.ok_or(FsError::NotFound)?;
+                return Ok(Box::new(S3File::new(self, key, meta, None)) as Box<dyn DavFile>);
+            }
+
+            // We can only replace objects, not update them.
+            if options.append || (!options.truncate && meta.is_some()) {
+                return Err(FsError::NotImplemented);
+            }
+            if options.create_new && meta.is_some() {
+                return Err(FsError::Exists);
+            }
+            if !options.create && meta.is_none() {
+                return Err(FsError::NotFound);
+            }
+            self.check_parent(&key).await?;
+            let meta = S3MetaData {
+                len:      0,
+                modified: Some(SystemTime::now()),
+                is_dir:   false,
+                etag:     None,
+            };
+            Ok(Box::new(S3File::new(self, key, meta, Some(Upload::default()))) as Box<dyn DavFile>)
+        }
+        .boxed()
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let key = s3_key(path)?;
+            let key = key.trim_end_matches('/');
+            if key.is_empty() {
+                return Err(FsError::Exists);
+            }
+            match self.lookup(key).await {
+                Ok(_) => return Err(FsError::Exists),
+                Err(FsError::NotFound) => {},
+                Err(e) => return Err(e),
+            }
+            self.check_parent(key).await?;
+            self.put_marker(&format!("{}/", key)).await
+        }
+        .boxed()
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let key = s3_key(path)?;
+            let prefix = format!("{}/", key.trim_end_matches('/'));
+            if prefix == "/" {
+                return Err(FsError::Forbidden);
+            }
+            if !self.lookup(&prefix).await?.is_dir {
+                return Err(FsError::Forbidden);
+            }
+            // only the marker may be left.
+            let keys = self.list_recursive(&prefix).await?;
+            if keys.iter().any(|k| k != &prefix) {
+                return Err(FsError::Forbidden);
+            }
+            self.delete_key(&prefix).await
+        }
+        .boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let key = s3_key(path)?;
+            if self.lookup(&key).await?.is_dir {
+                return Err(FsError::Forbidden);
+            }
+            self.delete_key(&key).await
+        }
+        .boxed()
+    }
+
+    // objects only show up once they are complete, and a rename is a copy.
+    fn temp_path(&self, _path: &DavPath) -> Option<DavPath> {
+        None
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            // S3 has no rename, so copy and delete.
+            self.do_copy(from, to).await?;
+            let key = s3_key(from)?;
+            if self.lookup(&key).await?.is_dir {
+                for key in self.list_recursive(&key).await? {
+                    self.delete_key(&key).await?;
+                }
+                Ok(())
+            } else {
+                self.delete_key(&key).await
+            }
+        }
+        .boxed()
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move { self.do_copy(from, to).await }.boxed()
+    }
+
+    // objects can only be replaced, not updated.
+    fn capabilities(&self) -> FsCapabilities {
+        FsCapabilities {
+            partial_update: false,
+            ..FsCapabilities::default()
+        }
+    }
+}
+
+impl S3MetaData {
+    fn dir(modified: Option<SystemTime>) -> S3MetaData {
+        S3MetaData {
+            len: 0,
+            modified,
+            is_dir: true,
+            etag: None,
+        }
+    }
+}
+
+impl DavMetaData for S3MetaData {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        self.modified.ok_or(FsError::NotImplemented)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    fn etag(&self) -> Option<String> {
+        if self.is_dir {
+            return None;
+        }
+        match self.etag {
+            Some(ref etag) => Some(etag.clone()),
+            None => {
+                let modified = self.modified?;
+                let t = modified.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+                Some(format!("{:x}-{:x}", self.len, t.as_micros()))
+            },
+        }
+    }
+}
+
+impl DavDirEntry for S3DirEntry {
+    fn name(&self) -> Vec<u8> {
+        self.name.clone()
+    }
+
+    fn metadata<'a>(&'a self) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        let meta = self.meta.clone();
+        async move { Ok(Box::new(meta) as Box<dyn DavMetaData>) }.boxed()
+    }
+}
+
+impl S3File {
+    fn new(fs: &S3Fs, key: String, meta: S3MetaData, upload: Option<Upload>) -> S3File {
+        S3File {
+            fs: fs.clone(),
+            key,
+            meta,
+            pos: 0,
+            rbuf: Bytes::new(),
+            rpos: 0,
+            upload,
+        }
+    }
+
+    // Send the data in the buffer as the next part of the multipart upload.
+    async fn send_part(&mut self) -> FsResult<()> {
+        let bucket = self.fs.bucket();
+        let upload = self.upload.as_mut().ok_or(FsError::Forbidden)?;
+        let upload_id = match upload.upload_id {
+            Some(ref id) => id.clone(),
+            None => {
+                let resp = bucket
+                    .initiate_multipart_upload(&self.key, CONTENT_TYPE)
+                    .await
+                    .map_err(s3_error)?;
+                upload.upload_id = Some(resp.upload_id.clone());
+                resp.upload_id
+            },
+        };
+        let chunk = upload.buf.split().to_vec();
+        let part_number = upload.parts.len() as u32 + 1;
+        let part = bucket
+            .put_multipart_chunk(chunk, &self.key, part_number, &upload_id, CONTENT_TYPE)
+            .await
+            .map_err(s3_error)?;
+        upload.parts.push(part);
+        Ok(())
+    }
+
+    async fn write(&mut self, mut buf: impl Buf) -> FsResult<()> {
+        let upload = self.upload.as_mut().ok_or(FsError::Forbidden)?;
+        if upload.done {
+            return Err(FsError::NotImplemented);
+        }
+        let len = buf.remaining() as u64;
+        while buf.has_remaining() {
+            let upload = self.upload.as_mut().unwrap();
+            let n = std::cmp::min(buf.chunk().len(), PART_SIZE - upload.buf.len());
+            upload.buf.extend_from_slice(&buf.chunk()[..n]);
+            buf.advance(n);
+            if upload.buf.len() >= PART_SIZE {
+                self.send_part().await?;
+            }
+        }
+        self.pos += len;
+        self.meta.len = self.pos;
+        Ok(())
+    }
+
+    // Finish the upload. Small files are sent with a single PutObject.
+    async fn finish(&mut self) -> FsResult<()> {
+        let fs = self.fs.clone();
+        let bucket = fs.bucket();
+        let upload = match self.upload.as_mut() {
+            Some(upload) if !upload.done => upload,
+            _ => return Ok(()),
+        };
+        if upload.upload_id.is_none() {
+            let resp = bucket
+                .put_object(&self.key, &upload.buf)
+                .await
+                .map_err(s3_error)?;
+            if resp.status_code() >= 300 {
+                return Err(status_error(resp.status_code()));
+            }
+            upload.buf.clear();
+        } else {
+            if !upload.buf.is_empty() {
+                self.send_part().await?;
+            }
+            let upload = self.upload.as_mut().unwrap();
+            let upload_id = upload.upload_id.clone().unwrap();
+            let parts = std::mem::take(&mut upload.parts);
+            let resp = bucket
+                .complete_multipart_upload(&self.key, &upload_id, parts)
+                .await
+                .map_err(s3_error)?;
+            if resp.status_code() >= 300 {
+                return Err(status_error(resp.status_code()));
+            }
+        }
+        let upload = self.upload.as_mut().unwrap();
+        upload.done = true;
+        self.meta.modified = Some(SystemTime::now());
+        if let Ok(meta) = self.fs.lookup(&self.key).await {
+            self.meta = meta;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for S3File {
+    fn drop(&mut self) {
+        // Abort an unfinished multipart upload, otherwise the parts
+        // stick around (and are billed for) forever.
+        let upload_id = match self.upload {
+            Some(Upload {
+                upload_id: Some(ref id),
+                done: false,
+                ..
+            }) => id.clone(),
+            _ => return,
+        };
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let fs = self.fs.clone();
+            let key = self.key.clone();
+            handle.spawn(async move {
+                if let Err(e) = fs.bucket().abort_upload(&key, &upload_id).await {
+                    debug!("S3File::drop: abort_upload {}: {}", key, e);
+                }
+            });
+        }
+    }
+}
+
+impl DavFile for S3File {
+    fn metadata<'a>(&'a mut self) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        let meta = self.meta.clone();
+        async move { Ok(Box::new(meta) as Box<dyn DavMetaData>) }.boxed()
+    }
+
+    fn write_buf<'a>(&'a mut self, buf: Box<dyn Buf + Send>) -> FsFuture<'a, ()> {
+        async move { self.write(buf).await }.boxed()
+    }
+
+    fn write_bytes<'a>(&'a mut self, buf: Bytes) -> FsFuture<'a, ()> {
+        async move { self.write(buf).await }.boxed()
+    }
+
+    fn read_bytes<'a>(&'a mut self, count: usize) -> FsFuture<'a, Bytes> {
+        async move {
+            if self.upload.is_some() {
+                return Err(FsError::Forbidden);
+            }
+            if self.pos >= self.meta.len || count == 0 {
+                return Ok(Bytes::new());
+            }
+
+            // refill the read-ahead buffer if needed.
+            let rend = self.rpos + self.rbuf.len() as u64;
+            if self.pos < self.rpos || self.pos >= rend {
+                let end = std::cmp::min(self.pos + std::cmp::max(count as u64, READ_AHEAD), self.meta.len);
+                let resp = self
+                    .fs
+                    .bucket()
+                    .get_object_range(&self.key, self.pos, Some(end - 1))
+                    .await
+                    .map_err(s3_error)?;
+                if resp.status_code() >= 300 {
+                    return Err(status_error(resp.status_code()));
+                }
+                self.rbuf = resp.bytes().clone();
+                self.rpos = self.pos;
+                if self.rbuf.is_empty() {
+                    return Ok(Bytes::new());
+                }
+            }
+
+            let start = (self.pos - self.rpos) as usize;
+            let end = std::cmp::min(start + count, self.rbuf.len());
+            let data = self.rbuf.slice(start..end);
+            self.pos += data.len() as u64;
+            Ok(data)
+        }
+        .boxed()
+    }
+
+    fn seek<'a>(&'a mut self, pos: SeekFrom) -> FsFuture<'a, u64> {
+        async move {
+            let newpos = match pos {
+                SeekFrom::Start(n) => n as i64,
+                SeekFrom::Current(n) => self.pos as i64 + n,
+                SeekFrom::End(n) => self.meta.len as i64 + n,
+            };
+            if newpos < 0 {
+                return Err(FsError::GeneralFailure);
+            }
+            // an upload can only be written sequentially.
+            if self.upload.is_some() && newpos as u64 != self.pos {
+                return Err(FsError::NotImplemented);
+            }
+            self.pos = newpos as u64;
+            Ok(self.pos)
+        }
+        .boxed()
+    }
+
+    fn flush<'a>(&'a mut self) -> FsFuture<'a, ()> {
+        async move { self.finish().await }.boxed()
+    }
+}
+
+// Map a DavPath onto an S3 key. We do not do the leading slash.
+fn s3_key(path: &DavPath) -> FsResult<String> {
+    let path = std::str::from_utf8(path.as_bytes()).map_err(|_| FsError::Forbidden)?;
+    Ok(path.trim_start_matches('/').to_string())
+}
+
+fn trim_etag(etag: &str) -> String {
+    etag.trim_start_matches("W/").trim_matches('"').to_string()
+}
+
+fn parse_rfc3339(s: &str) -> Option<SystemTime> {
+    use time::format_description::well_known::Rfc3339;
+    time::OffsetDateTime::parse(s, &Rfc3339)
+        .ok()
+        .map(SystemTime::from)
+}
+
+fn parse_httpdate(s: &str) -> Option<SystemTime> {
+    use headers::Header;
+    let value = http::HeaderValue::from_str(s).ok()?;
+    let lm = headers::LastModified::decode(&mut std::iter::once(&value)).ok()?;
+    Some(SystemTime::from(lm))
+}
+
+fn status_error(status: u16) -> FsError {
+    match status {
+        403 => FsError::Forbidden,
+        404 => FsError::NotFound,
+        412 => FsError::Exists,
+        507 => FsError::InsufficientStorage,
+        _ => FsError::GeneralFailure,
+    }
+}
+
+fn s3_error(e: S3Error) -> FsError {
+    match e {
+        S3Error::HttpFailWithBody(status, _) => status_error(status),
+        e => {
+            debug!("s3fs: {}", e);
+            FsError::GeneralFailure
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+
+    use http::{Request, Response, StatusCode};
+    use hyper::service::{make_service_fn, service_fn};
+    use percent_encoding::percent_decode_str;
+
+    use super::*;
+    use crate::testing::TestClient;
+    use crate::DavHandler;
+
+    // Just enough of S3 for S3Fs, with path-style requests for bucket "b".
+    #[derive(Default)]
+    struct FakeS3 {
+        objects:   BTreeMap<String, Vec<u8>>,
+        uploads:   HashMap<String, BTreeMap<u32, Vec<u8>>>,
+        completed: usize,
+    }
+
+    const DATE: &str = "Mon, 01 Jan 2024 00:00:00 GMT";
+
+    fn etag(data: &[u8]) -> String {
+        use std::hash::{Hash, Hasher};
+        let mut h = std::collections::hash_map::DefaultHasher::new();
+        data.hash(&mut h);
+        format!("\"{:x}\"", h.finish())
+    }
+
+    fn xml(body: String) -> Response<hyper::Body> {
+        Response::new(hyper::Body::from(body))
+    }
+
+    fn status(status: StatusCode) -> Response<hyper::Body> {
+        let mut resp = Response::new(hyper::Body::empty());
+        *resp.status_mut() = status;
+        resp
+    }
+
+    impl FakeS3 {
+        fn list(&self, query: &HashMap<String, String>) -> Response<hyper::Body> {
+            let prefix = query.get("prefix").cloned().unwrap_or_default();
+            let delimiter = query.get("delimiter").map(|d| d.as_str());
+            let mut contents = String::new();
+            let mut prefixes = Vec::new();
+            for (key, data) in self.objects.range(prefix.clone()..) {
+                let rest = match key.strip_prefix(&prefix) {
+                    Some(rest) => rest,
+                    None => break,
+                };
+                if let Some(idx) = rest.find('/').filter(|_| delimiter.is_some()) {
+                    let p = format!("{}{}", prefix, &rest[..=idx]);
+                    if !prefixes.contains(&p) {
+                        prefixes.push(p);
+                    }
+                    continue;
+                }
+                contents.push_str(&format!(
+                    "<Contents><Key>{}</Key><LastModified>2024-01-01T00:00:00.000Z</LastModified>\
+                     <ETag>{}</ETag><Size>{}</Size></Contents>",
+                    key,
+                    etag(data),
+                    data.len()
+                ));
+            }
+            let prefixes: String = prefixes
+                .iter()
+                .map(|p| format!("<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", p))
+                .collect();
+            xml(format!(
+                "<ListBucketResult><Name>b</Name><Prefix>{}</Prefix><IsTruncated>false</IsTruncated>{}{}\
+                 </ListBucketResult>",
+                prefix, contents, prefixes
+            ))
+        }
+
+        fn handle(&mut self, req: Request<()>, body: Vec<u8>) -> Response<hyper::Body> {
+            let path = percent_decode_str(req.uri().path()).decode_utf8_lossy().into_owned();
+            let key = match path.strip_prefix("/b/") {
+                Some(key) => key.to_string(),
+                None => return status(StatusCode::NOT_FOUND),
+            };
+            let query = req.uri().query().unwrap_or("");
+            let query: HashMap<_, _> = url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+            let upload_id = query.get("uploadId").cloned();
+
+            match (req.method().as_str(), upload_id) {
+                ("GET", _) if key.is_empty() => self.list(&query),
+                ("HEAD", _) | ("GET", _) => {
+                    let data = match self.objects.get(&key) {
+                        Some(data) => data,
+                        None => return status(StatusCode::NOT_FOUND),
+                    };
+                    let range = req.headers().get("range").and_then(|r| r.to_str().ok());
+                    let (start, end) = match range.and_then(|r| r.strip_prefix("bytes=")) {
+                        Some(r) => {
+                            let (s, e) = r.split_once('-').unwrap();
+                            (s.parse().unwrap(), e.parse::<usize>().map(|e| e + 1).unwrap_or(data.len()))
+                        },
+                        None => (0, data.len()),
+                    };
+                    let end = std::cmp::min(end, data.len());
+                    let body = if req.method() == "HEAD" { Vec::new() } else { data[start..end].to_vec() };
+                    Response::builder()
+                        .status(if range.is_some() { 206 } else { 200 })
+                        .header("content-length", end - start)
+                        .header("last-modified", DATE)
+                        .header("etag", etag(data))
+                        .body(hyper::Body::from(body))
+                        .unwrap()
+                },
+                ("PUT", Some(id)) => {
+                    let part = query["partNumber"].parse().unwrap();
+                    let tag = etag(&body);
+                    self.uploads.get_mut(&id).unwrap().insert(part, body);
+                    Response::builder().header("etag", tag).body(hyper::Body::empty()).unwrap()
+                },
+                ("PUT", None) => {
+                    if let Some(from) = req.headers().get("x-amz-copy-source") {
+                        let from = percent_decode_str(from.to_str().unwrap()).decode_utf8_lossy();
+                        let from = from.trim_start_matches('/').trim_start_matches("b/").to_string();
+                        match self.objects.get(&from).cloned() {
+                            Some(data) => self.objects.insert(key, data),
+                            None => return status(StatusCode::NOT_FOUND),
+                        };
+                        return xml("<CopyObjectResult><ETag>\"x\"</ETag></CopyObjectResult>".to_string());
+                    }
+                    self.objects.insert(key, body);
+                    status(StatusCode::OK)
+                },
+                ("POST", None) => {
+                    let id = format!("upload-{}", self.uploads.len() + self.completed);
+                    self.uploads.insert(id.clone(), BTreeMap::new());
+                    xml(format!(
+                        "<InitiateMultipartUploadResult><Bucket>b</Bucket><Key>{}</Key>\
+                         <UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                        key, id
+                    ))
+                },
+                ("POST", Some(id)) => {
+                    let parts = self.uploads.remove(&id).unwrap();
+                    self.objects.insert(key.clone(), parts.into_values().flatten().collect());
+                    self.completed += 1;
+                    xml(format!(
+                        "<CompleteMultipartUploadResult><Key>{}</Key></CompleteMultipartUploadResult>",
+                        key
+                    ))
+                },
+                ("DELETE", Some(id)) => {
+                    self.uploads.remove(&id);
+                    status(StatusCode::NO_CONTENT)
+                },
+                ("DELETE", None) => {
+                    self.objects.remove(&key);
+                    status(StatusCode::NO_CONTENT)
+                },
+                _ => status(StatusCode::NOT_IMPLEMENTED),
+            }
+        }
+    }
+
+    async fn serve() -> (Arc<Mutex<FakeS3>>, Box<S3Fs>) {
+        let s3 = Arc::new(Mutex::new(FakeS3::default()));
+        let state = s3.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<hyper::Body>| {
+                    let state = state.clone();
+                    async move {
+                        let (parts, body) = req.into_parts();
+                        let body = hyper::body::to_bytes(body).await.unwrap().to_vec();
+                        let resp = state.lock().unwrap().handle(Request::from_parts(parts, ()), body);
+                        Ok::<_, Infallible>(resp)
+                    }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let region = Region::Custom {
+            region:   "us-east-1".to_string(),
+            endpoint: format!("http://{}", server.local_addr()),
+        };
+        tokio::spawn(server);
+        let credentials = Credentials::new(Some("key"), Some("secret"), None, None, None).unwrap();
+        let bucket = Bucket::new("b", region, credentials).unwrap().with_path_style();
+        (s3, S3Fs::from_bucket(bucket))
+    }
+
+    #[tokio::test]
+    async fn s3fs() {
+        let (s3, fs) = serve().await;
+        let c = TestClient::new(DavHandler::builder().filesystem(fs).build_handler());
+        let objects = || s3.lock().unwrap().objects.keys().cloned().collect::<Vec<_>>();
+
+        // a collection is a marker object.
+        assert_eq!(c.request("MKCOL", "/dir/", "").await.status(), 201);
+        assert_eq!(c.request("MKCOL", "/dir/", "").await.status(), 405);
+        assert_eq!(c.request("PUT", "/nodir/file", "x").await.status(), 409);
+        assert_eq!(objects(), vec!["dir/"]);
+
+        // small files are sent at once, large ones in parts.
+        assert_eq!(c.request("PUT", "/dir/small", "hello world").await.status(), 201);
+        let big: Vec<u8> = (0..PART_SIZE + 1000).map(|i| (i % 251) as u8).collect();
+        assert_eq!(c.request("PUT", "/dir/big", big.clone()).await.status(), 201);
+        assert_eq!(s3.lock().unwrap().completed, 1);
+        assert!(s3.lock().unwrap().uploads.is_empty());
+        assert_eq!(s3.lock().unwrap().objects["dir/big"], big);
+
+        assert_eq!(c.request("GET", "/dir/small", "").await.into_body(), "hello world");
+        assert_eq!(c.request("GET", "/dir/big", "").await.into_body(), big);
+        let req = Request::get("/dir/big").header("Range", "bytes=8388608-8388611");
+        let resp = c.send(req.body("").unwrap()).await;
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.into_body(), &big[PART_SIZE..PART_SIZE + 4]);
+
+        // listings come from the prefix.
+        c.request("MKCOL", "/dir/sub/", "").await;
+        let req = Request::builder().method("PROPFIND").uri("/dir/").header("Depth", "1");
+        let resp = c.send(req.body("").unwrap()).await;
+        assert_eq!(resp.status(), 207);
+        let body = String::from_utf8_lossy(resp.body()).into_owned();
+        for href in ["/dir/", "/dir/small", "/dir/big", "/dir/sub/"] {
+            assert!(body.contains(&format!("<D:href>{}</D:href>", href)), "{}", body);
+        }
+        assert_eq!(body.matches("<D:response>").count(), 4, "{}", body);
+
+        // a move is a copy and a delete.
+        let req = Request::builder().method("MOVE").uri("/dir/").header("Destination", "/moved/");
+        assert_eq!(c.send(req.body("").unwrap()).await.status(), 201);
+        assert_eq!(objects(), vec!["moved/", "moved/big", "moved/small", "moved/sub/"]);
+        assert_eq!(c.request("GET", "/dir/small", "").await.status(), 404);
+
+        assert_eq!(c.request("DELETE", "/moved/", "").await.status(), 204);
+        assert!(objects().is_empty());
+    }
+}
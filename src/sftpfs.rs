@@ -0,0 +1,946 @@
+//! Filesystem backed by an SFTP server.
+//!
+//! Paths are mapped onto the server below a base directory, so that
+//! `/dir/file.txt` becomes `<base>/dir/file.txt` on the server. File
+//! contents are streamed through SFTP read and write handles.
+//!
+//! A small pool of SSH connections is kept open and shared by all requests.
+//! SFTP multiplexes requests over a single session, so a connection is not
+//! tied to a request. Connections that were closed by the server are
+//! re-opened the next time they are needed.
+//!
+//! The host key of the server is checked against `~/.ssh/known_hosts`,
+//! unless a key was set with [`SftpFs::with_host_key`].
+//!
+//! Example:
+//!
+//! ```no_run
+//! use webdav_handler::{sftpfs::{SftpAuth, SftpFs}, DavHandler};
+//!
+//! let auth = SftpAuth::password("user", "secret");
+//! let dav_server = DavHandler::builder()
+//!     .filesystem(SftpFs::new("sftp.example.com:22", auth, "/srv/files"))
+//!     .build_handler();
+//! ```
+//!
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{future::FutureExt, stream};
+use russh::client;
+use russh::keys::{PrivateKeyWithHashAlg, PublicKeyOrCertificate};
+use russh_sftp::client::error::Error as SftpError;
+use russh_sftp::client::fs::File;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+pub use russh::keys::{PrivateKey, PublicKey};
+
+// Number of SSH connections in the pool.
+const POOL_SIZE: usize = 4;
+
+// Size of the chunks in which a file is copied.
+const COPY_BUF_SIZE: usize = 65536;
+
+/// Credentials to log in to the SFTP server.
+#[derive(Clone)]
+pub enum SftpAuth {
+    /// Username and password.
+    Password { user: String, password: String },
+    /// Username and private key.
+    PublicKey { user: String, key: Arc<PrivateKey> },
+}
+
+impl SftpAuth {
+    /// Log in with a username and password.
+    pub fn password(user: impl Into<String>, password: impl Into<String>) -> SftpAuth {
+        SftpAuth::Password {
+            user:     user.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Log in with a private key, read from a file in OpenSSH format.
+    pub fn key_file(
+        user: impl Into<String>,
+        path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+    ) -> io::Result<SftpAuth>
+    {
+        let key = russh::keys::load_secret_key(path, passphrase)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(SftpAuth::PublicKey {
+            user: user.into(),
+            key:  Arc::new(key),
+        })
+    }
+
+    fn user(&self) -> &str {
+        match self {
+            SftpAuth::Password { user, .. } => user,
+            SftpAuth::PublicKey { user, .. } => user,
+        }
+    }
+}
+
+impl std::fmt::Debug for SftpAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // never print the secrets.
+        match self {
+            SftpAuth::Password { user, .. } => f.debug_struct("Password").field("user", user).finish(),
+            SftpAuth::PublicKey { user, .. } => f.debug_struct("PublicKey").field("user", user).finish(),
+        }
+    }
+}
+
+/// SFTP filesystem.
+#[derive(Debug, Clone)]
+pub struct SftpFs {
+    inner: Arc<SftpFsInner>,
+}
+
+#[derive(Debug)]
+struct SftpFsInner {
+    host:     String,
+    port:     u16,
+    auth:     SftpAuth,
+    base:     String,
+    host_key: Option<PublicKey>,
+    pool:     Vec<Mutex<Option<Arc<Conn>>>>,
+    next:     AtomicUsize,
+}
+
+struct Conn {
+    handle: client::Handle<Client>,
+    sftp:   SftpSession,
+}
+
+impl std::fmt::Debug for Conn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Conn")
+            .field("closed", &self.handle.is_closed())
+            .finish()
+    }
+}
+
+// Callbacks from the SSH client. We only need to check the host key.
+struct Client {
+    host:     String,
+    port:     u16,
+    host_key: Option<PublicKey>,
+}
+
+#[derive(Debug, Clone)]
+struct SftpMetaData(FileAttributes);
+
+#[derive(Debug)]
+struct SftpDirEntry {
+    name: Vec<u8>,
+    meta: SftpMetaData,
+}
+
+struct SftpFile {
+    file: File,
+}
+
+impl std::fmt::Debug for SftpFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SftpFile").finish()
+    }
+}
+
+impl SftpFs {
+    /// Create a new SFTP filesystem.
+    ///
+    /// `host` is a hostname, optionally followed by `:port`. The default
+    /// port is 22. All paths are relative to `base` on the server.
+    ///
+    /// No connection is made until the filesystem is first used.
+    pub fn new(host: &str, auth: SftpAuth, base: &str) -> Box<SftpFs> {
+        let (host, port) = match host.rsplit_once(':') {
+            Some((h, p)) if !h.contains(':') || h.starts_with('[') => {
+                match p.parse::<u16>() {
+                    Ok(port) => (h.trim_start_matches('[').trim_end_matches(']'), port),
+                    Err(_) => (host, 22),
+                }
+            },
+            _ => (host, 22),
+        };
+        let mut pool = Vec::new();
+        pool.resize_with(POOL_SIZE, || Mutex::new(None));
+        Box::new(SftpFs {
+            inner: Arc::new(SftpFsInner {
+                host: host.to_string(),
+                port,
+                auth,
+                base: base.trim_end_matches('/').to_string(),
+                host_key: None,
+                pool,
+                next: AtomicUsize::new(0),
+            }),
+        })
+    }
+
+    /// Only accept this host key, instead of looking in `known_hosts`.
+    ///
+    /// This must be called right after construction, before the `SftpFs` is cloned.
+    pub fn with_host_key(self: Box<Self>, key: PublicKey) -> Box<SftpFs> {
+        let mut this = self;
+        Arc::get_mut(&mut this.inner)
+            .expect("SftpFs::with_host_key: SftpFs has been cloned")
+            .host_key = Some(key);
+        this
+    }
+
+    // Get a session from the pool, (re)connecting if needed.
+    async fn session(&self) -> FsResult<Arc<Conn>> {
+        let idx = self.inner.next.fetch_add(1, Ordering::Relaxed) % POOL_SIZE;
+        let mut slot = self.inner.pool[idx].lock().await;
+        if let Some(conn) = slot.as_ref() {
+            if !conn.handle.is_closed() {
+                return Ok(conn.clone());
+            }
+        }
+        let conn = Arc::new(self.connect().await?);
+        *slot = Some(conn.clone());
+        Ok(conn)
+    }
+
+    async fn connect(&self) -> FsResult<Conn> {
+        let inner = &self.inner;
+        let client = Client {
+            host:     inner.host.clone(),
+            port:     inner.port,
+            host_key: inner.host_key.clone(),
+        };
+        let config = Arc::new(client::Config::default());
+        let mut handle = client::connect(config, (inner.host.as_str(), inner.port), client)
+            .await
+            .map_err(ssh_error)?;
+
+        let result = match inner.auth {
+            SftpAuth::Password {
+                ref user,
+                ref password,
+            } => {
+                handle
+                    .authenticate_password(user.as_str(), password.as_str())
+                    .await
+            },
+            SftpAuth::PublicKey { ref key, .. } => {
+                let hash_alg = handle.best_supported_rsa_hash().await.map_err(ssh_error)?;
+                let key = PrivateKeyWithHashAlg::new(key.clone(), hash_alg.flatten());
+                handle.authenticate_publickey(inner.auth.user(), key).await
+            },
+        };
+        if !result.map_err(ssh_error)?.success() {
+            debug!(
+                "sftpfs: {}@{}: authentication failed",
+                inner.auth.user(),
+                inner.host
+            );
+            return Err(FsError::Forbidden);
+        }
+
+        let channel = handle.channel_open_session().await.map_err(ssh_error)?;
+        channel.request_subsystem(true, "sftp").await.map_err(ssh_error)?;
+        let sftp = SftpSession::new(channel.into_stream())
+            .await
+            .map_err(sftp_error)?;
+        Ok(Conn { handle, sftp })
+    }
+
+    // Map a DavPath onto a path on the server.
+    fn sftp_path(&self, path: &DavPath) -> FsResult<String> {
+        let path = std::str::from_utf8(path.as_bytes()).map_err(|_| FsError::Forbidden)?;
+        let path = path.trim_end_matches('/');
+        Ok(if self.inner.base.is_empty() && path.is_empty() {
+            "/".to_string()
+        } else {
+            format!("{}{}", self.inner.base, path)
+        })
+    }
+
+    async fn exists(&self, sftp: &SftpSession, path: &str) -> FsResult<bool> {
+        match sftp.symlink_metadata(path).await {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                match sftp_error(e) {
+                    FsError::NotFound => Ok(false),
+                    e => Err(e),
+                }
+            },
+        }
+    }
+}
+
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, server_key: &PublicKeyOrCertificate) -> Result<bool, Self::Error> {
+        let key = match server_key {
+            PublicKeyOrCertificate::PublicKey { key, .. } => key,
+            PublicKeyOrCertificate::Certificate(_) => return Ok(false),
+        };
+        if let Some(ref host_key) = self.host_key {
+            return Ok(host_key.key_data() == key.key_data());
+        }
+        match russh::keys::check_known_hosts(&self.host, self.port, key) {
+            Ok(found) => Ok(found),
+            Err(e) => {
+                debug!("sftpfs: {}: {}", self.host, e);
+                Ok(false)
+            },
+        }
+    }
+}
+
+impl DavFileSystem for SftpFs {
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            let conn = self.session().await?;
+            let attrs = conn
+                .sftp
+                .metadata(self.sftp_path(path)?)
+                .await
+                .map_err(sftp_error)?;
+            Ok(Box::new(SftpMetaData(attrs)) as Box<dyn DavMetaData>)
+        }
+        .boxed()
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            let conn = self.session().await?;
+            let attrs = conn
+                .sftp
+                .symlink_metadata(self.sftp_path(path)?)
+                .await
+                .map_err(sftp_error)?;
+            Ok(Box::new(SftpMetaData(attrs)) as Box<dyn DavMetaData>)
+        }
+        .boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        _meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+    {
+        async move {
+            let conn = self.session().await?;
+            let entries = conn
+                .sftp
+                .read_dir(self.sftp_path(path)?)
+                .await
+                .map_err(sftp_error)?;
+            let entries = entries
+                .filter(|e| e.file_name() != "." && e.file_name() != "..")
+                .map(|e| {
+                    Box::new(SftpDirEntry {
+                        name: e.file_name().into_bytes(),
+                        meta: SftpMetaData(e.metadata()),
+                    }) as Box<dyn DavDirEntry>
+                })
+                .collect::<Vec<_>>();
+            Ok(Box::pin(stream::iter(entries)) as FsStream<Box<dyn DavDirEntry>>)
+        }
+        .boxed()
+    }
+
+    fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move {
+            let conn = self.session().await?;
+            let path = self.sftp_path(path)?;
+            let mut flags = OpenFlags::empty();
+            if options.read {
+                flags |= OpenFlags::READ;
+            }
+            if options.write {
+                flags |= OpenFlags::WRITE;
+            }
+            if options.append {
+                flags |= OpenFlags::WRITE | OpenFlags::APPEND;
+            }
+            if options.truncate {
+                flags |= OpenFlags::TRUNCATE;
+            }
+            if options.create {
+                flags |= OpenFlags::CREATE;
+            }
+            if options.create_new {
+                flags |= OpenFlags::CREATE | OpenFlags::EXCLUDE;
+            }
+            let file = match conn.sftp.open_with_flags(path.as_str(), flags).await {
+                Ok(file) => file,
+                Err(e) => {
+                    // most servers just say "Failure", so find out what's wrong.
+                    let e = sftp_error(e);
+                    if options.create_new &&
+                        e == FsError::GeneralFailure &&
+                        self.exists(&conn.sftp, &path).await?
+                    {
+                        return Err(FsError::Exists);
+                    }
+                    return Err(e);
+                },
+            };
+            Ok(Box::new(SftpFile { file }) as Box<dyn DavFile>)
+        }
+        .boxed()
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let conn = self.session().await?;
+            let path = self.sftp_path(path)?;
+            if self.exists(&conn.sftp, &path).await? {
+                return Err(FsError::Exists);
+            }
+            conn.sftp.create_dir(path).await.map_err(sftp_error)
+        }
+        .boxed()
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let conn = self.session().await?;
+            conn.sftp
+                .remove_dir(self.sftp_path(path)?)
+                .await
+                .map_err(sftp_error)
+        }
+        .boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let conn = self.session().await?;
+            conn.sftp
+                .remove_file(self.sftp_path(path)?)
+                .await
+                .map_err(sftp_error)
+        }
+        .boxed()
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let conn = self.session().await?;
+            conn.sftp
+                .rename(self.sftp_path(from)?, self.sftp_path(to)?)
+                .await
+                .map_err(sftp_error)
+        }
+        .boxed()
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            // SFTP has no copy, so read and write the data.
+            let conn = self.session().await?;
+            let mut src = conn
+                .sftp
+                .open_with_flags(self.sftp_path(from)?, OpenFlags::READ)
+                .await
+                .map_err(sftp_error)?;
+            let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+            let mut dst = conn
+                .sftp
+                .open_with_flags(self.sftp_path(to)?, flags)
+                .await
+                .map_err(sftp_error)?;
+            let mut buf = vec![0u8; COPY_BUF_SIZE];
+            loop {
+                let n = src.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                dst.write_all(&buf[..n]).await?;
+            }
+            dst.shutdown().await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn quota<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        async move {
+            let conn = self.session().await?;
+            let stat = conn
+                .sftp
+                .fs_info(self.sftp_path(path)?)
+                .await
+                .map_err(sftp_error)?;
+            let stat = stat.ok_or(FsError::NotImplemented)?;
+            let used = stat.blocks.saturating_sub(stat.blocks_free) * stat.fragment_size;
+            Ok((used, Some(used + stat.blocks_avail * stat.fragment_size)))
+        }
+        .boxed()
+    }
+}
+
+impl DavMetaData for SftpMetaData {
+    fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        self.0.modified().map_err(|_| FsError::NotImplemented)
+    }
+
+    fn accessed(&self) -> FsResult<SystemTime> {
+        self.0.accessed().map_err(|_| FsError::NotImplemented)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+
+    fn executable(&self) -> FsResult<bool> {
+        if self.0.is_regular() {
+            return Ok(self.0.permissions.map(|p| p & 0o100 != 0).unwrap_or(false));
+        }
+        Err(FsError::NotImplemented)
+    }
+}
+
+impl DavDirEntry for SftpDirEntry {
+    fn name(&self) -> Vec<u8> {
+        self.name.clone()
+    }
+
+    fn metadata<'a>(&'a self) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        let meta = self.meta.clone();
+        async move { Ok(Box::new(meta) as Box<dyn DavMetaData>) }.boxed()
+    }
+}
+
+impl DavFile for SftpFile {
+    fn metadata<'a>(&'a mut self) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            let attrs = self.file.metadata().await.map_err(sftp_error)?;
+            Ok(Box::new(SftpMetaData(attrs)) as Box<dyn DavMetaData>)
+        }
+        .boxed()
+    }
+
+    fn write_buf<'a>(&'a mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<'a, ()> {
+        async move {
+            while buf.has_remaining() {
+                let n = self.file.write(buf.chunk()).await?;
+                buf.advance(n);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn write_bytes<'a>(&'a mut self, buf: Bytes) -> FsFuture<'a, ()> {
+        async move {
+            self.file.write_all(&buf).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn read_bytes<'a>(&'a mut self, count: usize) -> FsFuture<'a, Bytes> {
+        async move {
+            let mut buf = BytesMut::with_capacity(count);
+            while buf.len() < count {
+                if self.file.read_buf(&mut buf).await? == 0 {
+                    break;
+                }
+            }
+            Ok(buf.freeze())
+        }
+        .boxed()
+    }
+
+    fn seek<'a>(&'a mut self, pos: SeekFrom) -> FsFuture<'a, u64> {
+        async move { Ok(self.file.seek(pos).await?) }.boxed()
+    }
+
+    fn flush<'a>(&'a mut self) -> FsFuture<'a, ()> {
+        async move {
+            self.file.flush().await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+fn sftp_error(e: SftpError) -> FsError {
+    match e {
+        SftpError::Status(ref status) => {
+            match status.status_code {
+                StatusCode::NoSuchFile => FsError::NotFound,
+                StatusCode::PermissionDenied => FsError::Forbidden,
+                StatusCode::OpUnsupported => FsError::NotImplemented,
+                _ => {
+                    debug!("sftpfs: {}", e);
+                    FsError::GeneralFailure
+                },
+            }
+        },
+        e => {
+            debug!("sftpfs: {}", e);
+            FsError::GeneralFailure
+        },
+    }
+}
+
+fn ssh_error(e: russh::Error) -> FsError {
+    debug!("sftpfs: ssh: {}", e);
+    FsError::GeneralFailure
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Read, Seek, Write};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use http::StatusCode as HttpStatus;
+    use russh::keys::ssh_key::private::Ed25519Keypair;
+    use russh::keys::PrivateKey;
+    use russh::server::{Auth, ChannelOpenHandle, Msg, Session};
+    use russh::{Channel, ChannelId};
+    use russh_sftp::protocol::{Attrs, Data, File as SftpEntry, Handle, Name, Status, Version};
+
+    use super::*;
+    use crate::testing::{tempdir, TestClient};
+    use crate::DavHandler;
+
+    // An SSH server that only does SFTP, on top of a local directory.
+    struct FakeSsh {
+        root:     PathBuf,
+        channels: HashMap<ChannelId, Channel<Msg>>,
+    }
+
+    impl russh::server::Handler for FakeSsh {
+        type Error = russh::Error;
+
+        async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+            if (user, password) == ("user", "secret") {
+                return Ok(Auth::Accept);
+            }
+            Ok(Auth::reject())
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            channel: Channel<Msg>,
+            reply: ChannelOpenHandle,
+            _session: &mut Session,
+        ) -> Result<(), Self::Error>
+        {
+            self.channels.insert(channel.id(), channel);
+            reply.accept().await;
+            Ok(())
+        }
+
+        async fn subsystem_request(
+            &mut self,
+            id: ChannelId,
+            name: &str,
+            session: &mut Session,
+        ) -> Result<(), Self::Error>
+        {
+            match self.channels.remove(&id) {
+                Some(channel) if name == "sftp" => {
+                    session.channel_success(id)?;
+                    let sftp = FakeSftp {
+                        root:    self.root.clone(),
+                        handles: HashMap::new(),
+                        next:    0,
+                    };
+                    russh_sftp::server::run(channel.into_stream(), sftp).await;
+                },
+                _ => session.channel_failure(id)?,
+            }
+            Ok(())
+        }
+    }
+
+    enum OpenHandle {
+        File(std::fs::File),
+        Dir(Option<Vec<SftpEntry>>),
+    }
+
+    struct FakeSftp {
+        root:    PathBuf,
+        handles: HashMap<String, OpenHandle>,
+        next:    u32,
+    }
+
+    fn status(e: io::Error) -> StatusCode {
+        // like most servers, do not say what went wrong if it is something else.
+        match e.kind() {
+            io::ErrorKind::NotFound => StatusCode::NoSuchFile,
+            io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+            _ => StatusCode::Failure,
+        }
+    }
+
+    fn ok(id: u32) -> Status {
+        Status {
+            id,
+            status_code:   StatusCode::Ok,
+            error_message: "Ok".to_string(),
+            language_tag:  "en-US".to_string(),
+        }
+    }
+
+    impl FakeSftp {
+        fn path(&self, path: &str) -> PathBuf {
+            self.root.join(path.trim_start_matches('/'))
+        }
+
+        fn insert(&mut self, id: u32, handle: OpenHandle) -> Handle {
+            self.next += 1;
+            self.handles.insert(self.next.to_string(), handle);
+            Handle {
+                id,
+                handle: self.next.to_string(),
+            }
+        }
+
+        fn file(&mut self, handle: &str) -> Result<&mut std::fs::File, StatusCode> {
+            match self.handles.get_mut(handle) {
+                Some(OpenHandle::File(file)) => Ok(file),
+                _ => Err(StatusCode::Failure),
+            }
+        }
+    }
+
+    impl russh_sftp::server::Handler for FakeSftp {
+        type Error = StatusCode;
+
+        fn unimplemented(&self) -> StatusCode {
+            StatusCode::OpUnsupported
+        }
+
+        async fn init(&mut self, _: u32, _: HashMap<String, String>) -> Result<Version, StatusCode> {
+            Ok(Version::new())
+        }
+
+        async fn open(
+            &mut self,
+            id: u32,
+            filename: String,
+            pflags: OpenFlags,
+            _: FileAttributes,
+        ) -> Result<Handle, StatusCode>
+        {
+            let file = std::fs::OpenOptions::new()
+                .read(pflags.contains(OpenFlags::READ))
+                .write(pflags.contains(OpenFlags::WRITE))
+                .append(pflags.contains(OpenFlags::APPEND))
+                .truncate(pflags.contains(OpenFlags::TRUNCATE))
+                .create(pflags.contains(OpenFlags::CREATE))
+                .create_new(pflags.contains(OpenFlags::CREATE | OpenFlags::EXCLUDE))
+                .open(self.path(&filename))
+                .map_err(status)?;
+            Ok(self.insert(id, OpenHandle::File(file)))
+        }
+
+        async fn close(&mut self, id: u32, handle: String) -> Result<Status, StatusCode> {
+            self.handles.remove(&handle);
+            Ok(ok(id))
+        }
+
+        async fn read(&mut self, id: u32, handle: String, offset: u64, len: u32) -> Result<Data, StatusCode> {
+            let file = self.file(&handle)?;
+            file.seek(SeekFrom::Start(offset)).map_err(status)?;
+            let mut data = Vec::new();
+            file.take(len as u64).read_to_end(&mut data).map_err(status)?;
+            if data.is_empty() {
+                return Err(StatusCode::Eof);
+            }
+            Ok(Data { id, data })
+        }
+
+        async fn write(
+            &mut self,
+            id: u32,
+            handle: String,
+            offset: u64,
+            data: Vec<u8>,
+        ) -> Result<Status, StatusCode>
+        {
+            let file = self.file(&handle)?;
+            file.seek(SeekFrom::Start(offset)).map_err(status)?;
+            file.write_all(&data).map_err(status)?;
+            Ok(ok(id))
+        }
+
+        async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, StatusCode> {
+            let meta = std::fs::symlink_metadata(self.path(&path)).map_err(status)?;
+            let attrs = FileAttributes::from(&meta);
+            Ok(Attrs { id, attrs })
+        }
+
+        async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, StatusCode> {
+            let meta = std::fs::metadata(self.path(&path)).map_err(status)?;
+            let attrs = FileAttributes::from(&meta);
+            Ok(Attrs { id, attrs })
+        }
+
+        async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, StatusCode> {
+            let meta = self.file(&handle)?.metadata().map_err(status)?;
+            let attrs = FileAttributes::from(&meta);
+            Ok(Attrs { id, attrs })
+        }
+
+        async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, StatusCode> {
+            let mut entries = vec![SftpEntry::dummy("."), SftpEntry::dummy("..")];
+            for entry in std::fs::read_dir(self.path(&path)).map_err(status)? {
+                let entry = entry.map_err(status)?;
+                let meta = entry.metadata().map_err(status)?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                entries.push(SftpEntry::new(name, FileAttributes::from(&meta)));
+            }
+            Ok(self.insert(id, OpenHandle::Dir(Some(entries))))
+        }
+
+        async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, StatusCode> {
+            match self.handles.get_mut(&handle) {
+                Some(OpenHandle::Dir(entries)) => {
+                    let files = entries.take().ok_or(StatusCode::Eof)?;
+                    Ok(Name { id, files })
+                },
+                _ => Err(StatusCode::Failure),
+            }
+        }
+
+        async fn remove(&mut self, id: u32, filename: String) -> Result<Status, StatusCode> {
+            std::fs::remove_file(self.path(&filename)).map_err(status)?;
+            Ok(ok(id))
+        }
+
+        async fn mkdir(&mut self, id: u32, path: String, _: FileAttributes) -> Result<Status, StatusCode> {
+            std::fs::create_dir(self.path(&path)).map_err(status)?;
+            Ok(ok(id))
+        }
+
+        async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, StatusCode> {
+            std::fs::remove_dir(self.path(&path)).map_err(status)?;
+            Ok(ok(id))
+        }
+
+        async fn rename(&mut self, id: u32, from: String, to: String) -> Result<Status, StatusCode> {
+            std::fs::rename(self.path(&from), self.path(&to)).map_err(status)?;
+            Ok(ok(id))
+        }
+    }
+
+    // Start the server, returns its address, host key and connection count.
+    async fn serve(root: PathBuf) -> (String, PublicKey, Arc<AtomicUsize>) {
+        let key = PrivateKey::from(Ed25519Keypair::from_seed(&[7u8; 32]));
+        let public = key.public_key().clone();
+        let config = Arc::new(russh::server::Config {
+            keys: vec![key],
+            auth_rejection_time: Duration::ZERO,
+            auth_rejection_time_initial: Some(Duration::ZERO),
+            ..Default::default()
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let count = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                count.fetch_add(1, Ordering::SeqCst);
+                let handler = FakeSsh {
+                    root:     root.clone(),
+                    channels: HashMap::new(),
+                };
+                if let Ok(session) = russh::server::run_stream(config.clone(), stream, handler).await {
+                    tokio::spawn(session);
+                }
+            }
+        });
+        (addr, public, connections)
+    }
+
+    #[tokio::test]
+    async fn sftpfs() {
+        let dir = tempdir();
+        std::fs::create_dir(dir.join("srv")).unwrap();
+        let (addr, key, connections) = serve(dir.to_path_buf()).await;
+        let fs = SftpFs::new(&addr, SftpAuth::password("user", "secret"), "/srv/").with_host_key(key.clone());
+        let dav = DavHandler::builder().filesystem(fs).build_handler();
+        let c = TestClient::new(dav);
+
+        // files end up below the base directory.
+        assert_eq!(c.request("PUT", "/file.txt", "hello").await.status(), HttpStatus::CREATED);
+        assert_eq!(std::fs::read(dir.join("srv/file.txt")).unwrap(), b"hello");
+        let resp = c.request("GET", "/file.txt", "").await;
+        assert_eq!(resp.status(), HttpStatus::OK);
+        assert_eq!(resp.headers()["content-length"], "5");
+        assert_eq!(resp.into_body(), "hello");
+        let req = http::Request::get("/file.txt").header("range", "bytes=1-3").body("").unwrap();
+        assert_eq!(c.send(req).await.into_body(), "ell");
+
+        assert_eq!(c.request("MKCOL", "/dir/", "").await.status(), HttpStatus::CREATED);
+        assert_eq!(c.request("MKCOL", "/dir/", "").await.status(), HttpStatus::METHOD_NOT_ALLOWED);
+        assert_eq!(c.request("MKCOL", "/no/dir/", "").await.status(), HttpStatus::CONFLICT);
+        let req = http::Request::builder()
+            .method("COPY")
+            .uri("/file.txt")
+            .header("destination", "/dir/copy.txt")
+            .body("")
+            .unwrap();
+        assert_eq!(c.send(req).await.status(), HttpStatus::CREATED);
+        assert_eq!(std::fs::read(dir.join("srv/dir/copy.txt")).unwrap(), b"hello");
+        let req = http::Request::builder()
+            .method("MOVE")
+            .uri("/file.txt")
+            .header("destination", "/moved.txt")
+            .body("")
+            .unwrap();
+        assert_eq!(c.send(req).await.status(), HttpStatus::CREATED);
+        assert_eq!(c.request("GET", "/file.txt", "").await.status(), HttpStatus::NOT_FOUND);
+
+        let req = http::Request::builder()
+            .method("PROPFIND")
+            .uri("/")
+            .header("depth", "1")
+            .body("")
+            .unwrap();
+        let resp = c.send(req).await;
+        assert_eq!(resp.status(), HttpStatus::MULTI_STATUS);
+        let body = String::from_utf8_lossy(resp.body());
+        for href in ["/", "/dir/", "/moved.txt"] {
+            assert!(body.contains(&format!("<D:href>{}</D:href>", href)), "{}", body);
+        }
+        assert_eq!(body.matches("<D:response>").count(), 3, "{}", body);
+
+        assert_eq!(c.request("DELETE", "/dir/", "").await.status(), HttpStatus::NO_CONTENT);
+        assert!(!dir.join("srv/dir").exists());
+
+        // requests share the connections in the pool.
+        assert!(connections.load(Ordering::SeqCst) <= POOL_SIZE);
+
+        // a wrong password is a 403.
+        let fs = SftpFs::new(&addr, SftpAuth::password("user", "wrong"), "/srv").with_host_key(key);
+        let c = TestClient::new(DavHandler::builder().filesystem(fs).build_handler());
+        assert_eq!(c.request("GET", "/moved.txt", "").await.status(), HttpStatus::FORBIDDEN);
+    }
+}
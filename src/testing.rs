@@ -0,0 +1,408 @@
+//! Helpers to test a handler without a server.
+//!
+//! [`TestClient`] drives [`DavHandler::handle`] with requests that are
+//! built in memory, and reads the whole response body, so tests do not
+//! need a TCP listener or an HTTP client.
+//!
+//! It can also replay a recorded conversation, in a simple text format.
+//! A line that starts with `>` is part of a request, and one that starts
+//! with `<` is what the response must look like:
+//!
+//! ```text
+//! # Lines starting with '#', and empty lines, are ignored.
+//! > PUT /dir/file.txt
+//! > Content-Type: text/plain
+//! >
+//! > hello
+//! < 201
+//!
+//! > PROPFIND /dir/file.txt
+//! > Depth: 0
+//! < 207
+//! < Content-Type: application/xml; charset=utf-8
+//! < ~<D:getcontentlength>5</D:getcontentlength>
+//! < !404
+//! ```
+//!
+//! A request starts with the method and the path, followed by headers.
+//! A line with just `>` ends the headers, and the lines after it are the
+//! body (joined with newlines). The response starts with the status code,
+//! followed by headers that must be present (a value of `*` matches any
+//! value), and by `~text` and `!text` lines: text that the body must, or
+//! must not, contain.
+//!
+//! Example:
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! use webdav_handler::{memfs::MemFs, testing::TestClient, DavHandler};
+//!
+//! let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+//! let client = TestClient::new(dav);
+//! let script = "> MKCOL /dir/\n< 201\n> MKCOL /dir/\n< 405\n";
+//! if let Err(e) = client.replay(script).await {
+//!     panic!("{}", e);
+//! }
+//! # }
+//! ```
+//!
+//! For tests of a filesystem that is on disk, [`tempdir`] creates a
+//! directory that is removed again when the test is done.
+//!
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use http::header::{HeaderName, HeaderValue};
+use http::{Request, Response, StatusCode};
+
+use crate::DavHandler;
+
+/// Sends requests to a `DavHandler`, in-process.
+#[derive(Clone)]
+pub struct TestClient {
+    dav: DavHandler,
+}
+
+/// A request and the response that is expected, from a script.
+#[derive(Debug)]
+pub struct Interaction {
+    /// The request.
+    pub request:       Request<Bytes>,
+    /// Expected status.
+    pub status:        StatusCode,
+    /// Headers that the response must have. `*` matches any value.
+    pub headers:       Vec<(HeaderName, String)>,
+    /// Text that the response body must contain.
+    pub body_contains: Vec<String>,
+    /// Text that the response body must not contain.
+    pub body_lacks:    Vec<String>,
+    // line number of the request in the script.
+    line:              usize,
+}
+
+/// Error from parsing or replaying a script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayError {
+    /// Line number in the script, starting at 1.
+    pub line:    usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ReplayError {}
+
+fn error<T>(line: usize, message: impl Into<String>) -> Result<T, ReplayError> {
+    Err(ReplayError {
+        line,
+        message: message.into(),
+    })
+}
+
+impl TestClient {
+    /// Create a new `TestClient` for `dav`.
+    pub fn new(dav: DavHandler) -> TestClient {
+        TestClient { dav }
+    }
+
+    /// Handle a request, and read the whole response body.
+    pub async fn send<B: Into<Bytes>>(&self, req: Request<B>) -> Response<Bytes> {
+        let (parts, body) = self.dav.handle_bytes(req).await.into_parts();
+        // the body of a response that we generated ourselves can't fail.
+        let body = body.into_bytes().await.unwrap_or_default();
+        Response::from_parts(parts, body)
+    }
+
+    /// Shortcut for a request with just a method, path and body.
+    pub async fn request(&self, method: &str, path: &str, body: impl Into<Bytes>) -> Response<Bytes> {
+        let req = Request::builder().method(method).uri(path).body(body.into());
+        match req {
+            Ok(req) => self.send(req).await,
+            Err(e) => panic!("TestClient::request: {} {}: {}", method, path, e),
+        }
+    }
+
+    /// Replay a script, see the [module documentation](self).
+    ///
+    /// Stops at the first response that is not as expected.
+    pub async fn replay(&self, script: &str) -> Result<(), ReplayError> {
+        for i in parse_script(script)?.into_iter() {
+            let method = i.request.method().clone();
+            let uri = i.request.uri().clone();
+            let what = format!("{} {}", method, uri);
+            let resp = self.send(i.request).await;
+
+            if resp.status() != i.status {
+                return error(i.line, format!("{}: expected {}, got {}", what, i.status, resp.status()));
+            }
+            for (name, value) in &i.headers {
+                match resp.headers().get(name).map(|v| v.to_str().unwrap_or("")) {
+                    Some(v) if value == "*" || v == value => {},
+                    Some(v) => {
+                        return error(i.line, format!("{}: {} is {:?}, not {:?}", what, name, v, value));
+                    },
+                    None => return error(i.line, format!("{}: no {} header", what, name)),
+                }
+            }
+            let body = String::from_utf8_lossy(resp.body());
+            for text in &i.body_contains {
+                if !body.contains(text.as_str()) {
+                    return error(i.line, format!("{}: body does not contain {:?}", what, text));
+                }
+            }
+            for text in &i.body_lacks {
+                if body.contains(text.as_str()) {
+                    return error(i.line, format!("{}: body contains {:?}", what, text));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A temporary directory, removed with everything in it when dropped.
+///
+/// It derefs to a `Path`, so `dir.join("file")` works.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+/// Create a new, empty, temporary directory.
+pub fn tempdir() -> TempDir {
+    let dir = std::env::temp_dir().join(format!("webdav-test-{}", uuid::Uuid::new_v4().simple()));
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        panic!("tempdir: {:?}: {}", dir, e);
+    }
+    TempDir(dir)
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// Where we are in the script.
+#[derive(PartialEq)]
+enum State {
+    Start,
+    Headers,
+    Body,
+    Response,
+}
+
+// An interaction that is being parsed.
+struct Partial {
+    line:     usize,
+    request:  http::request::Builder,
+    body:     Vec<String>,
+    status:   Option<StatusCode>,
+    expected: Interaction,
+}
+
+impl Partial {
+    fn finish(self) -> Result<Interaction, ReplayError> {
+        let mut i = self.expected;
+        i.status = match self.status {
+            Some(status) => status,
+            None => return error(self.line, "request without a response"),
+        };
+        i.request = match self.request.body(Bytes::from(self.body.join("\n"))) {
+            Ok(req) => req,
+            Err(e) => return error(self.line, e.to_string()),
+        };
+        Ok(i)
+    }
+}
+
+/// Parse a script, see the [module documentation](self).
+pub fn parse_script(script: &str) -> Result<Vec<Interaction>, ReplayError> {
+    let mut res = Vec::new();
+    let mut cur: Option<Partial> = None;
+    let mut state = State::Start;
+
+    for (n, line) in script.lines().enumerate() {
+        let n = n + 1;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (dir, rest) = line.split_at(line.chars().next().map_or(0, |c| c.len_utf8()));
+        let rest = rest.strip_prefix(' ').unwrap_or(rest);
+
+        match dir {
+            ">" if state == State::Start || state == State::Response => {
+                // a new request.
+                if let Some(p) = cur.take() {
+                    res.push(p.finish()?);
+                }
+                let (method, path) = match rest.split_once(' ') {
+                    Some((m, p)) if !p.trim().is_empty() => (m, p.trim()),
+                    _ => return error(n, "expected a method and a path"),
+                };
+                let expected = Interaction {
+                    request:       Request::new(Bytes::new()),
+                    status:        StatusCode::OK,
+                    headers:       Vec::new(),
+                    body_contains: Vec::new(),
+                    body_lacks:    Vec::new(),
+                    line:          n,
+                };
+                cur = Some(Partial {
+                    line: n,
+                    request: Request::builder().method(method).uri(path),
+                    body: Vec::new(),
+                    status: None,
+                    expected,
+                });
+                state = State::Headers;
+            },
+            ">" => {
+                let p = cur.as_mut().unwrap();
+                if state == State::Body {
+                    p.body.push(rest.to_string());
+                } else if rest.is_empty() {
+                    state = State::Body;
+                } else {
+                    let (name, value) = header(n, rest)?;
+                    let value = match HeaderValue::from_str(&value) {
+                        Ok(v) => v,
+                        Err(_) => return error(n, "invalid header value"),
+                    };
+                    p.request = std::mem::take(&mut p.request).header(name, value);
+                }
+            },
+            "<" => {
+                let p = match cur.as_mut() {
+                    Some(p) => p,
+                    None => return error(n, "response without a request"),
+                };
+                if state != State::Response {
+                    let status = rest.trim().parse::<u16>().ok();
+                    p.status = match status.and_then(|s| StatusCode::from_u16(s).ok()) {
+                        Some(status) => Some(status),
+                        None => return error(n, "expected a status code"),
+                    };
+                    state = State::Response;
+                } else if let Some(text) = rest.strip_prefix('~') {
+                    p.expected.body_contains.push(text.to_string());
+                } else if let Some(text) = rest.strip_prefix('!') {
+                    p.expected.body_lacks.push(text.to_string());
+                } else {
+                    let h = header(n, rest)?;
+                    p.expected.headers.push(h);
+                }
+            },
+            _ => return error(n, "a line must start with '>', '<' or '#'"),
+        }
+    }
+    if let Some(p) = cur.take() {
+        res.push(p.finish()?);
+    }
+    Ok(res)
+}
+
+fn header(n: usize, line: &str) -> Result<(HeaderName, String), ReplayError> {
+    let (name, value) = match line.split_once(':') {
+        Some(h) => h,
+        None => return error(n, "expected a header"),
+    };
+    match HeaderName::from_bytes(name.trim().as_bytes()) {
+        Ok(name) => Ok((name, value.trim().to_string())),
+        Err(_) => error(n, "invalid header name"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+
+    fn client() -> TestClient {
+        TestClient::new(DavHandler::builder().filesystem(MemFs::new()).build_handler())
+    }
+
+    #[tokio::test]
+    async fn replay() {
+        let script = r#"
+# create a file, and look at it.
+> MKCOL /dir/
+< 201
+
+> PUT /dir/file.txt
+> Content-Type: text/plain
+>
+> hello
+> world
+< 201
+< Content-Length: 0
+< ETag: *
+
+> GET /dir/file.txt
+< 200
+< ~hello
+< ~world
+
+> PROPFIND /dir/
+> Depth: 1
+>
+> <D:propfind xmlns:D="DAV:"><D:prop><D:getcontentlength/></D:prop></D:propfind>
+< 207
+< ~<D:href>/dir/file.txt</D:href>
+< ~<D:getcontentlength>11</D:getcontentlength>
+< !<D:status>HTTP/1.1 500
+"#;
+        client().replay(script).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn mismatch() {
+        let client = client();
+        let err = client.replay("> GET /nope\n< 200\n").await.unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(err.message.contains("expected 200 OK, got 404"));
+
+        let script = "> PUT /a\n>\n> x\n< 201\n\n> GET /a\n< 200\n< ~y\n";
+        assert_eq!(client.replay(script).await.unwrap_err().line, 6);
+
+        assert_eq!(parse_script("< 200").unwrap_err().line, 1);
+        assert_eq!(parse_script("> GET /\n< abc").unwrap_err().line, 2);
+        assert_eq!(parse_script("> GET /\n> Depth 1\n< 200").unwrap_err().line, 2);
+        assert_eq!(parse_script("> GET /\n").unwrap_err().line, 1);
+
+        let resp = client.request("GET", "/a", "").await;
+        assert_eq!(resp.into_body(), "x");
+    }
+
+    #[test]
+    fn tempdir_removed() {
+        let dir = tempdir();
+        std::fs::write(dir.join("file"), "x").unwrap();
+        let path = dir.to_path_buf();
+        assert!(path.is_dir());
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
@@ -58,6 +58,16 @@ pub(crate) fn systemtime_to_rfc3339(t: SystemTime) -> String {
         .unwrap_or("1970-01-01T00:00:00Z".into())
 }
 
+pub(crate) fn systemtime_to_clf(t: SystemTime) -> String {
+    static FORMAT: OnceLock<time::format_description::OwnedFormatItem> = OnceLock::new();
+    let format = FORMAT.get_or_init(|| {
+        // 10/Oct/2000:13:55:36 +0000
+        let f = "[day]/[month repr:short]/[year]:[hour]:[minute]:[second] +0000";
+        time::format_description::parse_owned::<2>(f).unwrap()
+    });
+    time::OffsetDateTime::from(t).format(&format).unwrap_or("01/Jan/1970:00:00:00 +0000".into())
+}
+
 pub(crate) fn systemtime_to_localtime(t: SystemTime, offset: Option<UtcOffset>) -> String {
     static FORMAT: OnceLock<time::format_description::OwnedFormatItem> = OnceLock::new();
     let format = FORMAT.get_or_init(|| {
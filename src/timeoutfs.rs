@@ -0,0 +1,223 @@
+//
+// Filesystem wrapper that puts a timeout on filesystem operations,
+// enabled with `DavConfig::fs_op_timeout`.
+//
+// Only the calls on the filesystem itself are covered. Reading from and
+// writing to an open file is not, nor is `copy`, because those can take
+// a long time for large files without anything being wrong.
+//
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime};
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+#[derive(Clone)]
+pub(crate) struct TimeoutFs {
+    inner:   Box<dyn DavFileSystem>,
+    timeout: Duration,
+}
+
+impl TimeoutFs {
+    // Wrap `fs` if there is a timeout.
+    pub(crate) fn wrap(fs: Box<dyn DavFileSystem>, timeout: Option<Duration>) -> Box<dyn DavFileSystem> {
+        match timeout {
+            Some(timeout) => Box::new(TimeoutFs { inner: fs, timeout }),
+            None => fs,
+        }
+    }
+
+    fn timeout<'a, T: Send + 'a>(
+        &self,
+        op: &'static str,
+        path: &'a DavPath,
+        fut: FsFuture<'a, T>,
+    ) -> FsFuture<'a, T>
+    {
+        let timeout = self.timeout;
+        Box::pin(async move {
+            match tokio::time::timeout(timeout, fut).await {
+                Ok(res) => res,
+                Err(_) => {
+                    warn!("fs: {} {}: no result after {:?}", op, path, timeout);
+                    Err(FsError::TimedOut)
+                },
+            }
+        })
+    }
+}
+
+impl DavFileSystem for TimeoutFs {
+    fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+        self.timeout("open", path, self.inner.open(path, options))
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+    {
+        self.timeout("read_dir", path, self.inner.read_dir(path, meta))
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.timeout("metadata", path, self.inner.metadata(path))
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.timeout("symlink_metadata", path, self.inner.symlink_metadata(path))
+    }
+
+    fn exists<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        self.timeout("exists", path, self.inner.exists(path))
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.timeout("create_dir", path, self.inner.create_dir(path))
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.timeout("remove_dir", path, self.inner.remove_dir(path))
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.timeout("remove_file", path, self.inner.remove_file(path))
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.timeout("remove_dir_all", path, self.inner.remove_dir_all(path))
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.timeout("rename", from, self.inner.rename(from, to))
+    }
+
+    fn temp_path(&self, path: &DavPath) -> Option<DavPath> {
+        self.inner.temp_path(path)
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.inner.copy(from, to)
+    }
+
+    fn bind<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.timeout("bind", from, self.inner.bind(from, to))
+    }
+
+    fn supports_bind(&self) -> bool {
+        self.inner.supports_bind()
+    }
+
+    fn capabilities(&self) -> FsCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.timeout("set_accessed", path, self.inner.set_accessed(path, tm))
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.timeout("set_modified", path, self.inner.set_modified(path, tm))
+    }
+
+    fn have_props<'a>(&'a self, path: &'a DavPath) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        self.inner.have_props(path)
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(http::StatusCode, DavProp)>>
+    {
+        self.timeout("patch_props", path, self.inner.patch_props(path, patch))
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        self.timeout("get_props", path, self.inner.get_props(path, do_content))
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        self.timeout("get_prop", path, self.inner.get_prop(path, prop))
+    }
+
+    fn get_named_props<'a>(&'a self, path: &'a DavPath, props: Vec<DavProp>) -> FsFuture<'a, Vec<DavProp>> {
+        self.timeout("get_named_props", path, self.inner.get_named_props(path, props))
+    }
+
+    fn quota<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, (u64, Option<u64>)> {
+        self.timeout("quota", path, self.inner.quota(path))
+    }
+
+    fn privileges<'a>(&'a self, path: &'a DavPath, principal: Option<&'a str>) -> FsFuture<'a, PrivilegeSet> {
+        self.timeout("privileges", path, self.inner.privileges(path, principal))
+    }
+
+    fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        self.timeout("content_type", path, self.inner.content_type(path))
+    }
+
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.timeout("collection_etag", path, self.inner.collection_etag(path))
+    }
+
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.timeout("sync_token", path, self.inner.sync_token(path))
+    }
+
+    fn changes_since<'a>(
+        &'a self,
+        path: &'a DavPath,
+        token: &'a str,
+        deep: bool,
+    ) -> FsFuture<'a, Vec<Change>> {
+        self.timeout("changes_since", path, self.inner.changes_since(path, token, deep))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+
+    // A filesystem where everything below /nfs/ hangs.
+    #[derive(Clone)]
+    struct HangFs(Box<MemFs>);
+
+    impl DavFileSystem for HangFs {
+        fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+            self.0.open(path, options)
+        }
+
+        fn read_dir<'a>(
+            &'a self,
+            path: &'a DavPath,
+            meta: ReadDirMeta,
+        ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>>
+        {
+            self.0.read_dir(path, meta)
+        }
+
+        fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+            if path.as_bytes().starts_with(b"/nfs/") {
+                return Box::pin(futures::future::pending());
+            }
+            self.0.metadata(path)
+        }
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let dav = crate::DavHandler::builder()
+            .filesystem(Box::new(HangFs(MemFs::new())))
+            .fs_op_timeout(Duration::from_millis(50))
+            .build_handler();
+        let req = |path: &str| http::Request::builder().uri(path).body(hyper::Body::empty()).unwrap();
+        let resp = dav.handle(req("/nfs/file")).await;
+        assert_eq!(resp.status(), http::StatusCode::GATEWAY_TIMEOUT);
+        let resp = dav.handle(req("/file")).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,99 @@
+//! Adapter for `tower`-based stacks.
+//!
+//! `DavService` wraps a `DavHandler` and implements `tower_service::Service`,
+//! so that middleware like timeouts, concurrency limits or tracing can
+//! be layered around the webdav handler.
+//!
+//! ```no_run
+//! use webdav_handler::{memfs::MemFs, memls::MemLs, tower::DavService, DavHandler};
+//!
+//! let dav_server = DavHandler::builder()
+//!     .filesystem(MemFs::new())
+//!     .locksystem(MemLs::new())
+//!     .build_handler();
+//! let service = DavService::new(dav_server);
+//! ```
+//!
+use std::convert::Infallible;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Buf;
+use http::{Request, Response};
+use http_body::Body as HttpBody;
+
+use crate::body::Body;
+use crate::DavHandler;
+
+/// `tower_service::Service` that runs a `DavHandler`.
+#[derive(Clone)]
+pub struct DavService {
+    handler: DavHandler,
+}
+
+impl DavService {
+    /// Create a new `DavService` from a pre-configured `DavHandler`.
+    pub fn new(handler: DavHandler) -> DavService {
+        DavService { handler }
+    }
+}
+
+impl From<DavHandler> for DavService {
+    fn from(handler: DavHandler) -> DavService {
+        DavService::new(handler)
+    }
+}
+
+impl<ReqBody, ReqData, ReqError> tower_service::Service<Request<ReqBody>> for DavService
+where
+    ReqData: Buf + Send + 'static,
+    ReqError: StdError + Send + Sync + 'static,
+    ReqBody: HttpBody<Data = ReqData, Error = ReqError> + Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    // The body is read as a stream of data frames, trailers are ignored.
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let handler = self.handler.clone();
+        let (parts, body) = req.into_parts();
+        let mut body = Box::pin(body);
+        let body = futures::stream::poll_fn(move |cx| body.as_mut().poll_data(cx));
+        Box::pin(async move { Ok(handler.handle_stream(Request::from_parts(parts, body)).await) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::{Context, Poll};
+
+    use tower_service::Service;
+
+    use super::DavService;
+    use crate::{memfs::MemFs, DavHandler};
+
+    #[tokio::test]
+    async fn service() {
+        let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+        let mut service = DavService::from(dav);
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let ready = Service::<http::Request<hyper::Body>>::poll_ready(&mut service, &mut cx);
+        assert!(matches!(ready, Poll::Ready(Ok(()))));
+
+        let req = http::Request::put("/file").body(hyper::Body::from("hello")).unwrap();
+        assert_eq!(service.call(req).await.unwrap().status(), 201);
+        let req = http::Request::get("/file").body(hyper::Body::empty()).unwrap();
+        let resp = service.call(req).await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.into_body().into_bytes().await.unwrap(), "hello");
+    }
+}
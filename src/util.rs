@@ -24,6 +24,10 @@ pub enum DavMethod {
     Delete    = 0x0400,
     Lock      = 0x0800,
     Unlock    = 0x1000,
+    Report    = 0x2000,
+    Bind      = 0x4000,
+    Unbind    = 0x8000,
+    Rebind    = 0x10000,
 }
 
 // translate method into our own enum that has webdav methods as well.
@@ -44,6 +48,10 @@ pub(crate) fn dav_method(m: &http::Method) -> DavResult<DavMethod> {
                 "MOVE" => DavMethod::Move,
                 "LOCK" => DavMethod::Lock,
                 "UNLOCK" => DavMethod::Unlock,
+                "REPORT" => DavMethod::Report,
+                "BIND" => DavMethod::Bind,
+                "UNBIND" => DavMethod::Unbind,
+                "REBIND" => DavMethod::Rebind,
                 _ => {
                     return Err(DavError::UnknownDavMethod);
                 },
@@ -75,7 +83,8 @@ impl DavMethodSet {
     pub const HTTP_RO: DavMethodSet =
         DavMethodSet(DavMethod::Get as u32 | DavMethod::Head as u32 | DavMethod::Options as u32);
     pub const HTTP_RW: DavMethodSet = DavMethodSet(Self::HTTP_RO.0 | DavMethod::Put as u32);
-    pub const WEBDAV_RO: DavMethodSet = DavMethodSet(Self::HTTP_RO.0 | DavMethod::PropFind as u32);
+    pub const WEBDAV_RO: DavMethodSet =
+        DavMethodSet(Self::HTTP_RO.0 | DavMethod::PropFind as u32 | DavMethod::Report as u32);
     pub const WEBDAV_RW: DavMethodSet = DavMethodSet(0xffffffff);
 
     /// New set, all methods allowed.
@@ -123,6 +132,10 @@ impl DavMethodSet {
                 "move" => DavMethod::Move as u32,
                 "lock" => DavMethod::Lock as u32,
                 "unlock" => DavMethod::Unlock as u32,
+                "report" => DavMethod::Report as u32,
+                "bind" => DavMethod::Bind as u32,
+                "unbind" => DavMethod::Unbind as u32,
+                "rebind" => DavMethod::Rebind as u32,
                 "http-ro" => Self::HTTP_RO.0,
                 "http-rw" => Self::HTTP_RW.0,
                 "webdav-ro" => Self::WEBDAV_RO.0,
@@ -138,6 +151,59 @@ impl DavMethodSet {
     }
 }
 
+// The items in an Accept-* header, lowercased, with their q-value.
+pub(crate) fn accept_list(req: &http::Request<()>, header: &str) -> Vec<(String, f32)> {
+    let mut res = Vec::new();
+    for value in req.headers().get_all(header).iter() {
+        let value = match value.to_str() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        for item in value.split(',') {
+            let mut parts = item.split(';');
+            let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let mut q = 1.0f32;
+            for param in parts {
+                let param = param.trim();
+                if param.starts_with("q=") || param.starts_with("Q=") {
+                    q = param[2..].trim().parse().unwrap_or(0.0);
+                }
+            }
+            res.push((name, q));
+        }
+    }
+    res
+}
+
+// Find the part of the path that was matched by the route, minus the tail.
+// Used by the axum and poem adapters.
+//
+// If the route ends in a wildcard (like `/dav/*path`) the prefix is
+// the part of the path before the wildcard. Otherwise, if we were nested,
+// it's the part the router stripped off.
+#[cfg(any(feature = "axum-compat", feature = "poem-compat"))]
+pub(crate) fn route_prefix(full_path: &str, path: &str, matched: Option<&str>) -> Option<String> {
+    let prefix = match matched.and_then(|m| m.find("/*").map(|idx| &m[..idx])) {
+        Some(route) => {
+            let nsegs = route.split('/').filter(|s| !s.is_empty()).count();
+            let mut end = 0;
+            for seg in full_path.split('/').filter(|s| !s.is_empty()).take(nsegs) {
+                end = full_path[end..].find(seg).unwrap() + end + seg.len();
+            }
+            &full_path[..end]
+        },
+        None if full_path.len() > path.len() && full_path.ends_with(path) => {
+            &full_path[..full_path.len() - path.len()]
+        },
+        None if full_path != path && path == "/" => full_path,
+        None => "",
+    };
+    match prefix.trim_end_matches('/') {
+        "" => None,
+        x => Some(x.to_string()),
+    }
+}
+
 pub(crate) fn dav_xml_error(body: &str) -> Body {
     let xml = format!(
         "{}\n{}\n{}\n{}\n",
@@ -155,6 +221,10 @@ impl MemBuffer {
         MemBuffer(Cursor::new(Vec::new()))
     }
 
+    pub fn len(&self) -> usize {
+        self.0.get_ref().len()
+    }
+
     pub fn take(&mut self) -> Bytes {
         let buf = std::mem::replace(self.0.get_mut(), Vec::new());
         self.0.set_position(0);
@@ -174,7 +244,7 @@ impl Write for MemBuffer {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use crate::time::systemtime_to_rfc3339;
     use std::time::UNIX_EPOCH;
 
     #[test]
@@ -6,6 +6,7 @@
 //! rejection.
 //!
 use std::convert::Infallible;
+use std::net::SocketAddr;
 use std::path::Path;
 
 use crate::{fakels::FakeLs, localfs::LocalFs, DavHandler};
@@ -25,9 +26,15 @@ pub fn dav_handler(handler: DavHandler) -> BoxedFilter<(impl Reply,)> {
         .and(warp::path::full())
         .and(warp::path::tail())
         .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::body::stream())
         .and_then(
-            move |method, path_full: FullPath, path_tail: Tail, headers: HeaderMap, body| {
+            move |method,
+                  path_full: FullPath,
+                  path_tail: Tail,
+                  headers: HeaderMap,
+                  addr: Option<SocketAddr>,
+                  body| {
                 let handler = handler.clone();
 
                 async move {
@@ -38,6 +45,9 @@ pub fn dav_handler(handler: DavHandler) -> BoxedFilter<(impl Reply,)> {
                     for (k, v) in headers.iter() {
                         builder = builder.header(k, v);
                     }
+                    if let Some(addr) = addr {
+                        builder = builder.extension(addr);
+                    }
                     let request = builder.body(body).unwrap();
 
                     let response = if handler.config.prefix.is_some() {